@@ -1,12 +1,17 @@
 use std::env;
+#[cfg(feature = "bindgen")]
 use std::path::PathBuf;
 
+#[cfg(feature = "bindgen")]
 use bindgen::callbacks::{EnumVariantValue, ParseCallbacks};
+#[cfg(feature = "bindgen")]
 use heck::ToUpperCamelCase;
 
+#[cfg(feature = "bindgen")]
 #[derive(Debug)]
 struct RenameCallbacks;
 
+#[cfg(feature = "bindgen")]
 impl ParseCallbacks for RenameCallbacks {
     fn enum_variant_name(
         &self,
@@ -22,17 +27,86 @@ impl ParseCallbacks for RenameCallbacks {
     }
 }
 
-fn main() {
+/// Return the compile-time settings of Nuked-OPL3, which are passed as defines to both bindgen and
+/// the C compiler so that they agree on the layout of the chip struct. The settings are also passed
+/// to the crate as environment variables, to be reported by `OplBuildConfig`.
+fn opl_defines() -> Vec<(&'static str, String)> {
+    let feature = |name: &str| env::var_os(format!("CARGO_FEATURE_{}", name)).is_some();
+    let setting = |name: &str| {
+        println!("cargo:rerun-if-env-changed={}", name);
+        env::var(name).ok()
+    };
+    let number = |name: &str, default: u64, min: u64| match setting(name) {
+        Some(value) => match value.trim().parse::<u64>() {
+            Ok(n) if n >= min => n,
+            _ => panic!(
+                "{} must be an integer of at least {}, got {:?}",
+                name, min, value
+            ),
+        },
+        None => default,
+    };
+
+    let stereoext = feature("STEREOEXT");
+    // The channel sample delay quirk is off by default with the stereo extension, as in opl3.c.
+    let sample_delay = match setting("OPL3_QUIRK_CHANNELSAMPLEDELAY") {
+        Some(value) => value.trim() != "0",
+        None => !stereoext && !feature("NO_CHANNEL_SAMPLE_DELAY"),
+    };
+    let writebuf_size = number("OPL3_WRITEBUF_SIZE", 1024, 1);
+    let writebuf_delay = number("OPL3_WRITEBUF_DELAY", 2, 0);
+    let sin = setting("OPL3_SIN");
+    if sin.is_some() && feature("RUST_CORE") {
+        panic!("OPL3_SIN is a C expression and cannot be used with the rust-core feature");
+    }
+
+    println!(
+        "cargo:rustc-env=OPL3_CONFIG_CHANNELSAMPLEDELAY={}",
+        sample_delay as u8
+    );
+    println!(
+        "cargo:rustc-env=OPL3_CONFIG_WRITEBUF_SIZE={}",
+        writebuf_size
+    );
+    println!(
+        "cargo:rustc-env=OPL3_CONFIG_WRITEBUF_DELAY={}",
+        writebuf_delay
+    );
+    println!(
+        "cargo:rustc-env=OPL3_CONFIG_CUSTOM_SIN={}",
+        sin.is_some() as u8
+    );
+
+    let mut defines = vec![
+        ("OPL_ENABLE_STEREOEXT", (stereoext as u8).to_string()),
+        (
+            "OPL_QUIRK_CHANNELSAMPLEDELAY",
+            (sample_delay as u8).to_string(),
+        ),
+        ("OPL_WRITEBUF_SIZE", writebuf_size.to_string()),
+        ("OPL_WRITEBUF_DELAY", writebuf_delay.to_string()),
+    ];
+    if let Some(sin) = sin {
+        defines.push(("OPL_SIN(x)", sin));
+    }
+    defines
+}
+
+/// Generate bindings to Nuked-OPL3 in `$OUT_DIR/bindings.rs`, for the `bindgen` feature.
+#[cfg(feature = "bindgen")]
+fn generate_bindings(defines: &[(&'static str, String)]) {
     // The path to the header file
-    let lib_path = "./src/nuked-opl3/";
     let header_path = "./src/nuked-opl3/opl3.h";
-
-    // Tell cargo to rerun build.rs when the C library changes
-    println!("cargo:rerun-if-changed={}", lib_path);
+    let clang_args: Vec<String> = defines
+        .iter()
+        .map(|(name, value)| format!("-D{}={}", name, value))
+        .collect();
 
     let bindings_result = bindgen::Builder::default()
+        .use_core()
         .no_copy(".*")
         .header(header_path)
+        .clang_args(clang_args)
         .allowlist_function("OPL3.*")
         .parse_callbacks(Box::new(RenameCallbacks))
         .generate();
@@ -47,11 +121,34 @@ fn main() {
         }
         Err(e) => panic!("Failed to generate bindings: {:?}", e),
     }
+}
+
+fn main() {
+    let lib_path = "./src/nuked-opl3/";
+
+    // Tell cargo to rerun build.rs when the C library changes
+    println!("cargo:rerun-if-changed={}", lib_path);
+    println!("cargo:rerun-if-changed=./src/layout.c");
+
+    let defines = opl_defines();
+
+    // The Rust core needs neither the bindings nor the C library.
+    if env::var_os("CARGO_FEATURE_RUST_CORE").is_some() {
+        return;
+    }
+
+    // Unless bindgen is requested, the checked-in bindings in src/ are used.
+    #[cfg(feature = "bindgen")]
+    generate_bindings(&defines);
 
-    // Compile the C library
-    cc::Build::new()
-        .file("./src/nuked-opl3/opl3.c")
-        .compile("opl3");
+    // Compile the C library, along with the layout information used to test the bindings
+    let mut build = cc::Build::new();
+    build.file("./src/nuked-opl3/opl3.c");
+    build.file("./src/layout.c");
+    for (name, value) in &defines {
+        build.define(name, value.as_str());
+    }
+    build.compile("opl3");
 
     // Link the compiled library
     println!("cargo:rustc-link-lib=static=opl3");
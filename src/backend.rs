@@ -0,0 +1,383 @@
+//! Synthesis backends for `Opl3Device`.
+//!
+//! `Opl3Device` implements the parts of an OPL3 that Nuked-OPL3 does not: the register file that
+//! can be read back, the timers and the status register. The synthesis itself is left to a
+//! backend, which receives register writes and produces samples. The default backend is
+//! `Opl3Chip`, which wraps Nuked-OPL3. Other backends can be used with
+//! `Opl3Device::with_backend`, such as `OplNullBackend`, which produces silence and is useful for
+//! tests that only look at register traffic and timing.
+//!
+//! Only `reset`, `write_register`, `generate_4ch` and `generate_4ch_resampled` must be implemented.
+//! The stereo and streaming methods are provided in terms of those, and a backend without a write
+//! buffer performs buffered writes immediately.
+//!
+//! # Example
+//!
+//! ```
+//! use opl3_rs::backend::OplNullBackend;
+//! use opl3_rs::{Opl3Device, OplRegisterFile};
+//!
+//! let mut device = Opl3Device::with_backend(Box::new(OplNullBackend::new()), 44100);
+//! device.write_register(0x20, 0x01, OplRegisterFile::Primary, false);
+//! assert_eq!(device.read_register(0x20, OplRegisterFile::Primary), 0x01);
+//! assert_eq!(device.backend::<OplNullBackend>().unwrap().writes(), 1);
+//! ```
+
+use crate::recorder::AsAny;
+use crate::{Opl3Chip, OplEnvelope, OplError, OplOverflowCallback};
+use alloc::boxed::Box;
+
+/// The `OplBackend` trait is implemented by types that synthesize the sound of an OPL3 for an
+/// `Opl3Device`. Registers are addressed as in Nuked-OPL3, with bit 8 selecting the secondary
+/// register file.
+pub trait OplBackend: AsAny + Send {
+    /// Reset the backend to its power-on state.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample_rate` - The sample rate of the resampled output.
+    fn reset(&mut self, sample_rate: u32);
+
+    /// Write a value to a register immediately.
+    ///
+    /// # Arguments
+    ///
+    /// * `reg`   - The register to write to.
+    /// * `value` - The value to write to the register.
+    fn write_register(&mut self, reg: u16, value: u8);
+
+    /// Generate a 4 channel audio sample at the native rate of the backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample` - A mutable slice of at least 4 elements that will receive the sample.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or an `OplError` on failure.
+    fn generate_4ch(&mut self, sample: &mut [i16]) -> Result<(), OplError>;
+
+    /// Generate a 4 channel audio sample at the sample rate passed to `reset`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample` - A mutable slice of at least 4 elements that will receive the sample.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or an `OplError` on failure.
+    fn generate_4ch_resampled(&mut self, sample: &mut [i16]) -> Result<(), OplError>;
+
+    /// Write a value to a register after any delay required since the previous buffered write.
+    /// The default implementation writes the register immediately.
+    fn write_register_buffered(&mut self, reg: u16, value: u8) {
+        self.write_register(reg, value);
+    }
+
+    /// Returns true if the next buffered write would have to perform an earlier write ahead of
+    /// time. The default implementation has no write buffer and always returns false.
+    fn is_write_buffer_full(&self) -> bool {
+        false
+    }
+
+    /// Return the number of buffered writes that have not yet been performed.
+    fn pending_writes(&self) -> usize {
+        0
+    }
+
+    /// Perform all pending buffered writes immediately.
+    fn flush_writes(&mut self) {}
+
+    /// Return the delay between buffered writes, in samples at the native rate.
+    fn write_delay(&self) -> u64 {
+        0
+    }
+
+    /// Set the delay between buffered writes, in samples at the native rate. Ignored by default.
+    fn set_write_delay(&mut self, _delay: u64) {}
+
+    /// Set a callback to be called when a buffered write finds the write buffer full. Ignored by
+    /// default.
+    fn set_overflow_callback(&mut self, _callback: Option<Box<OplOverflowCallback>>) {}
+
+    /// Return the state of the envelope generator of an operator, if the backend tracks it.
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - The operator index used by Nuked-OPL3, from 0 to 35.
+    fn envelope(&self, _slot: usize) -> Option<OplEnvelope> {
+        None
+    }
+
+    /// Generate a stereo audio sample at the native rate of the backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample` - A mutable slice of at least 2 elements that will receive the sample.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or an `OplError` on failure.
+    fn generate(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+        if sample.len() < 2 {
+            return Err(OplError::BufferUndersized);
+        }
+        let mut samples = [0; 4];
+        self.generate_4ch(&mut samples)?;
+        sample[..2].copy_from_slice(&samples[..2]);
+        Ok(())
+    }
+
+    /// Generate a stereo audio sample at the sample rate passed to `reset`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample` - A mutable slice of at least 2 elements that will receive the sample.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or an `OplError` on failure.
+    fn generate_resampled(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+        if sample.len() < 2 {
+            return Err(OplError::BufferUndersized);
+        }
+        let mut samples = [0; 4];
+        self.generate_4ch_resampled(&mut samples)?;
+        sample[..2].copy_from_slice(&samples[..2]);
+        Ok(())
+    }
+
+    /// Fill a buffer with interleaved stereo samples at the sample rate passed to `reset`.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - The buffer to fill. A trailing odd element is left unchanged.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or an `OplError` on failure.
+    fn generate_stream(&mut self, buffer: &mut [i16]) -> Result<(), OplError> {
+        if buffer.len() < 2 {
+            return Err(OplError::BufferUndersized);
+        }
+        for frame in buffer.chunks_exact_mut(2) {
+            self.generate_resampled(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Fill two buffers with interleaved stereo samples at the sample rate passed to `reset`, the
+    /// first with the first two output channels and the second with the other two.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer1` - The buffer for output channels 0 and 1.
+    /// * `buffer2` - The buffer for output channels 2 and 3, of the same length as `buffer1`.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or an `OplError` on failure.
+    fn generate_4ch_stream(
+        &mut self,
+        buffer1: &mut [i16],
+        buffer2: &mut [i16],
+    ) -> Result<(), OplError> {
+        if buffer1.len() != buffer2.len() {
+            return Err(OplError::BufferMismatch);
+        }
+        if buffer1.len() < 4 {
+            return Err(OplError::BufferUndersized);
+        }
+        let mut samples = [0; 4];
+        for (frame1, frame2) in buffer1.chunks_exact_mut(2).zip(buffer2.chunks_exact_mut(2)) {
+            self.generate_4ch_resampled(&mut samples)?;
+            frame1.copy_from_slice(&samples[..2]);
+            frame2.copy_from_slice(&samples[2..]);
+        }
+        Ok(())
+    }
+}
+
+impl OplBackend for Opl3Chip {
+    fn reset(&mut self, sample_rate: u32) {
+        Opl3Chip::reset(self, sample_rate);
+    }
+
+    fn write_register(&mut self, reg: u16, value: u8) {
+        Opl3Chip::write_register(self, reg, value);
+    }
+
+    fn generate_4ch(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+        Opl3Chip::generate_4ch(self, sample)
+    }
+
+    fn generate_4ch_resampled(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+        Opl3Chip::generate_4ch_resampled(self, sample)
+    }
+
+    fn write_register_buffered(&mut self, reg: u16, value: u8) {
+        Opl3Chip::write_register_buffered(self, reg, value);
+    }
+
+    fn is_write_buffer_full(&self) -> bool {
+        Opl3Chip::is_write_buffer_full(self)
+    }
+
+    fn pending_writes(&self) -> usize {
+        Opl3Chip::pending_writes(self)
+    }
+
+    fn flush_writes(&mut self) {
+        Opl3Chip::flush_writes(self);
+    }
+
+    fn write_delay(&self) -> u64 {
+        Opl3Chip::write_delay(self)
+    }
+
+    fn set_write_delay(&mut self, delay: u64) {
+        Opl3Chip::set_write_delay(self, delay);
+    }
+
+    fn set_overflow_callback(&mut self, callback: Option<Box<OplOverflowCallback>>) {
+        Opl3Chip::set_overflow_callback(self, callback);
+    }
+
+    fn envelope(&self, slot: usize) -> Option<OplEnvelope> {
+        Opl3Chip::envelope(self, slot)
+    }
+
+    fn generate(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+        Opl3Chip::generate(self, sample)
+    }
+
+    fn generate_resampled(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+        Opl3Chip::generate_resampled(self, sample)
+    }
+
+    fn generate_stream(&mut self, buffer: &mut [i16]) -> Result<(), OplError> {
+        Opl3Chip::generate_stream(self, buffer)
+    }
+
+    fn generate_4ch_stream(
+        &mut self,
+        buffer1: &mut [i16],
+        buffer2: &mut [i16],
+    ) -> Result<(), OplError> {
+        Opl3Chip::generate_4ch_stream(self, buffer1, buffer2)
+    }
+}
+
+/// A backend that produces silence. Register writes are counted but otherwise ignored.
+#[derive(Debug, Default)]
+pub struct OplNullBackend {
+    writes: usize,
+}
+
+impl OplNullBackend {
+    /// Create a new null backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the number of register writes received since the backend was created or reset.
+    pub fn writes(&self) -> usize {
+        self.writes
+    }
+}
+
+impl OplBackend for OplNullBackend {
+    fn reset(&mut self, _sample_rate: u32) {
+        self.writes = 0;
+    }
+
+    fn write_register(&mut self, _reg: u16, _value: u8) {
+        self.writes += 1;
+    }
+
+    fn generate_4ch(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+        self.generate_4ch_resampled(sample)
+    }
+
+    fn generate_4ch_resampled(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+        if sample.len() < 4 {
+            return Err(OplError::BufferUndersized);
+        }
+        sample[..4].fill(0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Opl3Device, OplRegisterFile};
+
+    /// A backend that outputs the number of samples generated so far on every channel.
+    struct CountingBackend(i16);
+
+    impl OplBackend for CountingBackend {
+        fn reset(&mut self, _sample_rate: u32) {
+            self.0 = 0;
+        }
+
+        fn write_register(&mut self, _reg: u16, _value: u8) {}
+
+        fn generate_4ch(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+            self.0 += 1;
+            sample[..4].fill(self.0);
+            Ok(())
+        }
+
+        fn generate_4ch_resampled(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+            self.generate_4ch(sample)
+        }
+    }
+
+    #[test]
+    fn null_backend() {
+        let mut device = Opl3Device::with_backend(Box::new(OplNullBackend::new()), 44100);
+        device.write_register(0x02, 0xFF, OplRegisterFile::Primary, true);
+        device.write_register(0x04, 0x01, OplRegisterFile::Primary, true);
+        device.write_register(0x05, 0x01, OplRegisterFile::Secondary, true);
+        assert_eq!(device.pending_writes(), 0);
+        assert_eq!(device.read_register(0x05, OplRegisterFile::Secondary), 0x01);
+
+        // The device's timers run without a chip behind them.
+        device.run(1000.0);
+        assert_ne!(device.read_status() & 0x40, 0);
+
+        let mut buffer = [1i16; 64];
+        device.generate_samples(&mut buffer).unwrap();
+        assert!(buffer.iter().all(|&s| s == 0));
+        assert_eq!(device.backend::<OplNullBackend>().unwrap().writes(), 3);
+        assert!(device.backend::<Opl3Chip>().is_none());
+        assert!(device.operator_envelope(0, 0).is_none());
+
+        device.reset(None).unwrap();
+        assert_eq!(device.backend::<OplNullBackend>().unwrap().writes(), 0);
+    }
+
+    #[test]
+    fn provided_methods() {
+        let mut backend = CountingBackend(0);
+        let mut buffer = [0i16; 7];
+        backend.generate_stream(&mut buffer).unwrap();
+        assert_eq!(buffer, [1, 1, 2, 2, 3, 3, 0]);
+
+        let mut buffer1 = [0i16; 4];
+        let mut buffer2 = [0i16; 4];
+        backend
+            .generate_4ch_stream(&mut buffer1, &mut buffer2)
+            .unwrap();
+        assert_eq!(buffer2, [4, 4, 5, 5]);
+        assert!(matches!(
+            backend.generate_4ch_stream(&mut buffer1, &mut buffer2[..2]),
+            Err(OplError::BufferMismatch)
+        ));
+        assert!(matches!(
+            backend.generate(&mut buffer1[..1]),
+            Err(OplError::BufferUndersized)
+        ));
+    }
+}
@@ -1,6 +1,6 @@
 /* automatically generated by rust-bindgen 0.69.4 */
 
-use std::marker::PhantomData;
+use core::marker::PhantomData;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -36,21 +36,22 @@ pub struct Opl3Slot {
     pub slot_num: u8,
 }
 #[test]
+#[cfg(target_pointer_width = "64")]
 fn bindgen_test_layout_Opl3Slot() {
-    const UNINIT: ::std::mem::MaybeUninit<Opl3Slot> = ::std::mem::MaybeUninit::uninit();
+    const UNINIT: ::core::mem::MaybeUninit<Opl3Slot> = ::core::mem::MaybeUninit::uninit();
     let ptr = UNINIT.as_ptr();
     assert_eq!(
-        ::std::mem::size_of::<Opl3Slot>(),
+        ::core::mem::size_of::<Opl3Slot>(),
         80usize,
         concat!("Size of: ", stringify!(Opl3Slot))
     );
     assert_eq!(
-        ::std::mem::align_of::<Opl3Slot>(),
+        ::core::mem::align_of::<Opl3Slot>(),
         8usize,
         concat!("Alignment of ", stringify!(Opl3Slot))
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).channel) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).channel) as usize - ptr as usize },
         0usize,
         concat!(
             "Offset of field: ",
@@ -60,7 +61,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).chip) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).chip) as usize - ptr as usize },
         8usize,
         concat!(
             "Offset of field: ",
@@ -70,7 +71,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).out) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).out) as usize - ptr as usize },
         16usize,
         concat!(
             "Offset of field: ",
@@ -80,7 +81,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).fbmod) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).fbmod) as usize - ptr as usize },
         18usize,
         concat!(
             "Offset of field: ",
@@ -90,7 +91,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).mod_) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).mod_) as usize - ptr as usize },
         24usize,
         concat!(
             "Offset of field: ",
@@ -100,7 +101,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).prout) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).prout) as usize - ptr as usize },
         32usize,
         concat!(
             "Offset of field: ",
@@ -110,7 +111,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_rout) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_rout) as usize - ptr as usize },
         34usize,
         concat!(
             "Offset of field: ",
@@ -120,7 +121,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_out) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_out) as usize - ptr as usize },
         36usize,
         concat!(
             "Offset of field: ",
@@ -130,7 +131,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_inc) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_inc) as usize - ptr as usize },
         38usize,
         concat!(
             "Offset of field: ",
@@ -140,7 +141,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_gen) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_gen) as usize - ptr as usize },
         39usize,
         concat!(
             "Offset of field: ",
@@ -150,7 +151,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_rate) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_rate) as usize - ptr as usize },
         40usize,
         concat!(
             "Offset of field: ",
@@ -160,7 +161,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_ksl) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_ksl) as usize - ptr as usize },
         41usize,
         concat!(
             "Offset of field: ",
@@ -170,7 +171,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).trem) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).trem) as usize - ptr as usize },
         48usize,
         concat!(
             "Offset of field: ",
@@ -180,7 +181,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_vib) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_vib) as usize - ptr as usize },
         56usize,
         concat!(
             "Offset of field: ",
@@ -190,7 +191,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_type) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_type) as usize - ptr as usize },
         57usize,
         concat!(
             "Offset of field: ",
@@ -200,7 +201,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_ksr) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_ksr) as usize - ptr as usize },
         58usize,
         concat!(
             "Offset of field: ",
@@ -210,7 +211,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_mult) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_mult) as usize - ptr as usize },
         59usize,
         concat!(
             "Offset of field: ",
@@ -220,7 +221,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_ksl) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_ksl) as usize - ptr as usize },
         60usize,
         concat!(
             "Offset of field: ",
@@ -230,7 +231,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_tl) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_tl) as usize - ptr as usize },
         61usize,
         concat!(
             "Offset of field: ",
@@ -240,7 +241,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_ar) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_ar) as usize - ptr as usize },
         62usize,
         concat!(
             "Offset of field: ",
@@ -250,7 +251,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_dr) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_dr) as usize - ptr as usize },
         63usize,
         concat!(
             "Offset of field: ",
@@ -260,7 +261,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_sl) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_sl) as usize - ptr as usize },
         64usize,
         concat!(
             "Offset of field: ",
@@ -270,7 +271,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_rr) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_rr) as usize - ptr as usize },
         65usize,
         concat!(
             "Offset of field: ",
@@ -280,7 +281,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg_wf) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg_wf) as usize - ptr as usize },
         66usize,
         concat!(
             "Offset of field: ",
@@ -290,7 +291,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).key) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).key) as usize - ptr as usize },
         67usize,
         concat!(
             "Offset of field: ",
@@ -300,7 +301,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).pg_reset) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).pg_reset) as usize - ptr as usize },
         68usize,
         concat!(
             "Offset of field: ",
@@ -310,7 +311,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).pg_phase) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).pg_phase) as usize - ptr as usize },
         72usize,
         concat!(
             "Offset of field: ",
@@ -320,7 +321,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).pg_phase_out) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).pg_phase_out) as usize - ptr as usize },
         76usize,
         concat!(
             "Offset of field: ",
@@ -330,7 +331,7 @@ fn bindgen_test_layout_Opl3Slot() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).slot_num) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).slot_num) as usize - ptr as usize },
         78usize,
         concat!(
             "Offset of field: ",
@@ -361,21 +362,22 @@ pub struct Opl3Channel {
     pub ch_num: u8,
 }
 #[test]
+#[cfg(target_pointer_width = "64")]
 fn bindgen_test_layout_Opl3Channel() {
-    const UNINIT: ::std::mem::MaybeUninit<Opl3Channel> = ::std::mem::MaybeUninit::uninit();
+    const UNINIT: ::core::mem::MaybeUninit<Opl3Channel> = ::core::mem::MaybeUninit::uninit();
     let ptr = UNINIT.as_ptr();
     assert_eq!(
-        ::std::mem::size_of::<Opl3Channel>(),
+        ::core::mem::size_of::<Opl3Channel>(),
         88usize,
         concat!("Size of: ", stringify!(Opl3Channel))
     );
     assert_eq!(
-        ::std::mem::align_of::<Opl3Channel>(),
+        ::core::mem::align_of::<Opl3Channel>(),
         8usize,
         concat!("Alignment of ", stringify!(Opl3Channel))
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).slotz) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).slotz) as usize - ptr as usize },
         0usize,
         concat!(
             "Offset of field: ",
@@ -385,7 +387,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).pair) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).pair) as usize - ptr as usize },
         16usize,
         concat!(
             "Offset of field: ",
@@ -395,7 +397,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).chip) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).chip) as usize - ptr as usize },
         24usize,
         concat!(
             "Offset of field: ",
@@ -405,7 +407,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).out) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).out) as usize - ptr as usize },
         32usize,
         concat!(
             "Offset of field: ",
@@ -415,7 +417,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).chtype) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).chtype) as usize - ptr as usize },
         64usize,
         concat!(
             "Offset of field: ",
@@ -425,7 +427,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).f_num) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).f_num) as usize - ptr as usize },
         66usize,
         concat!(
             "Offset of field: ",
@@ -435,7 +437,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).block) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).block) as usize - ptr as usize },
         68usize,
         concat!(
             "Offset of field: ",
@@ -445,7 +447,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).fb) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).fb) as usize - ptr as usize },
         69usize,
         concat!(
             "Offset of field: ",
@@ -455,7 +457,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).con) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).con) as usize - ptr as usize },
         70usize,
         concat!(
             "Offset of field: ",
@@ -465,7 +467,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).alg) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).alg) as usize - ptr as usize },
         71usize,
         concat!(
             "Offset of field: ",
@@ -475,7 +477,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).ksv) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).ksv) as usize - ptr as usize },
         72usize,
         concat!(
             "Offset of field: ",
@@ -485,7 +487,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).cha) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).cha) as usize - ptr as usize },
         74usize,
         concat!(
             "Offset of field: ",
@@ -495,7 +497,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).chb) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).chb) as usize - ptr as usize },
         76usize,
         concat!(
             "Offset of field: ",
@@ -505,7 +507,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).chc) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).chc) as usize - ptr as usize },
         78usize,
         concat!(
             "Offset of field: ",
@@ -515,7 +517,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).chd) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).chd) as usize - ptr as usize },
         80usize,
         concat!(
             "Offset of field: ",
@@ -525,7 +527,7 @@ fn bindgen_test_layout_Opl3Channel() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).ch_num) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).ch_num) as usize - ptr as usize },
         82usize,
         concat!(
             "Offset of field: ",
@@ -543,21 +545,22 @@ pub struct Opl3Writebuf {
     pub data: u8,
 }
 #[test]
+#[cfg(target_pointer_width = "64")]
 fn bindgen_test_layout_Opl3Writebuf() {
-    const UNINIT: ::std::mem::MaybeUninit<Opl3Writebuf> = ::std::mem::MaybeUninit::uninit();
+    const UNINIT: ::core::mem::MaybeUninit<Opl3Writebuf> = ::core::mem::MaybeUninit::uninit();
     let ptr = UNINIT.as_ptr();
     assert_eq!(
-        ::std::mem::size_of::<Opl3Writebuf>(),
+        ::core::mem::size_of::<Opl3Writebuf>(),
         16usize,
         concat!("Size of: ", stringify!(Opl3Writebuf))
     );
     assert_eq!(
-        ::std::mem::align_of::<Opl3Writebuf>(),
+        ::core::mem::align_of::<Opl3Writebuf>(),
         8usize,
         concat!("Alignment of ", stringify!(Opl3Writebuf))
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).time) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).time) as usize - ptr as usize },
         0usize,
         concat!(
             "Offset of field: ",
@@ -567,7 +570,7 @@ fn bindgen_test_layout_Opl3Writebuf() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).reg) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).reg) as usize - ptr as usize },
         8usize,
         concat!(
             "Offset of field: ",
@@ -577,7 +580,7 @@ fn bindgen_test_layout_Opl3Writebuf() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).data) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).data) as usize - ptr as usize },
         10usize,
         concat!(
             "Offset of field: ",
@@ -623,25 +626,26 @@ pub struct Opl3Chip {
     pub writebuf_cur: u32,
     pub writebuf_last: u32,
     pub writebuf_lasttime: u64,
-    pub writebuf: [Opl3Writebuf; 1024usize],
+    pub writebuf: [Opl3Writebuf; crate::config::OPL_WRITEBUF_SIZE],
     pub _marker: PhantomData<core::marker::PhantomPinned>,
 }
 #[test]
+#[cfg(target_pointer_width = "64")]
 fn bindgen_test_layout_Opl3Chip() {
-    const UNINIT: ::std::mem::MaybeUninit<Opl3Chip> = ::std::mem::MaybeUninit::uninit();
+    const UNINIT: ::core::mem::MaybeUninit<Opl3Chip> = ::core::mem::MaybeUninit::uninit();
     let ptr = UNINIT.as_ptr();
     assert_eq!(
-        ::std::mem::size_of::<Opl3Chip>(),
-        20960usize,
+        ::core::mem::size_of::<Opl3Chip>(),
+        4576usize + 16usize * crate::config::OPL_WRITEBUF_SIZE,
         concat!("Size of: ", stringify!(Opl3Chip))
     );
     assert_eq!(
-        ::std::mem::align_of::<Opl3Chip>(),
+        ::core::mem::align_of::<Opl3Chip>(),
         8usize,
         concat!("Alignment of ", stringify!(Opl3Chip))
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).channel) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).channel) as usize - ptr as usize },
         0usize,
         concat!(
             "Offset of field: ",
@@ -651,7 +655,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).slot) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).slot) as usize - ptr as usize },
         1584usize,
         concat!(
             "Offset of field: ",
@@ -661,7 +665,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).timer) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).timer) as usize - ptr as usize },
         4464usize,
         concat!(
             "Offset of field: ",
@@ -671,7 +675,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_timer) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_timer) as usize - ptr as usize },
         4472usize,
         concat!(
             "Offset of field: ",
@@ -681,7 +685,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_timerrem) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_timerrem) as usize - ptr as usize },
         4480usize,
         concat!(
             "Offset of field: ",
@@ -691,7 +695,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_state) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_state) as usize - ptr as usize },
         4481usize,
         concat!(
             "Offset of field: ",
@@ -701,7 +705,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_add) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_add) as usize - ptr as usize },
         4482usize,
         concat!(
             "Offset of field: ",
@@ -711,7 +715,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).eg_timer_lo) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).eg_timer_lo) as usize - ptr as usize },
         4483usize,
         concat!(
             "Offset of field: ",
@@ -721,7 +725,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).newm) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).newm) as usize - ptr as usize },
         4484usize,
         concat!(
             "Offset of field: ",
@@ -731,7 +735,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).nts) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).nts) as usize - ptr as usize },
         4485usize,
         concat!(
             "Offset of field: ",
@@ -741,7 +745,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).rhy) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).rhy) as usize - ptr as usize },
         4486usize,
         concat!(
             "Offset of field: ",
@@ -751,7 +755,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).vibpos) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).vibpos) as usize - ptr as usize },
         4487usize,
         concat!(
             "Offset of field: ",
@@ -761,7 +765,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).vibshift) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).vibshift) as usize - ptr as usize },
         4488usize,
         concat!(
             "Offset of field: ",
@@ -771,7 +775,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).tremolo) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).tremolo) as usize - ptr as usize },
         4489usize,
         concat!(
             "Offset of field: ",
@@ -781,7 +785,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).tremolopos) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).tremolopos) as usize - ptr as usize },
         4490usize,
         concat!(
             "Offset of field: ",
@@ -791,7 +795,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).tremoloshift) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).tremoloshift) as usize - ptr as usize },
         4491usize,
         concat!(
             "Offset of field: ",
@@ -801,7 +805,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).noise) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).noise) as usize - ptr as usize },
         4492usize,
         concat!(
             "Offset of field: ",
@@ -811,7 +815,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).zeromod) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).zeromod) as usize - ptr as usize },
         4496usize,
         concat!(
             "Offset of field: ",
@@ -821,7 +825,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).mixbuff) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).mixbuff) as usize - ptr as usize },
         4500usize,
         concat!(
             "Offset of field: ",
@@ -831,7 +835,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).rm_hh_bit2) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).rm_hh_bit2) as usize - ptr as usize },
         4516usize,
         concat!(
             "Offset of field: ",
@@ -841,7 +845,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).rm_hh_bit3) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).rm_hh_bit3) as usize - ptr as usize },
         4517usize,
         concat!(
             "Offset of field: ",
@@ -851,7 +855,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).rm_hh_bit7) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).rm_hh_bit7) as usize - ptr as usize },
         4518usize,
         concat!(
             "Offset of field: ",
@@ -861,7 +865,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).rm_hh_bit8) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).rm_hh_bit8) as usize - ptr as usize },
         4519usize,
         concat!(
             "Offset of field: ",
@@ -871,7 +875,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).rm_tc_bit3) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).rm_tc_bit3) as usize - ptr as usize },
         4520usize,
         concat!(
             "Offset of field: ",
@@ -881,7 +885,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).rm_tc_bit5) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).rm_tc_bit5) as usize - ptr as usize },
         4521usize,
         concat!(
             "Offset of field: ",
@@ -891,7 +895,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).rateratio) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).rateratio) as usize - ptr as usize },
         4524usize,
         concat!(
             "Offset of field: ",
@@ -901,7 +905,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).samplecnt) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).samplecnt) as usize - ptr as usize },
         4528usize,
         concat!(
             "Offset of field: ",
@@ -911,7 +915,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).oldsamples) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).oldsamples) as usize - ptr as usize },
         4532usize,
         concat!(
             "Offset of field: ",
@@ -921,7 +925,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).samples) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).samples) as usize - ptr as usize },
         4540usize,
         concat!(
             "Offset of field: ",
@@ -931,7 +935,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).writebuf_samplecnt) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).writebuf_samplecnt) as usize - ptr as usize },
         4552usize,
         concat!(
             "Offset of field: ",
@@ -941,7 +945,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).writebuf_cur) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).writebuf_cur) as usize - ptr as usize },
         4560usize,
         concat!(
             "Offset of field: ",
@@ -951,7 +955,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).writebuf_last) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).writebuf_last) as usize - ptr as usize },
         4564usize,
         concat!(
             "Offset of field: ",
@@ -961,7 +965,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).writebuf_lasttime) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).writebuf_lasttime) as usize - ptr as usize },
         4568usize,
         concat!(
             "Offset of field: ",
@@ -971,7 +975,7 @@ fn bindgen_test_layout_Opl3Chip() {
         )
     );
     assert_eq!(
-        unsafe { ::std::ptr::addr_of!((*ptr).writebuf) as usize - ptr as usize },
+        unsafe { ::core::ptr::addr_of!((*ptr).writebuf) as usize - ptr as usize },
         4576usize,
         concat!(
             "Offset of field: ",
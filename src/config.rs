@@ -0,0 +1,97 @@
+//! The compile-time configuration of Nuked-OPL3.
+//!
+//! Nuked-OPL3 has several settings that are fixed when the C library is compiled. They are chosen
+//! by `build.rs` from cargo features and environment variables, and passed to both bindgen and the
+//! C compiler so that the Rust bindings always match the layout of the chip struct. The Rust core
+//! enabled by the `rust-core` feature follows the same settings:
+//!
+//! | Setting                         | Feature or environment variable       | Default          |
+//! |---------------------------------|---------------------------------------|------------------|
+//! | `OPL_ENABLE_STEREOEXT`          | `stereoext` feature                   | Off              |
+//! | `OPL_QUIRK_CHANNELSAMPLEDELAY`  | `no-channel-sample-delay` feature, or | On, unless the   |
+//! |                                 | `OPL3_QUIRK_CHANNELSAMPLEDELAY=0/1`   | stereo extension |
+//! |                                 |                                       | is enabled       |
+//! | `OPL_WRITEBUF_SIZE`             | `OPL3_WRITEBUF_SIZE`                  | 1024             |
+//! | `OPL_WRITEBUF_DELAY`            | `OPL3_WRITEBUF_DELAY`                 | 2                |
+//! | `OPL_SIN(x)`                    | `OPL3_SIN`, a C expression of `x`     | `sin()` of libm  |
+//!
+//! The environment variable for the channel sample delay quirk takes precedence over the feature.
+//! `OPL_SIN` is only used by the stereo extension, to build its panning table. It cannot be set with
+//! the `rust-core` feature, which has its panning table built in.
+//!
+//! The configuration the library was built with can be queried at runtime with
+//! `OplBuildConfig::current`.
+//!
+//! # Example
+//!
+//! ```
+//! use opl3_rs::config::OplBuildConfig;
+//!
+//! let config = OplBuildConfig::current();
+//! println!("Write buffer: {} entries", config.writebuf_size);
+//! ```
+
+/// The number of entries in the write buffer used by buffered register writes.
+pub const OPL_WRITEBUF_SIZE: usize = parse(env!("OPL3_CONFIG_WRITEBUF_SIZE")) as usize;
+/// The delay between buffered register writes, in samples at the native rate of 49716 Hz.
+pub const OPL_WRITEBUF_DELAY: u64 = parse(env!("OPL3_CONFIG_WRITEBUF_DELAY"));
+/// Whether some channels are output one sample later on the left side than the right.
+pub const OPL_QUIRK_CHANNELSAMPLEDELAY: bool = parse(env!("OPL3_CONFIG_CHANNELSAMPLEDELAY")) != 0;
+
+/// The compile-time settings of the Nuked-OPL3 library.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OplBuildConfig {
+    /// Whether the stereo extension is compiled in. See the `stereo` module.
+    pub stereo_extension: bool,
+    /// Whether some channels are output one sample later on the left side than the right, as on
+    /// real hardware.
+    pub channel_sample_delay: bool,
+    /// The number of entries in the write buffer used by buffered register writes.
+    pub writebuf_size: usize,
+    /// The delay between buffered register writes, in samples at the native rate.
+    pub writebuf_delay: u64,
+    /// Whether a custom `OPL_SIN` expression was given for the stereo extension's panning table.
+    pub custom_sin: bool,
+    /// Whether the chip is emulated by the Rust port of Nuked-OPL3 instead of the C library. See
+    /// the `rust-core` feature.
+    pub rust_core: bool,
+}
+
+impl OplBuildConfig {
+    /// Return the configuration the library was built with.
+    pub const fn current() -> Self {
+        OplBuildConfig {
+            stereo_extension: cfg!(feature = "stereoext"),
+            channel_sample_delay: OPL_QUIRK_CHANNELSAMPLEDELAY,
+            writebuf_size: OPL_WRITEBUF_SIZE,
+            writebuf_delay: OPL_WRITEBUF_DELAY,
+            custom_sin: parse(env!("OPL3_CONFIG_CUSTOM_SIN")) != 0,
+            rust_core: cfg!(feature = "rust-core"),
+        }
+    }
+}
+
+/// Parse a decimal number set by `build.rs`, which has already validated it.
+const fn parse(value: &str) -> u64 {
+    let bytes = value.as_bytes();
+    let mut n = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        n = n * 10 + (bytes[i] - b'0') as u64;
+        i += 1;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_config() {
+        let config = OplBuildConfig::current();
+        assert_eq!(config.stereo_extension, cfg!(feature = "stereoext"));
+        assert!(config.writebuf_size > 0);
+        assert_eq!(parse("1024"), 1024);
+    }
+}
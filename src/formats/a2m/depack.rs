@@ -0,0 +1,406 @@
+//! Decompressors for the packing methods used by AdLib Tracker II modules.
+//!
+//! Each function decompresses a single block and fails if the output would grow beyond `max_len`
+//! bytes, so that a corrupt block cannot allocate without bound.
+
+use crate::OplError;
+use alloc::{vec, vec::Vec};
+
+fn corrupt() -> OplError {
+    OplError::InvalidFile("corrupt A2M compressed block")
+}
+
+fn check_len(out: &[u8], max_len: usize) -> Result<(), OplError> {
+    if out.len() > max_len {
+        return Err(corrupt());
+    }
+    Ok(())
+}
+
+// Sixpack is an adaptive Huffman coder combined with LZ77 copies over a 21 KB window.
+const SIX_MAX_FREQ: u16 = 2000;
+const SIX_MIN_COPY: usize = 3;
+const SIX_MAX_COPY: usize = 255;
+const SIX_COPY_RANGES: usize = 6;
+const SIX_CODES_PER_RANGE: usize = SIX_MAX_COPY - SIX_MIN_COPY + 1;
+const SIX_TERMINATE: usize = 256;
+const SIX_FIRST_CODE: usize = 257;
+const SIX_MAX_CHAR: usize = SIX_FIRST_CODE + SIX_COPY_RANGES * SIX_CODES_PER_RANGE - 1;
+const SIX_SUCC_MAX: usize = SIX_MAX_CHAR + 1;
+const SIX_TWICE_MAX: usize = 2 * SIX_MAX_CHAR + 1;
+const SIX_ROOT: usize = 1;
+const SIX_WINDOW_SIZE: usize = 21389 + SIX_MAX_COPY;
+const SIX_COPY_BITS: [u16; SIX_COPY_RANGES] = [4, 6, 8, 10, 12, 14];
+const SIX_COPY_MIN: [usize; SIX_COPY_RANGES] = [0, 16, 80, 336, 1360, 5456];
+
+/// Reads bits most significant first from a stream of little-endian 16-bit words.
+struct SixpackInput<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u16,
+    count: u8,
+}
+
+impl SixpackInput<'_> {
+    fn bit(&mut self) -> Result<bool, OplError> {
+        if self.count == 0 {
+            let word = self.data.get(self.pos..self.pos + 2).ok_or_else(corrupt)?;
+            self.buffer = u16::from_le_bytes([word[0], word[1]]);
+            self.pos += 2;
+            self.count = 15;
+        } else {
+            self.count -= 1;
+        }
+        let bit = self.buffer & 0x8000 != 0;
+        self.buffer <<= 1;
+        Ok(bit)
+    }
+
+    /// Read a value of `bits` bits, least significant bit first.
+    fn code(&mut self, bits: u16) -> Result<usize, OplError> {
+        let mut code = 0;
+        for i in 0..bits {
+            if self.bit()? {
+                code |= 1 << i;
+            }
+        }
+        Ok(code)
+    }
+}
+
+struct SixpackTree {
+    left: Vec<usize>,
+    right: Vec<usize>,
+    dad: Vec<usize>,
+    freq: Vec<u16>,
+}
+
+impl SixpackTree {
+    fn new() -> Self {
+        let mut tree = SixpackTree {
+            left: vec![0; SIX_MAX_CHAR + 1],
+            right: vec![0; SIX_MAX_CHAR + 1],
+            dad: vec![0; SIX_TWICE_MAX + 1],
+            freq: vec![1; SIX_TWICE_MAX + 1],
+        };
+        for i in 2..=SIX_TWICE_MAX {
+            tree.dad[i] = i / 2;
+        }
+        for i in 1..=SIX_MAX_CHAR {
+            tree.left[i] = 2 * i;
+            tree.right[i] = 2 * i + 1;
+        }
+        tree
+    }
+
+    fn sibling(&self, node: usize) -> usize {
+        let parent = self.dad[node];
+        if self.left[parent] == node {
+            self.right[parent]
+        } else {
+            self.left[parent]
+        }
+    }
+
+    fn update_freq(&mut self, mut a: usize, mut b: usize) {
+        loop {
+            self.freq[self.dad[a]] = self.freq[a] + self.freq[b];
+            a = self.dad[a];
+            if a == SIX_ROOT {
+                break;
+            }
+            b = self.sibling(a);
+        }
+        if self.freq[SIX_ROOT] == SIX_MAX_FREQ {
+            for freq in self.freq.iter_mut().skip(1) {
+                *freq >>= 1;
+            }
+        }
+    }
+
+    fn update_model(&mut self, code: usize) {
+        let mut a = code + SIX_SUCC_MAX;
+        self.freq[a] += 1;
+        if self.dad[a] == SIX_ROOT {
+            return;
+        }
+
+        let mut code1 = self.dad[a];
+        self.update_freq(a, self.sibling(a));
+        loop {
+            let code2 = self.dad[code1];
+            let b = self.sibling(code1);
+            if self.freq[a] > self.freq[b] {
+                if self.left[code2] == code1 {
+                    self.right[code2] = a;
+                } else {
+                    self.left[code2] = a;
+                }
+                let c = if self.left[code1] == a {
+                    self.left[code1] = b;
+                    self.right[code1]
+                } else {
+                    self.right[code1] = b;
+                    self.left[code1]
+                };
+                self.dad[b] = code1;
+                self.dad[a] = code2;
+                self.update_freq(b, c);
+                a = b;
+            }
+            a = self.dad[a];
+            code1 = self.dad[a];
+            if code1 == SIX_ROOT {
+                break;
+            }
+        }
+    }
+
+    fn decode(&mut self, input: &mut SixpackInput) -> Result<usize, OplError> {
+        let mut a = SIX_ROOT;
+        while a <= SIX_MAX_CHAR {
+            a = if input.bit()? {
+                self.right[a]
+            } else {
+                self.left[a]
+            };
+        }
+        let code = a - SIX_SUCC_MAX;
+        self.update_model(code);
+        Ok(code)
+    }
+}
+
+/// Decompress a block packed with Sixpack, as used by version 1 and 5 modules.
+pub(crate) fn sixpack(data: &[u8], max_len: usize) -> Result<Vec<u8>, OplError> {
+    let mut input = SixpackInput {
+        data,
+        pos: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut tree = SixpackTree::new();
+    let mut window = vec![0u8; SIX_WINDOW_SIZE];
+    let mut count = 0;
+    let mut out = Vec::new();
+
+    loop {
+        let code = tree.decode(&mut input)?;
+        if code == SIX_TERMINATE {
+            break;
+        }
+        if code < SIX_TERMINATE {
+            out.push(code as u8);
+            window[count] = code as u8;
+            count = (count + 1) % SIX_WINDOW_SIZE;
+        } else {
+            let t = code - SIX_FIRST_CODE;
+            let index = t / SIX_CODES_PER_RANGE;
+            let len = t + SIX_MIN_COPY - index * SIX_CODES_PER_RANGE;
+            let dist = input.code(SIX_COPY_BITS[index])? + len + SIX_COPY_MIN[index];
+            let mut j = count;
+            let mut k =
+                (count as isize - dist as isize).rem_euclid(SIX_WINDOW_SIZE as isize) as usize;
+            for _ in 0..len {
+                out.push(window[k]);
+                window[j] = window[k];
+                j = (j + 1) % SIX_WINDOW_SIZE;
+                k = (k + 1) % SIX_WINDOW_SIZE;
+            }
+            count = (count + len) % SIX_WINDOW_SIZE;
+        }
+        check_len(&out, max_len)?;
+    }
+    Ok(out)
+}
+
+// LZSS with a 4 KB window and matches of 3 to 18 bytes.
+const LZSS_WINDOW_SIZE: usize = 4096;
+const LZSS_MAX_MATCH: usize = 18;
+const LZSS_THRESHOLD: usize = 2;
+
+/// Decompress a block packed with LZSS, as used by version 3 and 7 modules.
+pub(crate) fn lzss(data: &[u8], max_len: usize) -> Result<Vec<u8>, OplError> {
+    let mut window = [0u8; LZSS_WINDOW_SIZE];
+    let mut pos = LZSS_WINDOW_SIZE - LZSS_MAX_MATCH;
+    let mut input = data.iter().copied();
+    let mut out = Vec::new();
+    let mut flags: u16 = 0;
+
+    loop {
+        flags >>= 1;
+        if flags & 0x100 == 0 {
+            let Some(byte) = input.next() else {
+                break;
+            };
+            flags = byte as u16 | 0xFF00;
+        }
+        if flags & 1 != 0 {
+            let Some(byte) = input.next() else {
+                break;
+            };
+            out.push(byte);
+            window[pos] = byte;
+            pos = (pos + 1) % LZSS_WINDOW_SIZE;
+        } else {
+            let (Some(lo), Some(hi)) = (input.next(), input.next()) else {
+                break;
+            };
+            let mut src = lo as usize | ((hi as usize & 0xF0) << 4);
+            let len = (hi & 0x0F) as usize + LZSS_THRESHOLD + 1;
+            for _ in 0..len {
+                let byte = window[src];
+                out.push(byte);
+                window[pos] = byte;
+                pos = (pos + 1) % LZSS_WINDOW_SIZE;
+                src = (src + 1) % LZSS_WINDOW_SIZE;
+            }
+        }
+        check_len(&out, max_len)?;
+    }
+    Ok(out)
+}
+
+struct AplibInput<'a> {
+    data: &'a [u8],
+    pos: usize,
+    tag: u8,
+    count: u8,
+}
+
+impl AplibInput<'_> {
+    fn byte(&mut self) -> Result<u8, OplError> {
+        let byte = *self.data.get(self.pos).ok_or_else(corrupt)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bit(&mut self) -> Result<bool, OplError> {
+        if self.count == 0 {
+            self.tag = self.byte()?;
+            self.count = 8;
+        }
+        self.count -= 1;
+        let bit = self.tag & 0x80 != 0;
+        self.tag <<= 1;
+        Ok(bit)
+    }
+
+    fn gamma(&mut self) -> Result<usize, OplError> {
+        let mut value = 1usize;
+        loop {
+            value = (value << 1) + self.bit()? as usize;
+            if !self.bit()? {
+                return Ok(value);
+            }
+            if value > 0x00FF_FFFF {
+                return Err(corrupt());
+            }
+        }
+    }
+}
+
+fn copy_match(out: &mut Vec<u8>, offset: usize, len: usize) -> Result<(), OplError> {
+    if offset == 0 || offset > out.len() {
+        return Err(corrupt());
+    }
+    for _ in 0..len {
+        out.push(out[out.len() - offset]);
+    }
+    Ok(())
+}
+
+/// Decompress a block packed with aPLib, as used by version 9 to 11 modules.
+pub(crate) fn aplib(data: &[u8], max_len: usize) -> Result<Vec<u8>, OplError> {
+    let mut input = AplibInput {
+        data,
+        pos: 0,
+        tag: 0,
+        count: 0,
+    };
+    let mut out = vec![input.byte()?];
+    let mut last_offset = 0;
+    let mut last_was_match = false;
+
+    loop {
+        if !input.bit()? {
+            // Literal byte.
+            out.push(input.byte()?);
+            last_was_match = false;
+        } else if !input.bit()? {
+            // Match with a gamma-coded offset, or a repeat of the last offset.
+            let high = input.gamma()?;
+            if !last_was_match && high == 2 {
+                let len = input.gamma()?;
+                copy_match(&mut out, last_offset, len)?;
+            } else {
+                let high = high - if last_was_match { 2 } else { 3 };
+                let offset = (high << 8) + input.byte()? as usize;
+                let mut len = input.gamma()?;
+                if offset >= 32000 {
+                    len += 1;
+                }
+                if offset >= 1280 {
+                    len += 1;
+                }
+                if offset < 128 {
+                    len += 2;
+                }
+                copy_match(&mut out, offset, len)?;
+                last_offset = offset;
+            }
+            last_was_match = true;
+        } else if !input.bit()? {
+            // Short match, or the end of the stream.
+            let byte = input.byte()? as usize;
+            let offset = byte >> 1;
+            if offset == 0 {
+                break;
+            }
+            copy_match(&mut out, offset, 2 + (byte & 1))?;
+            last_offset = offset;
+            last_was_match = true;
+        } else {
+            // Single byte from a 4-bit offset, or a zero byte.
+            let mut offset = 0;
+            for _ in 0..4 {
+                offset = (offset << 1) | input.bit()? as usize;
+            }
+            if offset == 0 {
+                out.push(0);
+            } else {
+                copy_match(&mut out, offset, 1)?;
+            }
+            last_was_match = false;
+        }
+        check_len(&out, max_len)?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lzss_match() {
+        // Three literals followed by a 6 byte match from the start of the window data.
+        let data = [0x07, b'A', b'B', b'C', 0xEE, 0xF3];
+        assert_eq!(lzss(&data, 100).unwrap(), b"ABCABCABC");
+        assert!(lzss(&data, 4).is_err());
+    }
+
+    #[test]
+    fn aplib_match() {
+        // Literal 'A', literal 'B', a short match of 2 bytes at offset 2, then the end marker.
+        let data = [b'A', 0x6C, b'B', 0x04, 0x00];
+        assert_eq!(aplib(&data, 100).unwrap(), b"ABAB");
+        assert!(aplib(&data[..4], 100).is_err());
+    }
+
+    #[test]
+    fn sixpack_terminate() {
+        // The initial code for the terminator is the path to leaf 2031.
+        assert_eq!(sixpack(&0xFBC0u16.to_le_bytes(), 100).unwrap(), b"");
+    }
+}
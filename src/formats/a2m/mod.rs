@@ -0,0 +1,628 @@
+//! Loader and player for AdLib Tracker II modules (A2M) and tiny modules (A2T).
+//!
+//! AdLib Tracker II songs use the full OPL3: up to 18 melodic channels across both register
+//! files, 4-op channel pairs and stereo panning. Both file types consist of a small header
+//! followed by a series of blocks that are compressed with a method that depends on the format
+//! version:
+//!
+//! | Versions | Compression |
+//! |----------|-------------|
+//! | 1, 5     | Sixpack     |
+//! | 2, 6     | LZW         |
+//! | 3, 7     | LZSS        |
+//! | 4, 8     | None        |
+//! | 9 - 11   | aPLib       |
+//! | 12 - 14  | LZH         |
+//!
+//! Modules using LZW and LZH compression are not currently supported, and are rejected with
+//! `OplError::UnsupportedVersion`.
+//!
+//! A2M modules store the song data, including instrument names, in the first block and the
+//! patterns in the remaining blocks. A2T modules carry the song settings in their header, and
+//! store the instruments, the order list and the patterns in separate blocks, without names.
+//!
+//! Instrument macros, arpeggio and vibrato tables and percussion mode are not played. Effects in
+//! version 1 to 4 modules use an older numbering and are ignored.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::formats::a2m::{A2mFile, A2mPlayer, A2mSequencer};
+//!
+//! let data = std::fs::read("song.a2m").unwrap();
+//! let a2m = A2mFile::parse(&data).unwrap();
+//! println!("{} by {}", a2m.song_name, a2m.composer);
+//! let mut player = A2mPlayer::new(A2mSequencer::new(a2m), 44100);
+//! let samples = player.render_to_vec(44100 * 600).unwrap();
+//! ```
+
+use alloc::{string::String, string::ToString, vec::Vec};
+mod depack;
+mod sequencer;
+
+pub use sequencer::{A2mPlayer, A2mSequencer};
+
+use crate::formats::ByteReader;
+use crate::OplError;
+
+/// The signature at the start of every A2M file.
+pub const A2M_SIGNATURE: &[u8; 10] = b"_A2module_";
+/// The signature at the start of every A2T file.
+pub const A2T_SIGNATURE: &[u8; 15] = b"_A2tiny_module_";
+
+/// The maximum number of tracks in an AdLib Tracker II pattern.
+pub const A2M_TRACKS: usize = 20;
+/// The number of entries in the order list.
+pub const A2M_ORDERS: usize = 128;
+/// The note value used for a key off.
+pub const A2M_NOTE_KEY_OFF: u8 = 0x80;
+
+const A2M_MAX_VERSION: u8 = 14;
+const A2M_DEFAULT_ROWS: usize = 64;
+// Upper bound on the size of a decompressed block. The largest block is the version 9 to 14 song
+// data, at a little over 1.1 MB.
+const A2M_MAX_BLOCK: usize = 2 * 1024 * 1024;
+
+// Song data layout for versions 1 to 8.
+const SONG_V1_NAME_SIZE: usize = 33;
+const SONG_V1_INSTRUMENTS: usize = 250;
+const SONG_V1_INSTRUMENT_SIZE: usize = 13;
+const SONG_V1_NAMES: usize = 86;
+const SONG_V1_INSTRUMENT_DATA: usize = SONG_V1_NAMES + SONG_V1_INSTRUMENTS * SONG_V1_NAME_SIZE;
+const SONG_V1_ORDER: usize =
+    SONG_V1_INSTRUMENT_DATA + SONG_V1_INSTRUMENTS * SONG_V1_INSTRUMENT_SIZE;
+
+// Song data layout for versions 9 to 14. The instrument macro tables and the arpeggio and vibrato
+// tables sit between the instrument data and the order list.
+const SONG_V9_NAME_SIZE: usize = 43;
+const SONG_V9_INSTRUMENTS: usize = 255;
+const SONG_V9_INSTRUMENT_SIZE: usize = 14;
+const SONG_V9_MACRO_SIZE: usize = 3831;
+const SONG_V9_ARPVIB_SIZE: usize = 521;
+const SONG_V9_NAMES: usize = 86;
+const SONG_V9_INSTRUMENT_DATA: usize = SONG_V9_NAMES + SONG_V9_INSTRUMENTS * SONG_V9_NAME_SIZE;
+const SONG_V9_ORDER: usize = SONG_V9_INSTRUMENT_DATA
+    + SONG_V9_INSTRUMENTS * (SONG_V9_INSTRUMENT_SIZE + SONG_V9_MACRO_SIZE + SONG_V9_ARPVIB_SIZE);
+
+const STRING_SIZE: usize = 43;
+
+/// The container type of an AdLib Tracker II song.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum A2mFormat {
+    /// An A2M module.
+    Module,
+    /// An A2T tiny module.
+    Tiny,
+}
+
+/// An AdLib Tracker II instrument.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct A2mInstrument {
+    /// The name of the instrument. Always empty for A2T modules.
+    pub name: String,
+    /// The modulator and carrier registers 0x20, 0x40, 0x60, 0x80 and 0xE0, in that order,
+    /// followed by the feedback and connection register 0xC0.
+    pub fm_data: [u8; 11],
+    /// The panning. 0 is center, 1 is left and 2 is right.
+    pub panning: u8,
+    /// The fine tune added to the frequency of each note.
+    pub fine_tune: i8,
+}
+
+/// An effect and its parameter.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct A2mEffect {
+    /// The effect number.
+    pub def: u8,
+    /// The effect parameter.
+    pub param: u8,
+}
+
+/// A single event in a pattern track.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct A2mEvent {
+    /// The note, 1 (C-0) to 96, `A2M_NOTE_KEY_OFF`, or 0 for no note.
+    pub note: u8,
+    /// The instrument number, or 0 for none.
+    pub instrument: u8,
+    /// The effects. Modules before version 9 only have a single effect column.
+    pub effects: [A2mEffect; 2],
+}
+
+/// A pattern, consisting of a list of events for each track.
+#[derive(Clone, Debug, Default)]
+pub struct A2mPattern {
+    /// The events of each track, indexed by track and then row.
+    pub tracks: Vec<Vec<A2mEvent>>,
+}
+
+/// A parsed A2M or A2T module.
+#[derive(Clone, Debug)]
+pub struct A2mFile {
+    /// Whether the song was loaded from an A2M or an A2T file.
+    pub format: A2mFormat,
+    /// The format version, 1 to 14.
+    pub version: u8,
+    /// The song name. Always empty for A2T modules.
+    pub song_name: String,
+    /// The composer. Always empty for A2T modules.
+    pub composer: String,
+    /// The instruments, indexed by instrument number - 1.
+    pub instruments: Vec<A2mInstrument>,
+    /// The order list. Entries with bit 7 set jump to the order given by the lower bits.
+    pub order_list: Vec<u8>,
+    /// The timer rate in Hz.
+    pub tempo: u8,
+    /// The initial speed, in ticks per row.
+    pub speed: u8,
+    /// The song option flags.
+    pub common_flag: u8,
+    /// The number of rows in each pattern.
+    pub pattern_length: usize,
+    /// The number of tracks used by the song.
+    pub track_count: usize,
+    /// The 4-op channel pairs enabled, in the format of OPL3 register 0x104.
+    pub flag_4op: u8,
+    /// The patterns.
+    pub patterns: Vec<A2mPattern>,
+}
+
+/// The size and arrangement of the patterns in each version.
+struct PatternLayout {
+    event_size: usize,
+    tracks: usize,
+    rows: usize,
+    per_block: usize,
+    row_major: bool,
+}
+
+impl PatternLayout {
+    fn for_version(version: u8) -> Self {
+        match version {
+            1..=4 => PatternLayout {
+                event_size: 4,
+                tracks: 9,
+                rows: 64,
+                per_block: 16,
+                row_major: true,
+            },
+            5..=8 => PatternLayout {
+                event_size: 4,
+                tracks: 18,
+                rows: 64,
+                per_block: 8,
+                row_major: false,
+            },
+            _ => PatternLayout {
+                event_size: 6,
+                tracks: A2M_TRACKS,
+                rows: 256,
+                per_block: 8,
+                row_major: false,
+            },
+        }
+    }
+}
+
+impl A2mFile {
+    /// Parse an A2M or A2T module from a byte slice.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the A2M or A2T file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `A2mFile` or an `OplError` on failure.
+    pub fn parse(data: &[u8]) -> Result<A2mFile, OplError> {
+        if data.starts_with(A2M_SIGNATURE) {
+            Self::parse_a2m(data)
+        } else if data.starts_with(A2T_SIGNATURE) {
+            Self::parse_a2t(data)
+        } else {
+            Err(OplError::BadSignature)
+        }
+    }
+
+    fn parse_a2m(data: &[u8]) -> Result<A2mFile, OplError> {
+        let mut reader = ByteReader::new(data);
+        reader.bytes(A2M_SIGNATURE.len())?;
+        let _crc = reader.u32_le()?;
+        let version = reader.u8()?;
+        let pattern_count = reader.u8()? as usize;
+        check_version(version)?;
+
+        let block_count = match version {
+            1..=4 => 5,
+            5..=8 => 9,
+            _ => 17,
+        };
+        let blocks = read_blocks(&mut reader, version, block_count)?;
+
+        let mut file = A2mFile::new(A2mFormat::Module, version);
+        let song = &blocks[0];
+        file.song_name = pascal_string(song.get(..STRING_SIZE).unwrap_or_default());
+        file.composer = pascal_string(song.get(STRING_SIZE..2 * STRING_SIZE).unwrap_or_default());
+
+        let (names, name_size, inst_data, inst_count, inst_size, order) = if version < 9 {
+            (
+                SONG_V1_NAMES,
+                SONG_V1_NAME_SIZE,
+                SONG_V1_INSTRUMENT_DATA,
+                SONG_V1_INSTRUMENTS,
+                SONG_V1_INSTRUMENT_SIZE,
+                SONG_V1_ORDER,
+            )
+        } else {
+            (
+                SONG_V9_NAMES,
+                SONG_V9_NAME_SIZE,
+                SONG_V9_INSTRUMENT_DATA,
+                SONG_V9_INSTRUMENTS,
+                SONG_V9_INSTRUMENT_SIZE,
+                SONG_V9_ORDER,
+            )
+        };
+        let settings = song
+            .get(order..order + A2M_ORDERS + 3)
+            .ok_or(OplError::UnexpectedEof)?;
+
+        file.instruments = song[inst_data..inst_data + inst_count * inst_size]
+            .chunks_exact(inst_size)
+            .zip(song[names..].chunks_exact(name_size))
+            .map(|(inst, name)| A2mInstrument {
+                name: pascal_string(name),
+                ..parse_instrument(inst)
+            })
+            .collect();
+        file.order_list = settings[..A2M_ORDERS].to_vec();
+        file.tempo = settings[A2M_ORDERS];
+        file.speed = settings[A2M_ORDERS + 1];
+        if version >= 5 {
+            file.common_flag = settings[A2M_ORDERS + 2];
+        }
+        if version >= 9 {
+            let extra = |offset: usize| song.get(order + A2M_ORDERS + offset).copied().unwrap_or(0);
+            file.set_pattern_size(u16::from_le_bytes([extra(3), extra(4)]), extra(5));
+        }
+        if version >= 10 {
+            file.flag_4op = song.get(order + A2M_ORDERS + 9).copied().unwrap_or(0);
+        }
+
+        file.patterns = file.decode_patterns(&blocks[1..], pattern_count);
+        Ok(file)
+    }
+
+    fn parse_a2t(data: &[u8]) -> Result<A2mFile, OplError> {
+        let mut reader = ByteReader::new(data);
+        reader.bytes(A2T_SIGNATURE.len())?;
+        let _crc = reader.u32_le()?;
+        let version = reader.u8()?;
+        let pattern_count = reader.u8()? as usize;
+        check_version(version)?;
+
+        let mut file = A2mFile::new(A2mFormat::Tiny, version);
+        file.tempo = reader.u8()?;
+        file.speed = reader.u8()?;
+        if version >= 5 {
+            file.common_flag = reader.u8()?;
+        }
+        if version >= 9 {
+            let pattern_length = reader.u16_le()?;
+            let track_count = reader.u8()?;
+            let _macro_speedup = reader.u16_le()?;
+            file.set_pattern_size(pattern_length, track_count);
+        }
+        if version >= 10 {
+            file.flag_4op = reader.u8()?;
+            let _lock_flags = reader.bytes(A2M_TRACKS)?;
+        }
+
+        // Versions 9 and later add blocks for the macro, arpeggio and vibrato tables, and version
+        // 11 a block for the disabled macro columns, between the instruments and the order list.
+        let (block_count, order_block) = match version {
+            1..=4 => (6, 1),
+            5..=8 => (10, 1),
+            9 | 10 => (20, 3),
+            _ => (21, 4),
+        };
+        let blocks = read_blocks(&mut reader, version, block_count)?;
+
+        let inst_size = if version < 9 {
+            SONG_V1_INSTRUMENT_SIZE
+        } else {
+            SONG_V9_INSTRUMENT_SIZE
+        };
+        file.instruments = blocks[0]
+            .chunks_exact(inst_size)
+            .map(parse_instrument)
+            .collect();
+        let order = &blocks[order_block];
+        file.order_list = (0..A2M_ORDERS)
+            .map(|i| order.get(i).copied().unwrap_or(0))
+            .collect();
+
+        file.patterns = file.decode_patterns(&blocks[order_block + 1..], pattern_count);
+        Ok(file)
+    }
+
+    fn new(format: A2mFormat, version: u8) -> Self {
+        let layout = PatternLayout::for_version(version);
+        A2mFile {
+            format,
+            version,
+            song_name: String::new(),
+            composer: String::new(),
+            instruments: Vec::new(),
+            order_list: Vec::new(),
+            tempo: 0,
+            speed: 0,
+            common_flag: 0,
+            pattern_length: A2M_DEFAULT_ROWS,
+            track_count: layout.tracks,
+            flag_4op: 0,
+            patterns: Vec::new(),
+        }
+    }
+
+    fn set_pattern_size(&mut self, pattern_length: u16, track_count: u8) {
+        if pattern_length > 0 {
+            self.pattern_length = (pattern_length as usize).min(256);
+        }
+        if track_count > 0 {
+            self.track_count = (track_count as usize).min(A2M_TRACKS);
+        }
+    }
+
+    fn decode_patterns(&self, blocks: &[Vec<u8>], count: usize) -> Vec<A2mPattern> {
+        let layout = PatternLayout::for_version(self.version);
+        let pattern_size = layout.tracks * layout.rows * layout.event_size;
+        let rows = self.pattern_length.min(layout.rows);
+
+        blocks
+            .iter()
+            .flat_map(|block| block.chunks(pattern_size).take(layout.per_block))
+            .take(count)
+            .map(|data| {
+                let tracks = (0..layout.tracks)
+                    .map(|track| {
+                        (0..rows)
+                            .map(|row| {
+                                let index = if layout.row_major {
+                                    row * layout.tracks + track
+                                } else {
+                                    track * layout.rows + row
+                                };
+                                let offset = index * layout.event_size;
+                                let b = |i: usize| data.get(offset + i).copied().unwrap_or(0);
+                                let mut event = A2mEvent {
+                                    note: b(0),
+                                    instrument: b(1),
+                                    ..Default::default()
+                                };
+                                if self.version >= 5 {
+                                    event.effects[0] = A2mEffect {
+                                        def: b(2),
+                                        param: b(3),
+                                    };
+                                }
+                                if layout.event_size == 6 {
+                                    event.effects[1] = A2mEffect {
+                                        def: b(4),
+                                        param: b(5),
+                                    };
+                                }
+                                event
+                            })
+                            .collect()
+                    })
+                    .collect();
+                A2mPattern { tracks }
+            })
+            .collect()
+    }
+}
+
+fn check_version(version: u8) -> Result<(), OplError> {
+    match version {
+        1 | 3..=5 | 7..=11 => Ok(()),
+        _ if version <= A2M_MAX_VERSION => Err(OplError::UnsupportedVersion),
+        _ => Err(OplError::InvalidFile("unknown A2M version")),
+    }
+}
+
+/// Read the table of block lengths and decompress each block that follows it.
+fn read_blocks(
+    reader: &mut ByteReader,
+    version: u8,
+    count: usize,
+) -> Result<Vec<Vec<u8>>, OplError> {
+    let mut lengths = Vec::with_capacity(count);
+    for _ in 0..count {
+        lengths.push(if version < 9 {
+            reader.u16_le()? as usize
+        } else {
+            reader.u32_le()? as usize
+        });
+    }
+    lengths
+        .into_iter()
+        .map(|len| match len {
+            0 => Ok(Vec::new()),
+            _ => depack_block(version, reader.bytes(len)?),
+        })
+        .collect()
+}
+
+fn depack_block(version: u8, data: &[u8]) -> Result<Vec<u8>, OplError> {
+    match version {
+        1 | 5 => depack::sixpack(data, A2M_MAX_BLOCK),
+        3 | 7 => depack::lzss(data, A2M_MAX_BLOCK),
+        9..=11 => depack::aplib(data, A2M_MAX_BLOCK),
+        _ => Ok(data.to_vec()),
+    }
+}
+
+fn parse_instrument(data: &[u8]) -> A2mInstrument {
+    let mut fm_data = [0u8; 11];
+    fm_data.copy_from_slice(&data[..11]);
+    A2mInstrument {
+        name: String::new(),
+        fm_data,
+        panning: data[11],
+        fine_tune: data[12] as i8,
+    }
+}
+
+fn pascal_string(data: &[u8]) -> String {
+    let Some((&len, text)) = data.split_first() else {
+        return String::new();
+    };
+    let text = &text[..(len as usize).min(text.len())];
+    String::from_utf8_lossy(text).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OplRegisterFile;
+    use alloc::vec;
+
+    /// Store data as an aPLib stream of literals.
+    fn aplib_store(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![data[0]];
+        let mut tag = 0;
+        let mut bits = 0;
+        let mut put_bit = |out: &mut Vec<u8>, bit: bool| {
+            if bits == 0 {
+                tag = out.len();
+                out.push(0);
+                bits = 8;
+            }
+            bits -= 1;
+            out[tag] |= (bit as u8) << bits;
+        };
+        for &byte in &data[1..] {
+            put_bit(&mut out, false);
+            out.push(byte);
+        }
+        for bit in [true, true, false] {
+            put_bit(&mut out, bit);
+        }
+        out.push(0);
+        out
+    }
+
+    #[test]
+    fn parse_and_play_v8_module() {
+        let mut song = vec![0u8; SONG_V1_ORDER + A2M_ORDERS + 3];
+        song[..5].copy_from_slice(b"\x04Song");
+        song[SONG_V1_NAMES..SONG_V1_NAMES + 6].copy_from_slice(b"\x05Piano");
+        song[SONG_V1_INSTRUMENT_DATA..SONG_V1_INSTRUMENT_DATA + 13].copy_from_slice(&[
+            0x01, 0x01, 0x10, 0x00, 0xF0, 0xF0, 0x77, 0x77, 0, 0, 0x00, 1, 0xFE,
+        ]);
+        song[SONG_V1_ORDER] = 0;
+        song[SONG_V1_ORDER + 1] = 0x80;
+        song[SONG_V1_ORDER + A2M_ORDERS] = 50;
+        song[SONG_V1_ORDER + A2M_ORDERS + 1] = 2;
+
+        // Track 0 plays C-4 with instrument 1, then breaks to the next order on row 1.
+        let mut pattern = vec![0u8; 18 * 64 * 4];
+        pattern[..4].copy_from_slice(&[49, 1, 0, 0]);
+        pattern[4..8].copy_from_slice(&[0, 0, 13, 0]);
+
+        let mut data = A2M_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0, 0, 0, 0, 8, 1]);
+        for len in [song.len(), pattern.len(), 0, 0, 0, 0, 0, 0, 0] {
+            data.extend_from_slice(&(len as u16).to_le_bytes());
+        }
+        data.extend_from_slice(&song);
+        data.extend_from_slice(&pattern);
+
+        let a2m = A2mFile::parse(&data).unwrap();
+        assert_eq!(a2m.format, A2mFormat::Module);
+        assert_eq!(a2m.song_name, "Song");
+        assert_eq!(a2m.instruments[0].name, "Piano");
+        assert_eq!(a2m.instruments[0].panning, 1);
+        assert_eq!(a2m.instruments[0].fine_tune, -2);
+        assert_eq!(a2m.patterns.len(), 1);
+        assert_eq!(a2m.patterns[0].tracks[0][1].effects[0].def, 13);
+
+        let mut player = A2mPlayer::new(A2mSequencer::new(a2m), 5000);
+        let samples = player.render_to_vec(1_000_000).unwrap();
+        assert_eq!(samples.len() / 2, 4 * 100);
+        assert!(player.sequencer().has_looped());
+        // Panned left, on only the left output.
+        assert!(samples.chunks(2).any(|s| s[0] != 0));
+        assert!(samples.chunks(2).all(|s| s[1] == 0));
+    }
+
+    #[test]
+    fn parse_and_play_v11_tiny_module() {
+        let mut data = A2T_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0, 0, 0, 0, 11, 1, 50, 3, 0]);
+        data.extend_from_slice(&16u16.to_le_bytes());
+        data.push(18);
+        data.extend_from_slice(&1u16.to_le_bytes());
+        // 4-op enabled on the first pair of tracks.
+        data.push(0x01);
+        data.extend_from_slice(&[0; A2M_TRACKS]);
+
+        let mut instruments = vec![0u8; 2 * SONG_V9_INSTRUMENT_SIZE];
+        instruments[..11].copy_from_slice(&[0x01, 0x01, 0x10, 0, 0xF0, 0xF0, 0x77, 0x77, 0, 0, 1]);
+        let mut order = vec![0x80; A2M_ORDERS];
+        order[0] = 0;
+        let mut pattern = vec![0u8; A2M_TRACKS * 256 * 6];
+        for track in [0, 1, 6] {
+            pattern[track * 256 * 6..track * 256 * 6 + 2].copy_from_slice(&[49, 1]);
+        }
+        let blocks = [
+            aplib_store(&instruments),
+            aplib_store(&order),
+            aplib_store(&pattern),
+        ];
+        let lengths = [blocks[0].len(), 0, 0, 0, blocks[1].len(), blocks[2].len()];
+        for i in 0..21 {
+            data.extend_from_slice(&(*lengths.get(i).unwrap_or(&0) as u32).to_le_bytes());
+        }
+        for block in &blocks {
+            data.extend_from_slice(block);
+        }
+
+        let a2t = A2mFile::parse(&data).unwrap();
+        assert_eq!(a2t.format, A2mFormat::Tiny);
+        assert_eq!((a2t.tempo, a2t.speed), (50, 3));
+        assert_eq!((a2t.pattern_length, a2t.track_count), (16, 18));
+        assert_eq!(a2t.instruments.len(), 2);
+        assert_eq!(a2t.patterns[0].tracks[6][0].note, 49);
+
+        let mut player = A2mPlayer::new(A2mSequencer::new(a2t), 5000);
+        let samples = player.render_to_vec(1_000_000).unwrap();
+        assert_eq!(samples.len() / 2, 16 * 3 * 100);
+        let device = player.device();
+        assert_eq!(device.read_register(0x04, OplRegisterFile::Secondary), 0x01);
+        assert_eq!(device.read_register(0x05, OplRegisterFile::Secondary), 0x01);
+        // The 4-op pair is keyed on through its first channel, and track 7 plays on the second
+        // register file.
+        assert_ne!(
+            device.read_register(0xB0, OplRegisterFile::Primary) & 0x20,
+            0
+        );
+        assert_ne!(
+            device.read_register(0xB6, OplRegisterFile::Secondary) & 0x20,
+            0
+        );
+    }
+
+    #[test]
+    fn unsupported_versions() {
+        for version in [2, 6, 12, 14] {
+            let mut data = A2M_SIGNATURE.to_vec();
+            data.extend_from_slice(&[0, 0, 0, 0, version, 1]);
+            assert!(matches!(
+                A2mFile::parse(&data),
+                Err(OplError::UnsupportedVersion)
+            ));
+        }
+    }
+}
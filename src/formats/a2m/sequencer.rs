@@ -0,0 +1,570 @@
+//! Playback of AdLib Tracker II songs.
+
+use super::{A2mEffect, A2mEvent, A2mFile, A2mInstrument, A2M_NOTE_KEY_OFF, A2M_ORDERS};
+use crate::formats::write_reg;
+use crate::player::{OplPlayer, OplSequencer};
+use crate::Opl3Device;
+
+/// The number of tracks that are played. Tracks 19 and 20 are only used for percussion.
+const A2M_PLAYED_TRACKS: usize = 18;
+const A2M_MAX_NOTE: u8 = 12 * 8;
+const A2M_ORDER_JUMP: u8 = 0x80;
+
+const FX_ARPEGGIO: u8 = 0;
+const FX_SLIDE_UP: u8 = 1;
+const FX_SLIDE_DOWN: u8 = 2;
+const FX_TONE_PORTAMENTO: u8 = 3;
+const FX_VIBRATO: u8 = 4;
+const FX_PORTA_VOL_SLIDE: u8 = 5;
+const FX_VIBRATO_VOL_SLIDE: u8 = 6;
+const FX_FINE_SLIDE_UP: u8 = 7;
+const FX_FINE_SLIDE_DOWN: u8 = 8;
+const FX_MODULATOR_VOLUME: u8 = 9;
+const FX_VOL_SLIDE: u8 = 10;
+const FX_POSITION_JUMP: u8 = 11;
+const FX_INSTRUMENT_VOLUME: u8 = 12;
+const FX_PATTERN_BREAK: u8 = 13;
+const FX_TEMPO: u8 = 14;
+const FX_SPEED: u8 = 15;
+const FX_PORTA_FINE_VOL_SLIDE: u8 = 16;
+const FX_VIBRATO_FINE_VOL_SLIDE: u8 = 17;
+const FX_CARRIER_VOLUME: u8 = 18;
+const FX_WAVEFORM: u8 = 19;
+const FX_FINE_VOL_SLIDE: u8 = 20;
+const FX_RETRIGGER: u8 = 21;
+
+/// The channel register offset used by each track. The first 6 pairs of tracks line up with the
+/// OPL3 4-op channel pairs, the second track of each pair being the primary channel.
+const TRACK_CHANNELS: [u16; A2M_PLAYED_TRACKS] = [
+    0x003, 0x000, 0x004, 0x001, 0x005, 0x002, 0x106, 0x107, 0x108, 0x103, 0x100, 0x104, 0x101,
+    0x105, 0x102, 0x006, 0x007, 0x008,
+];
+/// The first track of each 4-op pair, by bit of the 4-op flags.
+const FOUR_OP_TRACKS: [usize; 6] = [0, 2, 4, 9, 11, 13];
+
+const OP_OFFSETS: [u16; 9] = [0x00, 0x01, 0x02, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x12];
+const PANNING: [u8; 3] = [0x30, 0x10, 0x20];
+
+/// F-numbers for C to B.
+const NOTE_FREQ: [u16; 12] = [
+    0x157, 0x16B, 0x181, 0x198, 0x1B0, 0x1CA, 0x1E5, 0x202, 0x220, 0x241, 0x263, 0x287,
+];
+const FREQ_MIN: u16 = 0x156;
+const FREQ_MAX: u16 = 0x2AE;
+
+const VIBRATO_TABLE: [u8; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253, 255, 253, 250, 244,
+    235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24,
+];
+
+#[derive(Copy, Clone, Default)]
+struct A2mTrack {
+    instrument: u8,
+    note: u8,
+    /// The F-number in bits 0-9 and the block in bits 10-12.
+    freq: u16,
+    key_on: bool,
+    modulator: u8,
+    carrier: u8,
+    effects: [A2mEffect; 2],
+    porta_target: u16,
+    porta_speed: u8,
+    vibrato: u8,
+    vibrato_pos: u8,
+    arpeggio: u8,
+    retrigger: u8,
+}
+
+/// The `A2mSequencer` plays an `A2mFile` through an `Opl3Device`.
+pub struct A2mSequencer {
+    file: A2mFile,
+    tracks: [A2mTrack; A2M_PLAYED_TRACKS],
+    order: usize,
+    pattern: usize,
+    row: usize,
+    tempo: u8,
+    speed: u8,
+    speed_cnt: u8,
+    pattern_break: Option<usize>,
+    order_jump: Option<usize>,
+    looped: bool,
+    repeat: bool,
+}
+
+/// A player for AdLib Tracker II modules.
+pub type A2mPlayer = OplPlayer<A2mSequencer>;
+
+impl A2mSequencer {
+    /// Create a new sequencer for the given A2M or A2T module.
+    pub fn new(file: A2mFile) -> Self {
+        A2mSequencer {
+            tempo: file.tempo,
+            speed: file.speed.max(1),
+            file,
+            tracks: [A2mTrack::default(); A2M_PLAYED_TRACKS],
+            order: 0,
+            pattern: 0,
+            row: 0,
+            speed_cnt: 1,
+            pattern_break: None,
+            order_jump: None,
+            looped: false,
+            repeat: false,
+        }
+    }
+
+    /// Return the `A2mFile` being played.
+    pub fn file(&self) -> &A2mFile {
+        &self.file
+    }
+
+    /// Set whether the song repeats when it reaches the end, rather than finishing.
+    pub fn set_repeat(&mut self, repeat: bool) {
+        self.repeat = repeat;
+    }
+
+    /// Return the current position in the order list.
+    pub fn order_position(&self) -> usize {
+        self.order
+    }
+
+    /// Return the pattern being played.
+    pub fn pattern(&self) -> usize {
+        self.pattern
+    }
+
+    /// Return the next row of the current pattern to be played.
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    /// Return the current speed, in ticks per row.
+    pub fn speed(&self) -> u8 {
+        self.speed
+    }
+
+    /// Returns true once the song has reached its end and looped back.
+    pub fn has_looped(&self) -> bool {
+        self.looped
+    }
+
+    fn tick_rate(&self) -> f64 {
+        match self.tempo {
+            0 => 50.0,
+            // The default timer rate is the 18.2 Hz of the PC timer.
+            18 => 18.2,
+            tempo => tempo as f64,
+        }
+    }
+
+    fn instrument(&self, track: usize) -> Option<&A2mInstrument> {
+        let number = self.tracks[track].instrument as usize;
+        number
+            .checked_sub(1)
+            .and_then(|i| self.file.instruments.get(i))
+    }
+
+    /// Return the channel that holds the frequency and key on for a track, which for a 4-op pair
+    /// is the primary channel of the pair.
+    fn key_channel(&self, track: usize) -> u16 {
+        for (bit, &first) in FOUR_OP_TRACKS.iter().enumerate() {
+            if self.file.flag_4op & (1 << bit) != 0 && (track == first || track == first + 1) {
+                return TRACK_CHANNELS[first + 1];
+            }
+        }
+        TRACK_CHANNELS[track]
+    }
+
+    fn update(&mut self, device: &mut Opl3Device) {
+        self.speed_cnt -= 1;
+        if self.speed_cnt > 0 {
+            for track in 0..A2M_PLAYED_TRACKS {
+                self.update_effects(device, track);
+            }
+            return;
+        }
+
+        let track_count = self.file.track_count.min(A2M_PLAYED_TRACKS);
+        for track in 0..track_count {
+            let event = self
+                .file
+                .patterns
+                .get(self.pattern)
+                .and_then(|p| p.tracks.get(track))
+                .and_then(|t| t.get(self.row))
+                .copied()
+                .unwrap_or_default();
+            self.play_event(device, track, event);
+        }
+
+        self.speed_cnt = self.speed;
+        self.advance();
+    }
+
+    fn play_event(&mut self, device: &mut Opl3Device, track: usize, event: A2mEvent) {
+        let porta = event.effects.iter().any(|fx| {
+            matches!(
+                fx.def,
+                FX_TONE_PORTAMENTO | FX_PORTA_VOL_SLIDE | FX_PORTA_FINE_VOL_SLIDE
+            )
+        });
+        {
+            let state = &mut self.tracks[track];
+            state.effects = event.effects;
+            state.arpeggio = 0;
+            state.retrigger = 0;
+        }
+
+        if event.instrument != 0 {
+            self.set_instrument(device, track, event.instrument);
+        }
+
+        match event.note {
+            A2M_NOTE_KEY_OFF => self.key_off(device, track),
+            note @ 1..=A2M_MAX_NOTE if porta => {
+                self.tracks[track].porta_target = self.note_freq(track, note);
+                self.tracks[track].note = note;
+            }
+            note @ 1..=A2M_MAX_NOTE => self.note_on(device, track, note),
+            _ => {}
+        }
+
+        for fx in event.effects {
+            self.row_effect(device, track, fx);
+        }
+    }
+
+    fn row_effect(&mut self, device: &mut Opl3Device, track: usize, fx: A2mEffect) {
+        let state = &mut self.tracks[track];
+        match fx.def {
+            FX_TONE_PORTAMENTO if fx.param != 0 => state.porta_speed = fx.param,
+            FX_VIBRATO if fx.param != 0 => state.vibrato = fx.param,
+            FX_FINE_SLIDE_UP => self.slide(device, track, fx.param as i16),
+            FX_FINE_SLIDE_DOWN => self.slide(device, track, -(fx.param as i16)),
+            FX_MODULATOR_VOLUME => {
+                state.modulator = 63 - fx.param.min(63);
+                self.set_volume(device, track);
+            }
+            FX_CARRIER_VOLUME => {
+                state.carrier = 63 - fx.param.min(63);
+                self.set_volume(device, track);
+            }
+            FX_INSTRUMENT_VOLUME => {
+                let level = 63 - fx.param.min(63);
+                state.carrier = level;
+                if self.is_additive(track) {
+                    self.tracks[track].modulator = level;
+                }
+                self.set_volume(device, track);
+            }
+            FX_POSITION_JUMP => self.order_jump = Some(fx.param as usize),
+            FX_PATTERN_BREAK => self.pattern_break = Some(fx.param as usize),
+            FX_TEMPO if fx.param != 0 => self.tempo = fx.param,
+            FX_SPEED if fx.param != 0 => self.speed = fx.param,
+            FX_WAVEFORM => {
+                let (modulator, carrier) = self.operators(track);
+                if fx.param >> 4 != 0x0F {
+                    write_reg(device, 0xE0 + carrier, fx.param >> 4);
+                }
+                if fx.param & 0x0F != 0x0F {
+                    write_reg(device, 0xE0 + modulator, fx.param & 0x0F);
+                }
+            }
+            FX_PORTA_FINE_VOL_SLIDE | FX_VIBRATO_FINE_VOL_SLIDE | FX_FINE_VOL_SLIDE => {
+                self.volume_slide(device, track, fx.param)
+            }
+            _ => {}
+        }
+    }
+
+    fn update_effects(&mut self, device: &mut Opl3Device, track: usize) {
+        for fx in self.tracks[track].effects {
+            match fx.def {
+                FX_ARPEGGIO if fx.param != 0 => self.arpeggio(device, track, fx.param),
+                FX_SLIDE_UP => self.slide(device, track, fx.param as i16),
+                FX_SLIDE_DOWN => self.slide(device, track, -(fx.param as i16)),
+                FX_TONE_PORTAMENTO => self.portamento(device, track),
+                FX_VIBRATO => self.vibrato(device, track),
+                FX_PORTA_VOL_SLIDE => {
+                    self.portamento(device, track);
+                    self.volume_slide(device, track, fx.param);
+                }
+                FX_VIBRATO_VOL_SLIDE => {
+                    self.vibrato(device, track);
+                    self.volume_slide(device, track, fx.param);
+                }
+                FX_VOL_SLIDE => self.volume_slide(device, track, fx.param),
+                FX_PORTA_FINE_VOL_SLIDE => self.portamento(device, track),
+                FX_VIBRATO_FINE_VOL_SLIDE => self.vibrato(device, track),
+                FX_RETRIGGER if fx.param != 0 => {
+                    let state = &mut self.tracks[track];
+                    state.retrigger += 1;
+                    if state.retrigger >= fx.param {
+                        state.retrigger = 0;
+                        let note = state.note;
+                        self.note_on(device, track, note);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn advance(&mut self) {
+        let next_order = if let Some(target) = self.order_jump.take() {
+            if target <= self.order {
+                self.looped = true;
+            }
+            self.row = 0;
+            Some(target)
+        } else if let Some(row) = self.pattern_break.take() {
+            self.row = row;
+            Some(self.order + 1)
+        } else {
+            self.row += 1;
+            (self.row >= self.file.pattern_length).then(|| {
+                self.row = 0;
+                self.order + 1
+            })
+        };
+        self.pattern_break = None;
+
+        if let Some(order) = next_order {
+            if order >= A2M_ORDERS {
+                self.looped = true;
+            }
+            self.order = order % A2M_ORDERS;
+            self.resolve_order();
+        }
+        if self.row >= self.file.pattern_length {
+            self.row = 0;
+        }
+    }
+
+    fn resolve_order(&mut self) {
+        for _ in 0..A2M_ORDERS {
+            match self.file.order_list.get(self.order).copied() {
+                Some(entry) if entry & A2M_ORDER_JUMP != 0 => {
+                    let target = (entry & !A2M_ORDER_JUMP) as usize;
+                    if target <= self.order {
+                        self.looped = true;
+                    }
+                    self.order = target;
+                    self.row = 0;
+                }
+                Some(entry) if (entry as usize) < self.file.patterns.len() => {
+                    self.pattern = entry as usize;
+                    return;
+                }
+                // The end of the song.
+                _ => {
+                    self.order = 0;
+                    self.row = 0;
+                    self.looped = true;
+                }
+            }
+        }
+    }
+
+    fn operators(&self, track: usize) -> (u16, u16) {
+        let channel = TRACK_CHANNELS[track];
+        let modulator = OP_OFFSETS[(channel & 0xFF) as usize] | (channel & 0x100);
+        (modulator, modulator + 3)
+    }
+
+    fn is_additive(&self, track: usize) -> bool {
+        self.instrument(track)
+            .is_some_and(|inst| inst.fm_data[10] & 1 != 0)
+    }
+
+    fn set_instrument(&mut self, device: &mut Opl3Device, track: usize, instrument: u8) {
+        self.tracks[track].instrument = instrument;
+        let Some(inst) = self.instrument(track).cloned() else {
+            return;
+        };
+        let (modulator, carrier) = self.operators(track);
+        let fm = &inst.fm_data;
+        for (reg, i) in [(0x20, 0), (0x60, 4), (0x80, 6), (0xE0, 8)] {
+            write_reg(device, reg + modulator, fm[i]);
+            write_reg(device, reg + carrier, fm[i + 1]);
+        }
+        let channel = TRACK_CHANNELS[track];
+        write_reg(
+            device,
+            0xC0 + channel,
+            (fm[10] & 0x0F) | PANNING[inst.panning as usize % PANNING.len()],
+        );
+
+        let state = &mut self.tracks[track];
+        state.modulator = fm[2] & 63;
+        state.carrier = fm[3] & 63;
+        self.set_volume(device, track);
+    }
+
+    fn set_volume(&mut self, device: &mut Opl3Device, track: usize) {
+        let Some(inst) = self.instrument(track) else {
+            return;
+        };
+        let fm = inst.fm_data;
+        let (modulator, carrier) = self.operators(track);
+        let state = &self.tracks[track];
+        write_reg(device, 0x40 + modulator, (fm[2] & 0xC0) | state.modulator);
+        write_reg(device, 0x40 + carrier, (fm[3] & 0xC0) | state.carrier);
+    }
+
+    fn volume_slide(&mut self, device: &mut Opl3Device, track: usize, param: u8) {
+        let additive = self.is_additive(track);
+        let state = &mut self.tracks[track];
+        let slide = |level: u8| {
+            if param >> 4 != 0 {
+                level.saturating_sub(param >> 4)
+            } else {
+                (level + (param & 0x0F)).min(63)
+            }
+        };
+        state.carrier = slide(state.carrier);
+        if additive {
+            state.modulator = slide(state.modulator);
+        }
+        self.set_volume(device, track);
+    }
+
+    fn note_freq(&self, track: usize, note: u8) -> u16 {
+        let note = (note - 1).min(A2M_MAX_NOTE - 1);
+        let fine_tune = self.instrument(track).map_or(0, |inst| inst.fine_tune);
+        let fnum = NOTE_FREQ[(note % 12) as usize].wrapping_add_signed(fine_tune as i16);
+        fnum | ((note / 12) as u16) << 10
+    }
+
+    fn note_on(&mut self, device: &mut Opl3Device, track: usize, note: u8) {
+        let freq = self.note_freq(track, note);
+        let state = &mut self.tracks[track];
+        state.note = note;
+        state.freq = freq;
+        state.porta_target = freq;
+        state.vibrato_pos = 0;
+        state.key_on = true;
+
+        let channel = self.key_channel(track);
+        write_reg(device, 0xB0 + channel, (freq >> 8) as u8 & 0x1F);
+        self.write_freq(device, track, freq);
+    }
+
+    fn key_off(&mut self, device: &mut Opl3Device, track: usize) {
+        self.tracks[track].key_on = false;
+        let freq = self.tracks[track].freq;
+        self.write_freq(device, track, freq);
+    }
+
+    fn write_freq(&self, device: &mut Opl3Device, track: usize, freq: u16) {
+        let channel = self.key_channel(track);
+        let key = if self.tracks[track].key_on { 0x20 } else { 0 };
+        write_reg(device, 0xA0 + channel, freq as u8);
+        write_reg(device, 0xB0 + channel, ((freq >> 8) as u8 & 0x1F) | key);
+    }
+
+    fn slide(&mut self, device: &mut Opl3Device, track: usize, amount: i16) {
+        let freq = shift_freq(self.tracks[track].freq, amount);
+        self.tracks[track].freq = freq;
+        self.write_freq(device, track, freq);
+    }
+
+    fn portamento(&mut self, device: &mut Opl3Device, track: usize) {
+        let state = &self.tracks[track];
+        let (freq, target, speed) = (state.freq, state.porta_target, state.porta_speed as i16);
+        let freq = if freq < target {
+            shift_freq(freq, speed).min(target)
+        } else if freq > target {
+            shift_freq(freq, -speed).max(target)
+        } else {
+            return;
+        };
+        self.tracks[track].freq = freq;
+        self.write_freq(device, track, freq);
+    }
+
+    fn vibrato(&mut self, device: &mut Opl3Device, track: usize) {
+        let state = &mut self.tracks[track];
+        let (speed, depth) = (state.vibrato >> 4, state.vibrato & 0x0F);
+        let delta = (VIBRATO_TABLE[(state.vibrato_pos & 31) as usize] as i16 * depth as i16) >> 6;
+        let delta = if state.vibrato_pos & 32 != 0 {
+            -delta
+        } else {
+            delta
+        };
+        state.vibrato_pos = state.vibrato_pos.wrapping_add(speed) & 63;
+        let freq = shift_freq(state.freq, delta);
+        self.write_freq(device, track, freq);
+    }
+
+    fn arpeggio(&mut self, device: &mut Opl3Device, track: usize, param: u8) {
+        let state = &mut self.tracks[track];
+        state.arpeggio = (state.arpeggio + 1) % 3;
+        let offset = match state.arpeggio {
+            0 => 0,
+            1 => param >> 4,
+            _ => param & 0x0F,
+        };
+        let note = (state.note + offset).min(A2M_MAX_NOTE);
+        if note == 0 {
+            return;
+        }
+        let freq = self.note_freq(track, note);
+        self.write_freq(device, track, freq);
+    }
+}
+
+/// Shift a frequency up or down, moving between blocks so that the F-number stays within the
+/// range of a single octave.
+fn shift_freq(freq: u16, amount: i16) -> u16 {
+    let mut block = (freq >> 10) & 7;
+    let mut fnum = (freq & 0x3FF) as i16 + amount;
+    if fnum > FREQ_MAX as i16 {
+        if block == 7 {
+            fnum = FREQ_MAX as i16;
+        } else {
+            block += 1;
+            fnum -= (FREQ_MAX - FREQ_MIN) as i16;
+        }
+    } else if fnum < FREQ_MIN as i16 && amount < 0 {
+        if block == 0 {
+            fnum = FREQ_MIN as i16;
+        } else {
+            block -= 1;
+            fnum += (FREQ_MAX - FREQ_MIN) as i16;
+        }
+    }
+    (fnum.clamp(0, 0x3FF) as u16) | block << 10
+}
+
+impl OplSequencer for A2mSequencer {
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64> {
+        // Finish at the first row after the song loops, unless repeating.
+        if self.looped && !self.repeat && self.speed_cnt <= 1 {
+            return None;
+        }
+        self.update(device);
+        Some(1_000_000.0 / self.tick_rate())
+    }
+
+    fn rewind(&mut self, device: &mut Opl3Device) {
+        self.tracks = [A2mTrack::default(); A2M_PLAYED_TRACKS];
+        self.order = 0;
+        self.pattern = 0;
+        self.row = 0;
+        self.tempo = self.file.tempo;
+        self.speed = self.file.speed.max(1);
+        self.speed_cnt = 1;
+        self.pattern_break = None;
+        self.order_jump = None;
+        self.resolve_order();
+        self.looped = false;
+
+        // Enable OPL3 mode, the 4-op pairs used by the song and waveform selection.
+        write_reg(device, 0x105, 0x01);
+        write_reg(device, 0x104, self.file.flag_4op & 0x3F);
+        write_reg(device, 0x001, 0x20);
+        write_reg(device, 0x008, 0x00);
+        write_reg(device, 0x0BD, 0x00);
+    }
+}
@@ -0,0 +1,1269 @@
+//! Loader and driver for Westwood Studios AdLib music files (ADL).
+//!
+//! Westwood's games, including Dune II, The Legend of Kyrandia and Lands of Lore, play music and
+//! sound effects with an AdLib driver that runs small bytecode programs. An ADL file contains a
+//! table mapping track numbers to programs, a table of offsets to the programs and instruments,
+//! and the program data itself. Each program starts on one of 9 OPL2 channels, or on a control
+//! channel that starts other programs, and is interpreted at 72 Hz.
+//!
+//! Three layouts of the file are supported, and are detected when parsing:
+//!
+//! | Version | Games                           | Tracks | Programs | Jumps    |
+//! |---------|---------------------------------|--------|----------|----------|
+//! | `V1`    | Dune II                         | 120    | 150      | Absolute |
+//! | `V2`    | The Legend of Kyrandia          | 120    | 250      | Relative |
+//! | `V3`    | Kyrandia 2 and 3, Lands of Lore | 500    | 500      | Relative |
+//!
+//! An `AdlSequencer` plays a single track, and further tracks such as sound effects can be started
+//! on top of it with `AdlSequencer::play_track`, just as the games do. A track finishes when all
+//! of its programs have stopped. Most music tracks loop forever, so the caller should bound the
+//! amount of audio rendered.
+//!
+//! Pitch bends are calculated from the note frequency table rather than with the lookup tables of
+//! the original driver, and opcode 0xBF, which uses undocumented frequency tables, is ignored.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::formats::adl::{AdlFile, AdlPlayer, AdlSequencer};
+//!
+//! let data = std::fs::read("KYRA1A.ADL").unwrap();
+//! let adl = AdlFile::parse(&data).unwrap();
+//! let track = adl.valid_tracks()[0];
+//! let mut player = AdlPlayer::new(AdlSequencer::new(adl, track), 44100);
+//! let samples = player.render_to_vec(44100 * 120).unwrap();
+//! ```
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::formats::write_reg;
+use crate::player::{OplPlayer, OplSequencer};
+use crate::{Opl3Device, OplError};
+
+/// The rate at which the driver runs, in Hz.
+pub const ADL_CALLBACK_RATE: f64 = 72.0;
+
+const ADL_CHANNELS: usize = 10;
+/// The control channel, which has no OPL channel of its own.
+const ADL_CONTROL_CHANNEL: usize = 9;
+const ADL_SMALL_TRACK_TABLE: usize = 120;
+const ADL_LARGE_TRACK_TABLE: usize = 1000;
+const ADL_NO_PROGRAM: u16 = 0xFFFF;
+const ADL_QUEUE_SIZE: usize = 16;
+const ADL_STACK_SIZE: usize = 4;
+/// The driver's load address, subtracted from absolute pointers in the program data.
+const ADL_DATA_BASE: i32 = 191;
+
+const REG_OFFSETS: [u16; 9] = [0x00, 0x01, 0x02, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x12];
+const FREQ_TABLE: [u16; 12] = [
+    0x134, 0x147, 0x15A, 0x16F, 0x184, 0x19C, 0x1B4, 0x1CE, 0x1E9, 0x207, 0x225, 0x246,
+];
+/// The operator level registers of the hi-hat, cymbal, tom-tom, snare drum and bass drum, in the
+/// order of their bits in register 0xBD.
+const RHYTHM_LEVEL_REGS: [u16; 5] = [0x51, 0x55, 0x52, 0x54, 0x53];
+
+/// The layout of an ADL file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AdlVersion {
+    /// Dune II.
+    V1,
+    /// The Legend of Kyrandia.
+    V2,
+    /// The Legend of Kyrandia books 2 and 3, and Lands of Lore.
+    V3,
+}
+
+impl AdlVersion {
+    fn track_table_size(self) -> usize {
+        match self {
+            AdlVersion::V1 | AdlVersion::V2 => ADL_SMALL_TRACK_TABLE,
+            AdlVersion::V3 => ADL_LARGE_TRACK_TABLE,
+        }
+    }
+
+    /// Return the number of programs, which are followed by the instruments in the offset table.
+    pub fn program_count(self) -> usize {
+        match self {
+            AdlVersion::V1 => 150,
+            AdlVersion::V2 => 250,
+            AdlVersion::V3 => 500,
+        }
+    }
+}
+
+/// A parsed ADL file.
+#[derive(Clone, Debug)]
+pub struct AdlFile {
+    /// The layout of the file.
+    pub version: AdlVersion,
+    /// The program started by each track, if any.
+    pub tracks: Vec<Option<u16>>,
+    /// The offset table and program data, which follow the track table.
+    pub data: Vec<u8>,
+}
+
+impl AdlFile {
+    /// Parse an ADL file from a byte slice, detecting its version.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the ADL file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `AdlFile` or an `OplError` on failure.
+    pub fn parse(data: &[u8]) -> Result<AdlFile, OplError> {
+        let version = [AdlVersion::V3, AdlVersion::V2, AdlVersion::V1]
+            .into_iter()
+            .find(|&version| Self::layout_matches(data, version))
+            .ok_or(OplError::InvalidFile("ADL version not recognized"))?;
+        Self::parse_version(data, version)
+    }
+
+    /// Parse an ADL file from a byte slice with the given version.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the ADL file.
+    /// * `version` - The layout of the file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `AdlFile` or an `OplError` on failure.
+    pub fn parse_version(data: &[u8], version: AdlVersion) -> Result<AdlFile, OplError> {
+        let table_size = version.track_table_size();
+        if data.len() < table_size + version.program_count() * 2 {
+            return Err(OplError::UnexpectedEof);
+        }
+        let tracks = match version {
+            AdlVersion::V3 => data[..table_size]
+                .chunks_exact(2)
+                .map(|w| u16::from_le_bytes([w[0], w[1]]))
+                .map(|p| (p != ADL_NO_PROGRAM).then_some(p))
+                .collect(),
+            _ => data[..table_size]
+                .iter()
+                .map(|&p| (p != 0xFF).then_some(p as u16))
+                .collect(),
+        };
+        Ok(AdlFile {
+            version,
+            tracks,
+            data: data[table_size..].to_vec(),
+        })
+    }
+
+    /// Check that the offset table of a layout is consistent: the program data begins directly
+    /// after the table, and every entry in the table is either unused or points into the data.
+    fn layout_matches(data: &[u8], version: AdlVersion) -> bool {
+        let table_size = version.track_table_size();
+        let programs = version.program_count();
+        let Some(body) = data.get(table_size..) else {
+            return false;
+        };
+        let entry = |i: usize| {
+            body.get(i * 2..i * 2 + 2)
+                .map(|w| u16::from_le_bytes([w[0], w[1]]))
+        };
+
+        if version == AdlVersion::V3
+            && !(0..table_size / 2)
+                .map(|i| u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]))
+                .all(|p| p == ADL_NO_PROGRAM || (p as usize) < programs)
+        {
+            return false;
+        }
+
+        let Some(first) = (0..programs)
+            .filter_map(entry)
+            .filter(|&o| o != ADL_NO_PROGRAM && o != 0)
+            .min()
+        else {
+            return false;
+        };
+        let first = first as usize;
+        // A Dune II table can't be as large as a Kyrandia one without an unusual number of
+        // instruments, so a short table is taken to be Dune II.
+        let size_ok = match version {
+            AdlVersion::V1 => first < AdlVersion::V2.program_count() * 2,
+            _ => first >= programs * 2,
+        };
+        size_ok
+            && first.is_multiple_of(2)
+            && first <= body.len()
+            && (0..first / 2)
+                .filter_map(entry)
+                .all(|o| o == ADL_NO_PROGRAM || o == 0 || (o as usize) < body.len())
+    }
+
+    /// Return the track numbers that start a program.
+    pub fn valid_tracks(&self) -> Vec<usize> {
+        self.tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.is_some_and(|p| self.program(p as usize).is_some()))
+            .map(|(track, _)| track)
+            .collect()
+    }
+
+    /// Return the offset of a program or instrument within `data`.
+    fn program(&self, index: usize) -> Option<usize> {
+        let offset = self.data.get(index * 2..index * 2 + 2)?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]);
+        (offset != ADL_NO_PROGRAM && (offset as usize) < self.data.len()).then_some(offset as usize)
+    }
+
+    fn instrument(&self, index: u8) -> Option<usize> {
+        self.program(self.version.program_count() + index as usize)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum PrimaryEffect {
+    Slide,
+    Vibrato,
+}
+
+#[derive(Copy, Clone, Default)]
+struct AdlChannel {
+    // Not reset when a program starts on the channel.
+    op_extra_level2: u8,
+
+    dataptr: Option<usize>,
+    duration: u8,
+    repeat_counter: u8,
+    base_octave: i8,
+    priority: u8,
+    stack: [usize; ADL_STACK_SIZE],
+    stack_pos: usize,
+    base_note: i8,
+    slide_tempo: u8,
+    slide_timer: u8,
+    slide_step: i16,
+    vibrato_step: i16,
+    vibrato_step_range: i8,
+    vibrato_steps_countdown: u8,
+    vibrato_num_steps: u8,
+    vibrato_delay: u8,
+    vibrato_tempo: u8,
+    vibrato_timer: u8,
+    vibrato_delay_countdown: u8,
+    op_extra_level1: u8,
+    spacing2: u8,
+    base_freq: u8,
+    tempo: u8,
+    position: u8,
+    reg_ax: u8,
+    reg_bx: u8,
+    primary_effect: Option<PrimaryEffect>,
+    secondary_effect: bool,
+    fractional_spacing: u8,
+    op_level1: u8,
+    op_level2: u8,
+    op_extra_level3: u8,
+    two_chan: bool,
+    spacing1: u8,
+    duration_randomness: u8,
+    secondary_tempo: u8,
+    secondary_timer: u8,
+    secondary_size: i8,
+    secondary_pos: i8,
+    secondary_regbase: u8,
+    secondary_data: i32,
+    tempo_reset: u8,
+    raw_note: u8,
+    pitch_bend: i8,
+}
+
+impl AdlChannel {
+    fn init(&mut self) {
+        *self = AdlChannel {
+            op_extra_level2: self.op_extra_level2,
+            tempo: 0xFF,
+            spacing1: 1,
+            ..Default::default()
+        };
+    }
+}
+
+/// Clamp an operator level to 6 bits, as the driver does with 8-bit signed arithmetic.
+fn check_value(value: i32) -> u8 {
+    (value as u8 as i8).clamp(0, 0x3F) as u8
+}
+
+/// Advance an 8-bit timer, returning true when it overflows.
+fn advance(timer: &mut u8, tempo: u8) -> bool {
+    let (value, overflow) = timer.overflowing_add(tempo);
+    *timer = value;
+    overflow
+}
+
+/// The `AdlSequencer` interprets the programs of an `AdlFile` through an `Opl3Device`.
+pub struct AdlSequencer {
+    file: AdlFile,
+    track: usize,
+    channels: [AdlChannel; ADL_CHANNELS],
+    queue: VecDeque<u16>,
+    tempo: u8,
+    callback_timer: u8,
+    beat_divider: u8,
+    beat_div_cnt: u8,
+    beat_counter: u8,
+    beat_waiting: u8,
+    rhythm_section_bits: u8,
+    vibrato_and_am_depth_bits: u8,
+    rhythm_base: [u8; 5],
+    rhythm_extra1: [u8; 5],
+    rhythm_extra2: [u8; 5],
+    sound_trigger: u8,
+    rnd: u16,
+}
+
+/// A player for ADL files.
+pub type AdlPlayer = OplPlayer<AdlSequencer>;
+
+impl AdlSequencer {
+    /// Create a new sequencer that plays the given track of an ADL file.
+    pub fn new(file: AdlFile, track: usize) -> Self {
+        AdlSequencer {
+            file,
+            track,
+            channels: [AdlChannel::default(); ADL_CHANNELS],
+            queue: VecDeque::new(),
+            tempo: 0,
+            callback_timer: 0xFF,
+            beat_divider: 0,
+            beat_div_cnt: 0,
+            beat_counter: 0,
+            beat_waiting: 0,
+            rhythm_section_bits: 0,
+            vibrato_and_am_depth_bits: 0,
+            rhythm_base: [0; 5],
+            rhythm_extra1: [0; 5],
+            rhythm_extra2: [0; 5],
+            sound_trigger: 0,
+            rnd: 0x1234,
+        }
+    }
+
+    /// Return the `AdlFile` being played.
+    pub fn file(&self) -> &AdlFile {
+        &self.file
+    }
+
+    /// Return the track started when the sequencer is rewound.
+    pub fn track(&self) -> usize {
+        self.track
+    }
+
+    /// Set the track started when the sequencer is next rewound.
+    pub fn set_track(&mut self, track: usize) {
+        self.track = track;
+    }
+
+    /// Start a track alongside those already playing, such as a sound effect over the music. The
+    /// programs of the track take over their channels only if they have at least the priority of
+    /// the programs already playing on them.
+    ///
+    /// # Returns
+    ///
+    /// True if the track exists and was queued.
+    pub fn play_track(&mut self, track: usize) -> bool {
+        let Some(Some(program)) = self.file.tracks.get(track).copied() else {
+            return false;
+        };
+        if self.file.program(program as usize).is_none() || self.queue.len() >= ADL_QUEUE_SIZE {
+            return false;
+        }
+        self.queue.push_back(program);
+        true
+    }
+
+    /// Returns true while any program is queued or running.
+    pub fn is_playing(&self) -> bool {
+        !self.queue.is_empty() || self.channels.iter().any(|c| c.dataptr.is_some())
+    }
+
+    /// Return the last value set by a program for the game to synchronize with.
+    pub fn sound_trigger(&self) -> u8 {
+        self.sound_trigger
+    }
+
+    fn byte(&self, pos: usize) -> u8 {
+        self.file.data.get(pos).copied().unwrap_or(0)
+    }
+
+    /// Read the next byte of a program, advancing the data pointer.
+    fn next(&self, ptr: &mut Option<usize>) -> u8 {
+        let Some(pos) = ptr else {
+            return 0;
+        };
+        *pos += 1;
+        self.byte(*pos - 1)
+    }
+
+    fn random(&mut self) -> u16 {
+        self.rnd = self.rnd.wrapping_add(0x9248);
+        let low_bits = self.rnd & 7;
+        self.rnd = (self.rnd >> 3) | (low_bits << 13);
+        self.rnd
+    }
+
+    fn callback(&mut self, device: &mut Opl3Device) {
+        self.setup_programs(device);
+        self.execute_programs(device);
+
+        if advance(&mut self.callback_timer, self.tempo) {
+            self.beat_div_cnt = self.beat_div_cnt.wrapping_sub(1);
+            if self.beat_div_cnt == 0 {
+                self.beat_div_cnt = self.beat_divider;
+                self.beat_counter = self.beat_counter.wrapping_add(1);
+            }
+        }
+    }
+
+    fn setup_programs(&mut self, device: &mut Opl3Device) {
+        while let Some(program) = self.queue.pop_front() {
+            self.start_program(device, program as usize);
+        }
+    }
+
+    /// Start a program on the channel named in its header, if it has at least the priority of
+    /// the program already playing there.
+    fn start_program(&mut self, device: &mut Opl3Device, program: usize) {
+        let Some(pos) = self.file.program(program) else {
+            return;
+        };
+        let ch = self.byte(pos) as usize;
+        let priority = self.byte(pos + 1);
+        if ch >= ADL_CHANNELS || priority < self.channels[ch].priority {
+            return;
+        }
+
+        let channel = &mut self.channels[ch];
+        channel.init();
+        channel.priority = priority;
+        channel.dataptr = Some(pos + 2);
+        channel.tempo = 0xFF;
+        channel.position = 0xFF;
+        channel.duration = 1;
+        self.reset_channel(device, ch);
+    }
+
+    fn execute_programs(&mut self, device: &mut Opl3Device) {
+        for ch in (0..ADL_CHANNELS).rev() {
+            if self.channels[ch].dataptr.is_none() {
+                continue;
+            }
+            if self.channels[ch].tempo_reset != 0 {
+                self.channels[ch].tempo = self.tempo;
+            }
+
+            let mut result = 1;
+            let channel = &mut self.channels[ch];
+            if advance(&mut channel.position, channel.tempo) {
+                channel.duration = channel.duration.wrapping_sub(1);
+                if channel.duration != 0 {
+                    if channel.duration == channel.spacing2
+                        || (channel.duration == channel.spacing1 && ch != ADL_CONTROL_CHANNEL)
+                    {
+                        self.note_off(device, ch);
+                    }
+                } else {
+                    result = self.run_program(device, ch);
+                }
+            }
+
+            if result == 1 {
+                match self.channels[ch].primary_effect {
+                    Some(PrimaryEffect::Slide) => self.primary_effect_slide(device, ch),
+                    Some(PrimaryEffect::Vibrato) => self.primary_effect_vibrato(device, ch),
+                    None => {}
+                }
+                if self.channels[ch].secondary_effect {
+                    self.secondary_effect(device, ch);
+                }
+            }
+        }
+    }
+
+    /// Run opcodes until one of them waits, returning 1 to run the channel's effects afterwards
+    /// or 2 to skip them.
+    fn run_program(&mut self, device: &mut Opl3Device, ch: usize) -> u8 {
+        let mut ptr = self.channels[ch].dataptr;
+        let mut result = 1;
+        while let Some(pos) = ptr {
+            if pos + 1 >= self.file.data.len() {
+                // Running off the end of the data stops the channel.
+                self.channels[ch].priority = 0;
+                ptr = None;
+                result = 2;
+                break;
+            }
+            let opcode = self.next(&mut ptr);
+            let param = self.next(&mut ptr);
+
+            if opcode & 0x80 != 0 {
+                result = self.run_opcode(device, ch, opcode & 0x7F, param, &mut ptr);
+                self.channels[ch].dataptr = ptr;
+                if result != 0 {
+                    break;
+                }
+            } else {
+                self.setup_note(device, ch, opcode, false);
+                self.note_on(device, ch);
+                self.setup_duration(ch, param);
+                if param != 0 {
+                    result = 1;
+                    break;
+                }
+            }
+        }
+        self.channels[ch].dataptr = ptr;
+        result
+    }
+
+    fn run_opcode(
+        &mut self,
+        device: &mut Opl3Device,
+        ch: usize,
+        opcode: u8,
+        value: u8,
+        ptr: &mut Option<usize>,
+    ) -> u8 {
+        let back = |ptr: &mut Option<usize>, n: usize| {
+            if let Some(pos) = ptr {
+                *pos -= n;
+            }
+        };
+        match opcode {
+            // Set repeat
+            0x00 => self.channels[ch].repeat_counter = value,
+            // Check repeat
+            0x01 => {
+                let high = self.next(ptr);
+                let channel = &mut self.channels[ch];
+                channel.repeat_counter = channel.repeat_counter.wrapping_sub(1);
+                if channel.repeat_counter != 0 {
+                    self.relative_jump(ptr, i16::from_le_bytes([value, high]));
+                }
+            }
+            // Set up program
+            0x02 => {
+                if value != 0xFF {
+                    self.start_program(device, value as usize);
+                }
+            }
+            // Set note spacing
+            0x03 => self.channels[ch].spacing1 = value,
+            // Jump
+            0x04 => {
+                let offset = i16::from_le_bytes([value, self.next(ptr)]);
+                if self.file.version == AdlVersion::V1 {
+                    *ptr = Self::absolute(offset);
+                } else {
+                    self.relative_jump(ptr, offset);
+                }
+            }
+            // Jump to subroutine
+            0x05 => {
+                let offset = i16::from_le_bytes([value, self.next(ptr)]);
+                let channel = &mut self.channels[ch];
+                if channel.stack_pos >= ADL_STACK_SIZE {
+                    *ptr = None;
+                    return 2;
+                }
+                channel.stack[channel.stack_pos] = ptr.unwrap_or(0);
+                channel.stack_pos += 1;
+                if self.file.version == AdlVersion::V1 {
+                    *ptr = Self::absolute(offset);
+                } else {
+                    self.relative_jump(ptr, offset);
+                }
+            }
+            // Return from subroutine
+            0x06 => {
+                let channel = &mut self.channels[ch];
+                if channel.stack_pos == 0 {
+                    *ptr = None;
+                    return 2;
+                }
+                channel.stack_pos -= 1;
+                *ptr = Some(channel.stack[channel.stack_pos]);
+            }
+            // Set base octave
+            0x07 => self.channels[ch].base_octave = value as i8,
+            // Play rest
+            0x09 => {
+                self.setup_duration(ch, value);
+                self.note_off(device, ch);
+                return (value != 0) as u8;
+            }
+            // Write AdLib register
+            0x0A => {
+                let data = self.next(ptr);
+                write_reg(device, value as u16, data);
+            }
+            // Set up note and duration
+            0x0B => {
+                self.setup_note(device, ch, value, false);
+                let duration = self.next(ptr);
+                self.setup_duration(ch, duration);
+                return (duration != 0) as u8;
+            }
+            // Set base note
+            0x0C => self.channels[ch].base_note = value as i8,
+            // Set up secondary effect
+            0x0D => {
+                let size = self.next(ptr) as i8;
+                let regbase = self.next(ptr);
+                let data = u16::from_le_bytes([self.next(ptr), self.next(ptr)]);
+                let channel = &mut self.channels[ch];
+                channel.secondary_timer = value;
+                channel.secondary_tempo = value;
+                channel.secondary_size = size;
+                channel.secondary_pos = size;
+                channel.secondary_regbase = regbase;
+                channel.secondary_data = data as i32 - ADL_DATA_BASE;
+                channel.secondary_effect = true;
+            }
+            // Stop another channel
+            0x0E => {
+                if let Some(channel) = self.channels.get_mut(value as usize) {
+                    channel.duration = 0;
+                    channel.priority = 0;
+                    channel.dataptr = None;
+                }
+            }
+            // Wait for the end of a program
+            0x0F => {
+                let playing = self
+                    .file
+                    .program(value as usize)
+                    .map(|pos| self.byte(pos) as usize)
+                    .and_then(|other| self.channels.get(other))
+                    .is_some_and(|c| c.dataptr.is_some());
+                if playing {
+                    back(ptr, 2);
+                    return 2;
+                }
+            }
+            // Set up instrument
+            0x10 => {
+                if let Some(inst) = self.file.instrument(value) {
+                    self.setup_instrument(device, ch, ch, inst);
+                }
+            }
+            // Set up slide effect
+            0x11 => {
+                let step = i16::from_be_bytes([self.next(ptr), self.next(ptr)]);
+                let channel = &mut self.channels[ch];
+                channel.slide_timer = value;
+                channel.slide_tempo = value;
+                channel.slide_step = step;
+                channel.primary_effect = Some(PrimaryEffect::Slide);
+            }
+            // Remove slide effect
+            0x12 => {
+                back(ptr, 1);
+                self.channels[ch].primary_effect = None;
+                self.channels[ch].slide_step = 0;
+            }
+            // Set base frequency
+            0x13 => self.channels[ch].base_freq = value,
+            // Set up vibrato effect
+            0x15 => {
+                let range = self.next(ptr) as i8;
+                let steps = self.next(ptr);
+                let delay = self.next(ptr);
+                let channel = &mut self.channels[ch];
+                channel.vibrato_tempo = value;
+                channel.vibrato_step_range = range;
+                channel.vibrato_steps_countdown = steps.wrapping_add(1);
+                channel.vibrato_num_steps = steps.wrapping_shl(1);
+                channel.vibrato_delay = delay;
+                channel.primary_effect = Some(PrimaryEffect::Vibrato);
+            }
+            // Set priority
+            0x1A => self.channels[ch].priority = value,
+            // Set beat
+            0x1C => {
+                self.beat_divider = value >> 1;
+                self.beat_div_cnt = value >> 1;
+                self.callback_timer = 0xFF;
+                self.beat_counter = 0;
+                self.beat_waiting = 0;
+            }
+            // Wait for the next beat
+            0x1D => {
+                if self.beat_waiting != 0 && self.beat_counter & value != 0 {
+                    self.beat_waiting = 0;
+                    return 0;
+                }
+                if self.beat_counter & value == 0 {
+                    self.beat_waiting = self.beat_waiting.wrapping_add(1);
+                }
+                back(ptr, 2);
+                self.channels[ch].duration = 1;
+                return 2;
+            }
+            // Set extra level 1
+            0x1E => {
+                self.channels[ch].op_extra_level1 = value;
+                self.adjust_volume(device, ch);
+            }
+            // Set up duration
+            0x20 => {
+                self.setup_duration(ch, value);
+                return (value != 0) as u8;
+            }
+            // Play note
+            0x21 => {
+                self.setup_duration(ch, value);
+                self.note_on(device, ch);
+                return (value != 0) as u8;
+            }
+            // Set fractional note spacing
+            0x24 => self.channels[ch].fractional_spacing = value & 7,
+            // Set tempo
+            0x26 => self.tempo = value,
+            // Remove secondary effect
+            0x27 => {
+                back(ptr, 1);
+                self.channels[ch].secondary_effect = false;
+            }
+            // Set channel tempo
+            0x29 => self.channels[ch].tempo = value,
+            // Set extra level 3
+            0x2B => self.channels[ch].op_extra_level3 = value,
+            // Set or change the extra level 2 of another channel
+            0x2C | 0x2D => {
+                let level = self.next(ptr);
+                if let Some(channel) = self.channels.get_mut(value as usize) {
+                    channel.op_extra_level2 = if opcode == 0x2C {
+                        level
+                    } else {
+                        channel.op_extra_level2.wrapping_add(level)
+                    };
+                    self.adjust_volume(device, value as usize);
+                }
+            }
+            // Set AM depth
+            0x2E => {
+                self.vibrato_and_am_depth_bits =
+                    (self.vibrato_and_am_depth_bits & 0x7F) | ((value & 1) << 7);
+                write_reg(device, 0xBD, self.vibrato_and_am_depth_bits);
+            }
+            // Set vibrato depth
+            0x2F => {
+                self.vibrato_and_am_depth_bits =
+                    (self.vibrato_and_am_depth_bits & 0xBF) | ((value & 1) << 6);
+                write_reg(device, 0xBD, self.vibrato_and_am_depth_bits);
+            }
+            // Change extra level 1
+            0x30 => {
+                let channel = &mut self.channels[ch];
+                channel.op_extra_level1 = channel.op_extra_level1.wrapping_add(value);
+                self.adjust_volume(device, ch);
+            }
+            // Clear another channel
+            0x33 => self.clear_channel(device, value as usize),
+            // Change the note randomly
+            0x35 => {
+                back(ptr, 1);
+                let mask = u16::from_be_bytes([self.next(ptr), self.next(ptr)]);
+                let channel = self.channels[ch];
+                if ch < ADL_CONTROL_CHANNEL {
+                    let note = (((channel.reg_bx as u16 & 0x1F) << 8) | channel.reg_ax as u16)
+                        .wrapping_add(mask & self.random());
+                    let note = note | ((channel.reg_bx as u16 & 0x20) << 8);
+                    write_reg(device, 0xA0 + ch as u16, note as u8);
+                    write_reg(device, 0xB0 + ch as u16, (note >> 8) as u8);
+                }
+            }
+            // Remove vibrato effect
+            0x36 => {
+                back(ptr, 1);
+                self.channels[ch].primary_effect = None;
+            }
+            // Pitch bend
+            0x39 => {
+                self.channels[ch].pitch_bend = value as i8;
+                let note = self.channels[ch].raw_note;
+                self.setup_note(device, ch, note, true);
+            }
+            // Reset to the global tempo
+            0x3A => {
+                back(ptr, 1);
+                self.channels[ch].tempo = self.tempo;
+            }
+            // No operation
+            0x3B | 0x40 => back(ptr, 1),
+            // Set duration randomness
+            0x3C => self.channels[ch].duration_randomness = value,
+            // Change channel tempo
+            0x3D => {
+                let channel = &mut self.channels[ch];
+                channel.tempo = (channel.tempo as i16 + value as i8 as i16).clamp(1, 255) as u8;
+            }
+            // Select frequency tables, which are not implemented.
+            0x3F => {
+                self.next(ptr);
+            }
+            // Set up rhythm section
+            0x41 => self.setup_rhythm_section(device, ch, value, ptr),
+            // Play rhythm section
+            0x42 => {
+                write_reg(
+                    device,
+                    0xBD,
+                    (self.rhythm_section_bits & !(value & 0x1F)) | 0x20,
+                );
+                self.rhythm_section_bits |= value;
+                write_reg(
+                    device,
+                    0xBD,
+                    self.vibrato_and_am_depth_bits | 0x20 | self.rhythm_section_bits,
+                );
+            }
+            // Remove rhythm section
+            0x43 => {
+                back(ptr, 1);
+                self.rhythm_section_bits = 0;
+                write_reg(device, 0xBD, self.vibrato_and_am_depth_bits);
+            }
+            // Set rhythm level 2, change rhythm level 1 and set rhythm level 1
+            0x44..=0x46 => {
+                let level = self.next(ptr);
+                for i in (0..5).filter(|i| value & (1 << i) != 0) {
+                    let base = self.rhythm_base[i] as i32;
+                    let out = match opcode {
+                        0x44 => {
+                            self.rhythm_extra2[i] = level;
+                            check_value(level as i32 + base + self.rhythm_extra1[i] as i32)
+                        }
+                        0x45 => {
+                            self.rhythm_extra1[i] = check_value(
+                                level as i32
+                                    + base
+                                    + self.rhythm_extra1[i] as i32
+                                    + self.rhythm_extra2[i] as i32,
+                            );
+                            self.rhythm_extra1[i]
+                        }
+                        _ => {
+                            self.rhythm_extra1[i] = level;
+                            check_value(level as i32 + base + self.rhythm_extra2[i] as i32)
+                        }
+                    };
+                    write_reg(device, RHYTHM_LEVEL_REGS[i], out);
+                }
+            }
+            // Set sound trigger
+            0x47 => self.sound_trigger = value,
+            // Set tempo reset
+            0x48 => self.channels[ch].tempo_reset = value,
+            // Unknown, with one further parameter
+            0x49 => {
+                self.next(ptr);
+            }
+            // Stop channel, and unused opcodes
+            _ => {
+                self.channels[ch].priority = 0;
+                self.note_off(device, ch);
+                *ptr = None;
+                return 2;
+            }
+        }
+        0
+    }
+
+    fn relative_jump(&self, ptr: &mut Option<usize>, offset: i16) {
+        *ptr = ptr.and_then(|pos| pos.checked_add_signed(offset as isize));
+    }
+
+    fn absolute(address: i16) -> Option<usize> {
+        usize::try_from(address as u16 as i32 - ADL_DATA_BASE).ok()
+    }
+
+    /// Silence a channel when a program starts on it, then key it on with the envelope at its
+    /// slowest so that the next note starts cleanly.
+    fn reset_channel(&mut self, device: &mut Opl3Device, ch: usize) {
+        if ch >= ADL_CONTROL_CHANNEL || (self.rhythm_section_bits != 0 && ch >= 6) {
+            return;
+        }
+        let offset = REG_OFFSETS[ch];
+        write_reg(device, 0x60 + offset, 0xFF);
+        write_reg(device, 0x63 + offset, 0xFF);
+        write_reg(device, 0x80 + offset, 0xFF);
+        write_reg(device, 0x83 + offset, 0xFF);
+        write_reg(device, 0xB0 + ch as u16, 0x00);
+        write_reg(device, 0xB0 + ch as u16, 0x20);
+    }
+
+    fn clear_channel(&mut self, device: &mut Opl3Device, ch: usize) {
+        let Some(channel) = self.channels.get_mut(ch) else {
+            return;
+        };
+        channel.duration = 0;
+        channel.priority = 0;
+        channel.dataptr = None;
+        channel.op_extra_level2 = 0;
+        if ch != ADL_CONTROL_CHANNEL {
+            let offset = REG_OFFSETS[ch];
+            write_reg(device, 0xC0 + ch as u16, 0x00);
+            write_reg(device, 0x43 + offset, 0x3F);
+            write_reg(device, 0x83 + offset, 0xFF);
+            write_reg(device, 0xB0 + ch as u16, 0x00);
+        }
+    }
+
+    fn setup_duration(&mut self, ch: usize, duration: u8) {
+        if self.channels[ch].duration_randomness != 0 {
+            let random = self.random() as u8 & self.channels[ch].duration_randomness;
+            self.channels[ch].duration = duration.wrapping_add(random);
+            return;
+        }
+        let channel = &mut self.channels[ch];
+        if channel.fractional_spacing != 0 {
+            channel.spacing2 = (duration >> 3).wrapping_mul(channel.fractional_spacing);
+        }
+        channel.duration = duration;
+    }
+
+    fn setup_note(&mut self, device: &mut Opl3Device, ch: usize, raw_note: u8, bend: bool) {
+        if ch >= ADL_CONTROL_CHANNEL {
+            return;
+        }
+        let channel = &mut self.channels[ch];
+        channel.raw_note = raw_note;
+
+        let note = (raw_note & 0x0F) as i32 + channel.base_note as i32;
+        let octave = ((raw_note as i32 + channel.base_octave as i32) >> 4) & 0x0F;
+        let octave = octave + note.div_euclid(12);
+        let note = note.rem_euclid(12) as usize;
+
+        let mut freq = FREQ_TABLE[note] as i32 + channel.base_freq as i32;
+        if channel.pitch_bend != 0 || bend {
+            // The driver's tables bend by up to a semitone in 32 steps.
+            let next = if note == 11 {
+                FREQ_TABLE[0] as i32 * 2
+            } else {
+                FREQ_TABLE[note + 1] as i32
+            };
+            let amount = (channel.pitch_bend as i32).clamp(-31, 31);
+            freq += (next - FREQ_TABLE[note] as i32) * amount / 32;
+        }
+
+        channel.reg_ax = freq as u8;
+        channel.reg_bx = (channel.reg_bx & 0x20) | ((octave << 2) as u8) | ((freq >> 8) as u8 & 3);
+        write_reg(device, 0xA0 + ch as u16, channel.reg_ax);
+        write_reg(device, 0xB0 + ch as u16, channel.reg_bx);
+    }
+
+    /// Load an instrument into the operators of OPL channel `opl_ch`, keeping its levels in the
+    /// state of channel `ch`.
+    fn setup_instrument(&mut self, device: &mut Opl3Device, opl_ch: usize, ch: usize, pos: usize) {
+        if opl_ch >= ADL_CONTROL_CHANNEL {
+            return;
+        }
+        let offset = REG_OFFSETS[opl_ch];
+        let inst: [u8; 11] = core::array::from_fn(|i| self.byte(pos + i));
+
+        write_reg(device, 0x20 + offset, inst[0]);
+        write_reg(device, 0x23 + offset, inst[1]);
+        write_reg(device, 0xC0 + opl_ch as u16, inst[2]);
+        write_reg(device, 0xE0 + offset, inst[3]);
+        write_reg(device, 0xE3 + offset, inst[4]);
+
+        let channel = &mut self.channels[ch];
+        channel.two_chan = inst[2] & 1 != 0;
+        channel.op_level1 = inst[5];
+        channel.op_level2 = inst[6];
+        write_reg(device, 0x40 + offset, self.op_level1(ch));
+        write_reg(device, 0x43 + offset, self.op_level2(ch));
+
+        write_reg(device, 0x60 + offset, inst[7]);
+        write_reg(device, 0x63 + offset, inst[8]);
+        write_reg(device, 0x80 + offset, inst[9]);
+        write_reg(device, 0x83 + offset, inst[10]);
+    }
+
+    fn setup_rhythm_section(
+        &mut self,
+        device: &mut Opl3Device,
+        ch: usize,
+        value: u8,
+        ptr: &mut Option<usize>,
+    ) {
+        let instruments = [value, self.next(ptr), self.next(ptr)];
+        for (opl_ch, inst) in (6..9).zip(instruments) {
+            if let Some(pos) = self.file.instrument(inst) {
+                self.setup_instrument(device, opl_ch, ch, pos);
+            }
+            let channel = self.channels[ch];
+            match opl_ch {
+                6 => self.rhythm_base[4] = channel.op_level2,
+                7 => {
+                    self.rhythm_base[0] = channel.op_level1;
+                    self.rhythm_base[3] = channel.op_level2;
+                }
+                _ => {
+                    self.rhythm_base[2] = channel.op_level1;
+                    self.rhythm_base[1] = channel.op_level2;
+                }
+            }
+        }
+
+        for opl_ch in 6..9 {
+            let bx = self.next(ptr) & 0x2F;
+            let ax = self.next(ptr);
+            self.channels[opl_ch].reg_bx = bx;
+            write_reg(device, 0xB0 + opl_ch as u16, bx);
+            write_reg(device, 0xA0 + opl_ch as u16, ax);
+        }
+        self.rhythm_section_bits = 0x20;
+    }
+
+    fn op_level(&self, ch: usize, level: u8, extra: bool) -> u8 {
+        let channel = &self.channels[ch];
+        let mut value = (level & 0x3F) as i32;
+        if extra {
+            value += channel.op_extra_level1 as i32
+                + channel.op_extra_level2 as i32
+                + channel.op_extra_level3 as i32;
+        }
+        check_value(value) | (level & 0xC0)
+    }
+
+    /// The modulator level, which is only affected by the volume for additive instruments.
+    fn op_level1(&self, ch: usize) -> u8 {
+        let channel = &self.channels[ch];
+        self.op_level(ch, channel.op_level1, channel.two_chan)
+    }
+
+    fn op_level2(&self, ch: usize) -> u8 {
+        self.op_level(ch, self.channels[ch].op_level2, true)
+    }
+
+    fn adjust_volume(&self, device: &mut Opl3Device, ch: usize) {
+        if ch >= ADL_CONTROL_CHANNEL {
+            return;
+        }
+        write_reg(device, 0x43 + REG_OFFSETS[ch], self.op_level2(ch));
+        if self.channels[ch].two_chan {
+            write_reg(device, 0x40 + REG_OFFSETS[ch], self.op_level1(ch));
+        }
+    }
+
+    fn note_on(&mut self, device: &mut Opl3Device, ch: usize) {
+        if ch >= ADL_CONTROL_CHANNEL {
+            return;
+        }
+        let channel = &mut self.channels[ch];
+        channel.reg_bx |= 0x20;
+        write_reg(device, 0xB0 + ch as u16, channel.reg_bx);
+
+        let shift = (9 - channel.vibrato_step_range as i32).clamp(0, 15);
+        let freq = ((channel.reg_bx as u16) << 8 | channel.reg_ax as u16) & 0x3FF;
+        channel.vibrato_step = ((freq >> shift) & 0xFF) as i16;
+        channel.vibrato_delay_countdown = channel.vibrato_delay;
+    }
+
+    fn note_off(&mut self, device: &mut Opl3Device, ch: usize) {
+        if ch >= ADL_CONTROL_CHANNEL || (self.rhythm_section_bits != 0 && ch >= 6) {
+            return;
+        }
+        let channel = &mut self.channels[ch];
+        channel.reg_bx &= !0x20;
+        write_reg(device, 0xB0 + ch as u16, channel.reg_bx);
+    }
+
+    fn write_freq(&self, device: &mut Opl3Device, ch: usize) {
+        write_reg(device, 0xA0 + ch as u16, self.channels[ch].reg_ax);
+        write_reg(device, 0xB0 + ch as u16, self.channels[ch].reg_bx);
+    }
+
+    fn primary_effect_slide(&mut self, device: &mut Opl3Device, ch: usize) {
+        if ch >= ADL_CONTROL_CHANNEL {
+            return;
+        }
+        let channel = &mut self.channels[ch];
+        if !advance(&mut channel.slide_timer, channel.slide_tempo) {
+            return;
+        }
+
+        let mut freq = ((channel.reg_bx as i32 & 3) << 8) | channel.reg_ax as i32;
+        let mut octave = channel.reg_bx as i32 & 0x1C;
+        let key = channel.reg_bx & 0x20;
+        freq += (channel.slide_step as i32).clamp(-0x3FF, 0x3FF);
+
+        // Move to the next octave when the frequency leaves the range of the current one.
+        if channel.slide_step >= 0 && freq >= 734 {
+            freq >>= 1;
+            if freq & 0x3FF == 0 {
+                freq += 1;
+            }
+            octave += 4;
+        } else if channel.slide_step < 0 && freq < 388 {
+            freq <<= 1;
+            if freq & 0x3FF == 0 {
+                freq -= 1;
+            }
+            octave -= 4;
+        }
+
+        channel.reg_ax = freq as u8;
+        channel.reg_bx = key | (octave as u8 & 0x1C) | ((freq >> 8) as u8 & 3);
+        self.write_freq(device, ch);
+    }
+
+    fn primary_effect_vibrato(&mut self, device: &mut Opl3Device, ch: usize) {
+        if ch >= ADL_CONTROL_CHANNEL {
+            return;
+        }
+        let channel = &mut self.channels[ch];
+        // There is a delay after each note before the vibrato starts.
+        if channel.vibrato_delay_countdown != 0 {
+            channel.vibrato_delay_countdown -= 1;
+            return;
+        }
+        if !advance(&mut channel.vibrato_timer, channel.vibrato_tempo) {
+            return;
+        }
+
+        let freq = ((channel.reg_bx as u16) << 8 | channel.reg_ax as u16) & 0x3FF;
+        channel.vibrato_steps_countdown = channel.vibrato_steps_countdown.wrapping_sub(1);
+        if channel.vibrato_steps_countdown == 0 {
+            channel.vibrato_step = -channel.vibrato_step;
+            channel.vibrato_steps_countdown = channel.vibrato_num_steps;
+        }
+        let freq = freq.wrapping_add_signed(channel.vibrato_step);
+
+        channel.reg_ax = freq as u8;
+        channel.reg_bx = (channel.reg_bx & 0xFC) | ((freq >> 8) as u8 & 3);
+        self.write_freq(device, ch);
+    }
+
+    /// Cycle a register through a table of values in the program data.
+    fn secondary_effect(&mut self, device: &mut Opl3Device, ch: usize) {
+        if ch >= ADL_CONTROL_CHANNEL {
+            return;
+        }
+        let channel = &mut self.channels[ch];
+        if !advance(&mut channel.secondary_timer, channel.secondary_tempo) {
+            return;
+        }
+        channel.secondary_pos = channel.secondary_pos.wrapping_sub(1);
+        if channel.secondary_pos < 0 {
+            channel.secondary_pos = channel.secondary_size;
+        }
+        let pos = channel.secondary_pos as i32 + channel.secondary_data;
+        let reg = channel.secondary_regbase as u16 + REG_OFFSETS[ch];
+        let value = usize::try_from(pos).map_or(0, |pos| self.byte(pos));
+        write_reg(device, reg, value);
+    }
+}
+
+impl OplSequencer for AdlSequencer {
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64> {
+        if !self.is_playing() {
+            return None;
+        }
+        self.callback(device);
+        Some(1_000_000.0 / ADL_CALLBACK_RATE)
+    }
+
+    fn rewind(&mut self, device: &mut Opl3Device) {
+        let track = self.track;
+        let file = core::mem::replace(
+            &mut self.file,
+            AdlFile {
+                version: AdlVersion::V1,
+                tracks: Vec::new(),
+                data: Vec::new(),
+            },
+        );
+        *self = AdlSequencer::new(file, track);
+
+        write_reg(device, 0x01, 0x20);
+        write_reg(device, 0x08, 0x00);
+        write_reg(device, 0xBD, 0x00);
+        for ch in (0..ADL_CONTROL_CHANNEL).rev() {
+            write_reg(device, 0x40 + REG_OFFSETS[ch], 0x3F);
+            write_reg(device, 0x43 + REG_OFFSETS[ch], 0x3F);
+        }
+        self.play_track(track);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OplRegisterFile;
+    use alloc::vec;
+
+    const INSTRUMENT: [u8; 11] = [0x01, 0x01, 0x00, 0, 0, 0x10, 0x00, 0xF0, 0xF0, 0x77, 0x77];
+
+    /// Build a version 2 file from a list of programs, with a single instrument.
+    fn v2_file(programs: &[&[u8]]) -> Vec<u8> {
+        let count = AdlVersion::V2.program_count();
+        let mut tracks = vec![0xFF; ADL_SMALL_TRACK_TABLE];
+        let mut offsets = vec![0xFF; (count + 1) * 2];
+        let mut body = Vec::new();
+        for (i, program) in programs.iter().enumerate() {
+            tracks[i] = i as u8;
+            let offset = (offsets.len() + body.len()) as u16;
+            offsets[i * 2..i * 2 + 2].copy_from_slice(&offset.to_le_bytes());
+            body.extend_from_slice(program);
+        }
+        let offset = (offsets.len() + body.len()) as u16;
+        offsets[count * 2..count * 2 + 2].copy_from_slice(&offset.to_le_bytes());
+        body.extend_from_slice(&INSTRUMENT);
+
+        [tracks, offsets, body].concat()
+    }
+
+    #[test]
+    fn detect_version() {
+        let adl = AdlFile::parse(&v2_file(&[&[0, 1, 0x88, 0]])).unwrap();
+        assert_eq!(adl.version, AdlVersion::V2);
+        assert_eq!(adl.valid_tracks(), vec![0]);
+        assert_eq!(adl.instrument(0), Some(502 + 4));
+        assert!(AdlFile::parse(&[0xFF; 200]).is_err());
+    }
+
+    #[test]
+    fn play_note() {
+        // Channel 0: load instrument 0, play C in octave 4 for 10 ticks, then stop.
+        let data = v2_file(&[&[0, 1, 0x90, 0, 0x40, 10, 0x88, 0]]);
+        let adl = AdlFile::parse(&data).unwrap();
+        let mut player = AdlPlayer::new(AdlSequencer::new(adl, 0), 7200);
+        let samples = player.render_to_vec(100_000).unwrap();
+        let frames = samples.len() / 2;
+        assert!((1099..=1101).contains(&frames), "{frames}");
+        assert!(samples.iter().any(|&s| s != 0));
+
+        let reg = |r: u8| player.device().read_register(r, OplRegisterFile::Primary);
+        assert_eq!(reg(0xA0), 0x34);
+        assert_eq!(reg(0xB0), (4 << 2) | 1);
+        assert_eq!(reg(0x20), 0x01);
+    }
+
+    #[test]
+    fn control_channel_and_repeat() {
+        let data = v2_file(&[
+            // Control channel: start program 1, rest for 5 ticks and stop.
+            &[9, 1, 0x82, 1, 0x89, 5, 0x88, 0],
+            // Channel 1: play a note twice for 2 ticks each.
+            &[1, 1, 0x80, 2, 0x40, 2, 0x81, 0xFB, 0xFF, 0x88, 0],
+        ]);
+        let adl = AdlFile::parse(&data).unwrap();
+        let mut player = AdlPlayer::new(AdlSequencer::new(adl, 0), 7200);
+        let samples = player.render_to_vec(100_000).unwrap();
+        let frames = samples.len() / 2;
+        assert!((599..=601).contains(&frames), "{frames}");
+        assert!(!player.sequencer().is_playing());
+    }
+}
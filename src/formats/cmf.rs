@@ -0,0 +1,778 @@
+//! Parser and sequencer for Creative Music File (CMF) files.
+//!
+//! CMF files were produced by the Creative Labs tools shipped with the Sound Blaster. A CMF file
+//! contains a table of OPL2 instruments and a single MIDI event stream, timed in ticks of a fixed
+//! rate given in the header.
+//!
+//! MIDI channels are assigned to the 9 OPL2 channels as notes are played, loading the instrument
+//! selected by the last program change on the MIDI channel. Note velocity is ignored, as it was by
+//! the Creative driver. Pitch bends have a range of 2 semitones.
+//!
+//! Creative defined the following controllers:
+//!
+//! | Controller | Function                                                                  |
+//! |------------|---------------------------------------------------------------------------|
+//! | 0x63       | Set the AM (bit 1) and vibrato (bit 0) depth                              |
+//! | 0x66       | Set a song marker, which can be read back with `CmfSequencer::marker`     |
+//! | 0x67       | Enable (1) or disable (0) rhythm mode                                     |
+//! | 0x68       | Transpose the MIDI channel up, in 1/128ths of a semitone                  |
+//! | 0x69       | Transpose the MIDI channel down, in 1/128ths of a semitone                |
+//!
+//! In rhythm mode, only 6 melodic OPL channels are available and MIDI channels 12 to 16 play the
+//! bass drum, snare drum, tom-tom, cymbal and hi-hat respectively.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::formats::cmf::{CmfFile, CmfPlayer, CmfSequencer};
+//!
+//! let data = std::fs::read("song.cmf").unwrap();
+//! let cmf = CmfFile::parse(&data).unwrap();
+//! let mut player = CmfPlayer::new(CmfSequencer::new(cmf), 44100);
+//! let samples = player.render_to_vec(44100 * 600).unwrap();
+//! ```
+
+use crate::formats::{note_fnum, write_reg, ByteReader};
+use crate::player::{OplPlayer, OplSequencer};
+use crate::rhythm::OplDrum;
+use crate::{Opl3Device, OplError};
+use alloc::{string::String, vec::Vec};
+
+/// The signature at the start of every CMF file.
+pub const CMF_SIGNATURE: &[u8; 4] = b"CTMF";
+/// The size of an instrument definition in bytes.
+pub const CMF_INSTRUMENT_SIZE: usize = 16;
+
+/// Controller setting the AM and vibrato depth.
+pub const CMF_CONTROLLER_DEPTH: u8 = 0x63;
+/// Controller setting the song marker.
+pub const CMF_CONTROLLER_MARKER: u8 = 0x66;
+/// Controller enabling or disabling rhythm mode.
+pub const CMF_CONTROLLER_RHYTHM: u8 = 0x67;
+/// Controller transposing a MIDI channel up.
+pub const CMF_CONTROLLER_TRANSPOSE_UP: u8 = 0x68;
+/// Controller transposing a MIDI channel down.
+pub const CMF_CONTROLLER_TRANSPOSE_DOWN: u8 = 0x69;
+
+const CMF_VERSION_1_0: u16 = 0x0100;
+const CMF_VERSION_1_1: u16 = 0x0101;
+const CMF_MIDI_CHANNELS: usize = 16;
+const CMF_OPL_CHANNELS: usize = 9;
+const CMF_RHYTHM_OPL_CHANNELS: usize = 6;
+/// The first MIDI channel used for percussion in rhythm mode.
+const CMF_FIRST_RHYTHM_CHANNEL: u8 = 11;
+const CMF_BEND_RANGE: f64 = 2.0;
+
+const OP_OFFSETS: [u16; 9] = [0x00, 0x01, 0x02, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x12];
+
+/// A MIDI event from a CMF file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CmfEvent {
+    /// Release a note.
+    NoteOff {
+        /// The MIDI channel, 0 to 15.
+        channel: u8,
+        /// The MIDI note number.
+        note: u8,
+    },
+    /// Start a note.
+    NoteOn {
+        /// The MIDI channel, 0 to 15.
+        channel: u8,
+        /// The MIDI note number.
+        note: u8,
+        /// The note velocity.
+        velocity: u8,
+    },
+    /// Change the value of a controller.
+    Controller {
+        /// The MIDI channel, 0 to 15.
+        channel: u8,
+        /// The controller number.
+        controller: u8,
+        /// The new value.
+        value: u8,
+    },
+    /// Select the instrument played by a MIDI channel.
+    ProgramChange {
+        /// The MIDI channel, 0 to 15.
+        channel: u8,
+        /// The instrument number.
+        program: u8,
+    },
+    /// Bend the pitch of a MIDI channel.
+    PitchBend {
+        /// The MIDI channel, 0 to 15.
+        channel: u8,
+        /// The bend amount, from -8192 to 8191.
+        value: i16,
+    },
+}
+
+/// A MIDI event and the number of ticks to wait after it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CmfCommand {
+    /// The event.
+    pub event: CmfEvent,
+    /// The number of ticks to wait after the event.
+    pub delay: u32,
+}
+
+/// A parsed CMF file.
+#[derive(Clone, Debug)]
+pub struct CmfFile {
+    /// The format version, 0x0100 or 0x0101.
+    pub version: u16,
+    /// The number of ticks per quarter note.
+    pub ticks_per_quarter: u16,
+    /// The tick rate, in Hz.
+    pub ticks_per_second: u16,
+    /// The song title, if present.
+    pub title: Option<String>,
+    /// The composer, if present.
+    pub composer: Option<String>,
+    /// The remarks, if present.
+    pub remarks: Option<String>,
+    /// Which MIDI channels are used by the song.
+    pub channels_in_use: [bool; 16],
+    /// The instruments. Each consists of the modulator and carrier registers 0x20, 0x40, 0x60,
+    /// 0x80 and 0xE0, in that order, followed by the feedback and connection register 0xC0.
+    pub instruments: Vec<[u8; 11]>,
+    /// The MIDI event stream.
+    pub commands: Vec<CmfCommand>,
+}
+
+impl CmfFile {
+    /// Parse a CMF file from a byte slice.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the CMF file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `CmfFile` or an `OplError` on failure.
+    pub fn parse(data: &[u8]) -> Result<CmfFile, OplError> {
+        let mut reader = ByteReader::new(data);
+        if reader.bytes(CMF_SIGNATURE.len())? != CMF_SIGNATURE {
+            return Err(OplError::BadSignature);
+        }
+        let version = reader.u16_le()?;
+        if version != CMF_VERSION_1_0 && version != CMF_VERSION_1_1 {
+            return Err(OplError::UnsupportedVersion);
+        }
+        let instrument_offset = reader.u16_le()? as usize;
+        let music_offset = reader.u16_le()? as usize;
+        let ticks_per_quarter = reader.u16_le()?;
+        let ticks_per_second = reader.u16_le()?;
+        if ticks_per_second == 0 {
+            return Err(OplError::InvalidFile("CMF tick rate is zero"));
+        }
+        let title = c_string(data, reader.u16_le()? as usize);
+        let composer = c_string(data, reader.u16_le()? as usize);
+        let remarks = c_string(data, reader.u16_le()? as usize);
+        let mut channels_in_use = [false; CMF_MIDI_CHANNELS];
+        for (used, &b) in channels_in_use.iter_mut().zip(reader.bytes(16)?) {
+            *used = b != 0;
+        }
+        let instrument_count = if version == CMF_VERSION_1_0 {
+            reader.u8()? as usize
+        } else {
+            reader.u16_le()? as usize
+        };
+
+        reader.seek(instrument_offset)?;
+        let instruments = (0..instrument_count)
+            .map(|_| {
+                let mut inst = [0u8; 11];
+                inst.copy_from_slice(&reader.bytes(CMF_INSTRUMENT_SIZE)?[..11]);
+                Ok(inst)
+            })
+            .collect::<Result<Vec<_>, OplError>>()?;
+
+        reader.seek(music_offset)?;
+        let commands = parse_events(&mut reader)?;
+
+        Ok(CmfFile {
+            version,
+            ticks_per_quarter,
+            ticks_per_second,
+            title,
+            composer,
+            remarks,
+            channels_in_use,
+            instruments,
+            commands,
+        })
+    }
+
+    /// Return the total length of the song in ticks.
+    pub fn length_ticks(&self) -> u64 {
+        self.commands.iter().map(|c| c.delay as u64).sum()
+    }
+}
+
+fn c_string(data: &[u8], offset: usize) -> Option<String> {
+    if offset == 0 {
+        return None;
+    }
+    let text = data.get(offset..)?;
+    let end = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+    Some(String::from_utf8_lossy(&text[..end]).into_owned())
+}
+
+/// Parse the MIDI event stream up to the end of track event or the end of the file. Events that
+/// do not affect playback are dropped, with their delays added to the previous event.
+fn parse_events(reader: &mut ByteReader) -> Result<Vec<CmfCommand>, OplError> {
+    let mut commands: Vec<CmfCommand> = Vec::new();
+    let mut leading_delay = 0;
+    let mut running_status = 0u8;
+
+    while reader.remaining() > 0 {
+        let delta = reader.var_len()?;
+        match commands.last_mut() {
+            Some(last) => last.delay += delta,
+            None => leading_delay += delta,
+        }
+
+        let mut status = reader.u8()?;
+        if status < 0x80 {
+            // Running status: this byte is the first data byte.
+            if running_status == 0 {
+                return Err(OplError::InvalidFile("CMF data byte without status"));
+            }
+            reader.seek(reader.pos() - 1)?;
+            status = running_status;
+        } else if status < 0xF0 {
+            running_status = status;
+        }
+
+        let channel = status & 0x0F;
+        let event = match status & 0xF0 {
+            0x80 => {
+                let note = reader.u8()?;
+                reader.u8()?;
+                Some(CmfEvent::NoteOff { channel, note })
+            }
+            0x90 => {
+                let note = reader.u8()?;
+                let velocity = reader.u8()?;
+                Some(match velocity {
+                    0 => CmfEvent::NoteOff { channel, note },
+                    _ => CmfEvent::NoteOn {
+                        channel,
+                        note,
+                        velocity,
+                    },
+                })
+            }
+            0xA0 => {
+                reader.bytes(2)?;
+                None
+            }
+            0xB0 => {
+                let controller = reader.u8()?;
+                let value = reader.u8()?;
+                Some(CmfEvent::Controller {
+                    channel,
+                    controller,
+                    value,
+                })
+            }
+            0xC0 => Some(CmfEvent::ProgramChange {
+                channel,
+                program: reader.u8()?,
+            }),
+            0xD0 => {
+                reader.u8()?;
+                None
+            }
+            0xE0 => {
+                let lsb = reader.u8()? as i16;
+                let msb = reader.u8()? as i16;
+                Some(CmfEvent::PitchBend {
+                    channel,
+                    value: ((msb << 7) | lsb) - 0x2000,
+                })
+            }
+            _ => match status {
+                0xF0 | 0xF7 => {
+                    let len = reader.var_len()? as usize;
+                    reader.bytes(len)?;
+                    None
+                }
+                0xFF => {
+                    let meta = reader.u8()?;
+                    let len = reader.var_len()? as usize;
+                    reader.bytes(len)?;
+                    if meta == 0x2F {
+                        break;
+                    }
+                    None
+                }
+                _ => return Err(OplError::InvalidFile("unknown CMF event")),
+            },
+        };
+
+        if let Some(event) = event {
+            commands.push(CmfCommand { event, delay: 0 });
+        }
+    }
+
+    // A delay before the first event is played as a delay after a harmless controller change.
+    if leading_delay > 0 {
+        commands.insert(
+            0,
+            CmfCommand {
+                event: CmfEvent::Controller {
+                    channel: 0,
+                    controller: CMF_CONTROLLER_MARKER,
+                    value: 0,
+                },
+                delay: leading_delay,
+            },
+        );
+    }
+    Ok(commands)
+}
+
+#[derive(Copy, Clone, Default)]
+struct CmfMidiChannel {
+    program: u8,
+    /// The transposition in 1/128ths of a semitone.
+    transpose: i16,
+    bend: i16,
+}
+
+#[derive(Copy, Clone, Default)]
+struct CmfVoice {
+    channel: u8,
+    note: u8,
+    key_on: bool,
+    instrument: Option<usize>,
+    /// When the voice was last started or released, for choosing which voice to reuse.
+    age: u64,
+}
+
+/// The `CmfSequencer` plays a `CmfFile` through an `Opl3Device`.
+pub struct CmfSequencer {
+    file: CmfFile,
+    channels: [CmfMidiChannel; CMF_MIDI_CHANNELS],
+    voices: [CmfVoice; CMF_OPL_CHANNELS],
+    rhythm_instruments: [Option<usize>; 5],
+    position: usize,
+    clock: u64,
+    bd: u8,
+    marker: u8,
+    looped: bool,
+    repeat: bool,
+}
+
+/// A player for CMF files.
+pub type CmfPlayer = OplPlayer<CmfSequencer>;
+
+impl CmfSequencer {
+    /// Create a new sequencer for the given CMF file.
+    pub fn new(file: CmfFile) -> Self {
+        CmfSequencer {
+            file,
+            channels: [CmfMidiChannel::default(); CMF_MIDI_CHANNELS],
+            voices: [CmfVoice::default(); CMF_OPL_CHANNELS],
+            rhythm_instruments: [None; 5],
+            position: 0,
+            clock: 0,
+            bd: 0,
+            marker: 0,
+            looped: false,
+            repeat: false,
+        }
+    }
+
+    /// Return the `CmfFile` being played.
+    pub fn file(&self) -> &CmfFile {
+        &self.file
+    }
+
+    /// Set whether the song repeats when it reaches the end, rather than finishing.
+    pub fn set_repeat(&mut self, repeat: bool) {
+        self.repeat = repeat;
+    }
+
+    /// Returns true once the song has reached its end and looped back.
+    pub fn has_looped(&self) -> bool {
+        self.looped
+    }
+
+    /// Return the index of the next event to be played.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Return the value of the last marker controller event, or 0 if there has been none.
+    pub fn marker(&self) -> u8 {
+        self.marker
+    }
+
+    /// Returns true if rhythm mode is enabled.
+    pub fn rhythm_mode(&self) -> bool {
+        self.bd & 0x20 != 0
+    }
+
+    fn play_event(&mut self, device: &mut Opl3Device, event: CmfEvent) {
+        match event {
+            CmfEvent::NoteOn { channel, note, .. } => {
+                if self.rhythm_mode() && channel >= CMF_FIRST_RHYTHM_CHANNEL {
+                    self.rhythm_on(device, channel, note);
+                } else {
+                    self.note_on(device, channel, note);
+                }
+            }
+            CmfEvent::NoteOff { channel, note } => {
+                if self.rhythm_mode() && channel >= CMF_FIRST_RHYTHM_CHANNEL {
+                    let drum = OplDrum::ALL[(channel - CMF_FIRST_RHYTHM_CHANNEL) as usize];
+                    self.bd &= !drum.bit();
+                    write_reg(device, 0xBD, self.bd);
+                } else {
+                    self.note_off(device, channel, note);
+                }
+            }
+            CmfEvent::Controller {
+                channel,
+                controller,
+                value,
+            } => self.controller(device, channel, controller, value),
+            CmfEvent::ProgramChange { channel, program } => {
+                self.channels[channel as usize].program = program;
+            }
+            CmfEvent::PitchBend { channel, value } => {
+                self.channels[channel as usize].bend = value;
+                self.update_pitch(device, channel);
+            }
+        }
+    }
+
+    fn controller(&mut self, device: &mut Opl3Device, channel: u8, controller: u8, value: u8) {
+        match controller {
+            CMF_CONTROLLER_DEPTH => {
+                self.bd = (self.bd & 0x3F) | ((value & 3) << 6);
+                write_reg(device, 0xBD, self.bd);
+            }
+            CMF_CONTROLLER_MARKER => self.marker = value,
+            CMF_CONTROLLER_RHYTHM => self.set_rhythm_mode(device, value != 0),
+            CMF_CONTROLLER_TRANSPOSE_UP => {
+                self.channels[channel as usize].transpose = value as i16;
+                self.update_pitch(device, channel);
+            }
+            CMF_CONTROLLER_TRANSPOSE_DOWN => {
+                self.channels[channel as usize].transpose = -(value as i16);
+                self.update_pitch(device, channel);
+            }
+            _ => {}
+        }
+    }
+
+    fn set_rhythm_mode(&mut self, device: &mut Opl3Device, enabled: bool) {
+        if enabled == self.rhythm_mode() {
+            return;
+        }
+        // Release the voices taken over by, or returned from, the percussion instruments.
+        for ch in CMF_RHYTHM_OPL_CHANNELS..CMF_OPL_CHANNELS {
+            self.voices[ch].key_on = false;
+            self.voices[ch].instrument = None;
+            write_reg(device, 0xB0 + ch as u16, 0);
+        }
+        self.rhythm_instruments = [None; 5];
+        self.bd = if enabled {
+            (self.bd & 0xC0) | 0x20
+        } else {
+            self.bd & 0xC0
+        };
+        write_reg(device, 0xBD, self.bd);
+    }
+
+    fn instrument(&self, channel: u8) -> Option<usize> {
+        let count = self.file.instruments.len();
+        (count > 0).then(|| self.channels[channel as usize].program as usize % count)
+    }
+
+    fn pitch(&self, channel: u8, note: u8) -> (u16, u8) {
+        let state = &self.channels[channel as usize];
+        let note = note as f64
+            + state.transpose as f64 / 128.0
+            + state.bend as f64 * CMF_BEND_RANGE / 8192.0;
+        note_fnum(note)
+    }
+
+    fn write_pitch(&self, device: &mut Opl3Device, ch: u16, channel: u8, note: u8, key_on: bool) {
+        let (fnum, block) = self.pitch(channel, note);
+        let key = if key_on { 0x20 } else { 0 };
+        write_reg(device, 0xA0 + ch, fnum as u8);
+        write_reg(device, 0xB0 + ch, key | (block << 2) | (fnum >> 8) as u8);
+    }
+
+    fn note_on(&mut self, device: &mut Opl3Device, channel: u8, note: u8) {
+        let Some(instrument) = self.instrument(channel) else {
+            return;
+        };
+        let count = if self.rhythm_mode() {
+            CMF_RHYTHM_OPL_CHANNELS
+        } else {
+            CMF_OPL_CHANNELS
+        };
+        let voices = &self.voices[..count];
+        // Prefer a released voice that already has the instrument loaded, then the voice that
+        // was released the longest ago, and finally steal the oldest playing voice.
+        let ch = voices
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| !v.key_on)
+            .min_by_key(|(_, v)| (v.instrument != Some(instrument), v.age))
+            .or_else(|| voices.iter().enumerate().min_by_key(|(_, v)| v.age))
+            .map(|(ch, _)| ch)
+            .unwrap_or(0);
+
+        if self.voices[ch].key_on {
+            write_reg(device, 0xB0 + ch as u16, 0);
+        }
+        if self.voices[ch].instrument != Some(instrument) {
+            self.load_instrument(device, ch, instrument);
+        }
+        self.clock += 1;
+        self.voices[ch] = CmfVoice {
+            channel,
+            note,
+            key_on: true,
+            instrument: Some(instrument),
+            age: self.clock,
+        };
+        self.write_pitch(device, ch as u16, channel, note, true);
+    }
+
+    fn note_off(&mut self, device: &mut Opl3Device, channel: u8, note: u8) {
+        for ch in 0..CMF_OPL_CHANNELS {
+            let voice = self.voices[ch];
+            if voice.key_on && voice.channel == channel && voice.note == note {
+                self.clock += 1;
+                self.voices[ch].key_on = false;
+                self.voices[ch].age = self.clock;
+                self.write_pitch(device, ch as u16, channel, note, false);
+            }
+        }
+    }
+
+    fn update_pitch(&mut self, device: &mut Opl3Device, channel: u8) {
+        for ch in 0..CMF_OPL_CHANNELS {
+            let voice = self.voices[ch];
+            if voice.key_on && voice.channel == channel {
+                self.write_pitch(device, ch as u16, channel, voice.note, true);
+            }
+        }
+    }
+
+    fn load_instrument(&mut self, device: &mut Opl3Device, ch: usize, instrument: usize) {
+        let inst = self.file.instruments[instrument];
+        let op = OP_OFFSETS[ch];
+        for (reg, i) in [(0x20, 0), (0x40, 2), (0x60, 4), (0x80, 6), (0xE0, 8)] {
+            write_reg(device, reg + op, inst[i]);
+            write_reg(device, reg + op + 3, inst[i + 1]);
+        }
+        write_reg(device, 0xC0 + ch as u16, inst[10]);
+    }
+
+    fn rhythm_on(&mut self, device: &mut Opl3Device, channel: u8, note: u8) {
+        let slot = (channel - CMF_FIRST_RHYTHM_CHANNEL) as usize;
+        let drum = OplDrum::ALL[slot];
+        let Some(instrument) = self.instrument(channel) else {
+            return;
+        };
+
+        if self.rhythm_instruments[slot] != Some(instrument) {
+            self.rhythm_instruments[slot] = Some(instrument);
+            if slot == 0 {
+                self.load_instrument(device, drum.channel(), instrument);
+            } else {
+                let inst = self.file.instruments[instrument];
+                for (reg, i) in [(0x20, 0), (0x40, 2), (0x60, 4), (0x80, 6), (0xE0, 8)] {
+                    write_reg(device, reg + drum.operator_offset() as u16, inst[i]);
+                }
+            }
+        }
+
+        self.write_pitch(device, drum.channel() as u16, channel, note, false);
+        // Clear the bit first so that the instrument is retriggered.
+        write_reg(device, 0xBD, self.bd & !drum.bit());
+        self.bd |= drum.bit();
+        write_reg(device, 0xBD, self.bd);
+    }
+}
+
+impl OplSequencer for CmfSequencer {
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64> {
+        loop {
+            let Some(command) = self.file.commands.get(self.position).copied() else {
+                if !self.repeat || self.file.length_ticks() == 0 {
+                    return None;
+                }
+                self.position = 0;
+                self.looped = true;
+                continue;
+            };
+            self.position += 1;
+            self.play_event(device, command.event);
+            if command.delay > 0 {
+                return Some(
+                    command.delay as f64 * 1_000_000.0 / self.file.ticks_per_second as f64,
+                );
+            }
+        }
+    }
+
+    fn rewind(&mut self, device: &mut Opl3Device) {
+        self.channels = [CmfMidiChannel::default(); CMF_MIDI_CHANNELS];
+        self.voices = [CmfVoice::default(); CMF_OPL_CHANNELS];
+        self.rhythm_instruments = [None; 5];
+        self.position = 0;
+        self.clock = 0;
+        self.bd = 0;
+        self.marker = 0;
+        self.looped = false;
+
+        write_reg(device, 0x01, 0x20);
+        write_reg(device, 0x08, 0x00);
+        write_reg(device, 0xBD, 0x00);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OplRegisterFile;
+
+    const INSTRUMENT: [u8; 16] = [
+        0x01, 0x01, 0x10, 0x00, 0xF0, 0xF0, 0x77, 0x77, 0, 0, 0x00, 0, 0, 0, 0, 0,
+    ];
+
+    fn cmf(events: &[u8]) -> Vec<u8> {
+        let mut data = CMF_SIGNATURE.to_vec();
+        data.extend_from_slice(&CMF_VERSION_1_1.to_le_bytes());
+        for value in [40u16, 72, 96, 100, 0, 0, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[1; 16]);
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&120u16.to_le_bytes());
+        data.extend_from_slice(&INSTRUMENT);
+        data.extend_from_slice(&INSTRUMENT);
+        data.extend_from_slice(events);
+        data
+    }
+
+    #[test]
+    fn parse_events() {
+        let data = cmf(&[
+            0x00, 0xC0, 0x01, // Program 1
+            0x00, 0x90, 60, 100, // Note on
+            0x81, 0x00, 60, 0, // Running status note off after 128 ticks
+            0x00, 0xE0, 0x00, 0x40, // Pitch bend center
+            0x10, 0xFF, 0x2F, 0x00, // End of track
+            0x00, 0x90, 60, 100, // Ignored
+        ]);
+        let cmf = CmfFile::parse(&data).unwrap();
+        assert_eq!(cmf.ticks_per_second, 100);
+        assert_eq!(cmf.instruments.len(), 2);
+        assert_eq!(cmf.instruments[1][2], 0x10);
+        assert_eq!(cmf.title, None);
+        assert_eq!(cmf.commands.len(), 4);
+        assert_eq!(cmf.commands[1].delay, 128);
+        assert_eq!(
+            cmf.commands[2].event,
+            CmfEvent::NoteOff {
+                channel: 0,
+                note: 60
+            }
+        );
+        assert_eq!(
+            cmf.commands[3],
+            CmfCommand {
+                event: CmfEvent::PitchBend {
+                    channel: 0,
+                    value: 0
+                },
+                delay: 16
+            }
+        );
+        assert_eq!(cmf.length_ticks(), 144);
+    }
+
+    #[test]
+    fn play_notes_and_transpose() {
+        let data = cmf(&[
+            0x00,
+            0x90,
+            69,
+            100, // A4 on channel 0
+            0x00,
+            0x91,
+            69,
+            100, // A4 on channel 1, a semitone up
+            0x00,
+            0xB1,
+            CMF_CONTROLLER_TRANSPOSE_UP,
+            0x7F, //
+            0x00,
+            0xB0,
+            CMF_CONTROLLER_MARKER,
+            0x05, //
+            0x32,
+            0x80,
+            69,
+            0, // Release channel 0 after 50 ticks
+            0x32,
+            0xFF,
+            0x2F,
+            0x00,
+        ]);
+        let mut player = CmfPlayer::new(CmfSequencer::new(CmfFile::parse(&data).unwrap()), 1000);
+        let samples = player.render_to_vec(10_000).unwrap();
+        assert_eq!(samples.len() / 2, 1000);
+        assert!(samples.iter().any(|&s| s != 0));
+        assert_eq!(player.sequencer().marker(), 5);
+
+        let device = player.device();
+        let reg = |r: u8| device.read_register(r, OplRegisterFile::Primary);
+        // 440 Hz is F-number 580 in block 4.
+        assert_eq!(reg(0xA0), (580 & 0xFF) as u8);
+        assert_eq!(reg(0xB0), (4 << 2) | 2);
+        assert_eq!(reg(0xB1) & 0x20, 0x20);
+        assert!(reg(0xA1) as u16 | ((reg(0xB1) as u16 & 3) << 8) > 600);
+    }
+
+    #[test]
+    fn rhythm_mode() {
+        let data = cmf(&[
+            0x00,
+            0xB0,
+            CMF_CONTROLLER_RHYTHM,
+            1, //
+            0x00,
+            0x9B,
+            36,
+            100, // Bass drum
+            0x00,
+            0x9F,
+            60,
+            100, // Hi-hat
+            0x0A,
+            0x8F,
+            60,
+            0, //
+        ]);
+        let mut player = CmfPlayer::new(CmfSequencer::new(CmfFile::parse(&data).unwrap()), 1000);
+        player.render_to_vec(10_000).unwrap();
+        assert!(player.sequencer().rhythm_mode());
+        let bd = player
+            .device()
+            .read_register(0xBD, OplRegisterFile::Primary);
+        assert_eq!(bd, 0x20 | 0x10);
+    }
+}
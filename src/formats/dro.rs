@@ -0,0 +1,737 @@
+//! Parser and sequencer for DOSBox Raw OPL (DRO) captures.
+//!
+//! DOSBox has produced two incompatible versions of the DRO format. Version 0.1 files contain a
+//! byte stream where most bytes are register indices, with a handful of reserved codes for delays
+//! and bank switching. Version 2.0 files consist of (code, value) pairs, where register indices
+//! are looked up in a codemap stored in the file header and the delay codes are configurable.
+//!
+//! Both versions are parsed into a common list of `DroCommand`s which `DroSequencer` replays into
+//! an `Opl3Device`. `DroRecorder` captures the register writes of an `Opl3Device` into a
+//! `DroFile`, which can be saved as a version 2.0 file with `DroFile::to_bytes`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::formats::dro::{DroFile, DroPlayer, DroSequencer};
+//!
+//! let data = std::fs::read("song.dro").unwrap();
+//! let dro = DroFile::parse(&data).unwrap();
+//! let mut player = DroPlayer::new(DroSequencer::new(dro), 44100);
+//! let samples = player.render_to_vec(44100 * 600).unwrap();
+//! ```
+
+use crate::formats::ByteReader;
+use crate::player::{OplPlayer, OplSequencer};
+use crate::recorder::{initial_state, OplRecorder};
+use crate::{Opl3Device, OplError, OplRegisterFile};
+use alloc::vec::Vec;
+
+/// The signature at the start of every DRO file.
+pub const DRO_SIGNATURE: &[u8; 8] = b"DBRAWOPL";
+
+// Reserved command codes in version 0.1 files.
+const DRO_V1_DELAY_SHORT: u8 = 0x00;
+const DRO_V1_DELAY_LONG: u8 = 0x01;
+const DRO_V1_BANK_LOW: u8 = 0x02;
+const DRO_V1_BANK_HIGH: u8 = 0x03;
+const DRO_V1_ESCAPE: u8 = 0x04;
+
+// In version 2.0 files, bit 7 of a register code selects the high register bank.
+const DRO_V2_BANK_BIT: u8 = 0x80;
+
+// Version 2.0 register codes must stay below the bank bit and leave room for the two delay codes.
+const DRO_V2_MAX_CODEMAP: usize = 0x7E;
+
+// The longest delay that can be expressed by a single short or long delay command.
+const DRO_V2_SHORT_DELAY_MAX: u32 = 256;
+const DRO_V2_LONG_DELAY_MAX: u32 = 256 * 256;
+
+// Panning bits written to register 0xC0-0xC8 when mapping a dual OPL2 capture onto an OPL3.
+const OPL_PAN_LEFT: u8 = 0x10;
+const OPL_PAN_RIGHT: u8 = 0x20;
+
+/// The version of a DRO file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DroVersion {
+    /// The original DOSBox 0.61 - 0.72 capture format.
+    V0_1,
+    /// The DOSBox 0.73+ capture format, with a register codemap.
+    V2_0,
+}
+
+/// The hardware type a DRO file was captured from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DroHardware {
+    /// A single OPL2.
+    Opl2,
+    /// Two OPL2 chips, as found on the Sound Blaster Pro 1.
+    DualOpl2,
+    /// An OPL3.
+    Opl3,
+}
+
+impl DroHardware {
+    fn from_v1(value: u32) -> Self {
+        match value {
+            1 => DroHardware::Opl3,
+            2 => DroHardware::DualOpl2,
+            _ => DroHardware::Opl2,
+        }
+    }
+
+    fn from_v2(value: u8) -> Self {
+        match value {
+            1 => DroHardware::DualOpl2,
+            2 => DroHardware::Opl3,
+            _ => DroHardware::Opl2,
+        }
+    }
+
+    /// Return the value of the hardware type field used by version 2.0 files.
+    pub fn to_v2(self) -> u8 {
+        match self {
+            DroHardware::Opl2 => 0,
+            DroHardware::DualOpl2 => 1,
+            DroHardware::Opl3 => 2,
+        }
+    }
+}
+
+/// A single decoded command from a DRO file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DroCommand {
+    /// Wait for the specified number of milliseconds.
+    Delay(u32),
+    /// Write `value` to register `reg`. `bank` is 0 for the first chip or primary register file,
+    /// and 1 for the second chip or secondary register file.
+    Write {
+        /// The register bank, 0 or 1.
+        bank: u8,
+        /// The register index.
+        reg: u8,
+        /// The value written.
+        value: u8,
+    },
+}
+
+/// A parsed DRO file.
+#[derive(Clone, Debug)]
+pub struct DroFile {
+    /// The version of the file.
+    pub version: DroVersion,
+    /// The hardware type the capture was made from.
+    pub hardware: DroHardware,
+    /// The length of the song in milliseconds, as stated by the header.
+    pub length_ms: u32,
+    /// The code used for short delays in version 2.0 files.
+    pub short_delay_code: u8,
+    /// The code used for long delays in version 2.0 files.
+    pub long_delay_code: u8,
+    /// The register codemap of version 2.0 files. Empty for version 0.1 files.
+    pub codemap: Vec<u8>,
+    /// The decoded command stream.
+    pub commands: Vec<DroCommand>,
+}
+
+impl DroFile {
+    /// Parse a DRO file from a byte slice. Both version 0.1 and 2.0 files are supported.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the DRO file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `DroFile` or an `OplError` on failure.
+    pub fn parse(data: &[u8]) -> Result<DroFile, OplError> {
+        let mut reader = ByteReader::new(data);
+        if reader.bytes(8)? != DRO_SIGNATURE {
+            return Err(OplError::BadSignature);
+        }
+        let major = reader.u16_le()?;
+        let minor = reader.u16_le()?;
+        match (major, minor) {
+            // Version 0.1 was written as a single 32-bit value of 0x00010000.
+            (0, 1) => Self::parse_v1(reader),
+            (2, 0) => Self::parse_v2(reader),
+            _ => Err(OplError::UnsupportedVersion),
+        }
+    }
+
+    fn parse_v1(mut reader: ByteReader) -> Result<DroFile, OplError> {
+        let length_ms = reader.u32_le()?;
+        let length_bytes = reader.u32_le()? as usize;
+
+        // Some early version 0.1 files only used a single byte for the hardware type, and were
+        // later changed to use four bytes without a version change. If the three bytes following
+        // the first are not all zero, they are most likely data.
+        let hardware_pos = reader.pos();
+        let hardware = reader.u8()?;
+        let padding = reader.bytes(3.min(reader.remaining()))?;
+        if padding.len() < 3 || padding.iter().any(|&b| b != 0) {
+            reader.seek(hardware_pos + 1)?;
+        }
+        let hardware = DroHardware::from_v1(hardware as u32);
+
+        let stream = reader.bytes(length_bytes.min(reader.remaining()))?;
+        let mut stream = ByteReader::new(stream);
+        let mut commands = Vec::new();
+        let mut bank = 0;
+
+        while stream.remaining() > 0 {
+            let code = stream.u8()?;
+            match code {
+                DRO_V1_DELAY_SHORT => {
+                    commands.push(DroCommand::Delay(stream.u8()? as u32 + 1));
+                }
+                DRO_V1_DELAY_LONG => {
+                    commands.push(DroCommand::Delay(stream.u16_le()? as u32 + 1));
+                }
+                DRO_V1_BANK_LOW => bank = 0,
+                DRO_V1_BANK_HIGH => bank = 1,
+                DRO_V1_ESCAPE => {
+                    let reg = stream.u8()?;
+                    let value = stream.u8()?;
+                    commands.push(DroCommand::Write { bank, reg, value });
+                }
+                reg => {
+                    let value = stream.u8()?;
+                    commands.push(DroCommand::Write { bank, reg, value });
+                }
+            }
+        }
+
+        Ok(DroFile {
+            version: DroVersion::V0_1,
+            hardware,
+            length_ms,
+            short_delay_code: DRO_V1_DELAY_SHORT,
+            long_delay_code: DRO_V1_DELAY_LONG,
+            codemap: Vec::new(),
+            commands,
+        })
+    }
+
+    fn parse_v2(mut reader: ByteReader) -> Result<DroFile, OplError> {
+        let length_pairs = reader.u32_le()? as usize;
+        let length_ms = reader.u32_le()?;
+        let hardware = DroHardware::from_v2(reader.u8()?);
+        let format = reader.u8()?;
+        let compression = reader.u8()?;
+        if format != 0 || compression != 0 {
+            return Err(OplError::InvalidFile("unsupported DRO data format"));
+        }
+        let short_delay_code = reader.u8()?;
+        let long_delay_code = reader.u8()?;
+        let codemap_len = reader.u8()? as usize;
+        let codemap = reader.bytes(codemap_len)?.to_vec();
+
+        // The pair count can't be trusted to allocate with, as it may be corrupt.
+        let mut commands = Vec::with_capacity(length_pairs.min(reader.remaining() / 2));
+        for _ in 0..length_pairs {
+            if reader.remaining() < 2 {
+                // Truncated captures are common; play what is there.
+                break;
+            }
+            let code = reader.u8()?;
+            let value = reader.u8()?;
+            if code == short_delay_code {
+                commands.push(DroCommand::Delay(value as u32 + 1));
+            } else if code == long_delay_code {
+                commands.push(DroCommand::Delay((value as u32 + 1) << 8));
+            } else {
+                let bank = ((code & DRO_V2_BANK_BIT) != 0) as u8;
+                let reg = *codemap
+                    .get((code & !DRO_V2_BANK_BIT) as usize)
+                    .ok_or(OplError::InvalidFile("DRO register code outside codemap"))?;
+                commands.push(DroCommand::Write { bank, reg, value });
+            }
+        }
+
+        Ok(DroFile {
+            version: DroVersion::V2_0,
+            hardware,
+            length_ms,
+            short_delay_code,
+            long_delay_code,
+            codemap,
+            commands,
+        })
+    }
+
+    /// Serialize the file as a version 2.0 DRO file, regardless of the version it was parsed from.
+    /// The codemap and delay codes are recalculated from the command stream.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either a vector with the contents of the DRO file, or
+    /// `OplError::TooManyRegisters` if the commands write to more distinct registers than fit in
+    /// the codemap. This can't happen for captures made by `DroRecorder`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, OplError> {
+        let mut codemap: Vec<u8> = Vec::new();
+        for command in &self.commands {
+            if let DroCommand::Write { reg, .. } = *command {
+                if !codemap.contains(&reg) {
+                    if codemap.len() == DRO_V2_MAX_CODEMAP {
+                        return Err(OplError::TooManyRegisters);
+                    }
+                    codemap.push(reg);
+                }
+            }
+        }
+        let short_delay_code = codemap.len() as u8;
+        let long_delay_code = short_delay_code + 1;
+
+        let mut pairs: Vec<u8> = Vec::new();
+        for command in &self.commands {
+            match *command {
+                DroCommand::Delay(mut ms) => {
+                    while ms > 0 {
+                        if ms > DRO_V2_SHORT_DELAY_MAX {
+                            let count = (ms / DRO_V2_SHORT_DELAY_MAX).min(DRO_V2_SHORT_DELAY_MAX);
+                            pairs.extend_from_slice(&[long_delay_code, (count - 1) as u8]);
+                            ms -= count * DRO_V2_SHORT_DELAY_MAX;
+                        } else {
+                            pairs.extend_from_slice(&[short_delay_code, (ms - 1) as u8]);
+                            ms = 0;
+                        }
+                    }
+                }
+                DroCommand::Write { bank, reg, value } => {
+                    if let Some(code) = codemap.iter().position(|&r| r == reg) {
+                        let bank_bit = if bank != 0 { DRO_V2_BANK_BIT } else { 0 };
+                        pairs.extend_from_slice(&[code as u8 | bank_bit, value]);
+                    }
+                }
+            }
+        }
+
+        let mut data = Vec::with_capacity(26 + codemap.len() + pairs.len());
+        data.extend_from_slice(DRO_SIGNATURE);
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&((pairs.len() / 2) as u32).to_le_bytes());
+        data.extend_from_slice(&self.length_ms.to_le_bytes());
+        data.push(self.hardware.to_v2());
+        data.push(0); // Interleaved format
+        data.push(0); // No compression
+        data.push(short_delay_code);
+        data.push(long_delay_code);
+        data.push(codemap.len() as u8);
+        data.extend_from_slice(&codemap);
+        data.extend_from_slice(&pairs);
+        Ok(data)
+    }
+}
+
+/// The `DroRecorder` captures the register writes made to an `Opl3Device` in the same manner as
+/// DOSBox's raw OPL capture, producing a `DroFile`.
+///
+/// Attach the recorder with `Opl3Device::start_recording` and retrieve it again with
+/// `Opl3Device::stop_recording`. The state of the chip's registers at the time recording starts is
+/// written at the beginning of the capture, so that recording can be started mid-song. Like DOSBox,
+/// only writes to registers the OPL decodes are captured, so the codemap can't overflow.
+///
+/// # Example
+///
+/// ```
+/// use opl3_rs::{Opl3Device, OplRegisterFile};
+/// use opl3_rs::formats::dro::DroRecorder;
+///
+/// let mut device = Opl3Device::new(44100);
+/// device.start_recording(Box::new(DroRecorder::new(true)));
+/// device.write_register(0xB0, 0x31, OplRegisterFile::Primary, false);
+/// device.run(10_000.0);
+/// let recorder = device.stop_recording::<DroRecorder>().unwrap();
+/// let dro_bytes = recorder.finish().to_bytes().unwrap();
+/// ```
+pub struct DroRecorder {
+    commands: Vec<DroCommand>,
+    hardware: DroHardware,
+    skip_silence: bool,
+    sound_started: bool,
+    usec_pending: f64,
+    total_ms: u64,
+}
+
+impl DroRecorder {
+    /// Create a new DRO recorder.
+    ///
+    /// # Arguments
+    ///
+    /// * `skip_leading_silence` - If true, no delays are recorded until the first note is keyed
+    ///   on, so the capture starts with sound.
+    pub fn new(skip_leading_silence: bool) -> Self {
+        DroRecorder {
+            commands: Vec::new(),
+            hardware: DroHardware::Opl2,
+            skip_silence: skip_leading_silence,
+            sound_started: false,
+            usec_pending: 0.0,
+            total_ms: 0,
+        }
+    }
+
+    /// Return the length of the recording so far, in milliseconds.
+    pub fn length_ms(&self) -> u64 {
+        self.total_ms
+    }
+
+    /// Finish the recording, returning a `DroFile` containing the captured song.
+    pub fn finish(mut self) -> DroFile {
+        self.flush_delay();
+        DroFile {
+            version: DroVersion::V2_0,
+            hardware: self.hardware,
+            length_ms: self.total_ms.min(u32::MAX as u64) as u32,
+            short_delay_code: 0,
+            long_delay_code: 0,
+            codemap: Vec::new(),
+            commands: self.commands,
+        }
+    }
+
+    fn flush_delay(&mut self) {
+        // A small epsilon keeps accumulated floating point error from losing a millisecond.
+        let mut ms = (self.usec_pending / 1000.0 + 1e-6) as u64;
+        if ms == 0 {
+            return;
+        }
+        self.usec_pending -= ms as f64 * 1000.0;
+        self.total_ms += ms;
+        while ms > 0 {
+            let delay = ms.min(DRO_V2_LONG_DELAY_MAX as u64);
+            match self.commands.last_mut() {
+                Some(DroCommand::Delay(prev)) if (*prev as u64 + delay) <= u32::MAX as u64 => {
+                    *prev += delay as u32;
+                }
+                _ => self.commands.push(DroCommand::Delay(delay as u32)),
+            }
+            ms -= delay;
+        }
+    }
+
+    fn record(&mut self, bank: u8, reg: u8, value: u8) {
+        if !is_opl_register(reg) {
+            return;
+        }
+        if bank == 1 {
+            self.hardware = DroHardware::Opl3;
+        }
+        self.commands.push(DroCommand::Write { bank, reg, value });
+    }
+}
+
+impl OplRecorder for DroRecorder {
+    fn start(&mut self, registers: &[[u8; 256]; 2]) {
+        for (file, reg, value) in initial_state(registers) {
+            self.record((file == OplRegisterFile::Secondary) as u8, reg, value);
+        }
+        self.sound_started = registers[0][0xB0..=0xB8]
+            .iter()
+            .chain(registers[1][0xB0..=0xB8].iter())
+            .any(|&v| v & 0x20 != 0);
+    }
+
+    fn write(&mut self, reg: u8, value: u8, file: OplRegisterFile) {
+        let bank = match file {
+            OplRegisterFile::Primary => {
+                // The timer registers have no effect on the sound produced.
+                if (0x02..=0x04).contains(&reg) {
+                    return;
+                }
+                0
+            }
+            OplRegisterFile::Secondary => 1,
+        };
+
+        let key_on = ((0xB0..=0xB8).contains(&reg) && (value & 0x20) != 0)
+            || (bank == 0 && reg == 0xBD && (value & 0x1F) != 0);
+        if key_on {
+            self.sound_started = true;
+        }
+        self.flush_delay();
+        self.record(bank, reg, value);
+    }
+
+    fn advance(&mut self, usec: f64) {
+        if self.skip_silence && !self.sound_started {
+            return;
+        }
+        self.usec_pending += usec;
+    }
+}
+
+/// Returns true if the register is one that the OPL decodes, as listed in DOSBox's capture table.
+fn is_opl_register(reg: u8) -> bool {
+    match reg {
+        0x01 | 0x04 | 0x05 | 0x08 | 0xBD => true,
+        // The 18 operators of each operator register group.
+        0x20..=0x95 | 0xE0..=0xF5 => matches!(reg & 0x1F, 0x00..=0x05 | 0x08..=0x0D | 0x10..=0x15),
+        0xA0..=0xA8 | 0xB0..=0xB8 | 0xC0..=0xC8 => true,
+        _ => false,
+    }
+}
+
+/// The `DroSequencer` replays the commands of a `DroFile` into an `Opl3Device`.
+///
+/// Dual OPL2 captures are mapped onto the two register files of the OPL3, with the first chip
+/// panned left and the second chip panned right, as DOSBox does.
+pub struct DroSequencer {
+    file: DroFile,
+    position: usize,
+}
+
+/// A player for DRO files.
+pub type DroPlayer = OplPlayer<DroSequencer>;
+
+impl DroSequencer {
+    /// Create a new sequencer for the given DRO file.
+    pub fn new(file: DroFile) -> Self {
+        DroSequencer { file, position: 0 }
+    }
+
+    /// Return the `DroFile` being played.
+    pub fn file(&self) -> &DroFile {
+        &self.file
+    }
+
+    /// Return the index of the next command to be executed.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn write(&self, device: &mut Opl3Device, bank: u8, reg: u8, value: u8) {
+        if self.file.hardware != DroHardware::DualOpl2 {
+            let file = if bank == 0 {
+                OplRegisterFile::Primary
+            } else {
+                OplRegisterFile::Secondary
+            };
+            device.write_register(reg, value, file, false);
+            return;
+        }
+
+        // Each OPL2 of a dual OPL2 pair only has the OPL2 register set. The OPL3 global registers
+        // would be clobbered by the second chip's timer and test registers, and rhythm mode only
+        // exists in the primary register file, so these are dropped for the second chip.
+        if bank == 1 && (reg < 0x20 || reg == 0xBD) {
+            return;
+        }
+        let (file, pan) = if bank == 0 {
+            (OplRegisterFile::Primary, OPL_PAN_LEFT)
+        } else {
+            (OplRegisterFile::Secondary, OPL_PAN_RIGHT)
+        };
+        let value = if (0xC0..=0xC8).contains(&reg) {
+            (value & 0x0F) | pan
+        } else {
+            value
+        };
+        device.write_register(reg, value, file, false);
+    }
+}
+
+impl OplSequencer for DroSequencer {
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64> {
+        while let Some(command) = self.file.commands.get(self.position).copied() {
+            self.position += 1;
+            match command {
+                DroCommand::Delay(ms) => return Some(ms as f64 * 1000.0),
+                DroCommand::Write { bank, reg, value } => self.write(device, bank, reg, value),
+            }
+        }
+        None
+    }
+
+    fn rewind(&mut self, device: &mut Opl3Device) {
+        self.position = 0;
+        if self.file.hardware == DroHardware::DualOpl2 {
+            // Enable OPL3 mode so that the second register file is active, and pan each chip's
+            // channels to its own side.
+            device.write_register(0x05, 0x01, OplRegisterFile::Secondary, false);
+            for ch in 0..9 {
+                device.write_register(0xC0 + ch, OPL_PAN_LEFT, OplRegisterFile::Primary, false);
+                device.write_register(0xC0 + ch, OPL_PAN_RIGHT, OplRegisterFile::Secondary, false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{boxed::Box, vec};
+
+    fn v2_file(pairs: &[(u8, u8)], codemap: &[u8]) -> Vec<u8> {
+        let mut data = DRO_SIGNATURE.to_vec();
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&(pairs.len() as u32).to_le_bytes());
+        data.extend_from_slice(&1000u32.to_le_bytes());
+        data.extend_from_slice(&[2, 0, 0, 0xFE, 0xFF, codemap.len() as u8]);
+        data.extend_from_slice(codemap);
+        for (code, value) in pairs {
+            data.push(*code);
+            data.push(*value);
+        }
+        data
+    }
+
+    #[test]
+    fn parse_v2_codemap_and_delays() {
+        let data = v2_file(
+            &[(0, 0x21), (0xFE, 9), (0x81, 0x01), (0xFF, 1)],
+            &[0x20, 0x05],
+        );
+        let dro = DroFile::parse(&data).unwrap();
+        assert_eq!(dro.version, DroVersion::V2_0);
+        assert_eq!(dro.hardware, DroHardware::Opl3);
+        assert_eq!(
+            dro.commands,
+            vec![
+                DroCommand::Write {
+                    bank: 0,
+                    reg: 0x20,
+                    value: 0x21
+                },
+                DroCommand::Delay(10),
+                DroCommand::Write {
+                    bank: 1,
+                    reg: 0x05,
+                    value: 0x01
+                },
+                DroCommand::Delay(512),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_v2_truncated() {
+        let mut data = v2_file(&[(0, 0x21), (0xFE, 9)], &[0x20]);
+        data[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        data.pop();
+        let dro = DroFile::parse(&data).unwrap();
+        assert_eq!(
+            dro.commands,
+            vec![DroCommand::Write {
+                bank: 0,
+                reg: 0x20,
+                value: 0x21
+            }]
+        );
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let mut device = Opl3Device::new(44100);
+        device.write_register(0x20, 0x01, OplRegisterFile::Primary, false);
+        device.start_recording(Box::new(DroRecorder::new(true)));
+        device.run(5_000.0);
+        device.write_register(0xA0, 0x98, OplRegisterFile::Primary, false);
+        device.write_register(0xB0, 0x31, OplRegisterFile::Primary, false);
+        device.run(70_000.0);
+        device.write_register(0x05, 0x01, OplRegisterFile::Secondary, false);
+        device.run(300_500.0);
+        let dro = device.stop_recording::<DroRecorder>().unwrap().finish();
+
+        let parsed = DroFile::parse(&dro.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.hardware, DroHardware::Opl3);
+        assert_eq!(parsed.length_ms, 370);
+        assert_eq!(
+            parsed.commands,
+            vec![
+                DroCommand::Write {
+                    bank: 0,
+                    reg: 0x20,
+                    value: 0x01
+                },
+                DroCommand::Write {
+                    bank: 0,
+                    reg: 0xA0,
+                    value: 0x98
+                },
+                DroCommand::Write {
+                    bank: 0,
+                    reg: 0xB0,
+                    value: 0x31
+                },
+                DroCommand::Delay(70),
+                DroCommand::Write {
+                    bank: 1,
+                    reg: 0x05,
+                    value: 0x01
+                },
+                DroCommand::Delay(256),
+                DroCommand::Delay(44),
+            ]
+        );
+    }
+
+    #[test]
+    fn record_init_clear() {
+        // Drivers commonly clear every register at init, including ones the OPL doesn't decode.
+        let mut device = Opl3Device::new(44100);
+        device.start_recording(Box::new(DroRecorder::new(false)));
+        for reg in 0x01..=0xF5 {
+            device.write_register(reg, 0, OplRegisterFile::Primary, false);
+        }
+        for note in [0x31, 0x32] {
+            device.write_register(0xA0, 0x98, OplRegisterFile::Primary, false);
+            device.write_register(0xB0, note, OplRegisterFile::Primary, false);
+            device.run(10_000.0);
+        }
+        let dro = device.stop_recording::<DroRecorder>().unwrap().finish();
+
+        let parsed = DroFile::parse(&dro.to_bytes().unwrap()).unwrap();
+        assert!(parsed.codemap.len() <= DRO_V2_MAX_CODEMAP);
+        assert!(!parsed.codemap.contains(&0x06));
+        let key_ons = parsed
+            .commands
+            .iter()
+            .filter(|&&command| {
+                matches!(command, DroCommand::Write { reg: 0xB0, value, .. } if value & 0x20 != 0)
+            })
+            .count();
+        assert_eq!(key_ons, 2);
+
+        let mut too_many = parsed;
+        too_many.commands = (0..=0x7E)
+            .map(|reg| DroCommand::Write {
+                bank: 0,
+                reg,
+                value: 0,
+            })
+            .collect();
+        assert!(matches!(
+            too_many.to_bytes(),
+            Err(OplError::TooManyRegisters)
+        ));
+    }
+
+    #[test]
+    fn parse_v1_single_byte_hardware_type() {
+        let stream = [0x20, 0x01, 0x00, 0x04, 0x03, 0xB0, 0x22, 0x01, 0x00, 0x01];
+        let mut data = DRO_SIGNATURE.to_vec();
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&100u32.to_le_bytes());
+        data.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+        data.push(0);
+        data.extend_from_slice(&stream);
+        let dro = DroFile::parse(&data).unwrap();
+        assert_eq!(dro.version, DroVersion::V0_1);
+        assert_eq!(dro.hardware, DroHardware::Opl2);
+        assert_eq!(dro.commands.len(), 4);
+        assert_eq!(dro.commands[1], DroCommand::Delay(5));
+        assert_eq!(
+            dro.commands[2],
+            DroCommand::Write {
+                bank: 1,
+                reg: 0xB0,
+                value: 0x22
+            }
+        );
+        assert_eq!(dro.commands[3], DroCommand::Delay(257));
+    }
+}
@@ -0,0 +1,499 @@
+//! Parser and player for HSC-Tracker (HSC) modules.
+//!
+//! HSC modules have no header or signature. A file consists of 128 instruments of 12 bytes each,
+//! an order list of 51 entries, and up to 50 patterns of 64 rows for 9 channels, with 2 bytes per
+//! note. Songs are played at a fixed rate of 18.2 Hz on an OPL2.
+//!
+//! The supported effects are pattern break (01), fade in (03), 6-voice/percussion mode on (05) and
+//! off (06), manual slide up (1x) and down (2x), set feedback (6x), set carrier (Ax), modulator (Bx)
+//! and instrument (Cx) volume, position jump (Dx) and set speed (Fx). The global volume effects 02
+//! and 04, and the percussion instrument effect 5x, are not supported.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::formats::hsc::{HscFile, HscPlayer, HscSequencer};
+//!
+//! let data = std::fs::read("song.hsc").unwrap();
+//! let hsc = HscFile::parse(&data).unwrap();
+//! let mut player = HscPlayer::new(HscSequencer::new(hsc), 44100);
+//! let samples = player.render_to_vec(44100 * 600).unwrap();
+//! ```
+
+use crate::formats::write_reg;
+use crate::player::{OplPlayer, OplSequencer};
+use crate::{Opl3Device, OplError};
+use alloc::vec::Vec;
+
+/// The number of instruments in an HSC module.
+pub const HSC_INSTRUMENTS: usize = 128;
+/// The number of entries in the order list of an HSC module.
+pub const HSC_ORDERS: usize = 51;
+/// The number of rows in an HSC pattern.
+pub const HSC_ROWS: usize = 64;
+/// The number of channels in an HSC module.
+pub const HSC_CHANNELS: usize = 9;
+
+const HSC_MAX_PATTERNS: usize = 50;
+const HSC_INSTRUMENT_SIZE: usize = 12;
+const HSC_PATTERN_SIZE: usize = HSC_ROWS * HSC_CHANNELS * 2;
+const HSC_HEADER_SIZE: usize = HSC_INSTRUMENTS * HSC_INSTRUMENT_SIZE + HSC_ORDERS;
+
+// Order list entries with bit 7 set jump to the order given by the lower bits, and entries at or
+// above this value mark the end of the song.
+const HSC_ORDER_END: u8 = 0xB2;
+const HSC_ORDER_JUMP: u8 = 0x80;
+
+const HSC_TICK_RATE: f64 = 18.2;
+const HSC_DEFAULT_SPEED: u8 = 2;
+const HSC_FADE_IN: u8 = 31;
+
+// A note with bit 7 set sets the instrument given by the effect byte instead.
+const HSC_NOTE_SET_INSTRUMENT: u8 = 0x80;
+const HSC_NOTE_PAUSE: u8 = 0x7E;
+
+// F-numbers for C# through C.
+const NOTE_FREQ: [u16; 12] = [
+    0x16B, 0x181, 0x198, 0x1B0, 0x1CA, 0x1E5, 0x202, 0x220, 0x241, 0x263, 0x287, 0x2AE,
+];
+const OP_OFFSETS: [u16; HSC_CHANNELS] = [0x00, 0x01, 0x02, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x12];
+
+/// A single note entry in an HSC pattern.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HscNote {
+    /// The note, where 1 is C#-0, and 0x7F is a pause. If bit 7 is set, the effect byte is an
+    /// instrument number to set on the channel.
+    pub note: u8,
+    /// The effect, with the effect type in the upper nibble and its parameter in the lower nibble.
+    pub effect: u8,
+}
+
+/// An HSC pattern of `HSC_ROWS` rows of `HSC_CHANNELS` notes.
+pub type HscPattern = [[HscNote; HSC_CHANNELS]; HSC_ROWS];
+
+/// A parsed HSC module.
+#[derive(Clone, Debug)]
+pub struct HscFile {
+    /// The instruments. Bytes 0 to 10 are the carrier and modulator registers 0x20, 0x40, 0x60,
+    /// 0x80, the connection register 0xC0 and the carrier and modulator registers 0xE0. Byte 11 is
+    /// the fine tune added to the frequency of each note.
+    pub instruments: Vec<[u8; HSC_INSTRUMENT_SIZE]>,
+    /// The order list.
+    pub order_list: Vec<u8>,
+    /// The patterns present in the file.
+    pub patterns: Vec<HscPattern>,
+}
+
+impl HscFile {
+    /// Parse an HSC module from a byte slice.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the HSC file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `HscFile` or an `OplError` on failure.
+    pub fn parse(data: &[u8]) -> Result<HscFile, OplError> {
+        // With no signature to check, the file size is the only way to reject other files.
+        if data.len() <= HSC_HEADER_SIZE
+            || data.len() > HSC_HEADER_SIZE + HSC_MAX_PATTERNS * HSC_PATTERN_SIZE
+        {
+            return Err(OplError::InvalidFile("HSC file size out of range"));
+        }
+
+        let instruments = data[..HSC_INSTRUMENTS * HSC_INSTRUMENT_SIZE]
+            .chunks_exact(HSC_INSTRUMENT_SIZE)
+            .map(|chunk| {
+                let mut inst = [0u8; HSC_INSTRUMENT_SIZE];
+                inst.copy_from_slice(chunk);
+                // Bit 6 of the level bytes is stored inverted in bit 7.
+                inst[2] ^= (inst[2] & 0x40) << 1;
+                inst[3] ^= (inst[3] & 0x40) << 1;
+                inst[11] >>= 4;
+                inst
+            })
+            .collect();
+
+        let order_list = data[HSC_INSTRUMENTS * HSC_INSTRUMENT_SIZE..HSC_HEADER_SIZE].to_vec();
+
+        // A truncated final pattern is padded with empty notes.
+        let patterns = data[HSC_HEADER_SIZE..]
+            .chunks(HSC_PATTERN_SIZE)
+            .map(|chunk| {
+                let mut pattern = [[HscNote::default(); HSC_CHANNELS]; HSC_ROWS];
+                for (i, cell) in chunk.chunks_exact(2).enumerate() {
+                    pattern[i / HSC_CHANNELS][i % HSC_CHANNELS] = HscNote {
+                        note: cell[0],
+                        effect: cell[1],
+                    };
+                }
+                pattern
+            })
+            .collect();
+
+        Ok(HscFile {
+            instruments,
+            order_list,
+            patterns,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct HscChannel {
+    instrument: u8,
+    freq: u16,
+    slide: i16,
+    b0: u8,
+}
+
+/// The `HscSequencer` plays an `HscFile` through an `Opl3Device`.
+pub struct HscSequencer {
+    file: HscFile,
+    channels: [HscChannel; HSC_CHANNELS],
+    order: usize,
+    row: usize,
+    speed: u8,
+    delay: u8,
+    pattern_break: bool,
+    order_jump: Option<usize>,
+    six_voice: bool,
+    bd: u8,
+    fade_in: u8,
+    looped: bool,
+    repeat: bool,
+}
+
+/// A player for HSC modules.
+pub type HscPlayer = OplPlayer<HscSequencer>;
+
+impl HscSequencer {
+    /// Create a new sequencer for the given HSC module.
+    pub fn new(file: HscFile) -> Self {
+        HscSequencer {
+            file,
+            channels: [HscChannel::default(); HSC_CHANNELS],
+            order: 0,
+            row: 0,
+            speed: HSC_DEFAULT_SPEED,
+            delay: 1,
+            pattern_break: false,
+            order_jump: None,
+            six_voice: false,
+            bd: 0,
+            fade_in: 0,
+            looped: false,
+            repeat: false,
+        }
+    }
+
+    /// Return the `HscFile` being played.
+    pub fn file(&self) -> &HscFile {
+        &self.file
+    }
+
+    /// Set whether the song repeats when it reaches the end, rather than finishing.
+    pub fn set_repeat(&mut self, repeat: bool) {
+        self.repeat = repeat;
+    }
+
+    /// Return the current position in the order list.
+    pub fn order_position(&self) -> usize {
+        self.order
+    }
+
+    /// Return the next row of the current pattern to be played.
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    /// Returns true once the song has reached its end and looped back.
+    pub fn has_looped(&self) -> bool {
+        self.looped
+    }
+
+    fn instrument(&self, ch: usize) -> &[u8; HSC_INSTRUMENT_SIZE] {
+        &self.file.instruments[self.channels[ch].instrument as usize % HSC_INSTRUMENTS]
+    }
+
+    fn update(&mut self, device: &mut Opl3Device) {
+        self.delay -= 1;
+        if self.delay > 0 {
+            return;
+        }
+        self.fade_in = self.fade_in.saturating_sub(1);
+
+        let pattern = self
+            .file
+            .order_list
+            .get(self.order)
+            .and_then(|&p| self.file.patterns.get(p as usize))
+            .map(|p| p[self.row]);
+        if let Some(row) = pattern {
+            for (ch, &note) in row.iter().enumerate() {
+                self.play_note(device, ch, note);
+            }
+        }
+
+        self.delay = self.speed;
+        self.advance();
+    }
+
+    fn play_note(&mut self, device: &mut Opl3Device, ch: usize, note: HscNote) {
+        if note.note & HSC_NOTE_SET_INSTRUMENT != 0 {
+            self.set_instrument(device, ch, note.effect);
+            return;
+        }
+
+        let param = note.effect & 0x0F;
+        let op = OP_OFFSETS[ch];
+        let inst = *self.instrument(ch);
+        if note.note != 0 {
+            self.channels[ch].slide = 0;
+        }
+
+        match note.effect & 0xF0 {
+            0x00 => match param {
+                1 => self.pattern_break = true,
+                3 => self.fade_in = HSC_FADE_IN,
+                5 => self.six_voice = true,
+                6 => self.six_voice = false,
+                _ => {}
+            },
+            0x10 | 0x20 => {
+                let delta = if note.effect & 0x10 != 0 {
+                    param as i16
+                } else {
+                    -(param as i16)
+                };
+                let chan = &mut self.channels[ch];
+                chan.freq = chan.freq.wrapping_add_signed(delta);
+                chan.slide += delta;
+                if note.note == 0 {
+                    self.set_freq(device, ch, self.channels[ch].freq);
+                }
+            }
+            0x60 => write_reg(device, 0xC0 + ch as u16, (inst[8] & 1) | (param << 1)),
+            0xA0 => write_reg(device, 0x43 + op, (param << 2) | (inst[2] & !63)),
+            0xB0 => write_reg(device, 0x40 + op, (param << 2) | (inst[3] & !63)),
+            0xC0 => {
+                write_reg(device, 0x43 + op, (param << 2) | (inst[2] & !63));
+                if inst[8] & 1 != 0 {
+                    write_reg(device, 0x40 + op, (param << 2) | (inst[3] & !63));
+                }
+            }
+            0xD0 => self.order_jump = Some(param as usize),
+            0xF0 => {
+                self.speed = param + 1;
+                self.delay = self.speed;
+            }
+            _ => {}
+        }
+
+        if self.fade_in > 0 {
+            self.set_volume(device, ch, self.fade_in * 2, self.fade_in * 2);
+        }
+
+        if note.note == 0 {
+            return;
+        }
+        let note_num = note.note - 1;
+        if note_num == HSC_NOTE_PAUSE || note_num / 12 > 7 {
+            self.channels[ch].b0 &= !0x20;
+            write_reg(device, 0xB0 + ch as u16, self.channels[ch].b0);
+            return;
+        }
+
+        let block = (note_num / 12) << 2;
+        let freq = (NOTE_FREQ[(note_num % 12) as usize] + inst[11] as u16)
+            .wrapping_add_signed(self.channels[ch].slide);
+        self.channels[ch].freq = freq;
+        // Percussion channels in 6-voice mode are triggered through 0xBD instead of keyed on.
+        self.channels[ch].b0 = if !self.six_voice || ch < 6 {
+            block | 0x20
+        } else {
+            block
+        };
+        write_reg(device, 0xB0 + ch as u16, 0);
+        self.set_freq(device, ch, freq);
+
+        if self.six_voice {
+            let (clear, set) = match ch {
+                6 => (0x10, 0x30),
+                7 => (0x01, 0x21),
+                8 => (0x02, 0x22),
+                _ => (0, 0),
+            };
+            write_reg(device, 0xBD, self.bd & !clear);
+            self.bd |= set;
+            write_reg(device, 0xBD, self.bd);
+        }
+    }
+
+    fn advance(&mut self) {
+        let next_order = if let Some(target) = self.order_jump.take() {
+            if target <= self.order {
+                self.looped = true;
+            }
+            self.row = 0;
+            Some(target)
+        } else if self.pattern_break {
+            self.row = 0;
+            Some(self.order + 1)
+        } else {
+            self.row = (self.row + 1) % HSC_ROWS;
+            (self.row == 0).then_some(self.order + 1)
+        };
+        self.pattern_break = false;
+
+        if let Some(order) = next_order {
+            if order >= HSC_ORDERS {
+                self.looped = true;
+            }
+            self.order = order % HSC_ORDERS;
+            self.resolve_order();
+        }
+    }
+
+    fn resolve_order(&mut self) {
+        for _ in 0..HSC_ORDERS {
+            match self.file.order_list[self.order] {
+                entry if entry >= HSC_ORDER_END => {
+                    self.order = 0;
+                    self.looped = true;
+                }
+                entry if entry & HSC_ORDER_JUMP != 0 => {
+                    let target = (entry & !HSC_ORDER_JUMP) as usize % HSC_ORDERS;
+                    if target <= self.order {
+                        self.looped = true;
+                    }
+                    self.order = target;
+                    self.row = 0;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn set_instrument(&mut self, device: &mut Opl3Device, ch: usize, instrument: u8) {
+        self.channels[ch].instrument = instrument;
+        let inst = *self.instrument(ch);
+        let op = OP_OFFSETS[ch];
+
+        write_reg(device, 0xB0 + ch as u16, 0);
+        write_reg(device, 0xC0 + ch as u16, inst[8]);
+        for (reg, car, modulator) in [
+            (0x20, inst[0], inst[1]),
+            (0x60, inst[4], inst[5]),
+            (0x80, inst[6], inst[7]),
+            (0xE0, inst[9], inst[10]),
+        ] {
+            write_reg(device, reg + 3 + op, car);
+            write_reg(device, reg + op, modulator);
+        }
+        self.set_volume(device, ch, inst[2] & 63, inst[3] & 63);
+    }
+
+    fn set_volume(&mut self, device: &mut Opl3Device, ch: usize, carrier: u8, modulator: u8) {
+        let inst = *self.instrument(ch);
+        let op = OP_OFFSETS[ch];
+        write_reg(device, 0x43 + op, carrier | (inst[2] & !63));
+        // The modulator level is only a volume for additive instruments.
+        if inst[8] & 1 != 0 {
+            write_reg(device, 0x40 + op, modulator | (inst[3] & !63));
+        } else {
+            write_reg(device, 0x40 + op, inst[3]);
+        }
+    }
+
+    fn set_freq(&mut self, device: &mut Opl3Device, ch: usize, freq: u16) {
+        let chan = &mut self.channels[ch];
+        chan.b0 = (chan.b0 & !3) | ((freq >> 8) as u8 & 3);
+        write_reg(device, 0xA0 + ch as u16, freq as u8);
+        write_reg(device, 0xB0 + ch as u16, chan.b0);
+    }
+}
+
+impl OplSequencer for HscSequencer {
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64> {
+        // Finish at the first row after the song loops, unless repeating.
+        if self.looped && !self.repeat && self.delay <= 1 {
+            return None;
+        }
+        self.update(device);
+        Some(1_000_000.0 / HSC_TICK_RATE)
+    }
+
+    fn rewind(&mut self, device: &mut Opl3Device) {
+        self.channels = [HscChannel::default(); HSC_CHANNELS];
+        self.order = 0;
+        self.row = 0;
+        self.speed = HSC_DEFAULT_SPEED;
+        self.delay = 1;
+        self.pattern_break = false;
+        self.order_jump = None;
+        self.six_voice = false;
+        self.bd = 0;
+        self.fade_in = 0;
+        self.resolve_order();
+        self.looped = false;
+
+        write_reg(device, 0x01, 0x20);
+        write_reg(device, 0x08, 0x80);
+        write_reg(device, 0xBD, 0x00);
+        for ch in 0..HSC_CHANNELS {
+            self.set_instrument(device, ch, ch as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn module() -> Vec<u8> {
+        let mut data = vec![0u8; HSC_HEADER_SIZE + HSC_PATTERN_SIZE];
+        // Instrument 0: level bytes with bit 6 set, and a fine tune of 2.
+        data[..12].copy_from_slice(&[
+            0x01, 0x01, 0x40, 0x10, 0xF0, 0xF0, 0x77, 0x77, 0x00, 0, 0, 0x20,
+        ]);
+        let orders = HSC_INSTRUMENTS * HSC_INSTRUMENT_SIZE;
+        data[orders..HSC_HEADER_SIZE].fill(0xFF);
+        data[orders] = 0;
+        data[orders + 1] = 0;
+        // Row 0: channel 0 plays C-4. Row 1: pattern break and speed 3.
+        data[HSC_HEADER_SIZE] = 49;
+        data[HSC_HEADER_SIZE + 18] = 0;
+        data[HSC_HEADER_SIZE + 19] = 0x01;
+        data[HSC_HEADER_SIZE + 20] = 0;
+        data[HSC_HEADER_SIZE + 21] = 0xF2;
+        data
+    }
+
+    #[test]
+    fn parse() {
+        assert!(HscFile::parse(&[0; HSC_HEADER_SIZE]).is_err());
+        let hsc = HscFile::parse(&module()).unwrap();
+        assert_eq!(hsc.instruments.len(), HSC_INSTRUMENTS);
+        assert_eq!(hsc.instruments[0][2], 0xC0);
+        assert_eq!(hsc.instruments[0][11], 2);
+        assert_eq!(hsc.patterns.len(), 1);
+        assert_eq!(hsc.patterns[0][0][0].note, 49);
+        assert_eq!(hsc.patterns[0][1][1].effect, 0xF2);
+    }
+
+    #[test]
+    fn play_until_end() {
+        let hsc = HscFile::parse(&module()).unwrap();
+        let mut player = HscPlayer::new(HscSequencer::new(hsc), 18200);
+        let samples = player.render_to_vec(1_000_000).unwrap();
+        // Two orders of two rows: 2 ticks for the first row, then 3 ticks per row after the speed
+        // change, at 1000 samples per tick.
+        let frames = samples.len() / 2;
+        assert!((10999..=11001).contains(&frames), "{frames}");
+        assert!(player.sequencer().has_looped());
+        assert!(samples.iter().any(|&s| s != 0));
+    }
+}
@@ -0,0 +1,1279 @@
+//! Parser and player for Reality AdLib Tracker (RAD) modules.
+//!
+//! Both the original version 1.0 format and the version 2.1 format of RAD 2 are supported. Version
+//! 1.0 modules are OPL2 songs with 2-op instruments. Version 2.1 modules add named instruments with
+//! 4-op algorithms, stereo panning and detune, per-channel riffs, instrument riffs, BPM-based
+//! tempo and a number of new effects.
+//!
+//! Both versions are played through the same engine, which always places the chip in OPL3 mode.
+//! Each of the 9 RAD channels is mapped to a pair of OPL3 channels which are either combined into a
+//! 4-op channel or used as two detuned 2-op channels, following the reference player by Reality.
+//!
+//! The supported effects are portamento up (1) and down (2), tone slide (3), tone slide with
+//! volume slide (5), volume slide (A), set volume (C), jump to line (D), set speed (F), and on
+//! version 2.1 modules ignore instrument riff (I), operator multiplier (M), riff (R), transposed
+//! riff (T) and feedback (U).
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::formats::rad::{RadFile, RadPlayer, RadSequencer};
+//!
+//! let data = std::fs::read("song.rad").unwrap();
+//! let rad = RadFile::parse(&data).unwrap();
+//! println!("{}", rad.description);
+//! let mut player = RadPlayer::new(RadSequencer::new(rad), 44100);
+//! let mut buffer = [0i16; 1024];
+//! while !player.is_finished() {
+//!     player.generate_samples(&mut buffer).unwrap();
+//!     let seq = player.sequencer();
+//!     println!("Order {} Pattern {} Line {}", seq.order_position(), seq.pattern(), seq.line());
+//! }
+//! ```
+
+use crate::formats::{read_reg, write_reg, ByteReader};
+use crate::player::{OplPlayer, OplSequencer};
+use crate::{Opl3Device, OplError};
+use alloc::{string::String, vec, vec::Vec};
+
+/// The signature at the start of every RAD file.
+pub const RAD_SIGNATURE: &[u8; 16] = b"RAD by REALiTY!!";
+
+/// The number of lines in a RAD pattern or riff.
+pub const RAD_LINES: usize = 64;
+/// The number of channels in a RAD module.
+pub const RAD_CHANNELS: usize = 9;
+
+const RAD_VERSION_1: u8 = 0x10;
+const RAD_VERSION_2: u8 = 0x21;
+const RAD_V1_PATTERNS: usize = 32;
+const RAD_V2_PATTERNS: usize = 100;
+const RAD_RIFFS: usize = 10;
+const RAD_ORDER_JUMP: u8 = 0x80;
+const RAD_MIDI_ALGORITHM: u8 = 7;
+
+// Header flag bits.
+const RAD_FLAG_DESCRIPTION: u8 = 0x80;
+const RAD_FLAG_SLOW_TIMER: u8 = 0x40;
+const RAD_FLAG_BPM: u8 = 0x20;
+const RAD_FLAG_SPEED_MASK: u8 = 0x1F;
+
+const RAD_DEFAULT_BPM: u16 = 125;
+const RAD_SLOW_TIMER_HZ: f64 = 18.2;
+const RAD_FAST_TIMER_HZ: f64 = 50.0;
+
+/// The note value used for a key off.
+pub const RAD_NOTE_KEY_OFF: u8 = 15;
+
+// Effects. Effects beyond F are identified by letter in the tracker.
+const FX_PORTAMENTO_UP: u8 = 0x1;
+const FX_PORTAMENTO_DOWN: u8 = 0x2;
+const FX_TONE_SLIDE: u8 = 0x3;
+const FX_TONE_VOL_SLIDE: u8 = 0x5;
+const FX_VOL_SLIDE: u8 = 0xA;
+const FX_SET_VOLUME: u8 = 0xC;
+const FX_JUMP_TO_LINE: u8 = 0xD;
+const FX_SET_SPEED: u8 = 0xF;
+const FX_IGNORE: u8 = b'I' - 55;
+const FX_MULTIPLIER: u8 = b'M' - 55;
+const FX_RIFF: u8 = b'R' - 55;
+const FX_TRANSPOSE: u8 = b'T' - 55;
+const FX_FEEDBACK: u8 = b'U' - 55;
+
+// F-numbers for C# through C, and the range they are kept within when sliding.
+const NOTE_FREQ: [u16; 12] = [
+    0x16B, 0x181, 0x198, 0x1B0, 0x1CA, 0x1E5, 0x202, 0x220, 0x241, 0x263, 0x287, 0x2AE,
+];
+const FREQ_MIN: u16 = 0x156;
+const FREQ_MAX: u16 = 0x2AE;
+
+// The first and second OPL3 channel used by each RAD channel. The pairs for channels 0-5 can be
+// combined into 4-op channels.
+const CHAN_OFFSETS: [u16; RAD_CHANNELS] = [
+    0x000, 0x001, 0x002, 0x100, 0x101, 0x102, 0x006, 0x007, 0x008,
+];
+const CHAN2_OFFSETS: [u16; RAD_CHANNELS] = [
+    0x003, 0x004, 0x005, 0x103, 0x104, 0x105, 0x106, 0x107, 0x108,
+];
+// Operator offsets for each RAD channel, starting with the final carrier.
+const OP_OFFSETS: [[u16; 4]; RAD_CHANNELS] = [
+    [0x00B, 0x008, 0x003, 0x000],
+    [0x00C, 0x009, 0x004, 0x001],
+    [0x00D, 0x00A, 0x005, 0x002],
+    [0x10B, 0x108, 0x103, 0x100],
+    [0x10C, 0x109, 0x104, 0x101],
+    [0x10D, 0x10A, 0x105, 0x102],
+    [0x113, 0x110, 0x013, 0x010],
+    [0x114, 0x111, 0x014, 0x011],
+    [0x115, 0x112, 0x015, 0x012],
+];
+// Which operators are carriers for each algorithm, and so are scaled by volume.
+const ALG_CARRIERS: [[bool; 4]; 7] = [
+    [true, false, false, false],
+    [true, true, false, false],
+    [true, false, false, false],
+    [true, false, false, true],
+    [true, false, true, false],
+    [true, false, true, true],
+    [true, true, true, true],
+];
+// Operator values used to silence the unused half of a channel pair for 2-op algorithms.
+const BLANK_OPERATOR: [u8; 5] = [0x00, 0x3F, 0x00, 0xF0, 0x00];
+
+// Riffs are transposed relative to this octave and note.
+const RIFF_TRANSPOSE_OCTAVE: u8 = 3;
+const RIFF_TRANSPOSE_NOTE: u8 = 12;
+// Instrument riffs can trigger instruments with riffs of their own.
+const MAX_RIFF_DEPTH: u8 = 4;
+
+/// The version of a RAD module.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RadVersion {
+    /// Reality AdLib Tracker 1.x modules.
+    V1_0,
+    /// Reality AdLib Tracker 2.x modules.
+    V2_1,
+}
+
+/// A single note entry in a pattern or riff.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RadNote {
+    /// The note, 1 (C#) to 12 (C), `RAD_NOTE_KEY_OFF`, or 0 for no note.
+    pub note: u8,
+    /// The octave of the note, 0 to 7.
+    pub octave: u8,
+    /// The instrument number, or 0 for none.
+    pub instrument: u8,
+    /// Whether the note retriggers the last instrument played on the channel.
+    pub last_instrument: bool,
+    /// The effect number.
+    pub effect: u8,
+    /// The effect parameter.
+    pub param: u8,
+}
+
+impl RadNote {
+    fn is_empty(&self) -> bool {
+        *self == RadNote::default()
+    }
+}
+
+/// A pattern or riff, consisting of `RAD_LINES` lines of `RAD_CHANNELS` notes. Riffs only use the
+/// first channel.
+#[derive(Clone, Debug)]
+pub struct RadTrack {
+    /// The notes of each line.
+    pub lines: Vec<[RadNote; RAD_CHANNELS]>,
+    /// The number of lines that contain data. Riffs end after this many lines.
+    pub length: usize,
+}
+
+/// A RAD instrument. Version 1.0 instruments are converted to the version 2.1 representation.
+#[derive(Clone, Debug)]
+pub struct RadInstrument {
+    /// The name of the instrument. Always empty for version 1.0 modules.
+    pub name: String,
+    /// The algorithm. 0 and 1 are 2-op FM and AM, 2 to 6 are 4-op algorithms, and 7 is a MIDI
+    /// instrument, which is not played.
+    pub algorithm: u8,
+    /// The panning of each half of the channel pair. 0 is center, 1 is left and 2 is right.
+    pub panning: [u8; 2],
+    /// The feedback of each half of the channel pair.
+    pub feedback: [u8; 2],
+    /// The detune between the two halves of the channel pair.
+    pub detune: u8,
+    /// The speed of the instrument riff.
+    pub riff_speed: u8,
+    /// The instrument volume, 0 to 64.
+    pub volume: u8,
+    /// The operator registers 0x20, 0x40, 0x60, 0x80 and 0xE0 for each operator, starting with
+    /// the final carrier.
+    pub operators: [[u8; 5]; 4],
+    /// The instrument riff, if any.
+    pub riff: Option<RadTrack>,
+}
+
+/// A parsed RAD module.
+#[derive(Clone, Debug)]
+pub struct RadFile {
+    /// The version of the module.
+    pub version: RadVersion,
+    /// The module description.
+    pub description: String,
+    /// The initial speed, in ticks per line.
+    pub speed: u8,
+    /// The tempo in beats per minute. Version 1.0 modules always use 125.
+    pub bpm: u16,
+    /// Whether the module uses the 18.2 Hz timer instead of the BPM-based timer.
+    pub slow_timer: bool,
+    /// The instruments, indexed by instrument number - 1.
+    pub instruments: Vec<Option<RadInstrument>>,
+    /// The order list. Entries with bit 7 set jump to the order given by the lower bits.
+    pub order_list: Vec<u8>,
+    /// The patterns, indexed by pattern number.
+    pub patterns: Vec<Option<RadTrack>>,
+    /// The riffs, indexed by riff number and then channel - 1.
+    pub riffs: Vec<Vec<Option<RadTrack>>>,
+}
+
+impl RadFile {
+    /// Parse a RAD module from a byte slice. Both version 1.0 and 2.1 modules are supported.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the RAD file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `RadFile` or an `OplError` on failure.
+    pub fn parse(data: &[u8]) -> Result<RadFile, OplError> {
+        let mut reader = ByteReader::new(data);
+        if reader.bytes(16)? != RAD_SIGNATURE {
+            return Err(OplError::BadSignature);
+        }
+        match reader.u8()? {
+            RAD_VERSION_1 => Self::parse_v1(data, reader),
+            RAD_VERSION_2 => Self::parse_v2(reader),
+            _ => Err(OplError::UnsupportedVersion),
+        }
+    }
+
+    fn parse_v1(data: &[u8], mut reader: ByteReader) -> Result<RadFile, OplError> {
+        let flags = reader.u8()?;
+        let description = if flags & RAD_FLAG_DESCRIPTION != 0 {
+            read_description(&mut reader)?
+        } else {
+            String::new()
+        };
+
+        let mut instruments = vec![None; 31];
+        loop {
+            let num = reader.u8()? as usize;
+            if num == 0 {
+                break;
+            }
+            let b = reader.bytes(11)?;
+            let instrument = RadInstrument {
+                name: String::new(),
+                algorithm: b[8] & 1,
+                panning: [0, 0],
+                feedback: [(b[8] >> 1) & 7, 0],
+                detune: 0,
+                riff_speed: 0,
+                volume: 64,
+                operators: [
+                    [b[0], b[2], b[4], b[6], b[9]],
+                    [b[1], b[3], b[5], b[7], b[10]],
+                    [0; 5],
+                    [0; 5],
+                ],
+                riff: None,
+            };
+            if let Some(slot) = instruments.get_mut(num - 1) {
+                *slot = Some(instrument);
+            }
+        }
+
+        let order_len = reader.u8()? as usize;
+        let order_list = reader.bytes(order_len)?.to_vec();
+
+        let mut patterns = Vec::with_capacity(RAD_V1_PATTERNS);
+        for _ in 0..RAD_V1_PATTERNS {
+            let offset = reader.u16_le()? as usize;
+            patterns.push(match offset {
+                0 => None,
+                _ if offset >= data.len() => None,
+                _ => Some(decode_track(&data[offset..], RadVersion::V1_0, false)?),
+            });
+        }
+
+        Ok(RadFile {
+            version: RadVersion::V1_0,
+            description,
+            speed: flags & RAD_FLAG_SPEED_MASK,
+            bpm: RAD_DEFAULT_BPM,
+            slow_timer: flags & RAD_FLAG_SLOW_TIMER != 0,
+            instruments,
+            order_list,
+            patterns,
+            riffs: Vec::new(),
+        })
+    }
+
+    fn parse_v2(mut reader: ByteReader) -> Result<RadFile, OplError> {
+        let flags = reader.u8()?;
+        let bpm = if flags & RAD_FLAG_BPM != 0 {
+            reader.u16_le()?
+        } else {
+            RAD_DEFAULT_BPM
+        };
+        let description = read_description(&mut reader)?;
+
+        let mut instruments = vec![None; 127];
+        loop {
+            let num = reader.u8()? as usize;
+            if num == 0 {
+                break;
+            }
+            let name_len = reader.u8()? as usize;
+            let name = String::from_utf8_lossy(reader.bytes(name_len)?).into_owned();
+            let alg = reader.u8()?;
+            let mut instrument = RadInstrument {
+                name,
+                algorithm: alg & 7,
+                panning: [(alg >> 3) & 3, (alg >> 5) & 3],
+                feedback: [0, 0],
+                detune: 0,
+                riff_speed: 0,
+                volume: 0,
+                operators: [[0; 5]; 4],
+                riff: None,
+            };
+            if instrument.algorithm < RAD_MIDI_ALGORITHM {
+                let b = reader.bytes(23)?;
+                instrument.feedback = [b[0] & 0x0F, b[0] >> 4];
+                instrument.detune = b[1] >> 4;
+                instrument.riff_speed = b[1] & 0x0F;
+                instrument.volume = b[2];
+                for (i, op) in instrument.operators.iter_mut().enumerate() {
+                    op.copy_from_slice(&b[3 + i * 5..8 + i * 5]);
+                }
+            } else {
+                // MIDI instruments are not played. Skip the port and channel, version and octave,
+                // program, bank LSB, bank MSB and volume.
+                reader.bytes(6)?;
+            }
+            if alg & 0x80 != 0 {
+                let size = reader.u16_le()? as usize;
+                instrument.riff = Some(decode_track(reader.bytes(size)?, RadVersion::V2_1, true)?);
+            }
+            if let Some(slot) = instruments.get_mut(num - 1) {
+                *slot = Some(instrument);
+            }
+        }
+
+        let order_len = reader.u8()? as usize;
+        let order_list = reader.bytes(order_len)?.to_vec();
+
+        let mut patterns = vec![None; RAD_V2_PATTERNS];
+        loop {
+            let num = reader.u8()? as usize;
+            if num >= RAD_V2_PATTERNS {
+                break;
+            }
+            let size = reader.u16_le()? as usize;
+            patterns[num] = Some(decode_track(reader.bytes(size)?, RadVersion::V2_1, false)?);
+        }
+
+        let mut riffs = vec![vec![None; RAD_CHANNELS]; RAD_RIFFS];
+        while reader.remaining() > 0 {
+            let id = reader.u8()?;
+            let (riff, channel) = ((id >> 4) as usize, (id & 0x0F) as usize);
+            if riff >= RAD_RIFFS || channel == 0 || channel > RAD_CHANNELS {
+                break;
+            }
+            let size = reader.u16_le()? as usize;
+            riffs[riff][channel - 1] =
+                Some(decode_track(reader.bytes(size)?, RadVersion::V2_1, true)?);
+        }
+
+        Ok(RadFile {
+            version: RadVersion::V2_1,
+            description,
+            speed: flags & RAD_FLAG_SPEED_MASK,
+            bpm,
+            slow_timer: flags & RAD_FLAG_SLOW_TIMER != 0,
+            instruments,
+            order_list,
+            patterns,
+            riffs,
+        })
+    }
+
+    /// Return the timer rate of the module in Hz.
+    pub fn tick_rate(&self) -> f64 {
+        if self.slow_timer {
+            RAD_SLOW_TIMER_HZ
+        } else if self.version == RadVersion::V1_0 {
+            RAD_FAST_TIMER_HZ
+        } else {
+            self.bpm.max(1) as f64 * 2.0 / 5.0
+        }
+    }
+
+    fn riff(&self, source: RiffSource) -> Option<&RadTrack> {
+        match source {
+            RiffSource::Song(riff, channel) => self.riffs.get(riff)?.get(channel)?.as_ref(),
+            RiffSource::Instrument(inst) => self.instruments.get(inst)?.as_ref()?.riff.as_ref(),
+        }
+    }
+}
+
+/// Read a null-terminated description. Byte 0x01 is a line break, and bytes 0x02 to 0x1F are runs
+/// of that many spaces.
+fn read_description(reader: &mut ByteReader) -> Result<String, OplError> {
+    let mut description = String::new();
+    loop {
+        match reader.u8()? {
+            0 => break,
+            1 => description.push('\n'),
+            n @ 2..=0x1F => description.extend(core::iter::repeat_n(' ', n as usize)),
+            c => description.push(c as char),
+        }
+    }
+    Ok(description)
+}
+
+/// Decode the packed lines of a pattern or riff.
+fn decode_track(data: &[u8], version: RadVersion, riff: bool) -> Result<RadTrack, OplError> {
+    let mut reader = ByteReader::new(data);
+    let mut track = RadTrack {
+        lines: vec![[RadNote::default(); RAD_CHANNELS]; RAD_LINES],
+        length: 0,
+    };
+
+    while reader.remaining() > 0 {
+        let line_id = reader.u8()?;
+        let line = (line_id & 0x7F) as usize;
+        if line >= RAD_LINES {
+            return Err(OplError::InvalidFile("RAD line number out of range"));
+        }
+        track.length = line + 1;
+
+        loop {
+            let channel_id = reader.u8()?;
+            let mut note = RadNote::default();
+            if version == RadVersion::V2_1 {
+                if channel_id & 0x40 != 0 {
+                    let n = reader.u8()?;
+                    note.note = n & 0x0F;
+                    note.octave = (n >> 4) & 0x07;
+                    note.last_instrument = n & 0x80 != 0;
+                }
+                if channel_id & 0x20 != 0 {
+                    note.instrument = reader.u8()?;
+                }
+                if channel_id & 0x10 != 0 {
+                    note.effect = reader.u8()?;
+                    note.param = reader.u8()?;
+                }
+            } else {
+                let b1 = reader.u8()?;
+                let b2 = reader.u8()?;
+                note.note = b1 & 0x0F;
+                note.octave = (b1 >> 4) & 0x07;
+                note.instrument = (b2 >> 4) | ((b1 & 0x80) >> 3);
+                note.effect = b2 & 0x0F;
+                if note.effect != 0 {
+                    note.param = reader.u8()?;
+                }
+            }
+
+            let channel = if riff {
+                0
+            } else {
+                (channel_id & 0x0F) as usize
+            };
+            if channel < RAD_CHANNELS {
+                track.lines[line][channel] = note;
+            }
+            if channel_id & 0x80 != 0 {
+                break;
+            }
+        }
+
+        if line_id & 0x80 != 0 {
+            break;
+        }
+    }
+    Ok(track)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RiffSource {
+    Song(usize, usize),
+    Instrument(usize),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum NoteSource {
+    Track,
+    Riff,
+    InstrumentRiff,
+}
+
+#[derive(Copy, Clone, Default)]
+struct RadEffects {
+    port_slide: i8,
+    vol_slide: i8,
+    tone_slide_freq: u16,
+    tone_slide_oct: u8,
+    tone_slide_speed: u8,
+    tone_slide_dir: i8,
+}
+
+#[derive(Copy, Clone, Default)]
+struct RadRiff {
+    fx: RadEffects,
+    source: Option<RiffSource>,
+    line: usize,
+    speed: u8,
+    speed_cnt: u8,
+    transpose_octave: u8,
+    transpose_note: u8,
+    last_instrument: u8,
+}
+
+#[derive(Copy, Clone, Default)]
+struct RadChannel {
+    last_instrument: u8,
+    instrument: u8,
+    volume: u8,
+    detune_a: u16,
+    detune_b: u16,
+    curr_freq: u16,
+    curr_octave: u8,
+    keyed_on: bool,
+    fx: RadEffects,
+    riff: RadRiff,
+    instrument_riff: RadRiff,
+}
+
+/// The `RadSequencer` plays a `RadFile` through an `Opl3Device`.
+pub struct RadSequencer {
+    file: RadFile,
+    channels: [RadChannel; RAD_CHANNELS],
+    order: usize,
+    line: usize,
+    speed: u8,
+    speed_cnt: u8,
+    line_jump: Option<usize>,
+    looped: bool,
+    repeat: bool,
+}
+
+/// A player for RAD modules.
+pub type RadPlayer = OplPlayer<RadSequencer>;
+
+impl RadSequencer {
+    /// Create a new sequencer for the given RAD module.
+    pub fn new(file: RadFile) -> Self {
+        let speed = file.speed.max(1);
+        RadSequencer {
+            file,
+            channels: [RadChannel::default(); RAD_CHANNELS],
+            order: 0,
+            line: 0,
+            speed,
+            speed_cnt: 1,
+            line_jump: None,
+            looped: false,
+            repeat: false,
+        }
+    }
+
+    /// Return the `RadFile` being played.
+    pub fn file(&self) -> &RadFile {
+        &self.file
+    }
+
+    /// Set whether the song repeats from its loop point when it reaches the end, rather than
+    /// finishing.
+    pub fn set_repeat(&mut self, repeat: bool) {
+        self.repeat = repeat;
+    }
+
+    /// Return the current position in the order list.
+    pub fn order_position(&self) -> usize {
+        self.order
+    }
+
+    /// Return the pattern number at the current position in the order list.
+    pub fn pattern(&self) -> u8 {
+        self.file.order_list.get(self.order).copied().unwrap_or(0) & !RAD_ORDER_JUMP
+    }
+
+    /// Return the next line of the current pattern to be played.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Return the current speed, in ticks per line.
+    pub fn speed(&self) -> u8 {
+        self.speed
+    }
+
+    /// Returns true once the song has reached its end and looped back.
+    pub fn has_looped(&self) -> bool {
+        self.looped
+    }
+
+    fn instrument(&self, inst: u8) -> Option<&RadInstrument> {
+        self.file
+            .instruments
+            .get((inst as usize).checked_sub(1)?)?
+            .as_ref()
+    }
+
+    fn update(&mut self, device: &mut Opl3Device) {
+        for ch in 0..RAD_CHANNELS {
+            self.tick_riff(device, ch, NoteSource::InstrumentRiff, 0);
+            self.tick_riff(device, ch, NoteSource::Riff, 0);
+        }
+
+        self.play_line(device);
+
+        for ch in 0..RAD_CHANNELS {
+            for source in [
+                NoteSource::InstrumentRiff,
+                NoteSource::Riff,
+                NoteSource::Track,
+            ] {
+                self.continue_fx(device, ch, source);
+            }
+        }
+    }
+
+    fn play_line(&mut self, device: &mut Opl3Device) {
+        if self.speed_cnt > 1 {
+            self.speed_cnt -= 1;
+            return;
+        }
+        self.speed_cnt = self.speed;
+
+        for chan in self.channels.iter_mut() {
+            chan.fx = RadEffects::default();
+        }
+        self.line_jump = None;
+
+        let pattern = self.pattern() as usize;
+        for ch in 0..RAD_CHANNELS {
+            let note = match self.file.patterns.get(pattern) {
+                Some(Some(track)) => track.lines[self.line][ch],
+                _ => RadNote::default(),
+            };
+            if !note.is_empty() {
+                self.play_note(device, ch, note, NoteSource::Track, 0);
+            }
+        }
+
+        self.line += 1;
+        if self.line >= RAD_LINES || self.line_jump.is_some() {
+            self.line = self.line_jump.take().unwrap_or(0);
+            self.next_order();
+        }
+    }
+
+    fn next_order(&mut self) {
+        self.order += 1;
+        if self.order >= self.file.order_list.len() {
+            self.order = 0;
+            self.looped = true;
+        }
+        self.resolve_order_jumps();
+    }
+
+    fn resolve_order_jumps(&mut self) {
+        for _ in 0..self.file.order_list.len() {
+            match self.file.order_list.get(self.order) {
+                Some(&entry) if entry & RAD_ORDER_JUMP != 0 => {
+                    let target = (entry & !RAD_ORDER_JUMP) as usize;
+                    if target <= self.order {
+                        self.looped = true;
+                    }
+                    self.order = if target < self.file.order_list.len() {
+                        target
+                    } else {
+                        0
+                    };
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn tick_riff(&mut self, device: &mut Opl3Device, ch: usize, source: NoteSource, depth: u8) {
+        let riff = self.riff_mut(ch, source);
+        let Some(riff_source) = riff.source else {
+            return;
+        };
+        if riff.speed_cnt > 1 {
+            riff.speed_cnt -= 1;
+            return;
+        }
+        riff.speed_cnt = riff.speed.max(1);
+        riff.fx = RadEffects::default();
+        let line = riff.line;
+        riff.line += 1;
+
+        let note = match self.file.riff(riff_source) {
+            Some(track) if line < track.length => track.lines[line][0],
+            _ => {
+                self.riff_mut(ch, source).source = None;
+                return;
+            }
+        };
+        if !note.is_empty() {
+            self.play_note(device, ch, note, source, depth + 1);
+        }
+    }
+
+    fn riff_mut(&mut self, ch: usize, source: NoteSource) -> &mut RadRiff {
+        match source {
+            NoteSource::InstrumentRiff => &mut self.channels[ch].instrument_riff,
+            _ => &mut self.channels[ch].riff,
+        }
+    }
+
+    fn fx_mut(&mut self, ch: usize, source: NoteSource) -> &mut RadEffects {
+        match source {
+            NoteSource::Track => &mut self.channels[ch].fx,
+            NoteSource::Riff => &mut self.channels[ch].riff.fx,
+            NoteSource::InstrumentRiff => &mut self.channels[ch].instrument_riff.fx,
+        }
+    }
+
+    fn play_note(
+        &mut self,
+        device: &mut Opl3Device,
+        ch: usize,
+        note: RadNote,
+        source: NoteSource,
+        depth: u8,
+    ) {
+        if depth > MAX_RIFF_DEPTH {
+            return;
+        }
+        let mut note_num = note.note;
+        let mut octave = note.octave;
+
+        // Resolve the instrument, tracking the last instrument used by this source.
+        let last = match source {
+            NoteSource::Track => &mut self.channels[ch].last_instrument,
+            _ => &mut self.riff_mut(ch, source).last_instrument,
+        };
+        let inst_num = if note.instrument != 0 {
+            *last = note.instrument;
+            note.instrument
+        } else if note.last_instrument {
+            *last
+        } else {
+            0
+        };
+
+        // Notes played by riffs are transposed by the note that started the riff.
+        if source != NoteSource::Track && (1..=12).contains(&note_num) {
+            let riff = *self.riff_mut(ch, source);
+            let semitones = (octave as i32 * 12 + note_num as i32 - 1)
+                + (riff.transpose_octave as i32 * 12 + riff.transpose_note as i32 - 1)
+                - (RIFF_TRANSPOSE_OCTAVE as i32 * 12 + RIFF_TRANSPOSE_NOTE as i32 - 1);
+            let semitones = semitones.clamp(0, 8 * 12 - 1);
+            octave = (semitones / 12) as u8;
+            note_num = (semitones % 12) as u8 + 1;
+        }
+
+        let mut key_off = note_num == RAD_NOTE_KEY_OFF;
+        let mut key_on = false;
+
+        if inst_num > 0 {
+            let old_inst = self.channels[ch].instrument;
+            let Some(inst) = self.instrument(inst_num) else {
+                return;
+            };
+            if inst.algorithm == RAD_MIDI_ALGORITHM {
+                return;
+            }
+            let riff_speed = inst.riff_speed;
+            let has_riff = inst.riff.is_some();
+
+            self.channels[ch].instrument = inst_num;
+            self.load_instrument(device, ch);
+            key_off = true;
+            key_on = true;
+            self.channels[ch].instrument_riff.fx = RadEffects::default();
+
+            let ignore_riff = note.effect == FX_IGNORE;
+            if !ignore_riff && (source != NoteSource::InstrumentRiff || inst_num != old_inst) {
+                if has_riff && riff_speed > 0 {
+                    let (t_oct, t_note) = Self::take_transpose(&mut note_num, octave);
+                    self.channels[ch].instrument_riff = RadRiff {
+                        source: Some(RiffSource::Instrument(inst_num as usize - 1)),
+                        speed: riff_speed,
+                        speed_cnt: 1,
+                        transpose_octave: t_oct,
+                        transpose_note: t_note,
+                        ..Default::default()
+                    };
+                    self.tick_riff(device, ch, NoteSource::InstrumentRiff, depth);
+                } else {
+                    self.channels[ch].instrument_riff.source = None;
+                }
+            }
+        }
+
+        if note.effect == FX_RIFF || note.effect == FX_TRANSPOSE {
+            let (riff, channel) = ((note.param / 10) as usize, (note.param % 10) as usize);
+            let riff_source = RiffSource::Song(riff, channel.wrapping_sub(1));
+            self.channels[ch].riff = RadRiff::default();
+            if channel > 0 && self.file.riff(riff_source).is_some() {
+                let (t_oct, t_note) = if note.effect == FX_TRANSPOSE {
+                    Self::take_transpose(&mut note_num, octave)
+                } else {
+                    (RIFF_TRANSPOSE_OCTAVE, RIFF_TRANSPOSE_NOTE)
+                };
+                self.channels[ch].riff = RadRiff {
+                    source: Some(riff_source),
+                    speed: self.speed,
+                    speed_cnt: 1,
+                    transpose_octave: t_oct,
+                    transpose_note: t_note,
+                    ..Default::default()
+                };
+                self.tick_riff(device, ch, NoteSource::Riff, depth);
+            }
+        }
+
+        let tone_slide = note.effect == FX_TONE_SLIDE || note.effect == FX_TONE_VOL_SLIDE;
+        if (1..=12).contains(&note_num) {
+            if tone_slide {
+                let fx = self.fx_mut(ch, source);
+                fx.tone_slide_freq = NOTE_FREQ[note_num as usize - 1];
+                fx.tone_slide_oct = octave;
+            } else {
+                let chan = &mut self.channels[ch];
+                chan.curr_freq = NOTE_FREQ[note_num as usize - 1];
+                chan.curr_octave = octave;
+                key_off = true;
+                key_on = true;
+            }
+        }
+
+        if key_off && self.channels[ch].keyed_on {
+            self.channels[ch].keyed_on = false;
+            for offset in [CHAN_OFFSETS[ch], CHAN2_OFFSETS[ch]] {
+                let b0 = read_reg(device, 0xB0 + offset);
+                write_reg(device, 0xB0 + offset, b0 & !0x20);
+            }
+        }
+        if key_on {
+            self.channels[ch].keyed_on = true;
+            self.set_freq(device, ch);
+        }
+
+        self.process_effect(device, ch, note, source, tone_slide);
+    }
+
+    /// Consume a note given with a riff command as the riff's transpose.
+    fn take_transpose(note_num: &mut u8, octave: u8) -> (u8, u8) {
+        if (1..=12).contains(note_num) {
+            let transpose = (octave, *note_num);
+            *note_num = 0;
+            transpose
+        } else {
+            (RIFF_TRANSPOSE_OCTAVE, RIFF_TRANSPOSE_NOTE)
+        }
+    }
+
+    fn process_effect(
+        &mut self,
+        device: &mut Opl3Device,
+        ch: usize,
+        note: RadNote,
+        source: NoteSource,
+        tone_slide: bool,
+    ) {
+        let param = note.param;
+        match note.effect {
+            FX_PORTAMENTO_UP => self.fx_mut(ch, source).port_slide = param.min(127) as i8,
+            FX_PORTAMENTO_DOWN => self.fx_mut(ch, source).port_slide = -(param.min(127) as i8),
+            FX_TONE_SLIDE | FX_TONE_VOL_SLIDE => {
+                if note.effect == FX_TONE_VOL_SLIDE {
+                    self.fx_mut(ch, source).vol_slide = Self::vol_slide(param);
+                } else if param > 0 {
+                    self.fx_mut(ch, source).tone_slide_speed = param;
+                }
+                if tone_slide {
+                    self.set_slide_dir(ch, source);
+                }
+            }
+            FX_VOL_SLIDE => self.fx_mut(ch, source).vol_slide = Self::vol_slide(param),
+            FX_SET_VOLUME => self.set_volume(device, ch, param),
+            FX_JUMP_TO_LINE if source == NoteSource::Track && (param as usize) < RAD_LINES => {
+                self.line_jump = Some(param as usize);
+            }
+            FX_SET_SPEED if param > 0 => match source {
+                NoteSource::Track => self.speed = param,
+                _ => self.riff_mut(ch, source).speed = param,
+            },
+            FX_MULTIPLIER => {
+                let (op, value) = (param / 10, param % 10);
+                if (1..=4).contains(&op) {
+                    let reg = 0x20 + OP_OFFSETS[ch][op as usize - 1];
+                    let old = read_reg(device, reg);
+                    write_reg(device, reg, (old & 0xF0) | value);
+                }
+            }
+            FX_FEEDBACK => {
+                let (which, value) = (param / 10, param % 10);
+                let reg = 0xC0
+                    + match which {
+                        0 => CHAN2_OFFSETS[ch],
+                        _ => CHAN_OFFSETS[ch],
+                    };
+                let old = read_reg(device, reg);
+                write_reg(device, reg, (old & !0x0E) | ((value & 7) << 1));
+            }
+            _ => {}
+        }
+    }
+
+    /// Volume slide parameters of 1-49 slide down, and 51-99 slide up.
+    fn vol_slide(param: u8) -> i8 {
+        if param >= 50 {
+            -((param - 50).min(127) as i8)
+        } else {
+            param as i8
+        }
+    }
+
+    fn set_slide_dir(&mut self, ch: usize, source: NoteSource) {
+        let (freq, oct) = (self.channels[ch].curr_freq, self.channels[ch].curr_octave);
+        let fx = self.fx_mut(ch, source);
+        let speed = fx.tone_slide_speed.min(127) as i8;
+        let target = (fx.tone_slide_oct, fx.tone_slide_freq);
+        fx.tone_slide_dir = match target.cmp(&(oct, freq)) {
+            core::cmp::Ordering::Greater => speed,
+            core::cmp::Ordering::Less => -speed,
+            core::cmp::Ordering::Equal => 0,
+        };
+    }
+
+    fn continue_fx(&mut self, device: &mut Opl3Device, ch: usize, source: NoteSource) {
+        let fx = *self.fx_mut(ch, source);
+        if fx.port_slide != 0 {
+            self.portamento(device, ch, fx.port_slide, None);
+        }
+        if fx.vol_slide != 0 {
+            let volume = (self.channels[ch].volume as i16 - fx.vol_slide as i16).clamp(0, 64);
+            self.set_volume(device, ch, volume as u8);
+        }
+        if fx.tone_slide_dir != 0 {
+            let target = (fx.tone_slide_oct, fx.tone_slide_freq);
+            self.portamento(device, ch, fx.tone_slide_dir, Some(target));
+        }
+    }
+
+    fn portamento(
+        &mut self,
+        device: &mut Opl3Device,
+        ch: usize,
+        amount: i8,
+        target: Option<(u8, u16)>,
+    ) {
+        let chan = &mut self.channels[ch];
+        let mut freq = chan.curr_freq as i32 + amount as i32;
+        let mut oct = chan.curr_octave;
+
+        if freq < FREQ_MIN as i32 {
+            if oct > 0 {
+                oct -= 1;
+                freq += (FREQ_MAX - FREQ_MIN) as i32;
+            } else {
+                freq = FREQ_MIN as i32;
+            }
+        } else if freq > FREQ_MAX as i32 {
+            if oct < 7 {
+                oct += 1;
+                freq -= (FREQ_MAX - FREQ_MIN) as i32;
+            } else {
+                freq = FREQ_MAX as i32;
+            }
+        }
+
+        // Tone slides stop when they reach their target note.
+        if let Some((target_oct, target_freq)) = target {
+            let pos = (oct, freq as u16);
+            let target = (target_oct, target_freq);
+            if (amount > 0 && pos >= target) || (amount < 0 && pos <= target) {
+                oct = target_oct;
+                freq = target_freq as i32;
+            }
+        }
+
+        chan.curr_freq = freq as u16;
+        chan.curr_octave = oct;
+        self.set_freq(device, ch);
+    }
+
+    fn set_freq(&mut self, device: &mut Opl3Device, ch: usize) {
+        let chan = &self.channels[ch];
+        let key = if chan.keyed_on { 0x20 } else { 0 };
+        let block = (chan.curr_octave & 7) << 2;
+        for (offset, freq) in [
+            (
+                CHAN_OFFSETS[ch],
+                chan.curr_freq.saturating_sub(chan.detune_a),
+            ),
+            (CHAN2_OFFSETS[ch], chan.curr_freq + chan.detune_b),
+        ] {
+            write_reg(device, 0xA0 + offset, freq as u8);
+            write_reg(device, 0xB0 + offset, ((freq >> 8) as u8 & 3) | block | key);
+        }
+    }
+
+    fn load_instrument(&mut self, device: &mut Opl3Device, ch: usize) {
+        let Some(inst) = self.instrument(self.channels[ch].instrument).cloned() else {
+            return;
+        };
+        let alg = inst.algorithm as usize;
+        let chan = &mut self.channels[ch];
+        chan.volume = inst.volume;
+        chan.detune_a = (inst.detune as u16 + 1) >> 1;
+        chan.detune_b = inst.detune as u16 >> 1;
+
+        // Algorithms 2 and 3 use the OPL3's 4-op mode. Algorithms 4 to 6 are played with the two
+        // halves of the channel pair as separate 2-op channels.
+        if ch < 6 {
+            let mask = 1 << ch;
+            let four_op = if alg == 2 || alg == 3 { mask } else { 0 };
+            let old = read_reg(device, 0x104);
+            write_reg(device, 0x104, (old & !mask) | four_op);
+        }
+
+        let cnt1 = (alg == 3 || alg == 5 || alg == 6) as u8;
+        let cnt2 = (alg == 1 || alg == 6) as u8;
+        write_reg(
+            device,
+            0xC0 + CHAN_OFFSETS[ch],
+            ((inst.panning[1] ^ 3) << 4) | (inst.feedback[1] & 7) << 1 | cnt1,
+        );
+        write_reg(
+            device,
+            0xC0 + CHAN2_OFFSETS[ch],
+            ((inst.panning[0] ^ 3) << 4) | (inst.feedback[0] & 7) << 1 | cnt2,
+        );
+
+        for (i, &reg) in OP_OFFSETS[ch].iter().enumerate() {
+            let op = if alg < 2 && i >= 2 {
+                BLANK_OPERATOR
+            } else {
+                inst.operators[i]
+            };
+            write_reg(device, 0x20 + reg, op[0]);
+            write_reg(
+                device,
+                0x40 + reg,
+                Self::scale_level(op[1], alg, i, inst.volume),
+            );
+            write_reg(device, 0x60 + reg, op[2]);
+            write_reg(device, 0x80 + reg, op[3]);
+            write_reg(device, 0xE0 + reg, op[4]);
+        }
+    }
+
+    /// Scale the total level of an operator by the volume, if it is a carrier.
+    fn scale_level(level: u8, alg: usize, op: usize, volume: u8) -> u8 {
+        let mut vol = (!level & 0x3F) as u32;
+        if ALG_CARRIERS.get(alg).is_some_and(|c| c[op]) {
+            vol = vol * volume.min(64) as u32 / 64;
+        }
+        (level & 0xC0) | ((vol as u8 ^ 0x3F) & 0x3F)
+    }
+
+    fn set_volume(&mut self, device: &mut Opl3Device, ch: usize, volume: u8) {
+        let volume = volume.min(64);
+        self.channels[ch].volume = volume;
+        let Some(inst) = self.instrument(self.channels[ch].instrument) else {
+            return;
+        };
+        let alg = inst.algorithm as usize;
+        let levels: Vec<(usize, u8)> = (0..4)
+            .filter(|&i| ALG_CARRIERS.get(alg).is_some_and(|c| c[i]))
+            .map(|i| (i, Self::scale_level(inst.operators[i][1], alg, i, volume)))
+            .collect();
+        for (i, level) in levels {
+            write_reg(device, 0x40 + OP_OFFSETS[ch][i], level);
+        }
+    }
+}
+
+impl OplSequencer for RadSequencer {
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64> {
+        // Finish at the first line after the song loops, unless repeating.
+        if self.looped && !self.repeat && self.speed_cnt <= 1 {
+            return None;
+        }
+        self.update(device);
+        Some(1_000_000.0 / self.file.tick_rate())
+    }
+
+    fn rewind(&mut self, device: &mut Opl3Device) {
+        self.channels = [RadChannel::default(); RAD_CHANNELS];
+        self.order = 0;
+        self.line = 0;
+        self.speed = self.file.speed.max(1);
+        self.speed_cnt = 1;
+        self.line_jump = None;
+        self.looped = false;
+        self.resolve_order_jumps();
+        self.looped = false;
+
+        // Enable OPL3 mode and waveform selection.
+        write_reg(device, 0x105, 0x01);
+        write_reg(device, 0x001, 0x20);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v1_module() -> Vec<u8> {
+        let mut data = RAD_SIGNATURE.to_vec();
+        data.push(RAD_VERSION_1);
+        data.push(RAD_FLAG_DESCRIPTION | 2);
+        data.extend_from_slice(b"Hi\x01\x03there\0");
+        // Instrument 1
+        data.push(1);
+        data.extend_from_slice(&[0x01, 0x01, 0x00, 0x10, 0xF0, 0xF0, 0x77, 0x77, 0x00, 0, 0]);
+        data.push(0);
+        // Order list: pattern 0, then jump back to order 0.
+        data.extend_from_slice(&[2, 0x00, 0x80]);
+        let pattern_offset = data.len() + RAD_V1_PATTERNS * 2;
+        data.extend_from_slice(&(pattern_offset as u16).to_le_bytes());
+        data.extend_from_slice(&[0; (RAD_V1_PATTERNS - 1) * 2]);
+        // Line 0: channel 0 plays C-4 with instrument 1. Line 1: jump to line 0 of next order.
+        data.extend_from_slice(&[0x00, 0x80, 0x4C, 0x10]);
+        data.extend_from_slice(&[0x81, 0x80, 0x00, 0x0D, 0x00]);
+        data
+    }
+
+    #[test]
+    fn parse_v1() {
+        let rad = RadFile::parse(&v1_module()).unwrap();
+        assert_eq!(rad.version, RadVersion::V1_0);
+        assert_eq!(rad.description, "Hi\n   there");
+        assert_eq!(rad.speed, 2);
+        assert!(rad.instruments[0].is_some());
+        let pattern = rad.patterns[0].as_ref().unwrap();
+        assert_eq!(pattern.length, 2);
+        assert_eq!(
+            pattern.lines[0][0],
+            RadNote {
+                note: 12,
+                octave: 4,
+                instrument: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(pattern.lines[1][0].effect, FX_JUMP_TO_LINE);
+    }
+
+    #[test]
+    fn play_until_loop() {
+        let rad = RadFile::parse(&v1_module()).unwrap();
+        let mut player = RadPlayer::new(RadSequencer::new(rad), 50 * 100);
+        let samples = player.render_to_vec(1_000_000).unwrap();
+        // Two lines at speed 2, with 100 samples per tick.
+        assert_eq!(samples.len() / 2, 4 * 100);
+        assert!(player.sequencer().has_looped());
+        assert!(samples.iter().any(|&s| s != 0));
+        assert_eq!(
+            player
+                .device()
+                .read_register(0x05, crate::OplRegisterFile::Secondary),
+            1
+        );
+    }
+
+    #[test]
+    fn parse_v2_with_riff() {
+        let mut data = RAD_SIGNATURE.to_vec();
+        data.push(RAD_VERSION_2);
+        data.push(RAD_FLAG_BPM | 6);
+        data.extend_from_slice(&150u16.to_le_bytes());
+        data.extend_from_slice(b"Desc\0");
+        data.extend_from_slice(&[1, 4]);
+        data.extend_from_slice(b"Bass");
+        data.push(0x80 | 0x02);
+        data.extend_from_slice(&[0x21, 0x32, 48]);
+        data.extend_from_slice(&[0; 20]);
+        // Instrument riff: a single line playing D-3.
+        data.extend_from_slice(&[3, 0, 0x80, 0xC0, 0x32]);
+        data.push(0);
+        data.extend_from_slice(&[1, 0]);
+        // Pattern 0: line 0, channel 2 plays C#-5 with instrument 1 and effect M(12).
+        data.extend_from_slice(&[0, 6, 0]);
+        data.extend_from_slice(&[0x80, 0xF2, 0x51, 1, FX_MULTIPLIER, 12]);
+        data.push(0xFF);
+        // Riff 1 on channel 3.
+        data.extend_from_slice(&[0x13, 2, 0, 0x80, 0x80]);
+        data.push(0xFF);
+
+        let rad = RadFile::parse(&data).unwrap();
+        assert_eq!(rad.version, RadVersion::V2_1);
+        assert_eq!(rad.bpm, 150);
+        assert_eq!(rad.tick_rate(), 60.0);
+        let inst = rad.instruments[0].as_ref().unwrap();
+        assert_eq!(inst.name, "Bass");
+        assert_eq!(inst.algorithm, 2);
+        assert_eq!(inst.feedback, [1, 2]);
+        assert_eq!((inst.detune, inst.riff_speed, inst.volume), (3, 2, 48));
+        assert_eq!(inst.riff.as_ref().unwrap().lines[0][0].note, 2);
+        let note = rad.patterns[0].as_ref().unwrap().lines[0][2];
+        assert_eq!((note.note, note.octave, note.instrument), (1, 5, 1));
+        assert!(rad.riffs[1][2].is_some());
+
+        let mut player = RadPlayer::new(RadSequencer::new(rad), 44100);
+        player.render_to_vec(44100).unwrap();
+        // Instrument riff transposes D-3 by C#-5 relative to C-3, giving C#-5 + 2 semitones.
+        let device = player.device();
+        assert_eq!(
+            device.read_register(0x04, crate::OplRegisterFile::Secondary) & 0x04,
+            0x04
+        );
+        assert_eq!(
+            device.read_register(0x20 + 0x0D, crate::OplRegisterFile::Primary) & 0x0F,
+            2
+        );
+    }
+    #[test]
+    fn parse_v2_midi_instrument() {
+        let mut data = RAD_SIGNATURE.to_vec();
+        data.push(RAD_VERSION_2);
+        data.push(6);
+        data.push(0);
+        // Instrument 1 is a MIDI instrument, instrument 2 an OPL3 one.
+        data.extend_from_slice(&[1, 4]);
+        data.extend_from_slice(b"Midi");
+        data.push(RAD_MIDI_ALGORITHM);
+        data.extend_from_slice(&[0x01, 0x04, 0x20, 0x00, 0x00, 0x40]);
+        data.extend_from_slice(&[2, 4]);
+        data.extend_from_slice(b"Lead");
+        data.push(0x01);
+        data.extend_from_slice(&[0x10, 0x00, 40]);
+        data.extend_from_slice(&[0x21; 20]);
+        data.push(0);
+        data.extend_from_slice(&[1, 0]);
+        // Pattern 0: line 0, channel 0 plays C-4 with instrument 2.
+        data.extend_from_slice(&[0, 4, 0]);
+        data.extend_from_slice(&[0x80, 0xE0, 0x4C, 2]);
+        data.push(0xFF);
+        data.push(0);
+
+        let rad = RadFile::parse(&data).unwrap();
+        let midi = rad.instruments[0].as_ref().unwrap();
+        assert_eq!(
+            (midi.name.as_str(), midi.algorithm),
+            ("Midi", RAD_MIDI_ALGORITHM)
+        );
+        assert!(midi.riff.is_none());
+        let lead = rad.instruments[1].as_ref().unwrap();
+        assert_eq!((lead.name.as_str(), lead.algorithm), ("Lead", 1));
+        assert_eq!((lead.feedback, lead.volume), ([0, 1], 40));
+        assert_eq!(lead.operators, [[0x21; 5]; 4]);
+        assert_eq!(rad.order_list, [0]);
+        let note = rad.patterns[0].as_ref().unwrap().lines[0][0];
+        assert_eq!((note.note, note.octave, note.instrument), (12, 4, 2));
+    }
+}
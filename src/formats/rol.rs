@@ -0,0 +1,758 @@
+//! Parser and sequencer for AdLib Visual Composer songs (ROL), and the AdLib instrument bank
+//! files (BNK) that they use.
+//!
+//! A ROL file contains a global tempo track, and for each voice a track of notes and tracks of
+//! instrument changes, volume changes and pitch bends. Instruments are referred to by name, and
+//! must be resolved through an instrument bank supplied by the caller, which is usually the
+//! `STANDARD.BNK` file distributed with Visual Composer. Any type implementing `RolBank` can be
+//! used as a bank, and `BnkFile` implements it for AdLib bank files.
+//!
+//! Songs are played in either melodic mode, with 9 melodic voices, or percussive mode, with 6
+//! melodic voices followed by the bass drum, snare drum, tom-tom, cymbal and hi-hat. In percussive
+//! mode the snare drum is tuned 7 semitones above the tom-tom, and the cymbal and hi-hat have no
+//! pitch of their own.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::formats::rol::{BnkFile, RolFile, RolPlayer, RolSequencer};
+//!
+//! let bank = BnkFile::parse(&std::fs::read("STANDARD.BNK").unwrap()).unwrap();
+//! let rol = RolFile::parse(&std::fs::read("song.rol").unwrap()).unwrap();
+//! let mut player = RolPlayer::new(RolSequencer::new(rol, &bank), 44100);
+//! let samples = player.render_to_vec(44100 * 600).unwrap();
+//! ```
+
+use crate::formats::{note_fnum, read_reg, write_reg, ByteReader};
+use crate::player::{OplPlayer, OplSequencer};
+use crate::rhythm::OplDrum;
+use crate::{Opl3Device, OplError};
+use alloc::{string::String, vec, vec::Vec};
+
+/// The signature of an AdLib instrument bank file, following its version number.
+pub const BNK_SIGNATURE: &[u8; 6] = b"ADLIB-";
+/// The number of voices in a melodic mode song.
+pub const ROL_MELODIC_VOICES: usize = 9;
+/// The number of voices in a percussive mode song.
+pub const ROL_PERCUSSIVE_VOICES: usize = 11;
+
+const ROL_VERSION_MAJOR: u16 = 0;
+const ROL_VERSION_MINOR: u16 = 4;
+const ROL_TRACK_NAME_SIZE: usize = 15;
+const ROL_INSTRUMENT_NAME_SIZE: usize = 9;
+/// The offset of the basic tempo in the header.
+const ROL_TEMPO_OFFSET: usize = 197;
+const ROL_MAX_VOLUME: u32 = 0x7F;
+/// The number of melodic voices in percussive mode.
+const ROL_PERCUSSIVE_MELODIC_VOICES: usize = 6;
+/// The interval between the tom-tom and the snare drum, in semitones.
+const ROL_TOM_TO_SNARE: f64 = 7.0;
+const BNK_NAME_ENTRY_SIZE: usize = 12;
+const BNK_INSTRUMENT_SIZE: usize = 30;
+
+const OP_OFFSETS: [u16; 9] = [0x00, 0x01, 0x02, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x12];
+
+/// An OPL2 instrument from an AdLib instrument bank.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BnkInstrument {
+    /// True if the instrument is intended for a percussion voice.
+    pub percussive: bool,
+    /// The percussion voice the instrument is intended for.
+    pub voice: u8,
+    /// The modulator and carrier registers 0x20, 0x40, 0x60, 0x80 and 0xE0, in that order,
+    /// followed by the feedback and connection register 0xC0.
+    pub registers: [u8; 11],
+}
+
+/// A source of instruments for ROL songs, looked up by name.
+pub trait RolBank {
+    /// Return the instrument with the given name. Names should be matched without regard to
+    /// case.
+    fn find(&self, name: &str) -> Option<BnkInstrument>;
+}
+
+/// A parsed AdLib instrument bank (BNK) file.
+#[derive(Clone, Debug)]
+pub struct BnkFile {
+    /// The format version, as major and minor numbers.
+    pub version: (u8, u8),
+    /// The named instruments in the bank.
+    pub instruments: Vec<(String, BnkInstrument)>,
+}
+
+impl BnkFile {
+    /// Parse an AdLib instrument bank from a byte slice.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the BNK file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `BnkFile` or an `OplError` on failure.
+    pub fn parse(data: &[u8]) -> Result<BnkFile, OplError> {
+        let mut reader = ByteReader::new(data);
+        let version = (reader.u8()?, reader.u8()?);
+        if reader.bytes(BNK_SIGNATURE.len())? != BNK_SIGNATURE {
+            return Err(OplError::BadSignature);
+        }
+        let _used = reader.u16_le()?;
+        let count = reader.u16_le()? as usize;
+        let names_offset = reader.u32_le()? as usize;
+        let data_offset = reader.u32_le()? as usize;
+
+        let mut instruments = Vec::with_capacity(count);
+        for i in 0..count {
+            reader.seek(names_offset + i * BNK_NAME_ENTRY_SIZE)?;
+            let index = reader.u16_le()? as usize;
+            let _flags = reader.u8()?;
+            let name = c_string(reader.bytes(ROL_INSTRUMENT_NAME_SIZE)?);
+
+            reader.seek(data_offset + index * BNK_INSTRUMENT_SIZE)?;
+            let record = reader.bytes(BNK_INSTRUMENT_SIZE)?;
+            instruments.push((name, parse_bnk_instrument(record)));
+        }
+        Ok(BnkFile {
+            version,
+            instruments,
+        })
+    }
+}
+
+impl RolBank for BnkFile {
+    fn find(&self, name: &str) -> Option<BnkInstrument> {
+        self.instruments
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, inst)| inst)
+    }
+}
+
+/// Convert a BNK instrument record, which stores each register field in a separate byte.
+fn parse_bnk_instrument(record: &[u8]) -> BnkInstrument {
+    // The fields of each operator, in order: key scale level, multiplier, feedback, attack,
+    // sustain level, sustaining sound, decay, release, output level, tremolo, vibrato, key scale
+    // rate and connection.
+    let operator = |op: &[u8], wave: u8| {
+        [
+            ((op[9] & 1) << 7)
+                | ((op[10] & 1) << 6)
+                | ((op[5] & 1) << 5)
+                | ((op[11] & 1) << 4)
+                | (op[1] & 0x0F),
+            ((op[0] & 3) << 6) | (op[8] & 0x3F),
+            ((op[3] & 0x0F) << 4) | (op[6] & 0x0F),
+            ((op[4] & 0x0F) << 4) | (op[7] & 0x0F),
+            wave & 3,
+        ]
+    };
+    let modulator = operator(&record[2..15], record[28]);
+    let carrier = operator(&record[15..28], record[29]);
+
+    let mut registers = [0u8; 11];
+    for i in 0..5 {
+        registers[i * 2] = modulator[i];
+        registers[i * 2 + 1] = carrier[i];
+    }
+    registers[10] = ((record[4] & 7) << 1) | ((record[14] & 1) ^ 1);
+    BnkInstrument {
+        percussive: record[0] != 0,
+        voice: record[1],
+        registers,
+    }
+}
+
+fn c_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// The voice arrangement of a ROL song.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RolMode {
+    /// 6 melodic voices and 5 percussion voices.
+    Percussive,
+    /// 9 melodic voices.
+    Melodic,
+}
+
+/// A note in a voice's note track.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RolNote {
+    /// The MIDI note number, where 60 is middle C, or 0 for silence.
+    pub note: u8,
+    /// The duration of the note in ticks.
+    pub duration: u16,
+}
+
+/// A timed change of tempo, volume or pitch.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RolEvent {
+    /// The tick at which the change takes effect.
+    pub time: u16,
+    /// The new value. For tempo events, a multiplier of the basic tempo. For volume events, the
+    /// volume from 0.0 to 1.0. For pitch events, the pitch bend from 0.0 to 2.0, where 1.0 is no
+    /// bend and the range is one semitone in either direction.
+    pub value: f32,
+}
+
+/// A timed instrument change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RolInstrumentEvent {
+    /// The tick at which the change takes effect.
+    pub time: u16,
+    /// The name of the instrument in the bank.
+    pub name: String,
+}
+
+/// The tracks of a single voice.
+#[derive(Clone, Debug, Default)]
+pub struct RolVoice {
+    /// The notes, played one after the other.
+    pub notes: Vec<RolNote>,
+    /// The instrument changes.
+    pub instruments: Vec<RolInstrumentEvent>,
+    /// The volume changes.
+    pub volumes: Vec<RolEvent>,
+    /// The pitch bends.
+    pub pitches: Vec<RolEvent>,
+}
+
+impl RolVoice {
+    /// Return the total duration of the voice's notes in ticks.
+    pub fn length_ticks(&self) -> u32 {
+        self.notes.iter().map(|n| n.duration as u32).sum()
+    }
+}
+
+/// A parsed ROL file.
+#[derive(Clone, Debug)]
+pub struct RolFile {
+    /// The number of ticks per beat.
+    pub ticks_per_beat: u16,
+    /// The number of beats per measure.
+    pub beats_per_measure: u16,
+    /// The voice arrangement.
+    pub mode: RolMode,
+    /// The basic tempo in beats per minute.
+    pub basic_tempo: f32,
+    /// The tempo changes.
+    pub tempo_events: Vec<RolEvent>,
+    /// The voices. There are 9 voices in melodic mode and 11 in percussive mode.
+    pub voices: Vec<RolVoice>,
+}
+
+impl RolFile {
+    /// Parse a ROL file from a byte slice.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the ROL file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `RolFile` or an `OplError` on failure.
+    pub fn parse(data: &[u8]) -> Result<RolFile, OplError> {
+        let mut reader = ByteReader::new(data);
+        if reader.u16_le()? != ROL_VERSION_MAJOR || reader.u16_le()? != ROL_VERSION_MINOR {
+            return Err(OplError::UnsupportedVersion);
+        }
+        reader.bytes(40)?;
+        let ticks_per_beat = reader.u16_le()?;
+        let beats_per_measure = reader.u16_le()?;
+        let _edit_scale = (reader.u16_le()?, reader.u16_le()?);
+        reader.u8()?;
+        let mode = match reader.u8()? {
+            0 => RolMode::Percussive,
+            _ => RolMode::Melodic,
+        };
+        reader.seek(ROL_TEMPO_OFFSET)?;
+        let basic_tempo = reader.f32_le()?;
+        if ticks_per_beat == 0 || basic_tempo.is_nan() || basic_tempo <= 0.0 {
+            return Err(OplError::InvalidFile("ROL tempo is zero"));
+        }
+        let tempo_events = read_events(&mut reader)?;
+
+        let voice_count = match mode {
+            RolMode::Percussive => ROL_PERCUSSIVE_VOICES,
+            RolMode::Melodic => ROL_MELODIC_VOICES,
+        };
+        let mut voices = Vec::with_capacity(voice_count);
+        for _ in 0..voice_count {
+            let mut voice = RolVoice::default();
+
+            reader.bytes(ROL_TRACK_NAME_SIZE)?;
+            let length = reader.u16_le()? as u32;
+            let mut total = 0;
+            while total < length {
+                let note = reader.u16_le()?;
+                let duration = reader.u16_le()?;
+                total += duration as u32;
+                voice.notes.push(RolNote {
+                    note: note.min(127) as u8,
+                    duration,
+                });
+            }
+
+            reader.bytes(ROL_TRACK_NAME_SIZE)?;
+            let count = reader.u16_le()?;
+            for _ in 0..count {
+                let time = reader.u16_le()?;
+                let name = c_string(reader.bytes(ROL_INSTRUMENT_NAME_SIZE)?);
+                reader.bytes(3)?;
+                voice.instruments.push(RolInstrumentEvent { time, name });
+            }
+
+            reader.bytes(ROL_TRACK_NAME_SIZE)?;
+            voice.volumes = read_events(&mut reader)?;
+            reader.bytes(ROL_TRACK_NAME_SIZE)?;
+            voice.pitches = read_events(&mut reader)?;
+            voices.push(voice);
+        }
+
+        Ok(RolFile {
+            ticks_per_beat,
+            beats_per_measure,
+            mode,
+            basic_tempo,
+            tempo_events,
+            voices,
+        })
+    }
+
+    /// Return the length of the song in ticks, which is that of its longest voice.
+    pub fn length_ticks(&self) -> u32 {
+        self.voices
+            .iter()
+            .map(RolVoice::length_ticks)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+fn read_events(reader: &mut ByteReader) -> Result<Vec<RolEvent>, OplError> {
+    let count = reader.u16_le()?;
+    (0..count)
+        .map(|_| {
+            Ok(RolEvent {
+                time: reader.u16_le()?,
+                value: reader.f32_le()?,
+            })
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, Default)]
+struct RolVoiceState {
+    next_note: usize,
+    note_end: u32,
+    note: u8,
+    next_instrument: usize,
+    next_volume: usize,
+    next_pitch: usize,
+    instrument: Option<usize>,
+    volume: u8,
+    /// The pitch bend in semitones.
+    bend: f64,
+}
+
+/// The `RolSequencer` plays a `RolFile` through an `Opl3Device`.
+pub struct RolSequencer {
+    file: RolFile,
+    instruments: Vec<Option<BnkInstrument>>,
+    voices: Vec<RolVoiceState>,
+    tick: u32,
+    next_tempo: usize,
+    tempo_multiplier: f32,
+    bd: u8,
+    looped: bool,
+    repeat: bool,
+}
+
+/// A player for ROL files.
+pub type RolPlayer = OplPlayer<RolSequencer>;
+
+impl RolSequencer {
+    /// Create a new sequencer for the given ROL file.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The `RolFile` to play.
+    /// * `bank` - The bank used to look up the song's instruments. Instrument changes to names
+    ///   that are not found in the bank are ignored.
+    pub fn new(file: RolFile, bank: &dyn RolBank) -> Self {
+        let instruments = file
+            .voices
+            .iter()
+            .flat_map(|v| v.instruments.iter())
+            .map(|event| bank.find(&event.name))
+            .collect();
+        let voices = vec![RolVoiceState::default(); file.voices.len()];
+        RolSequencer {
+            file,
+            instruments,
+            voices,
+            tick: 0,
+            next_tempo: 0,
+            tempo_multiplier: 1.0,
+            bd: 0,
+            looped: false,
+            repeat: false,
+        }
+    }
+
+    /// Return the `RolFile` being played.
+    pub fn file(&self) -> &RolFile {
+        &self.file
+    }
+
+    /// Return the names of the instruments used by the song that were not found in the bank.
+    pub fn missing_instruments(&self) -> Vec<&str> {
+        let mut missing: Vec<&str> = self
+            .file
+            .voices
+            .iter()
+            .flat_map(|v| v.instruments.iter())
+            .zip(&self.instruments)
+            .filter(|(_, inst)| inst.is_none())
+            .map(|(event, _)| event.name.as_str())
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        missing
+    }
+
+    /// Set whether the song repeats when it reaches the end, rather than finishing.
+    pub fn set_repeat(&mut self, repeat: bool) {
+        self.repeat = repeat;
+    }
+
+    /// Returns true once the song has reached its end and looped back.
+    pub fn has_looped(&self) -> bool {
+        self.looped
+    }
+
+    /// Return the next tick to be played.
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    fn tick_rate(&self) -> f64 {
+        let tempo = self.file.basic_tempo as f64 * self.tempo_multiplier as f64;
+        (tempo * self.file.ticks_per_beat as f64 / 60.0).max(0.01)
+    }
+
+    fn is_percussion(&self, voice: usize) -> bool {
+        self.file.mode == RolMode::Percussive && voice >= ROL_PERCUSSIVE_MELODIC_VOICES
+    }
+
+    /// Return the operator holding the volume of a voice.
+    fn volume_operator(&self, voice: usize) -> u16 {
+        if self.is_percussion(voice) && voice > ROL_PERCUSSIVE_MELODIC_VOICES {
+            OplDrum::ALL[voice - ROL_PERCUSSIVE_MELODIC_VOICES].operator_offset() as u16
+        } else {
+            OP_OFFSETS[voice] + 3
+        }
+    }
+
+    fn update(&mut self, device: &mut Opl3Device) {
+        while let Some(event) = self.file.tempo_events.get(self.next_tempo) {
+            if event.time as u32 > self.tick {
+                break;
+            }
+            self.tempo_multiplier = event.value;
+            self.next_tempo += 1;
+        }
+
+        // Instrument events are numbered in order across all voices.
+        let mut instrument_base = 0;
+        for voice in 0..self.voices.len() {
+            self.update_voice(device, voice, instrument_base);
+            instrument_base += self.file.voices[voice].instruments.len();
+        }
+        self.tick += 1;
+    }
+
+    fn update_voice(&mut self, device: &mut Opl3Device, voice: usize, instrument_base: usize) {
+        let tick = self.tick;
+        let track = &self.file.voices[voice];
+        let mut state = self.voices[voice];
+
+        while let Some(event) = track.instruments.get(state.next_instrument) {
+            if event.time as u32 > tick {
+                break;
+            }
+            let index = instrument_base + state.next_instrument;
+            if self.instruments[index].is_some() {
+                state.instrument = Some(index);
+                self.voices[voice] = state;
+                self.load_instrument(device, voice);
+            }
+            state.next_instrument += 1;
+        }
+        while let Some(event) = track.volumes.get(state.next_volume) {
+            if event.time as u32 > tick {
+                break;
+            }
+            state.volume = (ROL_MAX_VOLUME as f32 * event.value.clamp(0.0, 1.0)) as u8;
+            state.next_volume += 1;
+            self.voices[voice] = state;
+            self.write_volume(device, voice);
+        }
+        while let Some(event) = track.pitches.get(state.next_pitch) {
+            if event.time as u32 > tick {
+                break;
+            }
+            state.bend = event.value.clamp(0.0, 2.0) as f64 - 1.0;
+            state.next_pitch += 1;
+            self.voices[voice] = state;
+            if state.note != 0 && !self.is_percussion(voice) {
+                self.write_pitch(device, voice as u16, state.note, state.bend, true);
+            }
+        }
+
+        if tick >= state.note_end {
+            let note = match track.notes.get(state.next_note) {
+                Some(note) => {
+                    state.next_note += 1;
+                    state.note_end = tick + note.duration as u32;
+                    note.note
+                }
+                None => {
+                    state.note_end = u32::MAX;
+                    0
+                }
+            };
+            state.note = note;
+            self.voices[voice] = state;
+            self.play_note(device, voice);
+        }
+        self.voices[voice] = state;
+    }
+
+    fn play_note(&mut self, device: &mut Opl3Device, voice: usize) {
+        let state = self.voices[voice];
+        if !self.is_percussion(voice) {
+            let b0 = read_reg(device, 0xB0 + voice as u16) & !0x20;
+            write_reg(device, 0xB0 + voice as u16, b0);
+            if state.note != 0 {
+                self.write_pitch(device, voice as u16, state.note, state.bend, true);
+            }
+            return;
+        }
+
+        let drum = OplDrum::ALL[voice - ROL_PERCUSSIVE_MELODIC_VOICES];
+        self.bd &= !drum.bit();
+        write_reg(device, 0xBD, self.bd);
+        if state.note == 0 {
+            return;
+        }
+        match voice - ROL_PERCUSSIVE_MELODIC_VOICES {
+            // Bass drum
+            0 => self.write_pitch(device, drum.channel() as u16, state.note, state.bend, false),
+            // Tom-tom, which also tunes the snare drum.
+            2 => {
+                self.write_pitch(device, drum.channel() as u16, state.note, state.bend, false);
+                self.write_pitch(
+                    device,
+                    OplDrum::SnareDrum.channel() as u16,
+                    state.note,
+                    state.bend + ROL_TOM_TO_SNARE,
+                    false,
+                );
+            }
+            _ => {}
+        }
+        self.bd |= drum.bit();
+        write_reg(device, 0xBD, self.bd);
+    }
+
+    fn write_pitch(&self, device: &mut Opl3Device, ch: u16, note: u8, bend: f64, key_on: bool) {
+        let (fnum, block) = note_fnum(note as f64 + bend);
+        let key = if key_on { 0x20 } else { 0 };
+        write_reg(device, 0xA0 + ch, fnum as u8);
+        write_reg(device, 0xB0 + ch, key | (block << 2) | (fnum >> 8) as u8);
+    }
+
+    fn load_instrument(&self, device: &mut Opl3Device, voice: usize) {
+        let Some(inst) = self.voices[voice]
+            .instrument
+            .and_then(|i| self.instruments[i])
+        else {
+            return;
+        };
+        let regs = inst.registers;
+        if self.is_percussion(voice) && voice > ROL_PERCUSSIVE_MELODIC_VOICES {
+            let op = self.volume_operator(voice);
+            for (reg, i) in [(0x20, 0), (0x60, 4), (0x80, 6), (0xE0, 8)] {
+                write_reg(device, reg + op, regs[i]);
+            }
+        } else {
+            let op = OP_OFFSETS[voice];
+            for (reg, i) in [(0x20, 0), (0x40, 2), (0x60, 4), (0x80, 6), (0xE0, 8)] {
+                write_reg(device, reg + op, regs[i]);
+                write_reg(device, reg + op + 3, regs[i + 1]);
+            }
+            write_reg(device, 0xC0 + voice as u16, regs[10]);
+        }
+        self.write_volume(device, voice);
+    }
+
+    /// Scale the output level of the instrument's carrier by the voice volume. Percussion voices
+    /// that use a single operator are loaded with the instrument's modulator settings.
+    fn write_volume(&self, device: &mut Opl3Device, voice: usize) {
+        let state = self.voices[voice];
+        let Some(inst) = state.instrument.and_then(|i| self.instruments[i]) else {
+            return;
+        };
+        let single_op = self.is_percussion(voice) && voice > ROL_PERCUSSIVE_MELODIC_VOICES;
+        let ksl_tl = inst.registers[if single_op { 2 } else { 3 }];
+        let level = (63 - (ksl_tl & 0x3F)) as u32;
+        let level = 63 - ((level * state.volume as u32 + ROL_MAX_VOLUME / 2) / ROL_MAX_VOLUME);
+        write_reg(
+            device,
+            0x40 + self.volume_operator(voice),
+            (ksl_tl & 0xC0) | level as u8,
+        );
+    }
+}
+
+impl OplSequencer for RolSequencer {
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64> {
+        if self.tick >= self.file.length_ticks() {
+            if !self.repeat || self.tick == 0 {
+                return None;
+            }
+            self.rewind(device);
+            self.looped = true;
+        }
+        self.update(device);
+        Some(1_000_000.0 / self.tick_rate())
+    }
+
+    fn rewind(&mut self, device: &mut Opl3Device) {
+        self.voices = vec![
+            RolVoiceState {
+                volume: ROL_MAX_VOLUME as u8,
+                ..Default::default()
+            };
+            self.file.voices.len()
+        ];
+        self.tick = 0;
+        self.next_tempo = 0;
+        self.tempo_multiplier = 1.0;
+        self.bd = match self.file.mode {
+            RolMode::Percussive => 0x20,
+            RolMode::Melodic => 0x00,
+        };
+        self.looped = false;
+
+        write_reg(device, 0x01, 0x20);
+        write_reg(device, 0x08, 0x00);
+        write_reg(device, 0xBD, self.bd);
+        for ch in 0..ROL_MELODIC_VOICES as u16 {
+            write_reg(device, 0xB0 + ch, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OplRegisterFile;
+
+    fn bank() -> Vec<u8> {
+        let mut data = vec![1, 0];
+        data.extend_from_slice(BNK_SIGNATURE);
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&28u32.to_le_bytes());
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        // Name entry
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.push(1);
+        data.extend_from_slice(b"PIANO1\0\0\0");
+        // Instrument: FM connection, feedback 3.
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&[1, 1, 3, 15, 7, 1, 7, 7, 16, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0, 1, 0, 15, 7, 1, 7, 7, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0, 0]);
+        data
+    }
+
+    fn song(mode: u8, voices: usize, notes: &[(u16, u16)]) -> Vec<u8> {
+        let mut data = vec![0u8; ROL_TEMPO_OFFSET];
+        data[2] = 4;
+        data[44..46].copy_from_slice(&4u16.to_le_bytes());
+        data[46..48].copy_from_slice(&4u16.to_le_bytes());
+        data[53] = mode;
+        data.extend_from_slice(&120f32.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        for voice in 0..voices {
+            let notes = if voice == 0 || voice == 6 { notes } else { &[] };
+            data.extend_from_slice(&[0; ROL_TRACK_NAME_SIZE]);
+            let length: u16 = notes.iter().map(|n| n.1).sum();
+            data.extend_from_slice(&length.to_le_bytes());
+            for &(note, duration) in notes {
+                data.extend_from_slice(&note.to_le_bytes());
+                data.extend_from_slice(&duration.to_le_bytes());
+            }
+            data.extend_from_slice(&[0; ROL_TRACK_NAME_SIZE]);
+            data.extend_from_slice(&1u16.to_le_bytes());
+            data.extend_from_slice(&0u16.to_le_bytes());
+            data.extend_from_slice(b"piano1\0\0\0\0\0\0");
+            data.extend_from_slice(&[0; ROL_TRACK_NAME_SIZE]);
+            data.extend_from_slice(&0u16.to_le_bytes());
+            data.extend_from_slice(&[0; ROL_TRACK_NAME_SIZE]);
+            data.extend_from_slice(&0u16.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn parse_bank() {
+        let bank = BnkFile::parse(&bank()).unwrap();
+        assert_eq!(bank.version, (1, 0));
+        assert_eq!(bank.instruments.len(), 1);
+        let inst = bank.find("Piano1").unwrap();
+        assert_eq!(
+            inst.registers,
+            [0x21, 0x21, 0x50, 0x00, 0xF7, 0xF7, 0x77, 0x77, 0, 0, 0x07]
+        );
+        assert!(bank.find("piano2").is_none());
+    }
+
+    #[test]
+    fn play_melodic() {
+        let rol = RolFile::parse(&song(1, ROL_MELODIC_VOICES, &[(60, 4), (0, 4)])).unwrap();
+        assert_eq!(rol.mode, RolMode::Melodic);
+        assert_eq!(rol.voices.len(), ROL_MELODIC_VOICES);
+        assert_eq!(rol.length_ticks(), 8);
+
+        let bank = BnkFile::parse(&bank()).unwrap();
+        let sequencer = RolSequencer::new(rol, &bank);
+        assert!(sequencer.missing_instruments().is_empty());
+        let mut player = RolPlayer::new(sequencer, 8000);
+        // 8 ticks at 8 Hz.
+        let samples = player.render_to_vec(100_000).unwrap();
+        assert_eq!(samples.len() / 2, 8000);
+        assert!(samples[..8000].iter().any(|&s| s != 0));
+        let reg = |r: u8| player.device().read_register(r, OplRegisterFile::Primary);
+        assert_eq!(reg(0xB0) & 0x20, 0);
+        assert_eq!(reg(0xC0), 0x07);
+    }
+
+    #[test]
+    fn play_percussive() {
+        let rol = RolFile::parse(&song(0, ROL_PERCUSSIVE_VOICES, &[(48, 2)])).unwrap();
+        assert_eq!(rol.voices.len(), ROL_PERCUSSIVE_VOICES);
+        let bank = BnkFile::parse(&bank()).unwrap();
+        let mut player = RolPlayer::new(RolSequencer::new(rol, &bank), 8000);
+        player.render_to_vec(100_000).unwrap();
+        let reg = |r: u8| player.device().read_register(r, OplRegisterFile::Primary);
+        // Voice 6 is the bass drum, which is still sounding at the end of the song.
+        assert_eq!(reg(0xBD), 0x30);
+        assert_eq!(reg(0xB6) & 0x20, 0);
+    }
+}
@@ -0,0 +1,891 @@
+//! Parser and player for Surprise! AdLib Tracker (SA2/SAT) modules.
+//!
+//! Module versions 1 to 9 are supported. Versions 1 to 6 store each pattern as 9 interleaved
+//! tracks with 5 bytes per note, version 7 packs notes into 3 bytes, and versions 8 and 9 store
+//! tracks separately with a track order table mapping each pattern to a track per channel. Version
+//! 9 adds a mask of active channels. Versions 5 and later include a table of special arpeggios that
+//! instruments can run on each tick.
+//!
+//! The supported effects are arpeggio (0), slide up (1) and down (2), tone portamento (3),
+//! vibrato (4), tone portamento (5) and vibrato (6) with volume slide, release note (8), volume
+//! slide (A), position jump (B), set volume (C), pattern break (D) and set speed or tempo (F).
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::formats::sa2::{Sa2File, Sa2Player, Sa2Sequencer};
+//!
+//! let data = std::fs::read("song.sa2").unwrap();
+//! let sa2 = Sa2File::parse(&data).unwrap();
+//! let mut player = Sa2Player::new(Sa2Sequencer::new(sa2), 44100);
+//! let samples = player.render_to_vec(44100 * 600).unwrap();
+//! ```
+
+use crate::formats::{write_reg, ByteReader};
+use crate::player::{OplPlayer, OplSequencer};
+use crate::{Opl3Device, OplError};
+use alloc::{string::String, string::ToString, vec::Vec};
+
+/// The signature at the start of every SA2 file.
+pub const SA2_SIGNATURE: &[u8; 4] = b"SAdT";
+
+/// The number of rows in an SA2 track.
+pub const SA2_ROWS: usize = 64;
+/// The number of channels in an SA2 module.
+pub const SA2_CHANNELS: usize = 9;
+
+const SA2_INSTRUMENTS: usize = 31;
+const SA2_INSTRUMENT_NAMES: usize = 29;
+const SA2_NAME_SIZE: usize = 17;
+const SA2_ORDERS: usize = 128;
+const SA2_PATTERNS: usize = 64;
+const SA2_ARPEGGIO_SIZE: usize = 256;
+
+/// The note value used for a key off.
+pub const SA2_NOTE_KEY_OFF: u8 = 127;
+const SA2_MAX_NOTE: u8 = 96;
+
+// Effect numbers that are not supported by a module version are converted to this.
+const FX_NONE: u8 = 0xFF;
+const FX_CONVERT: [u8; 16] = [
+    0, 1, 2, 3, 4, 5, 6, FX_NONE, 8, FX_NONE, 10, 11, 12, 13, FX_NONE, 15,
+];
+
+const FX_ARPEGGIO: u8 = 0x0;
+const FX_SLIDE_UP: u8 = 0x1;
+const FX_SLIDE_DOWN: u8 = 0x2;
+const FX_TONE_PORTAMENTO: u8 = 0x3;
+const FX_VIBRATO: u8 = 0x4;
+const FX_PORTAMENTO_VOL_SLIDE: u8 = 0x5;
+const FX_VIBRATO_VOL_SLIDE: u8 = 0x6;
+const FX_RELEASE: u8 = 0x8;
+const FX_VOL_SLIDE: u8 = 0xA;
+const FX_POSITION_JUMP: u8 = 0xB;
+const FX_SET_VOLUME: u8 = 0xC;
+const FX_PATTERN_BREAK: u8 = 0xD;
+const FX_SET_SPEED: u8 = 0xF;
+
+// Special arpeggio commands.
+const ARP_SET_VOLUME: u8 = 252;
+const ARP_RELEASE: u8 = 253;
+const ARP_JUMP: u8 = 254;
+const ARP_END: u8 = 255;
+
+const SA2_DEFAULT_SPEED: u8 = 6;
+// Speed effects above this value set the tempo instead.
+const SA2_MAX_SPEED: u8 = 0x1F;
+
+// F-numbers for C through B, and the range they are kept within when sliding.
+const NOTE_FREQ: [u16; 12] = [340, 363, 385, 408, 432, 458, 485, 514, 544, 577, 611, 647];
+const FREQ_MIN: i32 = 342;
+const FREQ_MAX: i32 = 686;
+const OP_OFFSETS: [u16; SA2_CHANNELS] = [0x00, 0x01, 0x02, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x12];
+const VIBRATO_TABLE: [u8; 32] = [
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6,
+    5, 4, 3, 2, 1,
+];
+
+/// An SA2 instrument.
+#[derive(Clone, Debug, Default)]
+pub struct Sa2Instrument {
+    /// The name of the instrument. Only the first 29 instruments have names.
+    pub name: String,
+    /// The connection register 0xC0, the modulator and carrier registers 0x20, 0x60, 0x80 and
+    /// 0xE0, then the modulator and carrier registers 0x40.
+    pub data: [u8; 11],
+    /// The start of the instrument's special arpeggio, or 0 for none.
+    pub arpeggio_start: u8,
+    /// The speed of the instrument's special arpeggio, in ticks per step.
+    pub arpeggio_speed: u8,
+}
+
+/// A single note entry in an SA2 track.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sa2Note {
+    /// The note, 1 (C-0) to 96, `SA2_NOTE_KEY_OFF`, or 0 for no note.
+    pub note: u8,
+    /// The instrument number, or 0 for none.
+    pub instrument: u8,
+    /// The effect number.
+    pub command: u8,
+    /// The upper nibble of the effect parameter.
+    pub param1: u8,
+    /// The lower nibble of the effect parameter.
+    pub param2: u8,
+}
+
+/// A parsed SA2 module.
+#[derive(Clone, Debug)]
+pub struct Sa2File {
+    /// The module version, 1 to 9.
+    pub version: u8,
+    /// The instruments, indexed by instrument number - 1.
+    pub instruments: Vec<Sa2Instrument>,
+    /// The order list of pattern numbers.
+    pub order_list: Vec<u8>,
+    /// The order the song restarts from when it reaches the end of the order list.
+    pub restart_position: u8,
+    /// The tempo in beats per minute.
+    pub bpm: u16,
+    /// The special arpeggio note table. Empty for modules before version 5.
+    pub arpeggio_notes: Vec<u8>,
+    /// The special arpeggio command table. Empty for modules before version 5.
+    pub arpeggio_commands: Vec<u8>,
+    /// The track number played by each channel of each pattern, or 0 for none. Track numbers
+    /// start at 1.
+    pub track_order: Vec<[u16; SA2_CHANNELS]>,
+    /// A mask of the active channels, with bit 15 for channel 0.
+    pub active_channels: u16,
+    /// The tracks.
+    pub tracks: Vec<[Sa2Note; SA2_ROWS]>,
+}
+
+impl Sa2File {
+    /// Parse an SA2 module from a byte slice.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the SA2 file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `Sa2File` or an `OplError` on failure.
+    pub fn parse(data: &[u8]) -> Result<Sa2File, OplError> {
+        let mut reader = ByteReader::new(data);
+        if reader.bytes(4)? != SA2_SIGNATURE {
+            return Err(OplError::BadSignature);
+        }
+        let version = reader.u8()?;
+        if !(1..=9).contains(&version) {
+            return Err(OplError::UnsupportedVersion);
+        }
+        // Notes in versions 1 to 5 are stored relative to a higher base octave.
+        let note_offset = match version {
+            1 | 2 => 0x18,
+            3..=5 => 0x0C,
+            _ => 0,
+        };
+
+        let mut instruments = Vec::with_capacity(SA2_INSTRUMENTS);
+        for _ in 0..SA2_INSTRUMENTS {
+            let mut inst = Sa2Instrument::default();
+            inst.data.copy_from_slice(reader.bytes(11)?);
+            if version >= 4 {
+                let arp = reader.bytes(4)?;
+                inst.arpeggio_start = arp[0];
+                inst.arpeggio_speed = arp[1];
+            }
+            instruments.push(inst);
+        }
+        for inst in instruments.iter_mut().take(SA2_INSTRUMENT_NAMES) {
+            // Names are stored as Pascal strings.
+            let name = reader.bytes(SA2_NAME_SIZE)?;
+            let len = (name[0] as usize).min(SA2_NAME_SIZE - 1);
+            inst.name = String::from_utf8_lossy(&name[1..1 + len])
+                .trim_end()
+                .to_string();
+        }
+
+        reader.bytes(3)?;
+        let orders = reader.bytes(SA2_ORDERS)?;
+        if version == 1 {
+            reader.bytes(127)?;
+        }
+        let _patterns = reader.u16_le()?;
+        let length = (reader.u8()? as usize).min(SA2_ORDERS);
+        let restart_position = reader.u8()?;
+        let mut bpm = reader.u16_le()?;
+        if version <= 6 {
+            // Older versions store the tick rate in Hz.
+            bpm = (bpm as u32 * 125 / 50).min(u16::MAX as u32) as u16;
+        }
+
+        let (arpeggio_notes, arpeggio_commands) = if version >= 5 {
+            (
+                reader.bytes(SA2_ARPEGGIO_SIZE)?.to_vec(),
+                reader.bytes(SA2_ARPEGGIO_SIZE)?.to_vec(),
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        let track_order = if version >= 8 {
+            let mut track_order = Vec::with_capacity(SA2_PATTERNS);
+            for _ in 0..SA2_PATTERNS {
+                let mut tracks = [0u16; SA2_CHANNELS];
+                for track in tracks.iter_mut() {
+                    *track = reader.u8()? as u16;
+                }
+                track_order.push(tracks);
+            }
+            track_order
+        } else {
+            (0..SA2_PATTERNS)
+                .map(|p| core::array::from_fn(|ch| (p * SA2_CHANNELS + ch + 1) as u16))
+                .collect()
+        };
+
+        let active_channels = if version >= 9 {
+            reader.u16_le()?
+        } else {
+            0xFFFF
+        };
+
+        let remaining = reader.bytes(reader.remaining())?;
+        let tracks = match version {
+            1..=6 => decode_interleaved(remaining, 5, |b| Sa2Note {
+                note: if b[0] != 0 {
+                    b[0].saturating_add(note_offset)
+                } else {
+                    0
+                },
+                instrument: b[1],
+                command: FX_CONVERT[(b[2] & 0x0F) as usize],
+                // Each parameter nibble is stored in a byte of its own.
+                param1: b[3] & 0x0F,
+                param2: b[4] & 0x0F,
+            }),
+            7 => decode_interleaved(remaining, 3, unpack_note),
+            _ => remaining
+                .chunks(SA2_ROWS * 3)
+                .map(|chunk| {
+                    let mut track = [Sa2Note::default(); SA2_ROWS];
+                    for (row, b) in track.iter_mut().zip(chunk.chunks_exact(3)) {
+                        *row = unpack_note(b);
+                    }
+                    track
+                })
+                .collect(),
+        };
+
+        Ok(Sa2File {
+            version,
+            instruments,
+            order_list: orders[..length].to_vec(),
+            restart_position,
+            bpm,
+            arpeggio_notes,
+            arpeggio_commands,
+            track_order,
+            active_channels,
+            tracks,
+        })
+    }
+
+    /// Return the timer rate of the module in Hz.
+    pub fn tick_rate(&self) -> f64 {
+        self.bpm.max(1) as f64 * 2.0 / 5.0
+    }
+
+    fn track(&self, pattern: u8, ch: usize) -> Option<&[Sa2Note; SA2_ROWS]> {
+        let track = *self.track_order.get(pattern as usize)?.get(ch)?;
+        self.tracks.get((track as usize).checked_sub(1)?)
+    }
+}
+
+/// Unpack a 3-byte note as used by version 7 and later.
+fn unpack_note(b: &[u8]) -> Sa2Note {
+    Sa2Note {
+        note: b[0] >> 1,
+        instrument: ((b[0] & 1) << 4) | (b[1] >> 4),
+        command: FX_CONVERT[(b[1] & 0x0F) as usize],
+        param1: b[2] >> 4,
+        param2: b[2] & 0x0F,
+    }
+}
+
+/// Decode patterns stored as 9 interleaved tracks. A truncated final pattern is padded with empty
+/// notes.
+fn decode_interleaved(
+    data: &[u8],
+    note_size: usize,
+    decode: impl Fn(&[u8]) -> Sa2Note,
+) -> Vec<[Sa2Note; SA2_ROWS]> {
+    let mut tracks = Vec::new();
+    for pattern in data.chunks(SA2_ROWS * SA2_CHANNELS * note_size) {
+        let first = tracks.len();
+        tracks.resize(first + SA2_CHANNELS, [Sa2Note::default(); SA2_ROWS]);
+        for (i, b) in pattern.chunks_exact(note_size).enumerate() {
+            tracks[first + i % SA2_CHANNELS][i / SA2_CHANNELS] = decode(b);
+        }
+    }
+    tracks
+}
+
+#[derive(Copy, Clone, Default)]
+struct Sa2Channel {
+    instrument: Option<usize>,
+    note: u8,
+    freq: u16,
+    octave: u8,
+    vol1: u8,
+    vol2: u8,
+    key: bool,
+    fx: u8,
+    param1: u8,
+    param2: u8,
+    porta_speed: u8,
+    porta_freq: u16,
+    porta_octave: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    trigger: u8,
+    arp_pos: u8,
+    arp_count: u8,
+}
+
+/// The `Sa2Sequencer` plays an `Sa2File` through an `Opl3Device`.
+pub struct Sa2Sequencer {
+    file: Sa2File,
+    channels: [Sa2Channel; SA2_CHANNELS],
+    order: usize,
+    row: usize,
+    speed: u8,
+    bpm: u16,
+    delay: u8,
+    order_jump: Option<usize>,
+    row_break: Option<usize>,
+    looped: bool,
+    repeat: bool,
+}
+
+/// A player for SA2 modules.
+pub type Sa2Player = OplPlayer<Sa2Sequencer>;
+
+impl Sa2Sequencer {
+    /// Create a new sequencer for the given SA2 module.
+    pub fn new(file: Sa2File) -> Self {
+        let bpm = file.bpm;
+        Sa2Sequencer {
+            file,
+            channels: [Sa2Channel::default(); SA2_CHANNELS],
+            order: 0,
+            row: 0,
+            speed: SA2_DEFAULT_SPEED,
+            bpm,
+            delay: 0,
+            order_jump: None,
+            row_break: None,
+            looped: false,
+            repeat: false,
+        }
+    }
+
+    /// Return the `Sa2File` being played.
+    pub fn file(&self) -> &Sa2File {
+        &self.file
+    }
+
+    /// Set whether the song repeats from its restart position when it reaches the end, rather
+    /// than finishing.
+    pub fn set_repeat(&mut self, repeat: bool) {
+        self.repeat = repeat;
+    }
+
+    /// Return the current position in the order list.
+    pub fn order_position(&self) -> usize {
+        self.order
+    }
+
+    /// Return the next row of the current pattern to be played.
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    /// Return the current speed, in ticks per row.
+    pub fn speed(&self) -> u8 {
+        self.speed
+    }
+
+    /// Returns true once the song has reached its end and looped back.
+    pub fn has_looped(&self) -> bool {
+        self.looped
+    }
+
+    fn update(&mut self, device: &mut Opl3Device) {
+        if self.delay == 0 {
+            self.play_row(device);
+            self.delay = self.speed.max(1);
+        } else {
+            for ch in 0..SA2_CHANNELS {
+                self.tick_fx(device, ch);
+            }
+        }
+        for ch in 0..SA2_CHANNELS {
+            self.tick_arpeggio(device, ch);
+        }
+        self.delay -= 1;
+    }
+
+    fn play_row(&mut self, device: &mut Opl3Device) {
+        let Some(&pattern) = self.file.order_list.get(self.order) else {
+            self.looped = true;
+            return;
+        };
+
+        for ch in 0..SA2_CHANNELS {
+            if self.file.active_channels & (0x8000 >> ch) == 0 {
+                continue;
+            }
+            let Some(note) = self.file.track(pattern, ch).map(|t| t[self.row]) else {
+                continue;
+            };
+            self.play_note(device, ch, note);
+        }
+
+        self.advance();
+    }
+
+    fn play_note(&mut self, device: &mut Opl3Device, ch: usize, note: Sa2Note) {
+        if note.instrument > 0 && (note.instrument as usize) <= SA2_INSTRUMENTS {
+            let inst = &self.file.instruments[note.instrument as usize - 1];
+            let chan = &mut self.channels[ch];
+            chan.instrument = Some(note.instrument as usize - 1);
+            chan.vol1 = 63 - (inst.data[10] & 63);
+            chan.vol2 = 63 - (inst.data[9] & 63);
+            self.set_volume(device, ch);
+        }
+
+        let info = note.param1 << 4 | note.param2;
+        let chan = &mut self.channels[ch];
+        chan.fx = note.command;
+        chan.param1 = note.param1;
+        chan.param2 = note.param2;
+
+        let porta = note.command == FX_TONE_PORTAMENTO || note.command == FX_PORTAMENTO_VOL_SLIDE;
+        if note.note == SA2_NOTE_KEY_OFF {
+            self.channels[ch].key = false;
+            self.set_freq(device, ch);
+        } else if note.note != 0 && porta {
+            let (freq, octave) = Self::note_freq(note.note);
+            let chan = &mut self.channels[ch];
+            chan.porta_freq = freq;
+            chan.porta_octave = octave;
+        } else if note.note != 0 {
+            let chan = &mut self.channels[ch];
+            chan.note = note.note;
+            chan.trigger = 0;
+            chan.arp_count = 0;
+            chan.arp_pos = chan
+                .instrument
+                .map_or(0, |i| self.file.instruments[i].arpeggio_start);
+            self.set_note(ch, note.note);
+            self.key_on(device, ch);
+        }
+
+        match note.command {
+            FX_TONE_PORTAMENTO if info > 0 => self.channels[ch].porta_speed = info,
+            FX_VIBRATO => {
+                let chan = &mut self.channels[ch];
+                if note.param1 > 0 {
+                    chan.vibrato_speed = note.param1;
+                }
+                if note.param2 > 0 {
+                    chan.vibrato_depth = note.param2;
+                }
+            }
+            FX_RELEASE => {
+                self.channels[ch].key = false;
+                self.set_freq(device, ch);
+            }
+            FX_POSITION_JUMP => self.order_jump = Some(info as usize),
+            FX_SET_VOLUME => {
+                let chan = &mut self.channels[ch];
+                chan.vol1 = info.min(63);
+                if self.is_additive(ch) {
+                    self.channels[ch].vol2 = info.min(63);
+                }
+                self.set_volume(device, ch);
+            }
+            FX_PATTERN_BREAK => {
+                self.row_break = Some((note.param1 as usize * 10 + note.param2 as usize) % SA2_ROWS)
+            }
+            FX_SET_SPEED if info > SA2_MAX_SPEED => self.bpm = info as u16,
+            FX_SET_SPEED if info > 0 => self.speed = info,
+            _ => {}
+        }
+    }
+
+    fn advance(&mut self) {
+        if let Some(target) = self.order_jump.take() {
+            if target <= self.order {
+                self.looped = true;
+            }
+            self.order = target;
+            self.row = self.row_break.take().unwrap_or(0);
+        } else if let Some(row) = self.row_break.take() {
+            self.order += 1;
+            self.row = row;
+        } else {
+            self.row += 1;
+            if self.row >= SA2_ROWS {
+                self.row = 0;
+                self.order += 1;
+            }
+        }
+
+        if self.order >= self.file.order_list.len() {
+            self.order = self.file.restart_position as usize;
+            if self.order >= self.file.order_list.len() {
+                self.order = 0;
+            }
+            self.looped = true;
+        }
+    }
+
+    fn tick_fx(&mut self, device: &mut Opl3Device, ch: usize) {
+        let chan = self.channels[ch];
+        let info = chan.param1 << 4 | chan.param2;
+        match chan.fx {
+            FX_ARPEGGIO if info > 0 => {
+                let trigger = (chan.trigger + 1) % 3;
+                self.channels[ch].trigger = trigger;
+                let offset = [0, chan.param1, chan.param2][trigger as usize];
+                self.set_note(ch, chan.note.saturating_add(offset));
+                self.set_freq(device, ch);
+            }
+            FX_SLIDE_UP => {
+                self.slide_up(ch, info);
+                self.set_freq(device, ch);
+            }
+            FX_SLIDE_DOWN => {
+                self.slide_down(ch, info);
+                self.set_freq(device, ch);
+            }
+            FX_TONE_PORTAMENTO => self.tone_portamento(device, ch),
+            FX_VIBRATO => self.vibrato(device, ch),
+            FX_PORTAMENTO_VOL_SLIDE => {
+                self.tone_portamento(device, ch);
+                self.vol_slide(device, ch);
+            }
+            FX_VIBRATO_VOL_SLIDE => {
+                self.vibrato(device, ch);
+                self.vol_slide(device, ch);
+            }
+            FX_VOL_SLIDE => self.vol_slide(device, ch),
+            _ => {}
+        }
+    }
+
+    fn tick_arpeggio(&mut self, device: &mut Opl3Device, ch: usize) {
+        let Some(inst) = self.channels[ch].instrument else {
+            return;
+        };
+        let (arp_start, arp_speed) = (
+            self.file.instruments[inst].arpeggio_start,
+            self.file.instruments[inst].arpeggio_speed,
+        );
+        if arp_start == 0 || self.file.arpeggio_commands.is_empty() {
+            return;
+        }
+        if self.channels[ch].arp_count > 0 {
+            self.channels[ch].arp_count -= 1;
+            return;
+        }
+
+        let mut pos = self.channels[ch].arp_pos as usize;
+        let command = self.file.arpeggio_commands[pos];
+        if command == ARP_END {
+            return;
+        }
+        let op = OP_OFFSETS[ch];
+        match command {
+            ARP_SET_VOLUME => {
+                let volume = self.file.arpeggio_notes[pos].min(63);
+                self.channels[ch].vol1 = volume;
+                if self.is_additive(ch) {
+                    self.channels[ch].vol2 = volume;
+                }
+                self.set_volume(device, ch);
+            }
+            ARP_RELEASE => self.channels[ch].key = false,
+            ARP_JUMP => pos = self.file.arpeggio_notes[pos] as usize,
+            // Other commands select the carrier and modulator waveforms.
+            0 => {}
+            _ => {
+                if command / 10 > 0 {
+                    write_reg(device, 0xE3 + op, command / 10 - 1);
+                }
+                if !command.is_multiple_of(10) {
+                    write_reg(device, 0xE0 + op, command % 10 - 1);
+                }
+            }
+        }
+
+        let note = self.channels[ch].note;
+        let value = self.file.arpeggio_notes[pos];
+        if command == ARP_SET_VOLUME {
+            self.set_note(ch, note);
+        } else if value <= SA2_MAX_NOTE {
+            self.set_note(ch, note.saturating_add(value));
+        } else if value >= 100 {
+            self.set_note(ch, value - 100);
+        }
+        self.set_freq(device, ch);
+
+        if self.file.arpeggio_commands[pos] != ARP_END {
+            pos = (pos + 1) % SA2_ARPEGGIO_SIZE;
+        }
+        let chan = &mut self.channels[ch];
+        chan.arp_pos = pos as u8;
+        chan.arp_count = arp_speed.saturating_sub(1);
+    }
+
+    fn is_additive(&self, ch: usize) -> bool {
+        self.channels[ch]
+            .instrument
+            .is_some_and(|i| self.file.instruments[i].data[0] & 1 != 0)
+    }
+
+    fn note_freq(note: u8) -> (u16, u8) {
+        let note = note.clamp(1, SA2_MAX_NOTE) - 1;
+        (NOTE_FREQ[(note % 12) as usize], note / 12)
+    }
+
+    fn set_note(&mut self, ch: usize, note: u8) {
+        let (freq, octave) = Self::note_freq(note);
+        self.channels[ch].freq = freq;
+        self.channels[ch].octave = octave;
+    }
+
+    fn slide_up(&mut self, ch: usize, amount: u8) {
+        let chan = &mut self.channels[ch];
+        let mut freq = chan.freq as i32 + amount as i32;
+        if freq >= FREQ_MAX {
+            if chan.octave < 7 {
+                chan.octave += 1;
+                freq >>= 1;
+            } else {
+                freq = FREQ_MAX;
+            }
+        }
+        chan.freq = freq as u16;
+    }
+
+    fn slide_down(&mut self, ch: usize, amount: u8) {
+        let chan = &mut self.channels[ch];
+        let mut freq = chan.freq as i32 - amount as i32;
+        if freq <= FREQ_MIN {
+            if chan.octave > 0 {
+                chan.octave -= 1;
+                freq <<= 1;
+            } else {
+                freq = FREQ_MIN;
+            }
+        }
+        chan.freq = freq as u16;
+    }
+
+    fn tone_portamento(&mut self, device: &mut Opl3Device, ch: usize) {
+        let pitch = |freq: u16, octave: u8| freq as i32 + ((octave as i32) << 10);
+        let chan = self.channels[ch];
+        let target = pitch(chan.porta_freq, chan.porta_octave);
+        let current = pitch(chan.freq, chan.octave);
+        if current < target {
+            self.slide_up(ch, chan.porta_speed);
+        } else if current > target {
+            self.slide_down(ch, chan.porta_speed);
+        }
+        let chan = &mut self.channels[ch];
+        let moved = pitch(chan.freq, chan.octave);
+        if (current < target && moved > target) || (current > target && moved < target) {
+            chan.freq = chan.porta_freq;
+            chan.octave = chan.porta_octave;
+        }
+        self.set_freq(device, ch);
+    }
+
+    fn vibrato(&mut self, device: &mut Opl3Device, ch: usize) {
+        let (speed, depth) = (
+            self.channels[ch].vibrato_speed,
+            self.channels[ch].vibrato_depth.min(14),
+        );
+        if speed == 0 || depth == 0 {
+            return;
+        }
+        for _ in 0..speed {
+            let trigger = (self.channels[ch].trigger + 1) % 64;
+            self.channels[ch].trigger = trigger;
+            let divisor = 16 - depth;
+            match trigger {
+                0..=15 => self.slide_up(ch, VIBRATO_TABLE[trigger as usize + 16] / divisor),
+                16..=47 => self.slide_down(ch, VIBRATO_TABLE[trigger as usize - 16] / divisor),
+                _ => self.slide_up(ch, VIBRATO_TABLE[trigger as usize - 48] / divisor),
+            }
+        }
+        self.set_freq(device, ch);
+    }
+
+    fn vol_slide(&mut self, device: &mut Opl3Device, ch: usize) {
+        let (up, down) = (self.channels[ch].param1, self.channels[ch].param2);
+        let additive = self.is_additive(ch);
+        let chan = &mut self.channels[ch];
+        let slide = |vol: u8| {
+            if up > 0 {
+                vol.saturating_add(up).min(63)
+            } else {
+                vol.saturating_sub(down)
+            }
+        };
+        chan.vol1 = slide(chan.vol1);
+        if additive {
+            chan.vol2 = slide(chan.vol2);
+        }
+        self.set_volume(device, ch);
+    }
+
+    fn key_on(&mut self, device: &mut Opl3Device, ch: usize) {
+        let Some(inst) = self.channels[ch].instrument else {
+            return;
+        };
+        let data = self.file.instruments[inst].data;
+        let op = OP_OFFSETS[ch];
+
+        write_reg(device, 0xB0 + ch as u16, 0);
+        for (i, reg) in [0x20, 0x60, 0x80, 0xE0].into_iter().enumerate() {
+            write_reg(device, reg + op, data[1 + i * 2]);
+            write_reg(device, reg + 3 + op, data[2 + i * 2]);
+        }
+        write_reg(device, 0xC0 + ch as u16, data[0]);
+
+        self.channels[ch].key = true;
+        self.set_freq(device, ch);
+        self.set_volume(device, ch);
+    }
+
+    fn set_volume(&mut self, device: &mut Opl3Device, ch: usize) {
+        let chan = &self.channels[ch];
+        let Some(inst) = chan.instrument else {
+            return;
+        };
+        let data = &self.file.instruments[inst].data;
+        let op = OP_OFFSETS[ch];
+        write_reg(device, 0x40 + op, (63 - chan.vol2) | (data[9] & 0xC0));
+        write_reg(device, 0x43 + op, (63 - chan.vol1) | (data[10] & 0xC0));
+    }
+
+    fn set_freq(&mut self, device: &mut Opl3Device, ch: usize) {
+        let chan = &self.channels[ch];
+        let key = if chan.key { 0x20 } else { 0 };
+        write_reg(device, 0xA0 + ch as u16, chan.freq as u8);
+        write_reg(
+            device,
+            0xB0 + ch as u16,
+            ((chan.freq >> 8) as u8 & 3) | ((chan.octave & 7) << 2) | key,
+        );
+    }
+}
+
+impl OplSequencer for Sa2Sequencer {
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64> {
+        // Finish at the first row after the song loops, unless repeating.
+        if self.looped && !self.repeat && self.delay == 0 {
+            return None;
+        }
+        self.update(device);
+        Some(2_500_000.0 / self.bpm.max(1) as f64)
+    }
+
+    fn rewind(&mut self, device: &mut Opl3Device) {
+        self.channels = [Sa2Channel::default(); SA2_CHANNELS];
+        self.order = 0;
+        self.row = 0;
+        self.speed = SA2_DEFAULT_SPEED;
+        self.bpm = self.file.bpm;
+        self.delay = 0;
+        self.order_jump = None;
+        self.row_break = None;
+        self.looped = self.file.order_list.is_empty();
+
+        write_reg(device, 0x01, 0x20);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn header(version: u8, order_list: &[u8], bpm: u16) -> Vec<u8> {
+        let mut data = SA2_SIGNATURE.to_vec();
+        data.push(version);
+        let inst_size = if version >= 4 { 15 } else { 11 };
+        let mut inst = vec![0u8; inst_size * SA2_INSTRUMENTS];
+        inst[..11].copy_from_slice(&[0x00, 0x01, 0x01, 0xF0, 0xF0, 0x77, 0x77, 0, 0, 0x10, 0x00]);
+        data.extend_from_slice(&inst);
+        let mut names = vec![0u8; SA2_NAME_SIZE * SA2_INSTRUMENT_NAMES];
+        names[..5].copy_from_slice(b"\x04Bass");
+        data.extend_from_slice(&names);
+        data.extend_from_slice(&[0; 3]);
+        let mut orders = [0u8; SA2_ORDERS];
+        orders[..order_list.len()].copy_from_slice(order_list);
+        data.extend_from_slice(&orders);
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&[order_list.len() as u8, 0]);
+        data.extend_from_slice(&bpm.to_le_bytes());
+        if version >= 5 {
+            data.extend_from_slice(&[0; SA2_ARPEGGIO_SIZE * 2]);
+        }
+        data
+    }
+
+    #[test]
+    fn parse_v4_patterns() {
+        let mut data = header(4, &[0], 50);
+        let mut pattern = vec![0u8; SA2_ROWS * SA2_CHANNELS * 5];
+        // Row 0, channel 1: note 1 with instrument 1 and a pattern break.
+        pattern[5..10].copy_from_slice(&[1, 1, 0x0D, 0, 0]);
+        data.extend_from_slice(&pattern);
+
+        let sa2 = Sa2File::parse(&data).unwrap();
+        assert_eq!(sa2.version, 4);
+        assert_eq!(sa2.bpm, 125);
+        assert_eq!(sa2.instruments[0].name, "Bass");
+        assert_eq!(sa2.tracks.len(), SA2_CHANNELS);
+        let note = sa2.track(0, 1).unwrap()[0];
+        assert_eq!((note.note, note.instrument, note.command), (13, 1, 13));
+    }
+
+    #[test]
+    fn out_of_range_notes() {
+        let mut data = header(4, &[0], 50);
+        let mut pattern = vec![0u8; SA2_ROWS * SA2_CHANNELS * 5];
+        // Row 0, channel 0: note 0xFA with instrument 1 and an arpeggio.
+        pattern[..5].copy_from_slice(&[0xFA, 1, 0x00, 0x0F, 0x0F]);
+        // Rows 1 and 2: a volume slide and a pattern break, with parameters out of nibble range.
+        let row = SA2_CHANNELS * 5;
+        pattern[row..row + 5].copy_from_slice(&[0, 0, 0x0A, 0xFF, 0xFF]);
+        pattern[row * 2..row * 2 + 5].copy_from_slice(&[0, 0, 0x0D, 0xFF, 0xFF]);
+        data.extend_from_slice(&pattern);
+
+        let sa2 = Sa2File::parse(&data).unwrap();
+        let track = sa2.track(0, 0).unwrap();
+        assert_eq!(track[0].note, 0xFF);
+        assert_eq!((track[1].param1, track[1].param2), (0x0F, 0x0F));
+        let mut player = Sa2Player::new(Sa2Sequencer::new(sa2), 5000);
+        player.render_to_vec(5000).unwrap();
+    }
+
+    #[test]
+    fn play_v9_until_end() {
+        let mut data = header(9, &[0, 0], 125);
+        let mut track_order = [0u8; SA2_PATTERNS * SA2_CHANNELS];
+        track_order[0] = 1;
+        data.extend_from_slice(&track_order);
+        data.extend_from_slice(&0x8000u16.to_le_bytes());
+        let mut track = vec![0u8; SA2_ROWS * 3];
+        // Row 0: C-4 with instrument 1 and speed 2. Row 1: pattern break.
+        track[..3].copy_from_slice(&[49 << 1, 0x1F, 0x02]);
+        track[3..6].copy_from_slice(&[0, 0x0D, 0]);
+        data.extend_from_slice(&track);
+
+        let sa2 = Sa2File::parse(&data).unwrap();
+        assert_eq!(sa2.active_channels, 0x8000);
+        let mut player = Sa2Player::new(Sa2Sequencer::new(sa2), 5000);
+        let samples = player.render_to_vec(1_000_000).unwrap();
+        // Two orders of two rows at speed 2, with 100 samples per tick at 125 BPM.
+        assert_eq!(samples.len() / 2, 8 * 100);
+        assert!(player.sequencer().has_looped());
+        assert!(samples.iter().any(|&s| s != 0));
+    }
+}
@@ -0,0 +1,614 @@
+//! Loader and player for Standard MIDI Files (SMF).
+//!
+//! Format 0 (single track) and format 1 (multiple simultaneous tracks) files are supported. The
+//! tracks of a format 1 file are merged into a single stream of events in time order, and a tempo
+//! map built from the tempo changes of all tracks converts ticks to microseconds. Each event is
+//! timed from the start of the song rather than from the previous event, so the conversion to
+//! samples does not accumulate rounding errors. Files with SMPTE time division are played at a
+//! fixed number of ticks per second.
+//!
+//! The channel voice messages are played with a `MidiSynth`. Loops are marked either with marker
+//! meta events with the text `loopStart` and `loopEnd`, or with controller 111 as used by RPG
+//! Maker, which loops from the controller to the end of the song. When repeat is enabled the loop
+//! is played forever, or the whole song if it has no loop markers.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::formats::smf::{SmfFile, SmfPlayer, SmfSequencer};
+//! use opl3_rs::midi::MidiBank;
+//!
+//! let bank = MidiBank::parse_op2(&std::fs::read("GENMIDI.OP2").unwrap()).unwrap();
+//! let smf = SmfFile::parse(&std::fs::read("song.mid").unwrap()).unwrap();
+//! let mut player = SmfPlayer::new(SmfSequencer::new(smf, bank), 44100);
+//! let samples = player.render_to_vec(44100 * 600).unwrap();
+//! ```
+
+use crate::formats::ByteReader;
+use crate::midi::{MidiBank, MidiMessage, MidiSynth};
+use crate::player::{OplPlayer, OplSequencer};
+use crate::{Opl3Device, OplError};
+use alloc::{vec, vec::Vec};
+
+/// The signature of the header chunk of a Standard MIDI File.
+pub const SMF_SIGNATURE: &[u8; 4] = b"MThd";
+const SMF_TRACK_SIGNATURE: &[u8; 4] = b"MTrk";
+/// The tempo used until the first tempo change, in microseconds per quarter note (120 BPM).
+pub const SMF_DEFAULT_TEMPO: u32 = 500_000;
+/// The controller used by RPG Maker to mark the start of a loop.
+pub const SMF_CONTROLLER_LOOP_START: u8 = 111;
+
+const META_MARKER: u8 = 0x06;
+const META_END_OF_TRACK: u8 = 0x2F;
+const META_TEMPO: u8 = 0x51;
+
+/// The time division of a Standard MIDI File.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SmfDivision {
+    /// Ticks are a fraction of a quarter note, whose length is set by the tempo.
+    TicksPerQuarter(u16),
+    /// Ticks are a fraction of an SMPTE frame.
+    Smpte {
+        /// The SMPTE frame rate: 24, 25, 29 (for 29.97) or 30.
+        frames_per_second: u8,
+        /// The number of ticks in each frame.
+        ticks_per_frame: u8,
+    },
+}
+
+/// The events of a Standard MIDI File that affect playback.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SmfEventKind {
+    /// A channel voice message.
+    Message(MidiMessage),
+    /// A tempo change, in microseconds per quarter note.
+    Tempo(u32),
+    /// The start of the loop.
+    LoopStart,
+    /// The end of the loop.
+    LoopEnd,
+    /// The end of a track.
+    EndOfTrack,
+}
+
+/// An event at an absolute position in a track.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SmfEvent {
+    /// The position of the event in ticks from the start of the song.
+    pub tick: u64,
+    /// The event.
+    pub kind: SmfEventKind,
+}
+
+/// A parsed Standard MIDI File.
+#[derive(Clone, Debug)]
+pub struct SmfFile {
+    /// The file format, 0 or 1.
+    pub format: u16,
+    /// The time division.
+    pub division: SmfDivision,
+    /// The events of each track.
+    pub tracks: Vec<Vec<SmfEvent>>,
+}
+
+impl SmfFile {
+    /// Parse a Standard MIDI File from a byte slice.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the MIDI file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `SmfFile` or an `OplError` on failure. Format 2
+    /// files, whose tracks are independent sequences, are not supported.
+    pub fn parse(data: &[u8]) -> Result<SmfFile, OplError> {
+        let mut reader = ByteReader::new(data);
+        if reader.bytes(4)? != SMF_SIGNATURE {
+            return Err(OplError::BadSignature);
+        }
+        let header_len = reader.u32_be()? as usize;
+        if header_len < 6 {
+            return Err(OplError::InvalidFile("SMF header too short"));
+        }
+        let header_end = reader.pos() + header_len;
+        let format = reader.u16_be()?;
+        let track_count = reader.u16_be()?;
+        let division = reader.u16_be()?;
+        reader.seek(header_end)?;
+
+        if format > 1 {
+            return Err(OplError::UnsupportedVersion);
+        }
+        let division = if division & 0x8000 != 0 {
+            SmfDivision::Smpte {
+                frames_per_second: (-((division >> 8) as i8)) as u8,
+                ticks_per_frame: division as u8,
+            }
+        } else {
+            SmfDivision::TicksPerQuarter(division)
+        };
+        let zero = match division {
+            SmfDivision::TicksPerQuarter(ticks) => ticks == 0,
+            SmfDivision::Smpte {
+                frames_per_second,
+                ticks_per_frame,
+            } => frames_per_second == 0 || ticks_per_frame == 0,
+        };
+        if zero {
+            return Err(OplError::InvalidFile("SMF time division is zero"));
+        }
+
+        let mut tracks = Vec::new();
+        while tracks.len() < track_count as usize && reader.remaining() >= 8 {
+            let id = reader.bytes(4)?;
+            let len = reader.u32_be()? as usize;
+            let chunk = reader.bytes(len.min(reader.remaining()))?;
+            // Chunks of unknown types are skipped.
+            if id == SMF_TRACK_SIGNATURE {
+                tracks.push(Self::parse_track(chunk)?);
+            }
+        }
+        if tracks.is_empty() {
+            return Err(OplError::InvalidFile("SMF has no tracks"));
+        }
+
+        Ok(SmfFile {
+            format,
+            division,
+            tracks,
+        })
+    }
+
+    fn parse_track(data: &[u8]) -> Result<Vec<SmfEvent>, OplError> {
+        let mut reader = ByteReader::new(data);
+        let mut events = Vec::new();
+        let mut tick = 0u64;
+        let mut running_status = 0u8;
+
+        while reader.remaining() > 0 {
+            tick += reader.var_len()? as u64;
+            let mut status = reader.u8()?;
+            if status < 0x80 {
+                // Running status: this byte is the first data byte.
+                if running_status == 0 {
+                    return Err(OplError::InvalidFile("SMF data byte without status"));
+                }
+                reader.seek(reader.pos() - 1)?;
+                status = running_status;
+            } else if status < 0xF0 {
+                running_status = status;
+            }
+
+            let kind = match status {
+                0x80..=0xEF => {
+                    let len = if matches!(status & 0xF0, 0xC0 | 0xD0) {
+                        1
+                    } else {
+                        2
+                    };
+                    let data = reader.bytes(len)?;
+                    let message = MidiMessage::parse(&[&[status], data].concat())?;
+                    Some(match message {
+                        MidiMessage::ControlChange {
+                            controller: SMF_CONTROLLER_LOOP_START,
+                            ..
+                        } => SmfEventKind::LoopStart,
+                        _ => SmfEventKind::Message(message),
+                    })
+                }
+                0xF0 | 0xF7 => {
+                    let len = reader.var_len()? as usize;
+                    reader.bytes(len)?;
+                    None
+                }
+                0xFF => {
+                    let kind = reader.u8()?;
+                    let len = reader.var_len()? as usize;
+                    let data = reader.bytes(len)?;
+                    match kind {
+                        META_END_OF_TRACK => {
+                            events.push(SmfEvent {
+                                tick,
+                                kind: SmfEventKind::EndOfTrack,
+                            });
+                            return Ok(events);
+                        }
+                        META_TEMPO if len == 3 => Some(SmfEventKind::Tempo(u32::from_be_bytes([
+                            0, data[0], data[1], data[2],
+                        ]))),
+                        META_MARKER if data.eq_ignore_ascii_case(b"loopStart") => {
+                            Some(SmfEventKind::LoopStart)
+                        }
+                        META_MARKER if data.eq_ignore_ascii_case(b"loopEnd") => {
+                            Some(SmfEventKind::LoopEnd)
+                        }
+                        _ => None,
+                    }
+                }
+                _ => return Err(OplError::InvalidFile("SMF system message in track")),
+            };
+            if let Some(kind) = kind {
+                events.push(SmfEvent { tick, kind });
+            }
+        }
+
+        // Tolerate a missing end of track event.
+        events.push(SmfEvent {
+            tick,
+            kind: SmfEventKind::EndOfTrack,
+        });
+        Ok(events)
+    }
+
+    /// Merge the events of all tracks into a single list in time order. Events at the same tick
+    /// are kept in track order.
+    pub fn merged_events(&self) -> Vec<SmfEvent> {
+        let mut events: Vec<SmfEvent> = self.tracks.iter().flatten().copied().collect();
+        events.sort_by_key(|e| e.tick);
+        events
+    }
+
+    /// Return the length of the song in ticks, which is the end of the longest track.
+    pub fn length_ticks(&self) -> u64 {
+        self.tracks
+            .iter()
+            .filter_map(|track| track.last())
+            .map(|e| e.tick)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct TempoPoint {
+    tick: u64,
+    usec: f64,
+    tempo: u32,
+}
+
+/// Converts between ticks and time using the tempo changes of a song.
+#[derive(Clone, Debug)]
+pub struct SmfTempoMap {
+    division: SmfDivision,
+    points: Vec<TempoPoint>,
+}
+
+impl SmfTempoMap {
+    /// Build the tempo map of a song.
+    pub fn new(file: &SmfFile) -> Self {
+        let ticks_per_quarter = match file.division {
+            SmfDivision::TicksPerQuarter(ticks) => ticks as f64,
+            // The tempo is ignored with SMPTE time division.
+            SmfDivision::Smpte { .. } => 1.0,
+        };
+        let mut points = vec![TempoPoint {
+            tick: 0,
+            usec: 0.0,
+            tempo: SMF_DEFAULT_TEMPO,
+        }];
+        for event in file.merged_events() {
+            let SmfEventKind::Tempo(tempo) = event.kind else {
+                continue;
+            };
+            let last = points.last_mut().unwrap();
+            if last.tick == event.tick {
+                // A later tempo change at the same tick replaces the earlier one.
+                last.tempo = tempo;
+            } else {
+                let usec = last.usec
+                    + (event.tick - last.tick) as f64 * last.tempo as f64 / ticks_per_quarter;
+                points.push(TempoPoint {
+                    tick: event.tick,
+                    usec,
+                    tempo,
+                });
+            }
+        }
+        SmfTempoMap {
+            division: file.division,
+            points,
+        }
+    }
+
+    /// Return the time of a tick from the start of the song, in microseconds.
+    pub fn usec_at(&self, tick: u64) -> f64 {
+        match self.division {
+            SmfDivision::TicksPerQuarter(ticks) => {
+                let i = self.points.partition_point(|p| p.tick <= tick).max(1) - 1;
+                let point = &self.points[i];
+                point.usec + (tick - point.tick) as f64 * point.tempo as f64 / ticks as f64
+            }
+            SmfDivision::Smpte {
+                frames_per_second,
+                ticks_per_frame,
+            } => {
+                let fps = match frames_per_second {
+                    29 => 30_000.0 / 1001.0,
+                    fps => fps as f64,
+                };
+                tick as f64 * 1_000_000.0 / (fps * ticks_per_frame as f64)
+            }
+        }
+    }
+}
+
+/// The `SmfSequencer` plays a Standard MIDI File through a `MidiSynth`.
+pub struct SmfSequencer {
+    file: SmfFile,
+    events: Vec<SmfEvent>,
+    tempo_map: SmfTempoMap,
+    synth: MidiSynth,
+    pos: usize,
+    tick: u64,
+    loop_start: Option<usize>,
+    loop_end: Option<usize>,
+    repeat: bool,
+    looped: bool,
+}
+
+/// A player for Standard MIDI Files.
+pub type SmfPlayer = OplPlayer<SmfSequencer>;
+
+impl SmfSequencer {
+    /// Create a new sequencer for a MIDI file, played with the instruments of the given bank.
+    pub fn new(file: SmfFile, bank: MidiBank) -> Self {
+        let events = file.merged_events();
+        let tempo_map = SmfTempoMap::new(&file);
+        // A loop start at the end of the song would loop over nothing, so the whole song is
+        // repeated instead.
+        let loop_start = events
+            .iter()
+            .position(|e| e.kind == SmfEventKind::LoopStart)
+            .filter(|&start| events[start].tick < file.length_ticks());
+        // A loop end is only used if it comes after the loop start.
+        let loop_end = events
+            .iter()
+            .position(|e| e.kind == SmfEventKind::LoopEnd)
+            .filter(|&end| {
+                let start_tick = loop_start.map_or(0, |start| events[start].tick);
+                end > loop_start.unwrap_or(0) && events[end].tick > start_tick
+            });
+        SmfSequencer {
+            file,
+            events,
+            tempo_map,
+            synth: MidiSynth::new(bank),
+            pos: 0,
+            tick: 0,
+            loop_start,
+            loop_end,
+            repeat: false,
+            looped: false,
+        }
+    }
+
+    /// Return the `SmfFile` being played.
+    pub fn file(&self) -> &SmfFile {
+        &self.file
+    }
+
+    /// Return the tempo map of the song.
+    pub fn tempo_map(&self) -> &SmfTempoMap {
+        &self.tempo_map
+    }
+
+    /// Return the synthesizer that plays the song.
+    pub fn synth(&self) -> &MidiSynth {
+        &self.synth
+    }
+
+    /// Set whether the loop, or the whole song if it has no loop markers, repeats forever.
+    pub fn set_repeat(&mut self, repeat: bool) {
+        self.repeat = repeat;
+    }
+
+    /// Returns true once playback has jumped back to the start of the loop.
+    pub fn has_looped(&self) -> bool {
+        self.looped
+    }
+
+    /// Return the current position in ticks.
+    pub fn position_ticks(&self) -> u64 {
+        self.tick
+    }
+
+    /// Return the current position in seconds from the start of the song.
+    pub fn position_seconds(&self) -> f64 {
+        self.tempo_map.usec_at(self.tick) / 1_000_000.0
+    }
+
+    /// Return the length of the song in seconds.
+    pub fn length_seconds(&self) -> f64 {
+        self.tempo_map.usec_at(self.file.length_ticks()) / 1_000_000.0
+    }
+
+    /// Jump back to the start of the loop, releasing any held notes.
+    fn jump_to_loop(&mut self, device: &mut Opl3Device) {
+        for channel in 0..16 {
+            self.synth.send(
+                device,
+                MidiMessage::ControlChange {
+                    channel,
+                    // All notes off
+                    controller: 123,
+                    value: 0,
+                },
+            );
+        }
+        self.pos = self.loop_start.unwrap_or(0);
+        self.tick = self.events[self.pos].tick;
+        self.looped = true;
+    }
+}
+
+impl OplSequencer for SmfSequencer {
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64> {
+        loop {
+            let Some(event) = self.events.get(self.pos).copied() else {
+                if self.repeat && self.file.length_ticks() > 0 {
+                    self.jump_to_loop(device);
+                    continue;
+                }
+                return None;
+            };
+            if event.tick > self.tick {
+                let usec = self.tempo_map.usec_at(event.tick) - self.tempo_map.usec_at(self.tick);
+                self.tick = event.tick;
+                return Some(usec);
+            }
+
+            match event.kind {
+                SmfEventKind::Message(message) => self.synth.send(device, message),
+                SmfEventKind::LoopEnd if self.repeat && self.loop_end == Some(self.pos) => {
+                    self.jump_to_loop(device);
+                    continue;
+                }
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn rewind(&mut self, device: &mut Opl3Device) {
+        self.synth.reset(device);
+        self.pos = 0;
+        self.tick = 0;
+        self.looped = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OplRegisterFile;
+
+    fn smf(format: u16, division: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut data = SMF_SIGNATURE.to_vec();
+        data.extend_from_slice(&6u32.to_be_bytes());
+        data.extend_from_slice(&format.to_be_bytes());
+        data.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        data.extend_from_slice(&division.to_be_bytes());
+        for track in tracks {
+            data.extend_from_slice(SMF_TRACK_SIGNATURE);
+            data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+            data.extend_from_slice(track);
+        }
+        data
+    }
+
+    #[test]
+    fn tempo_map_and_merging() {
+        let data = smf(
+            1,
+            96,
+            &[
+                // Tempo 120 BPM, then 240 BPM after a quarter note.
+                &[
+                    0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20, //
+                    0x60, 0xFF, 0x51, 3, 0x03, 0xD0, 0x90, //
+                    0x00, 0xFF, 0x2F, 0x00,
+                ],
+                &[
+                    0x00, 0x90, 60, 100, //
+                    0x81, 0x40, 60, 0, // Running status note off after 192 ticks
+                    0x00, 0xFF, 0x2F, 0x00,
+                ],
+            ],
+        );
+        let file = SmfFile::parse(&data).unwrap();
+        assert_eq!(file.division, SmfDivision::TicksPerQuarter(96));
+        assert_eq!(file.tracks.len(), 2);
+        assert_eq!(file.length_ticks(), 192);
+
+        let events = file.merged_events();
+        assert_eq!(events[0].kind, SmfEventKind::Tempo(500_000));
+        assert!(matches!(
+            events[1].kind,
+            SmfEventKind::Message(MidiMessage::NoteOn { .. })
+        ));
+        assert_eq!(
+            events[events.len() - 2].kind,
+            SmfEventKind::Message(MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 0
+            })
+        );
+
+        let map = SmfTempoMap::new(&file);
+        assert_eq!(map.usec_at(48), 250_000.0);
+        assert_eq!(map.usec_at(96), 500_000.0);
+        assert_eq!(map.usec_at(192), 750_000.0);
+        assert!(SmfFile::parse(&smf(2, 96, &[&[0x00, 0xFF, 0x2F, 0x00]])).is_err());
+    }
+
+    #[test]
+    fn render_song() {
+        let data = smf(
+            0,
+            96,
+            &[&[
+                0x00, 0x90, 69, 100, //
+                0x60, 0x80, 69, 0, //
+                0x60, 0xFF, 0x2F, 0x00,
+            ]],
+        );
+        let sequencer = SmfSequencer::new(SmfFile::parse(&data).unwrap(), MidiBank::new());
+        assert_eq!(sequencer.length_seconds(), 1.0);
+        let mut player = SmfPlayer::new(sequencer, 1000);
+        let samples = player.render_to_vec(10_000).unwrap();
+        assert_eq!(samples.len() / 2, 1000);
+        assert!(samples.iter().any(|&s| s != 0));
+        assert_eq!(
+            player
+                .device()
+                .read_register(0xB0, OplRegisterFile::Primary)
+                & 0x20,
+            0
+        );
+    }
+
+    #[test]
+    fn loop_markers() {
+        let data = smf(
+            0,
+            96,
+            &[&[
+                0x00, 0x90, 60, 100, //
+                0x60, 0xFF, 0x06, 9, b'l', b'o', b'o', b'p', b'S', b't', b'a', b'r', b't', //
+                0x60, 0xFF, 0x06, 7, b'l', b'o', b'o', b'p', b'E', b'n', b'd', //
+                0x60, 0xFF, 0x2F, 0x00,
+            ]],
+        );
+        let file = SmfFile::parse(&data).unwrap();
+        let mut player = SmfPlayer::new(SmfSequencer::new(file.clone(), MidiBank::new()), 1000);
+        assert_eq!(player.render_to_vec(10_000).unwrap().len() / 2, 1500);
+        assert!(!player.sequencer().has_looped());
+
+        let mut sequencer = SmfSequencer::new(file, MidiBank::new());
+        sequencer.set_repeat(true);
+        let mut player = SmfPlayer::new(sequencer, 1000);
+        assert_eq!(player.render_to_vec(3000).unwrap().len() / 2, 3000);
+        assert!(player.sequencer().has_looped());
+        assert!(player.sequencer().position_ticks() >= 96);
+    }
+
+    #[test]
+    fn loop_start_at_end() {
+        let data = smf(
+            0,
+            96,
+            &[&[
+                0x00, 0x90, 60, 100, //
+                0x60, 0xFF, 0x06, 9, b'l', b'o', b'o', b'p', b'S', b't', b'a', b'r', b't', //
+                0x00, 0xFF, 0x2F, 0x00,
+            ]],
+        );
+        let mut sequencer = SmfSequencer::new(SmfFile::parse(&data).unwrap(), MidiBank::new());
+        sequencer.set_repeat(true);
+        let mut player = SmfPlayer::new(sequencer, 1000);
+        assert_eq!(player.render_to_vec(5000).unwrap().len() / 2, 5000);
+        assert!(player.sequencer().has_looped());
+    }
+}
@@ -0,0 +1,950 @@
+//! Parser and player for Video Game Music (VGM) files containing OPL family chip streams.
+//!
+//! VGM files are a log of chip register writes separated by wait commands measured in samples at
+//! 44100 Hz. This module plays the YM3526 (OPL), YM3812 (OPL2), Y8950 (MSX-AUDIO) and YMF262
+//! (OPL3) commands of a file through one `Opl3Device`, or two if the file uses the dual-chip flag.
+//! Commands for other chips are skipped. The YM3526 and Y8950 are register-compatible subsets of
+//! the OPL2 for the purposes of FM synthesis; the Y8950's ADPCM channel is not emulated.
+//!
+//! Gzip-compressed VGZ files can be loaded if the `vgz` feature is enabled.
+//!
+//! `VgmRecorder` logs the register writes made to an `Opl3Device` as a VGM file.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::formats::vgm::{VgmFile, VgmPlayer, VgmSequencer};
+//!
+//! let data = std::fs::read("song.vgm").unwrap();
+//! let vgm = VgmFile::parse(&data).unwrap();
+//! if let Some(gd3) = &vgm.gd3 {
+//!     println!("Playing {} from {}", gd3.track_name, gd3.game_name);
+//! }
+//! let mut player = VgmPlayer::new(VgmSequencer::new(vgm), 44100);
+//! let samples = player.render_to_vec(44100 * 600).unwrap();
+//! ```
+
+use crate::formats::ByteReader;
+use crate::player::{OplPlayer, OplSequencer};
+use crate::recorder::{initial_state, OplRecorder};
+use crate::{Opl3Device, OplError, OplRegisterFile};
+use alloc::{string::String, vec, vec::Vec};
+
+/// The signature at the start of every VGM file.
+pub const VGM_SIGNATURE: &[u8; 4] = b"Vgm ";
+/// The signature at the start of a GD3 tag.
+pub const GD3_SIGNATURE: &[u8; 4] = b"Gd3 ";
+/// The sample rate that VGM wait commands are measured in.
+pub const VGM_SAMPLE_RATE: u32 = 44100;
+
+// Header field offsets.
+const VGM_EOF_OFFSET: usize = 0x04;
+const VGM_VERSION: usize = 0x08;
+const VGM_GD3_OFFSET: usize = 0x14;
+const VGM_TOTAL_SAMPLES: usize = 0x18;
+const VGM_LOOP_OFFSET: usize = 0x1C;
+const VGM_LOOP_SAMPLES: usize = 0x20;
+const VGM_RATE: usize = 0x24;
+const VGM_DATA_OFFSET: usize = 0x34;
+const VGM_YM3812_CLOCK: usize = 0x50;
+const VGM_YM3526_CLOCK: usize = 0x54;
+const VGM_Y8950_CLOCK: usize = 0x58;
+const VGM_YMF262_CLOCK: usize = 0x5C;
+
+// Files before version 1.50 have their data at a fixed offset.
+const VGM_LEGACY_DATA_START: usize = 0x40;
+// Bit 30 of a chip's clock field indicates that two instances of the chip are used.
+const VGM_DUAL_CHIP_BIT: u32 = 0x4000_0000;
+const VGM_CLOCK_MASK: u32 = 0x3FFF_FFFF;
+
+// Commands.
+const VGM_CMD_YM3812: u8 = 0x5A;
+const VGM_CMD_YM3526: u8 = 0x5B;
+const VGM_CMD_Y8950: u8 = 0x5C;
+const VGM_CMD_YMF262_PORT0: u8 = 0x5E;
+const VGM_CMD_YMF262_PORT1: u8 = 0x5F;
+// Writes to the second chip of a dual-chip pair use the first chip's command + 0x50.
+const VGM_SECOND_CHIP_OFFSET: u8 = 0x50;
+const VGM_CMD_WAIT: u8 = 0x61;
+const VGM_CMD_WAIT_NTSC: u8 = 0x62;
+const VGM_CMD_WAIT_PAL: u8 = 0x63;
+const VGM_CMD_END: u8 = 0x66;
+const VGM_CMD_DATA_BLOCK: u8 = 0x67;
+const VGM_CMD_PCM_RAM_WRITE: u8 = 0x68;
+const VGM_WAIT_NTSC_SAMPLES: u32 = 735;
+const VGM_WAIT_PAL_SAMPLES: u32 = 882;
+const VGM_CMD_WAIT_SHORT: u8 = 0x70;
+const VGM_WAIT_SHORT_MAX: u32 = 16;
+
+// Values written to the header by VgmRecorder.
+const VGM_WRITER_VERSION: u32 = 0x151;
+const VGM_WRITER_HEADER_SIZE: usize = 0x80;
+/// The standard clock of a YM3812, in Hz.
+pub const YM3812_CLOCK: u32 = 3_579_545;
+/// The standard clock of a YMF262, in Hz.
+pub const YMF262_CLOCK: u32 = 14_318_180;
+
+/// The OPL family chips that can be present in a VGM file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VgmChip {
+    /// The Yamaha YM3526 (OPL).
+    Ym3526,
+    /// The Yamaha YM3812 (OPL2).
+    Ym3812,
+    /// The Yamaha Y8950 (MSX-AUDIO).
+    Y8950,
+    /// The Yamaha YMF262 (OPL3).
+    Ymf262,
+}
+
+/// GD3 tag metadata. Each string field is provided in English and, where present, the original
+/// (typically Japanese) language.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Gd3Tag {
+    /// The version of the GD3 tag.
+    pub version: u32,
+    /// The track name, in English.
+    pub track_name: String,
+    /// The track name, in the original language.
+    pub track_name_original: String,
+    /// The game name, in English.
+    pub game_name: String,
+    /// The game name, in the original language.
+    pub game_name_original: String,
+    /// The system name, in English.
+    pub system_name: String,
+    /// The system name, in the original language.
+    pub system_name_original: String,
+    /// The original composer, in English.
+    pub author: String,
+    /// The original composer, in the original language.
+    pub author_original: String,
+    /// The release date of the game.
+    pub release_date: String,
+    /// The name of the person who converted the music to VGM.
+    pub converted_by: String,
+    /// Free-form notes.
+    pub notes: String,
+}
+
+impl Gd3Tag {
+    /// Parse a GD3 tag, starting at its signature.
+    pub fn parse(data: &[u8]) -> Result<Gd3Tag, OplError> {
+        let mut reader = ByteReader::new(data);
+        if reader.bytes(4)? != GD3_SIGNATURE {
+            return Err(OplError::BadSignature);
+        }
+        let version = reader.u32_le()?;
+        let length = reader.u32_le()? as usize;
+        let body = reader.bytes(length.min(reader.remaining()))?;
+
+        let mut strings = body
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<u16>>()
+            .split(|&c| c == 0)
+            .map(String::from_utf16_lossy)
+            .collect::<Vec<String>>()
+            .into_iter();
+        let mut next = || strings.next().unwrap_or_default();
+
+        Ok(Gd3Tag {
+            version,
+            track_name: next(),
+            track_name_original: next(),
+            game_name: next(),
+            game_name_original: next(),
+            system_name: next(),
+            system_name_original: next(),
+            author: next(),
+            author_original: next(),
+            release_date: next(),
+            converted_by: next(),
+            notes: next(),
+        })
+    }
+
+    /// Serialize the GD3 tag, including its signature and header.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body: Vec<u8> = Vec::new();
+        for s in [
+            &self.track_name,
+            &self.track_name_original,
+            &self.game_name,
+            &self.game_name_original,
+            &self.system_name,
+            &self.system_name_original,
+            &self.author,
+            &self.author_original,
+            &self.release_date,
+            &self.converted_by,
+            &self.notes,
+        ] {
+            for c in s.encode_utf16().chain(core::iter::once(0)) {
+                body.extend_from_slice(&c.to_le_bytes());
+            }
+        }
+        let mut data = GD3_SIGNATURE.to_vec();
+        data.extend_from_slice(&0x0100u32.to_le_bytes());
+        data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        data.extend_from_slice(&body);
+        data
+    }
+}
+
+/// A parsed VGM file.
+#[derive(Clone, Debug)]
+pub struct VgmFile {
+    /// The VGM version, in BCD. For example, version 1.51 is 0x151.
+    pub version: u32,
+    /// The total length of the song, in samples at 44100 Hz.
+    pub total_samples: u32,
+    /// The length of the looped section, in samples at 44100 Hz. Zero if the song does not loop.
+    pub loop_samples: u32,
+    /// The recording rate of the file, in Hz. This is informational only.
+    pub rate: u32,
+    /// The YM3812 clock in Hz, or zero if the chip is not used.
+    pub ym3812_clock: u32,
+    /// The YM3526 clock in Hz, or zero if the chip is not used.
+    pub ym3526_clock: u32,
+    /// The Y8950 clock in Hz, or zero if the chip is not used.
+    pub y8950_clock: u32,
+    /// The YMF262 clock in Hz, or zero if the chip is not used.
+    pub ymf262_clock: u32,
+    /// Whether two instances of the OPL chip are used.
+    pub dual_chip: bool,
+    /// The GD3 tag, if present.
+    pub gd3: Option<Gd3Tag>,
+    data: Vec<u8>,
+    data_start: usize,
+    data_end: usize,
+    loop_start: Option<usize>,
+}
+
+impl VgmFile {
+    /// Parse a VGM file from a byte slice. If the `vgz` feature is enabled, gzip-compressed VGZ
+    /// files are decompressed automatically.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the VGM or VGZ file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `VgmFile` or an `OplError` on failure.
+    pub fn parse(data: &[u8]) -> Result<VgmFile, OplError> {
+        if data.starts_with(&[0x1F, 0x8B]) {
+            return Self::parse_vgz(data);
+        }
+        Self::parse_vgm(data.to_vec())
+    }
+
+    #[cfg(feature = "vgz")]
+    fn parse_vgz(data: &[u8]) -> Result<VgmFile, OplError> {
+        use std::io::Read;
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(data)
+            .read_to_end(&mut decoded)
+            .map_err(|_| OplError::InvalidFile("VGZ decompression failed"))?;
+        Self::parse_vgm(decoded)
+    }
+
+    #[cfg(not(feature = "vgz"))]
+    fn parse_vgz(_data: &[u8]) -> Result<VgmFile, OplError> {
+        Err(OplError::InvalidFile(
+            "VGZ files require the vgz feature to be enabled",
+        ))
+    }
+
+    fn parse_vgm(data: Vec<u8>) -> Result<VgmFile, OplError> {
+        let mut reader = ByteReader::new(&data);
+        if reader.bytes(4)? != VGM_SIGNATURE {
+            return Err(OplError::BadSignature);
+        }
+
+        let header_u32 = |offset: usize| -> Result<u32, OplError> {
+            let mut r = ByteReader::new(&data);
+            r.seek(offset)?;
+            r.u32_le()
+        };
+        // Header offsets are relative to the position of the field itself.
+        let relative = |offset: usize| -> Result<Option<usize>, OplError> {
+            Ok(match header_u32(offset)? {
+                0 => None,
+                value => Some(offset + value as usize),
+            })
+        };
+
+        let version = header_u32(VGM_VERSION)?;
+        let data_start = if version >= 0x150 {
+            relative(VGM_DATA_OFFSET)?.unwrap_or(VGM_LEGACY_DATA_START)
+        } else {
+            VGM_LEGACY_DATA_START
+        };
+        if data_start > data.len() {
+            return Err(OplError::UnexpectedEof);
+        }
+        // Header fields that overlap the start of the data are not present and read as zero.
+        let clock = |offset: usize| -> Result<u32, OplError> {
+            if version < 0x151 || offset + 4 > data_start {
+                Ok(0)
+            } else {
+                header_u32(offset)
+            }
+        };
+
+        let ym3812_clock = clock(VGM_YM3812_CLOCK)?;
+        let ym3526_clock = clock(VGM_YM3526_CLOCK)?;
+        let y8950_clock = clock(VGM_Y8950_CLOCK)?;
+        let ymf262_clock = clock(VGM_YMF262_CLOCK)?;
+        let dual_chip = [ym3812_clock, ym3526_clock, y8950_clock, ymf262_clock]
+            .iter()
+            .any(|c| c & VGM_DUAL_CHIP_BIT != 0);
+
+        let data_end = relative(VGM_EOF_OFFSET)?
+            .unwrap_or(data.len())
+            .min(data.len());
+        let gd3_start = relative(VGM_GD3_OFFSET)?;
+        let gd3 = match gd3_start {
+            Some(start) if start < data.len() => Gd3Tag::parse(&data[start..]).ok(),
+            _ => None,
+        };
+        // The command stream ends at the GD3 tag, if it follows the data.
+        let data_end = match gd3_start {
+            Some(start) if start > data_start && start < data_end => start,
+            _ => data_end,
+        };
+
+        Ok(VgmFile {
+            version,
+            total_samples: header_u32(VGM_TOTAL_SAMPLES)?,
+            loop_samples: header_u32(VGM_LOOP_SAMPLES)?,
+            rate: header_u32(VGM_RATE)?,
+            ym3812_clock: ym3812_clock & VGM_CLOCK_MASK,
+            ym3526_clock: ym3526_clock & VGM_CLOCK_MASK,
+            y8950_clock: y8950_clock & VGM_CLOCK_MASK,
+            ymf262_clock: ymf262_clock & VGM_CLOCK_MASK,
+            dual_chip,
+            gd3,
+            loop_start: relative(VGM_LOOP_OFFSET)?.filter(|&l| l >= data_start && l < data_end),
+            data_start,
+            data_end,
+            data,
+        })
+    }
+
+    /// Return the OPL family chip used by the file, preferring the most capable chip if the
+    /// header lists more than one. Returns None if the file does not use any OPL family chip.
+    pub fn chip(&self) -> Option<VgmChip> {
+        if self.ymf262_clock != 0 {
+            Some(VgmChip::Ymf262)
+        } else if self.ym3812_clock != 0 {
+            Some(VgmChip::Ym3812)
+        } else if self.y8950_clock != 0 {
+            Some(VgmChip::Y8950)
+        } else if self.ym3526_clock != 0 {
+            Some(VgmChip::Ym3526)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the song has a loop point.
+    pub fn has_loop(&self) -> bool {
+        self.loop_start.is_some()
+    }
+
+    /// Return the raw command stream of the file.
+    pub fn commands(&self) -> &[u8] {
+        &self.data[self.data_start..self.data_end]
+    }
+}
+
+/// The `VgmSequencer` plays the OPL family commands of a VGM file through the player's
+/// `Opl3Device`. If the file uses the dual-chip flag, the second chip is played through a device
+/// of the sequencer's own, whose output is mixed with the player's.
+pub struct VgmSequencer {
+    file: VgmFile,
+    second_device: Option<Opl3Device>,
+    mix_buffer: Vec<i16>,
+    position: usize,
+    loops_played: u32,
+    loop_count: Option<u32>,
+    samples_played: u64,
+}
+
+/// A player for VGM files.
+pub type VgmPlayer = OplPlayer<VgmSequencer>;
+
+impl VgmSequencer {
+    /// Create a new sequencer for the given VGM file.
+    pub fn new(file: VgmFile) -> Self {
+        let position = file.data_start;
+        VgmSequencer {
+            file,
+            second_device: None,
+            mix_buffer: Vec::new(),
+            position,
+            loops_played: 0,
+            loop_count: Some(0),
+            samples_played: 0,
+        }
+    }
+
+    /// Return the VGM file being played.
+    pub fn file(&self) -> &VgmFile {
+        &self.file
+    }
+
+    /// Return a reference to the `Opl3Device` for the second chip of a dual-chip file. It is
+    /// created when playback starts, at the sample rate of the player's device.
+    pub fn second_device(&self) -> Option<&Opl3Device> {
+        self.second_device.as_ref()
+    }
+
+    /// Return a mutable reference to the `Opl3Device` for the second chip of a dual-chip file.
+    pub fn second_device_mut(&mut self) -> Option<&mut Opl3Device> {
+        self.second_device.as_mut()
+    }
+
+    /// Set how many times the looped section of the song is repeated before playback ends.
+    ///
+    /// # Arguments
+    ///
+    /// * `loops` - The number of times to repeat the loop, or None to loop forever. The default is
+    ///   Some(0), which plays the song through once.
+    pub fn set_loop_count(&mut self, loops: Option<u32>) {
+        self.loop_count = loops;
+    }
+
+    /// Return the number of times the loop point has been passed.
+    pub fn loops_played(&self) -> u32 {
+        self.loops_played
+    }
+
+    /// Return the playback position, in samples at 44100 Hz.
+    pub fn position_samples(&self) -> u64 {
+        self.samples_played
+    }
+
+    fn write(
+        &mut self,
+        device: &mut Opl3Device,
+        chip: usize,
+        reg: u8,
+        value: u8,
+        file: OplRegisterFile,
+    ) {
+        let device = match chip {
+            0 => Some(device),
+            _ => self.second_device.as_mut(),
+        };
+        if let Some(device) = device {
+            device.write_register(reg, value, file, false);
+        }
+    }
+
+    /// Execute commands until a wait is reached, returning the length of the wait in samples at
+    /// 44100 Hz, or None at the end of the song.
+    fn next_wait(&mut self, device: &mut Opl3Device) -> Option<u32> {
+        let mut wait = 0;
+        while wait == 0 {
+            let data = &self.file.data[..self.file.data_end];
+            let Some(&cmd) = data.get(self.position) else {
+                if self.restart_loop() {
+                    continue;
+                }
+                return None;
+            };
+            let operand = |n: usize| data.get(self.position + 1 + n).copied().unwrap_or(0);
+            let (reg, value) = (operand(0), operand(1));
+
+            let length = match cmd {
+                VGM_CMD_YM3812 | VGM_CMD_YM3526 | VGM_CMD_Y8950 | VGM_CMD_YMF262_PORT0 => {
+                    self.write(device, 0, reg, value, OplRegisterFile::Primary);
+                    3
+                }
+                VGM_CMD_YMF262_PORT1 => {
+                    self.write(device, 0, reg, value, OplRegisterFile::Secondary);
+                    3
+                }
+                c if c == VGM_CMD_YM3812 + VGM_SECOND_CHIP_OFFSET
+                    || c == VGM_CMD_YM3526 + VGM_SECOND_CHIP_OFFSET
+                    || c == VGM_CMD_Y8950 + VGM_SECOND_CHIP_OFFSET
+                    || c == VGM_CMD_YMF262_PORT0 + VGM_SECOND_CHIP_OFFSET =>
+                {
+                    self.write(device, 1, reg, value, OplRegisterFile::Primary);
+                    3
+                }
+                c if c == VGM_CMD_YMF262_PORT1 + VGM_SECOND_CHIP_OFFSET => {
+                    self.write(device, 1, reg, value, OplRegisterFile::Secondary);
+                    3
+                }
+                VGM_CMD_WAIT => {
+                    wait = u16::from_le_bytes([reg, value]) as u32;
+                    3
+                }
+                VGM_CMD_WAIT_NTSC => {
+                    wait = VGM_WAIT_NTSC_SAMPLES;
+                    1
+                }
+                VGM_CMD_WAIT_PAL => {
+                    wait = VGM_WAIT_PAL_SAMPLES;
+                    1
+                }
+                VGM_CMD_END => {
+                    if self.restart_loop() {
+                        continue;
+                    }
+                    return None;
+                }
+                VGM_CMD_DATA_BLOCK => {
+                    let size = u32::from_le_bytes([operand(2), operand(3), operand(4), operand(5)]);
+                    7 + (size & 0x7FFF_FFFF) as usize
+                }
+                VGM_CMD_PCM_RAM_WRITE => 12,
+                0x70..=0x7F => {
+                    wait = (cmd & 0x0F) as u32 + 1;
+                    1
+                }
+                0x80..=0x8F => {
+                    wait = (cmd & 0x0F) as u32;
+                    1
+                }
+                0x90 | 0x91 | 0x95 => 5,
+                0x92 => 6,
+                0x93 => 11,
+                0x94 => 2,
+                0x30..=0x3F | 0x4F | 0x50 => 2,
+                0x40..=0x4E | 0x51..=0x5F | 0xA0..=0xBF => 3,
+                0xC0..=0xDF => 4,
+                0xE0..=0xFF => 5,
+                // Unknown commands in the reserved range have no defined length, so playback
+                // cannot continue past them.
+                _ => return None,
+            };
+            self.position += length;
+        }
+        Some(wait)
+    }
+
+    /// Jump back to the loop point at the end of the song, if the song loops and the requested
+    /// number of loops has not yet been played. Returns true if playback continues.
+    fn restart_loop(&mut self) -> bool {
+        let Some(loop_start) = self.file.loop_start else {
+            return false;
+        };
+        // A loop without any waits in it would never return.
+        if self.file.loop_samples == 0 || self.loop_count.is_some_and(|c| self.loops_played >= c) {
+            return false;
+        }
+        self.loops_played += 1;
+        self.position = loop_start;
+        true
+    }
+}
+
+impl OplSequencer for VgmSequencer {
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64> {
+        let samples = self.next_wait(device)?;
+        self.samples_played += samples as u64;
+        let usec = samples as f64 * 1_000_000.0 / VGM_SAMPLE_RATE as f64;
+        if let Some(second) = &mut self.second_device {
+            second.run(usec);
+        }
+        Some(usec)
+    }
+
+    fn rewind(&mut self, device: &mut Opl3Device) {
+        self.position = self.file.data_start;
+        self.loops_played = 0;
+        self.samples_played = 0;
+        match &mut self.second_device {
+            Some(second) => _ = second.reset(Some(device.sample_rate)),
+            None if self.file.dual_chip => {
+                self.second_device = Some(Opl3Device::new(device.sample_rate));
+            }
+            None => {}
+        }
+    }
+
+    fn generate_samples(
+        &mut self,
+        device: &mut Opl3Device,
+        buffer: &mut [i16],
+    ) -> Result<(), OplError> {
+        device.generate_samples(buffer)?;
+        if let Some(second) = &mut self.second_device {
+            self.mix_buffer.resize(buffer.len(), 0);
+            second.generate_samples(&mut self.mix_buffer)?;
+            for (out, second) in buffer.iter_mut().zip(self.mix_buffer.iter()) {
+                *out = out.saturating_add(*second);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A register write or wait captured by `VgmRecorder`.
+#[derive(Copy, Clone, Debug)]
+enum VgmEvent {
+    Write(OplRegisterFile, u8, u8),
+    Wait(u32),
+    LoopPoint,
+}
+
+/// The `VgmRecorder` logs the register writes made to an `Opl3Device` as a VGM file.
+///
+/// Writes are logged as YMF262 commands if OPL3 mode is enabled, by bit 0 of register 0x105, at
+/// any point during the recording. Otherwise they are logged as YM3812 commands, and writes to the
+/// secondary register file, which the YM3812 doesn't have, are dropped. Time reported to
+/// `Opl3Device::run` is converted into wait commands at the VGM sample rate of 44100 Hz, carrying
+/// any fractional sample forward so that the log does not drift.
+///
+/// # Example
+///
+/// ```
+/// use opl3_rs::{Opl3Device, OplRegisterFile};
+/// use opl3_rs::formats::vgm::{Gd3Tag, VgmRecorder};
+///
+/// let mut device = Opl3Device::new(44100);
+/// device.start_recording(Box::new(VgmRecorder::new()));
+/// device.write_register(0xB0, 0x31, OplRegisterFile::Primary, false);
+/// device.run(10_000.0);
+/// if let Some(recorder) = device.recorder_mut::<VgmRecorder>() {
+///     recorder.set_loop_point();
+/// }
+/// device.run(10_000.0);
+/// let mut recorder = device.stop_recording::<VgmRecorder>().unwrap();
+/// recorder.set_gd3_tag(Gd3Tag {
+///     track_name: "Title".to_string(),
+///     ..Default::default()
+/// });
+/// let vgm_bytes = recorder.finish();
+/// ```
+pub struct VgmRecorder {
+    events: Vec<VgmEvent>,
+    /// Whether OPL3 mode is enabled, from bit 0 of register 0x105.
+    opl3_mode: bool,
+    /// Whether OPL3 mode has been enabled at any point during the recording.
+    opl3: bool,
+    usec_pending: f64,
+    total_samples: u64,
+    loop_samples: Option<u64>,
+    gd3: Option<Gd3Tag>,
+}
+
+impl Default for VgmRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VgmRecorder {
+    /// Create a new VGM recorder.
+    pub fn new() -> Self {
+        VgmRecorder {
+            events: Vec::new(),
+            opl3_mode: false,
+            opl3: false,
+            usec_pending: 0.0,
+            total_samples: 0,
+            loop_samples: None,
+            gd3: None,
+        }
+    }
+
+    /// Mark the current position in the recording as the loop point. The song will loop back to
+    /// this position when it reaches the end. Setting the loop point again replaces the previous
+    /// one.
+    pub fn set_loop_point(&mut self) {
+        self.flush_wait();
+        self.events.retain(|e| !matches!(e, VgmEvent::LoopPoint));
+        self.events.push(VgmEvent::LoopPoint);
+        self.loop_samples = Some(self.total_samples);
+    }
+
+    /// Remove the loop point, if one was set.
+    pub fn clear_loop_point(&mut self) {
+        self.events.retain(|e| !matches!(e, VgmEvent::LoopPoint));
+        self.loop_samples = None;
+    }
+
+    /// Set the GD3 tag metadata to be written with the file.
+    pub fn set_gd3_tag(&mut self, tag: Gd3Tag) {
+        self.gd3 = Some(tag);
+    }
+
+    /// Return the length of the recording so far, in samples at 44100 Hz.
+    pub fn total_samples(&self) -> u64 {
+        self.total_samples
+    }
+
+    /// Finish the recording, returning the contents of a VGM file.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.flush_wait();
+
+        let mut data = vec![0u8; VGM_WRITER_HEADER_SIZE];
+        let mut loop_offset = None;
+        for event in &self.events {
+            match *event {
+                VgmEvent::Write(file, reg, value) => {
+                    let cmd = match (self.opl3, file) {
+                        (false, OplRegisterFile::Primary) => VGM_CMD_YM3812,
+                        // The YM3812 has no second register file.
+                        (false, OplRegisterFile::Secondary) => continue,
+                        (true, OplRegisterFile::Primary) => VGM_CMD_YMF262_PORT0,
+                        (true, OplRegisterFile::Secondary) => VGM_CMD_YMF262_PORT1,
+                    };
+                    data.extend_from_slice(&[cmd, reg, value]);
+                }
+                VgmEvent::Wait(samples) => Self::encode_wait(&mut data, samples),
+                VgmEvent::LoopPoint => loop_offset = Some(data.len()),
+            }
+        }
+        data.push(VGM_CMD_END);
+
+        let gd3_offset = data.len();
+        if let Some(gd3) = &self.gd3 {
+            data.extend_from_slice(&gd3.to_bytes());
+        }
+
+        let mut put = |offset: usize, value: u32| {
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        let total_samples = self.total_samples.min(u32::MAX as u64) as u32;
+        put(VGM_VERSION, VGM_WRITER_VERSION);
+        put(VGM_TOTAL_SAMPLES, total_samples);
+        put(
+            VGM_DATA_OFFSET,
+            (VGM_WRITER_HEADER_SIZE - VGM_DATA_OFFSET) as u32,
+        );
+        if self.opl3 {
+            put(VGM_YMF262_CLOCK, YMF262_CLOCK);
+        } else {
+            put(VGM_YM3812_CLOCK, YM3812_CLOCK);
+        }
+        if let (Some(offset), Some(loop_start)) = (loop_offset, self.loop_samples) {
+            put(VGM_LOOP_OFFSET, (offset - VGM_LOOP_OFFSET) as u32);
+            put(VGM_LOOP_SAMPLES, total_samples - loop_start as u32);
+        }
+        if self.gd3.is_some() {
+            put(VGM_GD3_OFFSET, (gd3_offset - VGM_GD3_OFFSET) as u32);
+        }
+        data[0..4].copy_from_slice(VGM_SIGNATURE);
+        let eof_offset = (data.len() - VGM_EOF_OFFSET) as u32;
+        data[VGM_EOF_OFFSET..VGM_EOF_OFFSET + 4].copy_from_slice(&eof_offset.to_le_bytes());
+        data
+    }
+
+    fn encode_wait(data: &mut Vec<u8>, mut samples: u32) {
+        while samples > 0 {
+            match samples {
+                VGM_WAIT_NTSC_SAMPLES => {
+                    data.push(VGM_CMD_WAIT_NTSC);
+                    samples = 0;
+                }
+                VGM_WAIT_PAL_SAMPLES => {
+                    data.push(VGM_CMD_WAIT_PAL);
+                    samples = 0;
+                }
+                1..=VGM_WAIT_SHORT_MAX => {
+                    data.push(VGM_CMD_WAIT_SHORT | (samples - 1) as u8);
+                    samples = 0;
+                }
+                _ => {
+                    let wait = samples.min(u16::MAX as u32);
+                    data.push(VGM_CMD_WAIT);
+                    data.extend_from_slice(&(wait as u16).to_le_bytes());
+                    samples -= wait;
+                }
+            }
+        }
+    }
+
+    fn flush_wait(&mut self) {
+        // A small epsilon keeps accumulated floating point error from losing a whole sample.
+        let samples = (self.usec_pending * VGM_SAMPLE_RATE as f64 / 1_000_000.0 + 1e-6) as u64;
+        if samples == 0 {
+            return;
+        }
+        self.usec_pending -= samples as f64 * 1_000_000.0 / VGM_SAMPLE_RATE as f64;
+        self.total_samples += samples;
+        let samples = samples.min(u32::MAX as u64) as u32;
+        match self.events.last_mut() {
+            Some(VgmEvent::Wait(prev)) => *prev = prev.saturating_add(samples),
+            _ => self.events.push(VgmEvent::Wait(samples)),
+        }
+    }
+
+    fn record(&mut self, reg: u8, value: u8, file: OplRegisterFile) {
+        if file == OplRegisterFile::Secondary && reg == 0x05 {
+            self.opl3_mode = value & 0x01 != 0;
+        }
+        self.opl3 |= self.opl3_mode;
+        self.events.push(VgmEvent::Write(file, reg, value));
+    }
+}
+
+impl OplRecorder for VgmRecorder {
+    fn start(&mut self, registers: &[[u8; 256]; 2]) {
+        self.opl3_mode = registers[1][0x05] & 0x01 != 0;
+        for (file, reg, value) in initial_state(registers) {
+            self.record(reg, value, file);
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u8, file: OplRegisterFile) {
+        self.flush_wait();
+        self.record(reg, value, file);
+    }
+
+    fn advance(&mut self, usec: f64) {
+        self.usec_pending += usec;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    fn vgm_file(commands: &[u8], loop_offset: Option<usize>) -> Vec<u8> {
+        let mut data = vec![0u8; 0x100];
+        data[0..4].copy_from_slice(VGM_SIGNATURE);
+        data[VGM_VERSION..VGM_VERSION + 4].copy_from_slice(&0x151u32.to_le_bytes());
+        data[VGM_DATA_OFFSET..VGM_DATA_OFFSET + 4]
+            .copy_from_slice(&((0x100 - VGM_DATA_OFFSET) as u32).to_le_bytes());
+        data[VGM_YM3812_CLOCK..VGM_YM3812_CLOCK + 4].copy_from_slice(&3579545u32.to_le_bytes());
+        if let Some(offset) = loop_offset {
+            data[VGM_LOOP_OFFSET..VGM_LOOP_OFFSET + 4]
+                .copy_from_slice(&((0x100 + offset - VGM_LOOP_OFFSET) as u32).to_le_bytes());
+            data[VGM_LOOP_SAMPLES..VGM_LOOP_SAMPLES + 4].copy_from_slice(&441u32.to_le_bytes());
+        }
+        data.extend_from_slice(commands);
+        let gd3 = Gd3Tag {
+            track_name: "Title".into(),
+            game_name: "Game".into(),
+            ..Default::default()
+        };
+        let gd3_pos = data.len();
+        data[VGM_GD3_OFFSET..VGM_GD3_OFFSET + 4]
+            .copy_from_slice(&((gd3_pos - VGM_GD3_OFFSET) as u32).to_le_bytes());
+        data.extend_from_slice(&gd3.to_bytes());
+        let eof = (data.len() - VGM_EOF_OFFSET) as u32;
+        data[VGM_EOF_OFFSET..VGM_EOF_OFFSET + 4].copy_from_slice(&eof.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn parse_header_and_gd3() {
+        let vgm = VgmFile::parse(&vgm_file(&[0x5A, 0x20, 0x01, 0x62, 0x66], None)).unwrap();
+        assert_eq!(vgm.chip(), Some(VgmChip::Ym3812));
+        assert!(!vgm.dual_chip);
+        assert_eq!(vgm.commands(), &[0x5A, 0x20, 0x01, 0x62, 0x66]);
+        let gd3 = vgm.gd3.unwrap();
+        assert_eq!(gd3.track_name, "Title");
+        assert_eq!(gd3.game_name, "Game");
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let mut device = Opl3Device::new(VGM_SAMPLE_RATE);
+        device.start_recording(Box::new(VgmRecorder::new()));
+        device.write_register(0x20, 0x01, OplRegisterFile::Primary, false);
+        device.run(1_000_000.0 / 60.0);
+        device
+            .recorder_mut::<VgmRecorder>()
+            .unwrap()
+            .set_loop_point();
+        device.write_register(0x05, 0x01, OplRegisterFile::Secondary, false);
+        device.run(100.0);
+        device.run(2_000_000.0);
+        let mut recorder = device.stop_recording::<VgmRecorder>().unwrap();
+        recorder.set_gd3_tag(Gd3Tag {
+            author: "Author".into(),
+            ..Default::default()
+        });
+
+        let vgm = VgmFile::parse(&recorder.finish()).unwrap();
+        assert_eq!(vgm.chip(), Some(VgmChip::Ymf262));
+        assert_eq!(vgm.ymf262_clock, YMF262_CLOCK);
+        assert_eq!(vgm.total_samples, 735 + 88_204);
+        assert_eq!(vgm.loop_samples, 88_204);
+        assert_eq!(vgm.gd3.as_ref().unwrap().author, "Author");
+        assert_eq!(
+            vgm.commands(),
+            &[0x5E, 0x20, 0x01, 0x62, 0x5F, 0x05, 0x01, 0x61, 0xFF, 0xFF, 0x61, 0x8D, 0x58, 0x66]
+        );
+    }
+
+    #[test]
+    fn record_opl2_mode() {
+        // Clearing the OPL3 mode bit doesn't make the recording an OPL3 one.
+        let mut device = Opl3Device::new(VGM_SAMPLE_RATE);
+        device.start_recording(Box::new(VgmRecorder::new()));
+        device.write_register(0x05, 0x00, OplRegisterFile::Secondary, false);
+        device.write_register(0x20, 0x01, OplRegisterFile::Primary, false);
+        let recorder = device.stop_recording::<VgmRecorder>().unwrap();
+        let vgm = VgmFile::parse(&recorder.finish()).unwrap();
+        assert_eq!(vgm.chip(), Some(VgmChip::Ym3812));
+        assert_eq!(vgm.commands(), &[0x5A, 0x20, 0x01, 0x66]);
+
+        // OPL3 mode enabled before recording starts is taken from the registers.
+        device.write_register(0x05, 0x01, OplRegisterFile::Secondary, false);
+        device.start_recording(Box::new(VgmRecorder::new()));
+        device.write_register(0x20, 0x01, OplRegisterFile::Primary, false);
+        let recorder = device.stop_recording::<VgmRecorder>().unwrap();
+        let vgm = VgmFile::parse(&recorder.finish()).unwrap();
+        assert_eq!(vgm.chip(), Some(VgmChip::Ymf262));
+    }
+
+    #[test]
+    fn waits_and_loops() {
+        // 735 + 16 + 1 samples, then a 441 sample looped section.
+        let commands = [0x62, 0x7F, 0x80, 0x81, 0x61, 0xB9, 0x01, 0x66];
+        let vgm = VgmFile::parse(&vgm_file(&commands, Some(4))).unwrap();
+        let mut sequencer = VgmSequencer::new(vgm);
+        sequencer.set_loop_count(Some(2));
+        let mut player = VgmPlayer::new(sequencer, VGM_SAMPLE_RATE);
+        let samples = player.render_to_vec(VGM_SAMPLE_RATE as usize).unwrap();
+        assert_eq!(player.sequencer().loops_played(), 2);
+        assert_eq!(player.sequencer().position_samples(), 752 + 441 * 3);
+        assert_eq!(samples.len() / 2, 752 + 441 * 3);
+    }
+
+    #[test]
+    fn dual_chip_mix() {
+        // A note keyed on by the second chip only.
+        let mut commands = Vec::new();
+        for (reg, value) in [
+            (0x20, 0x01),
+            (0x23, 0x01),
+            (0x63, 0xF0),
+            (0x83, 0x0F),
+            (0xA0, 0x44),
+            (0xB0, 0x32),
+        ] {
+            commands.extend_from_slice(&[VGM_CMD_YM3812 + VGM_SECOND_CHIP_OFFSET, reg, value]);
+        }
+        commands.extend_from_slice(&[VGM_CMD_WAIT_NTSC, VGM_CMD_END]);
+        let mut data = vgm_file(&commands, None);
+        let clock = 3579545u32 | VGM_DUAL_CHIP_BIT;
+        data[VGM_YM3812_CLOCK..VGM_YM3812_CLOCK + 4].copy_from_slice(&clock.to_le_bytes());
+
+        let vgm = VgmFile::parse(&data).unwrap();
+        assert!(vgm.dual_chip);
+        let mut player = VgmPlayer::new(VgmSequencer::new(vgm), VGM_SAMPLE_RATE);
+        let samples = player.render_to_vec(VGM_SAMPLE_RATE as usize).unwrap();
+        assert_eq!(samples.len() / 2, 735);
+        assert!(samples.iter().any(|&sample| sample != 0));
+        assert_eq!(
+            player
+                .device()
+                .read_register(0xB0, OplRegisterFile::Primary),
+            0
+        );
+        let second = player.sequencer().second_device().unwrap();
+        assert_eq!(second.read_register(0xB0, OplRegisterFile::Primary), 0x32);
+    }
+}
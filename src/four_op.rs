@@ -0,0 +1,363 @@
+//! OPL3 4-operator channels.
+//!
+//! In OPL3 mode, six pairs of channels can be joined to play a single voice with four operators.
+//! Each pair is enabled by a bit of register 0x104, and the way the four operators are connected
+//! is selected by the connection (CNT) bits of both channels in registers 0xC0 to 0xC8. The pairs
+//! are channels 0 and 3, 1 and 4, 2 and 5 in the primary register file, and the same channels in
+//! the secondary register file. The frequency and key on bit of the first (primary) channel of a
+//! pair control all four operators.
+//!
+//! This module adds methods to `Opl3Device` for enabling and disabling each pair, selecting one of
+//! the four algorithms, loading 4-op patches, and setting the frequency and key on state of a pair.
+//! Pairs are numbered 0 to 5 in the order of their bits in register 0x104. 4-op mode only takes
+//! effect while the OPL3 mode bit of register 0x105 is set.
+//!
+//! # Example
+//!
+//! ```
+//! use opl3_rs::four_op::{OplFourOpAlgorithm, OplFourOpPatch};
+//! use opl3_rs::{Opl3Device, OplOperatorPatch, OplRegisterFile};
+//!
+//! let mut device = Opl3Device::new(44100);
+//! device.write_register(0x05, 0x01, OplRegisterFile::Secondary, false);
+//! device.set_four_op(0, true).unwrap();
+//!
+//! let operator = OplOperatorPatch {
+//!     characteristic: 0x01,
+//!     level: 0x10,
+//!     attack_decay: 0xF2,
+//!     sustain_release: 0x54,
+//!     waveform: 0x00,
+//! };
+//! let patch = OplFourOpPatch {
+//!     operators: [operator; 4],
+//!     algorithm: OplFourOpAlgorithm::FmAm,
+//!     feedback: 0x36,
+//! };
+//! device.set_four_op_patch(0, &patch).unwrap();
+//! device.set_four_op_frequency(0, 0x244, 4).unwrap();
+//! device.set_four_op_key(0, true).unwrap();
+//! ```
+
+use crate::voice::FOUR_OP_PAIRS;
+use crate::{Opl3Device, OplError, OplOperatorPatch, OplRegisterFile};
+
+const FOUR_OP_REGISTER: u8 = 0x04;
+const OP_OFFSETS: [u8; 9] = [0x00, 0x01, 0x02, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x12];
+
+/// The ways the four operators of a 4-op channel pair can be connected. The operators are
+/// numbered 1 to 4: the modulator and carrier of the primary channel, then the modulator and
+/// carrier of the secondary channel.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OplFourOpAlgorithm {
+    /// All four operators in series, with operator 4 as the only carrier.
+    #[default]
+    FmFm,
+    /// Operator 1 alone, added to operators 2 to 4 in series.
+    AmFm,
+    /// Operators 1 and 2 in series, added to operators 3 and 4 in series.
+    FmAm,
+    /// Operator 1 alone, added to operators 2 and 3 in series, added to operator 4 alone.
+    AmAm,
+}
+
+impl OplFourOpAlgorithm {
+    /// Return the algorithm selected by the connection bits of the primary and secondary
+    /// channels of a pair.
+    pub fn from_connections(primary: bool, secondary: bool) -> Self {
+        match (primary, secondary) {
+            (false, false) => OplFourOpAlgorithm::FmFm,
+            (true, false) => OplFourOpAlgorithm::AmFm,
+            (false, true) => OplFourOpAlgorithm::FmAm,
+            (true, true) => OplFourOpAlgorithm::AmAm,
+        }
+    }
+
+    /// Return the connection bits of the primary and secondary channels that select the
+    /// algorithm.
+    pub fn connections(self) -> (bool, bool) {
+        match self {
+            OplFourOpAlgorithm::FmFm => (false, false),
+            OplFourOpAlgorithm::AmFm => (true, false),
+            OplFourOpAlgorithm::FmAm => (false, true),
+            OplFourOpAlgorithm::AmAm => (true, true),
+        }
+    }
+
+    /// Return which of the four operators are carriers, whose levels set the loudness of the
+    /// voice.
+    pub fn carriers(self) -> [bool; 4] {
+        match self {
+            OplFourOpAlgorithm::FmFm => [false, false, false, true],
+            OplFourOpAlgorithm::AmFm => [true, false, false, true],
+            OplFourOpAlgorithm::FmAm => [false, true, false, true],
+            OplFourOpAlgorithm::AmAm => [true, false, true, true],
+        }
+    }
+}
+
+/// The settings of a 4-op voice.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OplFourOpPatch {
+    /// The four operators, in the order of the modulator and carrier of the primary channel, then
+    /// the modulator and carrier of the secondary channel.
+    pub operators: [OplOperatorPatch; 4],
+    /// How the operators are connected.
+    pub algorithm: OplFourOpAlgorithm,
+    /// The output select and feedback bits of the primary channel (register 0xC0). The output
+    /// select bits are also written to the secondary channel, and the connection bit is set by
+    /// `algorithm`.
+    pub feedback: u8,
+}
+
+impl Opl3Device {
+    /// Enable or disable 4-op mode for a channel pair.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair`   - The pair, from 0 to 5.
+    /// * `enable` - Whether to join the channels of the pair.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or `OplError::ChannelOutOfRange` if the pair
+    /// number is out of range.
+    pub fn set_four_op(&mut self, pair: usize, enable: bool) -> Result<(), OplError> {
+        let bit = Self::four_op_bit(pair)?;
+        let mask = self.four_op_mask();
+        let mask = if enable { mask | bit } else { mask & !bit };
+        self.write_register(FOUR_OP_REGISTER, mask, OplRegisterFile::Secondary, true);
+        Ok(())
+    }
+
+    /// Returns true if 4-op mode is enabled for a channel pair. Out of range pairs return false.
+    pub fn is_four_op(&self, pair: usize) -> bool {
+        Self::four_op_bit(pair).is_ok_and(|bit| self.four_op_mask() & bit != 0)
+    }
+
+    /// Return the value of register 0x104, which has one bit for each pair in 4-op mode.
+    pub fn four_op_mask(&self) -> u8 {
+        self.read_register(FOUR_OP_REGISTER, OplRegisterFile::Secondary) & 0x3F
+    }
+
+    /// Select the algorithm of a channel pair by setting the connection bits of both of its
+    /// channels. The other bits of registers 0xC0 to 0xC8 are left unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair`      - The pair, from 0 to 5.
+    /// * `algorithm` - The algorithm to select.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or `OplError::ChannelOutOfRange` if the pair
+    /// number is out of range.
+    pub fn set_four_op_algorithm(
+        &mut self,
+        pair: usize,
+        algorithm: OplFourOpAlgorithm,
+    ) -> Result<(), OplError> {
+        let (primary, secondary) = Self::four_op_channels(pair)?;
+        let (cnt1, cnt2) = algorithm.connections();
+        for (channel, cnt) in [(primary, cnt1), (secondary, cnt2)] {
+            let (reg, file) = Self::channel_register(0xC0, channel);
+            let value = (self.read_register(reg, file) & !0x01) | cnt as u8;
+            self.write_register(reg, value, file, true);
+        }
+        Ok(())
+    }
+
+    /// Return the algorithm selected by the connection bits of a channel pair.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair` - The pair, from 0 to 5.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the algorithm or `OplError::ChannelOutOfRange` if the pair
+    /// number is out of range.
+    pub fn four_op_algorithm(&self, pair: usize) -> Result<OplFourOpAlgorithm, OplError> {
+        let (primary, secondary) = Self::four_op_channels(pair)?;
+        let cnt = |channel| {
+            let (reg, file) = Self::channel_register(0xC0, channel);
+            self.read_register(reg, file) & 0x01 != 0
+        };
+        Ok(OplFourOpAlgorithm::from_connections(
+            cnt(primary),
+            cnt(secondary),
+        ))
+    }
+
+    /// Load a patch into the four operators of a channel pair and select its algorithm. 4-op mode
+    /// is not enabled for the pair by this function; see `set_four_op`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair`  - The pair, from 0 to 5.
+    /// * `patch` - The patch to load.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or `OplError::ChannelOutOfRange` if the pair
+    /// number is out of range.
+    pub fn set_four_op_patch(
+        &mut self,
+        pair: usize,
+        patch: &OplFourOpPatch,
+    ) -> Result<(), OplError> {
+        let (primary, secondary) = Self::four_op_channels(pair)?;
+        let offsets = [primary, secondary].map(Self::operator_offset);
+        for (i, operator) in patch.operators.iter().enumerate() {
+            self.write_operator(offsets[i / 2] + (i % 2) as u16 * 3, operator);
+        }
+
+        let (cnt1, cnt2) = patch.algorithm.connections();
+        let values = [
+            (patch.feedback & 0xFE) | cnt1 as u8,
+            (patch.feedback & 0xF0) | cnt2 as u8,
+        ];
+        for (channel, value) in [primary, secondary].into_iter().zip(values) {
+            let (reg, file) = Self::channel_register(0xC0, channel);
+            self.write_register(reg, value, file, true);
+        }
+        Ok(())
+    }
+
+    /// Set the frequency of a channel pair through its primary channel. The key on bit is left
+    /// unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair`  - The pair, from 0 to 5.
+    /// * `fnum`  - The 10-bit F-number.
+    /// * `block` - The 3-bit block (octave).
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or `OplError::ChannelOutOfRange` if the pair
+    /// number is out of range.
+    pub fn set_four_op_frequency(
+        &mut self,
+        pair: usize,
+        fnum: u16,
+        block: u8,
+    ) -> Result<(), OplError> {
+        let (primary, _) = Self::four_op_channels(pair)?;
+        let (a0, file) = Self::channel_register(0xA0, primary);
+        let (b0, _) = Self::channel_register(0xB0, primary);
+        let key = self.read_register(b0, file) & 0x20;
+        let value = key | ((block & 0x07) << 2) | ((fnum >> 8) & 0x03) as u8;
+        self.write_register(a0, fnum as u8, file, true);
+        self.write_register(b0, value, file, true);
+        Ok(())
+    }
+
+    /// Key a channel pair on or off through its primary channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair` - The pair, from 0 to 5.
+    /// * `on`   - Whether to key the voice on.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or `OplError::ChannelOutOfRange` if the pair
+    /// number is out of range.
+    pub fn set_four_op_key(&mut self, pair: usize, on: bool) -> Result<(), OplError> {
+        let (primary, _) = Self::four_op_channels(pair)?;
+        let (b0, file) = Self::channel_register(0xB0, primary);
+        let value = self.read_register(b0, file) & !0x20;
+        self.write_register(b0, value | if on { 0x20 } else { 0 }, file, true);
+        Ok(())
+    }
+
+    fn four_op_bit(pair: usize) -> Result<u8, OplError> {
+        if pair < FOUR_OP_PAIRS.len() {
+            Ok(1 << pair)
+        } else {
+            Err(OplError::ChannelOutOfRange)
+        }
+    }
+
+    /// Return the primary and secondary channels of a pair, from 0 to 17.
+    fn four_op_channels(pair: usize) -> Result<(usize, usize), OplError> {
+        let primary = *FOUR_OP_PAIRS.get(pair).ok_or(OplError::ChannelOutOfRange)?;
+        Ok((primary, primary + 3))
+    }
+
+    /// Return the register and register file of a channel register such as 0xC0.
+    fn channel_register(base: u8, channel: usize) -> (u8, OplRegisterFile) {
+        let file = if channel < 9 {
+            OplRegisterFile::Primary
+        } else {
+            OplRegisterFile::Secondary
+        };
+        (base + (channel % 9) as u8, file)
+    }
+
+    /// Return the 9-bit offset of the modulator registers of a channel.
+    fn operator_offset(channel: usize) -> u16 {
+        (channel / 9 * 0x100) as u16 + OP_OFFSETS[channel % 9] as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_and_algorithms() {
+        let mut device = Opl3Device::new(44100);
+        device.write_register(0xC3, 0x31, OplRegisterFile::Primary, false);
+        device.set_four_op(0, true).unwrap();
+        device.set_four_op(5, true).unwrap();
+        device.set_four_op(0, false).unwrap();
+        assert_eq!(device.four_op_mask(), 0x20);
+        assert!(device.is_four_op(5) && !device.is_four_op(0));
+        assert!(matches!(
+            device.set_four_op(6, true),
+            Err(OplError::ChannelOutOfRange)
+        ));
+
+        device
+            .set_four_op_algorithm(0, OplFourOpAlgorithm::AmFm)
+            .unwrap();
+        assert_eq!(device.read_register(0xC0, OplRegisterFile::Primary), 0x01);
+        assert_eq!(device.read_register(0xC3, OplRegisterFile::Primary), 0x30);
+        for algorithm in [
+            OplFourOpAlgorithm::FmFm,
+            OplFourOpAlgorithm::FmAm,
+            OplFourOpAlgorithm::AmAm,
+        ] {
+            device.set_four_op_algorithm(4, algorithm).unwrap();
+            assert_eq!(device.four_op_algorithm(4).unwrap(), algorithm);
+        }
+    }
+
+    #[test]
+    fn patch_and_key() {
+        let mut device = Opl3Device::new(44100);
+        let mut patch = OplFourOpPatch {
+            algorithm: OplFourOpAlgorithm::FmAm,
+            feedback: 0x3E,
+            ..Default::default()
+        };
+        for (i, operator) in patch.operators.iter_mut().enumerate() {
+            operator.level = i as u8 + 1;
+        }
+        // Pair 4 is channels 10 and 13, the second and fifth channels of the secondary file.
+        device.set_four_op_patch(4, &patch).unwrap();
+        let reg = |reg| device.read_register(reg, OplRegisterFile::Secondary);
+        assert_eq!([reg(0x41), reg(0x44), reg(0x49), reg(0x4C)], [1, 2, 3, 4]);
+        assert_eq!((reg(0xC1), reg(0xC4)), (0x3E, 0x31));
+
+        device.set_four_op_frequency(4, 0x2AE, 5).unwrap();
+        device.set_four_op_key(4, true).unwrap();
+        device.set_four_op_frequency(4, 0x158, 5).unwrap();
+        let reg = |reg| device.read_register(reg, OplRegisterFile::Secondary);
+        assert_eq!((reg(0xA1), reg(0xB1)), (0x58, 0x35));
+        device.set_four_op_key(4, false).unwrap();
+        assert_eq!(device.read_register(0xB1, OplRegisterFile::Secondary), 0x15);
+    }
+}
@@ -0,0 +1,54 @@
+/*
+ * The layout of the Nuked-OPL3 structs as compiled, for the layout test of the Rust bindings.
+ * The order of the entries must match the test in lib.rs.
+ */
+
+#include <stddef.h>
+#include "nuked-opl3/opl3.h"
+
+#define ALIGNOF(type) offsetof(struct { char c; type t; }, t)
+
+static const size_t opl3_layout[] = {
+    sizeof(opl3_slot),
+    ALIGNOF(opl3_slot),
+    offsetof(opl3_slot, eg_out),
+    offsetof(opl3_slot, eg_gen),
+    offsetof(opl3_slot, reg_mult),
+    offsetof(opl3_slot, slot_num),
+    sizeof(opl3_channel),
+    ALIGNOF(opl3_channel),
+    offsetof(opl3_channel, chtype),
+    offsetof(opl3_channel, ch_num),
+    sizeof(opl3_writebuf),
+    ALIGNOF(opl3_writebuf),
+    offsetof(opl3_writebuf, reg),
+    offsetof(opl3_writebuf, data),
+    sizeof(opl3_chip),
+    ALIGNOF(opl3_chip),
+    offsetof(opl3_chip, slot),
+    offsetof(opl3_chip, timer),
+    offsetof(opl3_chip, eg_timer),
+    offsetof(opl3_chip, noise),
+    offsetof(opl3_chip, zeromod),
+    offsetof(opl3_chip, mixbuff),
+    offsetof(opl3_chip, rm_tc_bit5),
+#if OPL_ENABLE_STEREOEXT
+    offsetof(opl3_chip, stereoext),
+#endif
+    offsetof(opl3_chip, rateratio),
+    offsetof(opl3_chip, samples),
+    offsetof(opl3_chip, writebuf_samplecnt),
+    offsetof(opl3_chip, writebuf_cur),
+    offsetof(opl3_chip, writebuf_last),
+    offsetof(opl3_chip, writebuf_lasttime),
+    offsetof(opl3_chip, writebuf),
+};
+
+size_t opl3_rs_layout(size_t index)
+{
+    if (index >= sizeof(opl3_layout) / sizeof(opl3_layout[0]))
+    {
+        return 0;
+    }
+    return opl3_layout[index];
+}
@@ -5,6 +5,7 @@
 // Nuked OPL3 Copyright (C) 2013-2020 Nuke.YKT
 #![warn(missing_docs)]
 #![doc = include_str!("./docs.md")]
+#![no_std]
 
 /*
 * Nuked OPL3 is free software: you can redistribute it and/or modify
@@ -32,9 +33,61 @@
 *          YMF262 and VRC VII decaps and die shots.
 */
 
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+use alloc::boxed::Box;
 use thiserror::Error;
 
+#[cfg(not(any(
+    feature = "prebuilt-bindings",
+    feature = "bindgen",
+    feature = "rust-core"
+)))]
+compile_error!("one of the prebuilt-bindings, bindgen or rust-core features must be enabled");
+
+// Buffered writes are queued by Opl3Chip itself, so not every binding is used.
+#[cfg(not(any(feature = "rust-core", feature = "bindgen")))]
+#[allow(dead_code)]
+#[cfg_attr(feature = "stereoext", path = "bindings_stereoext.rs")]
 mod bindings;
+#[cfg(all(feature = "bindgen", not(feature = "rust-core")))]
+#[allow(
+    dead_code,
+    non_snake_case,
+    non_camel_case_types,
+    non_upper_case_globals
+)]
+mod bindings {
+    include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+}
+#[cfg(feature = "rust-core")]
+use rust_core as bindings;
+pub mod backend;
+pub mod config;
+pub mod formats;
+pub mod four_op;
+pub mod midi;
+pub mod player;
+pub mod recorder;
+pub mod rhythm;
+#[cfg(target_has_atomic = "ptr")]
+mod ring;
+// Without the rust-core feature, the Rust core is only used by its differential tests.
+#[cfg(any(feature = "rust-core", test))]
+#[cfg_attr(not(feature = "rust-core"), allow(dead_code))]
+mod rust_core;
+#[cfg(target_has_atomic = "ptr")]
+pub mod sample_ring;
+#[cfg(target_has_atomic = "ptr")]
+pub mod shared;
+#[cfg(feature = "stereoext")]
+pub mod stereo;
+pub mod voice;
+
+use backend::OplBackend;
+use recorder::OplRecorder;
 
 unsafe impl Send for Opl3Chip {}
 
@@ -65,12 +118,38 @@ pub enum OplError {
     #[error("Register number out of range")]
     /// The specified register number is out of range.
     RegisterOutOfRange,
+    #[error("Channel number out of range")]
+    /// The specified channel or 4-op channel pair number is out of range.
+    ChannelOutOfRange,
+    #[error("Write buffer is full")]
+    /// A buffered register write was rejected because the write buffer is full.
+    WriteBufferFull,
+    #[error("Command queue is full")]
+    /// A register write to a shared device was rejected because the queue of commands for its
+    /// audio handle is full.
+    CommandQueueFull,
     #[error("Failed to lock mutex")]
     /// Failed to lock the mutex for the OPL3 device.
     MutexLockFailed,
+    #[error("File signature not recognized")]
+    /// The file provided to a format parser did not have the expected signature.
+    BadSignature,
+    #[error("Unsupported file version")]
+    /// The file provided to a format parser is of a version that is not supported.
+    UnsupportedVersion,
+    #[error("Unexpected end of file")]
+    /// The file provided to a format parser ended before all expected data was read.
+    UnexpectedEof,
+    #[error("Invalid file: {0}")]
+    /// The file provided to a format parser contained invalid data.
+    InvalidFile(&'static str),
+    #[error("Too many registers for the file format")]
+    /// A file could not be written because it uses more distinct registers than its format can
+    /// encode.
+    TooManyRegisters,
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 /// The `Opl3RegisterFile` enum represents the two register files available on the OPL3 chip.
 /// If in OPL2 mode, only the primary register file is available.
 pub enum OplRegisterFile {
@@ -80,6 +159,54 @@ pub enum OplRegisterFile {
     Secondary,
 }
 
+/// The stage of an operator's envelope generator.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OplEnvelopeStage {
+    /// The envelope is rising after key on.
+    Attack,
+    /// The envelope is falling to the sustain level.
+    Decay,
+    /// The envelope is held at the sustain level.
+    Sustain,
+    /// The envelope is falling after key off, or the operator has never been keyed on.
+    Release,
+}
+
+/// A snapshot of the envelope generator of an operator.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OplEnvelope {
+    /// The current stage of the envelope.
+    pub stage: OplEnvelopeStage,
+    /// The attenuation applied by the envelope, from 0 (full volume) to 511 (silent) in steps of
+    /// 0.1875 dB.
+    pub attenuation: u16,
+}
+
+impl OplEnvelope {
+    /// The attenuation of an operator whose envelope has fully decayed.
+    pub const SILENT: u16 = 0x1FF;
+
+    /// Returns true if the operator has finished releasing and is no longer audible.
+    pub fn is_silent(&self) -> bool {
+        self.stage == OplEnvelopeStage::Release && self.attenuation >= Self::SILENT
+    }
+}
+
+/// The register values of a single operator.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OplOperatorPatch {
+    /// Tremolo, vibrato, sustain, key scale rate and frequency multiplier (register 0x20).
+    pub characteristic: u8,
+    /// Key scale level and total level (register 0x40).
+    pub level: u8,
+    /// Attack and decay rates (register 0x60).
+    pub attack_decay: u8,
+    /// Sustain level and release rate (register 0x80).
+    pub sustain_release: u8,
+    /// Waveform select (register 0xE0).
+    pub waveform: u8,
+}
+
 /// The `Opl3DeviceStats` struct contains statistics about the OPL3 device.
 /// It can be retrieved via the `get_stats` function on `Opl3Device`.
 #[derive(Copy, Clone, Default)]
@@ -96,7 +223,7 @@ pub struct Opl3DeviceStats {
 }
 
 /// The `Opl3Device` maintains two internal timers.
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 struct OplTimer {
     enabled: bool,
     masked: bool,
@@ -167,18 +294,102 @@ impl OplTimer {
     }
 }
 
+/// The two timers of an OPL3 and the status register bits they drive. This state is kept by
+/// `Opl3Device`, and mirrored by `shared::OplControlHandle` so that the status register can be read
+/// without waiting for the audio thread.
+#[derive(Clone, Debug)]
+pub(crate) struct OplTimers {
+    timers: [OplTimer; 2],
+    usec_accumulator: f64,
+}
+
+impl OplTimers {
+    pub(crate) fn new() -> Self {
+        OplTimers {
+            timers: [
+                OplTimer::new(OPL_TIMER_1_RATE),
+                OplTimer::new(OPL_TIMER_2_RATE),
+            ],
+            usec_accumulator: 0.0,
+        }
+    }
+
+    /// Advance the timers by the given number of microseconds.
+    pub(crate) fn run(&mut self, usec: f64) {
+        self.usec_accumulator += usec;
+        while self.usec_accumulator >= OPL_TICK_RATE {
+            self.usec_accumulator -= OPL_TICK_RATE;
+            self.timers[0].tick(OPL_TICK_RATE);
+            self.timers[1].tick(OPL_TICK_RATE);
+        }
+    }
+
+    /// Return the value of the status register.
+    pub(crate) fn status(&self) -> u8 {
+        let mut status_reg = 0;
+
+        status_reg |= if self.timers[0].is_elapsed() {
+            OPL_TIMER_1_MASK
+        } else {
+            0
+        };
+
+        status_reg |= if self.timers[1].is_elapsed() {
+            OPL_TIMER_2_MASK
+        } else {
+            0
+        };
+
+        status_reg |= if self.timers[0].is_elapsed() || self.timers[1].is_elapsed() {
+            OPL_IRQ_FLAG
+        } else {
+            0
+        };
+
+        status_reg
+    }
+
+    /// Handle a write to a register of the primary register file. Nuked-OPL3 doesn't emulate the
+    /// timer registers, so they are intercepted here.
+    pub(crate) fn write_register(&mut self, reg: u8, value: u8) {
+        match reg {
+            OPL_TIMER_1_REGISTER => {
+                self.timers[0].counter = value;
+            }
+            OPL_TIMER_2_REGISTER => {
+                self.timers[1].counter = value;
+            }
+            OPL_TIMER_CONTROL_REGISTER => {
+                if (value & OPL_IRQ_FLAG) != 0 {
+                    // Reset the timer and IRQ flags in the status register.
+                    // All other bits are ignored when this bit is set.
+                    self.timers[0].reset_elapsed();
+                    self.timers[1].reset_elapsed();
+                } else {
+                    // Mask & enable the timers based on the timer start bits.
+                    self.timers[0].mask((value & OPL_TIMER_1_MASK) != 0);
+                    self.timers[1].mask((value & OPL_TIMER_2_MASK) != 0);
+                    self.timers[0].enable((value & OPL_TIMER_1_START) != 0);
+                    self.timers[1].enable((value & OPL_TIMER_2_START) != 0);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 /// The `Opl3Device` struct provides convenience functions for fully implementing an OPL3 device on
-/// top of Nuked-OPL3.
+/// top of Nuked-OPL3, or another synthesis backend (see the `backend` module).
 /// By keeping a copy of all registers written, we can implement a read_register function.
 pub struct Opl3Device {
     addr_reg: [u8; 2],
     sample_rate: u32,
     registers: [[u8; 256]; 2],
-    timers: [OplTimer; 2],
+    timers: OplTimers,
     stats: Opl3DeviceStats,
-    inner_chip: Opl3Chip,
+    inner_chip: Box<dyn OplBackend>,
     samples_fpart: f64,
-    usec_accumulator: f64,
+    recorder: Option<Box<dyn OplRecorder>>,
 }
 
 impl Opl3Device {
@@ -187,21 +398,43 @@ impl Opl3Device {
     /// It provides the rest of an OPL3 implementation on top of the chip, including register
     /// tracking and a read_register function.
     pub fn new(sample_rate: u32) -> Self {
+        Self::with_backend(Box::new(Opl3Chip::new(sample_rate)), sample_rate)
+    }
+
+    /// Create a new OPL3 device instance that synthesizes sound with the given backend instead of
+    /// Nuked-OPL3.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend`     - The synthesis backend, which should already be set up for `sample_rate`.
+    /// * `sample_rate` - The sample rate of the generated audio.
+    ///
+    /// # Returns
+    ///
+    /// The new Opl3Device instance.
+    pub fn with_backend(backend: Box<dyn OplBackend>, sample_rate: u32) -> Self {
         Opl3Device {
             addr_reg: [0, 0],
             sample_rate,
             registers: [[0; 256], [0; 256]],
-            timers: [
-                OplTimer::new(OPL_TIMER_1_RATE),
-                OplTimer::new(OPL_TIMER_2_RATE),
-            ],
+            timers: OplTimers::new(),
             stats: Opl3DeviceStats::default(),
-            inner_chip: Opl3Chip::new(sample_rate),
+            inner_chip: backend,
             samples_fpart: 0.0,
-            usec_accumulator: 0.0,
+            recorder: None,
         }
     }
 
+    /// Return a reference to the synthesis backend, if it is of type `B`.
+    pub fn backend<B: OplBackend>(&self) -> Option<&B> {
+        self.inner_chip.as_any().downcast_ref::<B>()
+    }
+
+    /// Return a mutable reference to the synthesis backend, if it is of type `B`.
+    pub fn backend_mut<B: OplBackend>(&mut self) -> Option<&mut B> {
+        self.inner_chip.as_any_mut().downcast_mut::<B>()
+    }
+
     /// Retrieve the statistics for the OPL3 device in the form of an `Opl3DeviceStats` struct.
     ///
     /// # Returns
@@ -221,17 +454,16 @@ impl Opl3Device {
     /// The Opl3Device maintains a fractional accumulator, so you can use this returned value to
     /// determine how many samples to generate.
     pub fn run(&mut self, usec: f64) -> usize {
-        self.usec_accumulator += usec;
-        while self.usec_accumulator >= OPL_TICK_RATE {
-            self.usec_accumulator -= OPL_TICK_RATE;
-            self.timers[0].tick(OPL_TICK_RATE);
-            self.timers[1].tick(OPL_TICK_RATE);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.advance(usec);
         }
 
+        self.timers.run(usec);
+
         let samples_f = (usec / 1_000_000.0 * self.sample_rate as f64) + self.samples_fpart;
 
         let samples = samples_f as usize;
-        self.samples_fpart = samples_f - samples_f.floor();
+        self.samples_fpart = samples_f - libm::floor(samples_f);
 
         samples
     }
@@ -242,28 +474,7 @@ impl Opl3Device {
     /// emulate this timer state, it is necessary to call run() on the OPL3 device periodically.
     pub fn read_status(&mut self) -> u8 {
         self.stats.status_reads = self.stats.status_reads.saturating_add(1);
-
-        let mut status_reg = 0;
-
-        status_reg |= if self.timers[0].is_elapsed() {
-            OPL_TIMER_1_MASK
-        } else {
-            0
-        };
-
-        status_reg |= if self.timers[1].is_elapsed() {
-            OPL_TIMER_2_MASK
-        } else {
-            0
-        };
-
-        status_reg |= if self.timers[0].is_elapsed() || self.timers[1].is_elapsed() {
-            OPL_IRQ_FLAG
-        } else {
-            0
-        };
-
-        status_reg
+        self.timers.status()
     }
 
     /// Write a byte to the OPL3 device's Address register.
@@ -335,6 +546,46 @@ impl Opl3Device {
         }
     }
 
+    /// Return the state of the envelope generator of one of a channel's operators. This can be
+    /// used to tell when a released note has become silent.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel`  - The channel, from 0 to 17. Channels 9 to 17 are in the secondary register
+    ///   file.
+    /// * `operator` - 0 for the channel's first operator (the modulator), 1 for the second.
+    ///
+    /// # Returns
+    ///
+    /// The envelope state, or None if the channel or operator is out of range.
+    pub fn operator_envelope(&self, channel: usize, operator: usize) -> Option<OplEnvelope> {
+        // The first operator index of each channel, as used by Nuked-OPL3.
+        const CHANNEL_SLOTS: [usize; 18] = [
+            0, 1, 2, 6, 7, 8, 12, 13, 14, 18, 19, 20, 24, 25, 26, 30, 31, 32,
+        ];
+        if operator > 1 {
+            return None;
+        }
+        self.inner_chip
+            .envelope(CHANNEL_SLOTS.get(channel)? + operator * 3)
+    }
+
+    /// Write the registers of an operator, using the 9-bit OPL3 address of its register offset
+    /// where bit 8 selects the secondary register file.
+    pub(crate) fn write_operator(&mut self, offset: u16, operator: &OplOperatorPatch) {
+        let file = if offset & 0x100 != 0 {
+            OplRegisterFile::Secondary
+        } else {
+            OplRegisterFile::Primary
+        };
+        let offset = offset as u8;
+        self.write_register(0x20 + offset, operator.characteristic, file, true);
+        self.write_register(0x40 + offset, operator.level, file, true);
+        self.write_register(0x60 + offset, operator.attack_decay, file, true);
+        self.write_register(0x80 + offset, operator.sustain_release, file, true);
+        self.write_register(0xE0 + offset, operator.waveform, file, true);
+    }
+
     /// Write to the specified register directly. This will update the internal state of the
     /// Opl3Device so that the register value can later be read.
     ///
@@ -364,29 +615,11 @@ impl Opl3Device {
         // We need to intercept certain register addresses that Nuked-OPL3 doesn't emulate, namely
         // the timer registers.
         if let OplRegisterFile::Primary = file {
-            match reg {
-                OPL_TIMER_1_REGISTER => {
-                    self.timers[0].counter = value;
-                }
-                OPL_TIMER_2_REGISTER => {
-                    self.timers[1].counter = value;
-                }
-                OPL_TIMER_CONTROL_REGISTER => {
-                    if (value & OPL_IRQ_FLAG) != 0 {
-                        // Reset the timer and IRQ flags in the status register.
-                        // All other bits are ignored when this bit is set.
-                        self.timers[0].reset_elapsed();
-                        self.timers[1].reset_elapsed();
-                    } else {
-                        // Mask & enable the timers based on the timer start bits.
-                        self.timers[0].mask((value & OPL_TIMER_1_MASK) != 0);
-                        self.timers[1].mask((value & OPL_TIMER_2_MASK) != 0);
-                        self.timers[0].enable((value & OPL_TIMER_1_START) != 0);
-                        self.timers[1].enable((value & OPL_TIMER_2_START) != 0);
-                    }
-                }
-                _ => {}
-            }
+            self.timers.write_register(reg, value);
+        }
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.write(reg, value, file);
         }
 
         self.stats.data_writes = self.stats.data_writes.saturating_add(1);
@@ -397,6 +630,113 @@ impl Opl3Device {
         }
     }
 
+    /// Write to the specified register in buffered mode, unless the write buffer is full. The
+    /// internal state of the Opl3Device is only updated if the write is accepted.
+    ///
+    /// # Arguments
+    ///
+    /// * `reg` - The internal register index to write.
+    /// * `value` - The value to write to the register.
+    /// * `file` - The register file to write to.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or `OplError::WriteBufferFull` if the write
+    /// buffer is full. Call `flush_writes` or generate samples to make room.
+    pub fn try_write_register(
+        &mut self,
+        reg: u8,
+        value: u8,
+        file: OplRegisterFile,
+    ) -> Result<(), OplError> {
+        if self.inner_chip.is_write_buffer_full() {
+            return Err(OplError::WriteBufferFull);
+        }
+        self.write_register(reg, value, file, true);
+        Ok(())
+    }
+
+    /// Return the number of buffered writes that have not yet been performed by the chip.
+    pub fn pending_writes(&self) -> usize {
+        self.inner_chip.pending_writes()
+    }
+
+    /// Perform all pending buffered writes immediately. See `Opl3Chip::flush_writes`.
+    pub fn flush_writes(&mut self) {
+        self.inner_chip.flush_writes();
+    }
+
+    /// Return the delay between buffered writes, in samples at the native rate of 49716 Hz.
+    pub fn write_delay(&self) -> u64 {
+        self.inner_chip.write_delay()
+    }
+
+    /// Set the delay between buffered writes, in samples at the native rate of 49716 Hz.
+    /// See `Opl3Chip::set_write_delay`.
+    pub fn set_write_delay(&mut self, delay: u64) {
+        self.inner_chip.set_write_delay(delay);
+    }
+
+    /// Set a callback to be called when a buffered write finds the write buffer full.
+    /// See `Opl3Chip::set_overflow_callback`.
+    pub fn set_overflow_callback(&mut self, callback: Option<Box<OplOverflowCallback>>) {
+        self.inner_chip.set_overflow_callback(callback);
+    }
+
+    /// Attach a recorder to the device and start recording. The recorder will receive every
+    /// register write made via `write_register` or `write_data`, and the time elapsed via `run`.
+    /// Any previously attached recorder is stopped and returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `recorder` - The recorder to attach, such as a `DroRecorder`.
+    ///
+    /// # Returns
+    ///
+    /// The previously attached recorder, if any.
+    pub fn start_recording(
+        &mut self,
+        mut recorder: Box<dyn OplRecorder>,
+    ) -> Option<Box<dyn OplRecorder>> {
+        let previous = self.detach_recorder();
+        recorder.start(&self.registers);
+        self.recorder = Some(recorder);
+        previous
+    }
+
+    /// Stop recording and return the attached recorder, if it is of type `R`.
+    /// If a recorder of a different type is attached, it is left attached and None is returned.
+    ///
+    /// # Returns
+    ///
+    /// An Option containing the detached recorder.
+    pub fn stop_recording<R: OplRecorder>(&mut self) -> Option<R> {
+        if !self.recorder.as_deref_mut()?.as_any_mut().is::<R>() {
+            return None;
+        }
+        let recorder = self.detach_recorder()?;
+        recorder.into_any().downcast::<R>().ok().map(|r| *r)
+    }
+
+    /// Return a mutable reference to the attached recorder, if it is of type `R`.
+    pub fn recorder_mut<R: OplRecorder>(&mut self) -> Option<&mut R> {
+        self.recorder
+            .as_deref_mut()?
+            .as_any_mut()
+            .downcast_mut::<R>()
+    }
+
+    /// Returns true if a recorder is attached to the device.
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    fn detach_recorder(&mut self) -> Option<Box<dyn OplRecorder>> {
+        let mut recorder = self.recorder.take()?;
+        recorder.stop();
+        Some(recorder)
+    }
+
     /// Reset the Opl3Device.
     /// Reset the state of the OPL3 device, including the internal registers and the internal
     /// Nuked-OPL3 instance.
@@ -454,14 +794,23 @@ impl Opl3Device {
 /// The `Opl3Chip` struct provides a safe interface for interacting with the Nuked-OPL3 library.
 pub struct Opl3Chip {
     chip: *mut bindings::Opl3Chip,
+    write_delay: u64,
+    overflow_callback: Option<Box<OplOverflowCallback>>,
 }
 
+/// A callback that is called with the register and value of a buffered write that found the write
+/// buffer full. See `Opl3Chip::set_overflow_callback`.
+pub type OplOverflowCallback = dyn FnMut(u16, u8) + Send;
+
+/// The flag Nuked-OPL3 sets on the register number of a pending write buffer entry.
+const OPL_WRITEBUF_PENDING: u16 = 0x200;
+
 impl Drop for Opl3Chip {
     /// Drop the Opl3Chip instance by deallocating the memory used by the Nuked-OPL3 instance.
     fn drop(&mut self) {
         unsafe {
-            let layout = std::alloc::Layout::new::<bindings::Opl3Chip>();
-            std::alloc::dealloc(self.chip as *mut u8, layout);
+            let layout = alloc::alloc::Layout::new::<bindings::Opl3Chip>();
+            alloc::alloc::dealloc(self.chip as *mut u8, layout);
         }
     }
 }
@@ -488,10 +837,17 @@ impl Opl3Chip {
     /// ```
     pub fn new(sample_rate: u32) -> Self {
         unsafe {
-            let layout = std::alloc::Layout::new::<bindings::Opl3Chip>();
-            let chip = std::alloc::alloc(layout) as *mut bindings::Opl3Chip;
+            let layout = alloc::alloc::Layout::new::<bindings::Opl3Chip>();
+            let chip = alloc::alloc::alloc(layout) as *mut bindings::Opl3Chip;
+            if chip.is_null() {
+                alloc::alloc::handle_alloc_error(layout);
+            }
             bindings::Opl3Reset(chip, sample_rate);
-            Opl3Chip { chip }
+            Opl3Chip {
+                chip,
+                write_delay: config::OPL_WRITEBUF_DELAY,
+                overflow_callback: None,
+            }
         }
     }
 
@@ -617,9 +973,137 @@ impl Opl3Chip {
     /// chip.write_register_buffered(0x20, 0x01);
     /// ```
     pub fn write_register_buffered(&mut self, reg: u16, value: u8) {
-        unsafe {
-            bindings::Opl3WriteRegBuffered(&mut *self.chip, reg, value);
+        // This follows OPL3_WriteRegBuffered, with the delay between writes set at runtime and the
+        // overflow reported to the callback.
+        let chip = unsafe { &mut *self.chip };
+        let last = chip.writebuf_last as usize;
+        if chip.writebuf[last].reg & OPL_WRITEBUF_PENDING != 0 {
+            if let Some(callback) = &mut self.overflow_callback {
+                callback(reg, value);
+            }
+            // Perform the oldest write early to make room.
+            let (oldest_reg, data, time) = {
+                let entry = &chip.writebuf[last];
+                (entry.reg & 0x1FF, entry.data, entry.time)
+            };
+            unsafe {
+                bindings::Opl3WriteReg(chip, oldest_reg, data);
+            }
+            chip.writebuf_cur = ((last + 1) % config::OPL_WRITEBUF_SIZE) as u32;
+            chip.writebuf_samplecnt = time;
+        }
+
+        let time = (chip.writebuf_lasttime + self.write_delay).max(chip.writebuf_samplecnt);
+        let entry = &mut chip.writebuf[last];
+        entry.reg = reg | OPL_WRITEBUF_PENDING;
+        entry.data = value;
+        entry.time = time;
+        chip.writebuf_lasttime = time;
+        chip.writebuf_last = ((last + 1) % config::OPL_WRITEBUF_SIZE) as u32;
+    }
+
+    /// Write a value to an OPL register in buffered mode, unless the write buffer is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `reg` - The register to write to.
+    /// * `value` - The value to write to the register.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or `OplError::WriteBufferFull` if the write
+    /// could not be queued without performing an earlier write ahead of time.
+    pub fn try_write_register_buffered(&mut self, reg: u16, value: u8) -> Result<(), OplError> {
+        if self.is_write_buffer_full() {
+            return Err(OplError::WriteBufferFull);
         }
+        self.write_register_buffered(reg, value);
+        Ok(())
+    }
+
+    /// Return the number of buffered writes that have not yet been performed.
+    pub fn pending_writes(&self) -> usize {
+        let chip = unsafe { &*self.chip };
+        if self.is_write_buffer_full() {
+            return config::OPL_WRITEBUF_SIZE;
+        }
+        let size = config::OPL_WRITEBUF_SIZE;
+        (chip.writebuf_last as usize + size - chip.writebuf_cur as usize) % size
+    }
+
+    /// Returns true if the write buffer is full, so that the next buffered write would have to
+    /// perform the oldest pending write ahead of time.
+    pub fn is_write_buffer_full(&self) -> bool {
+        let chip = unsafe { &*self.chip };
+        chip.writebuf[chip.writebuf_last as usize].reg & OPL_WRITEBUF_PENDING != 0
+    }
+
+    /// Perform all pending buffered writes immediately, in the order they were made. Writes made
+    /// afterward are delayed from the current time rather than from the flushed writes.
+    pub fn flush_writes(&mut self) {
+        let chip = unsafe { &mut *self.chip };
+        loop {
+            let cur = chip.writebuf_cur as usize;
+            let entry = &mut chip.writebuf[cur];
+            if entry.reg & OPL_WRITEBUF_PENDING == 0 {
+                break;
+            }
+            entry.reg &= 0x1FF;
+            let (reg, data) = (entry.reg, entry.data);
+            unsafe {
+                bindings::Opl3WriteReg(chip, reg, data);
+            }
+            chip.writebuf_cur = ((cur + 1) % config::OPL_WRITEBUF_SIZE) as u32;
+        }
+        chip.writebuf_lasttime = chip.writebuf_samplecnt;
+    }
+
+    /// Return the delay between buffered writes, in samples at the native rate of 49716 Hz.
+    pub fn write_delay(&self) -> u64 {
+        self.write_delay
+    }
+
+    /// Set the delay between buffered writes, in samples at the native rate of 49716 Hz. The
+    /// default is `config::OPL_WRITEBUF_DELAY`. Writes already in the buffer keep their timing.
+    pub fn set_write_delay(&mut self, delay: u64) {
+        self.write_delay = delay;
+    }
+
+    /// Set a callback to be called when a buffered write finds the write buffer full. The oldest
+    /// pending write is then performed ahead of time to make room, as Nuked-OPL3 does, which
+    /// shortens the delay before it. Pass None to remove the callback.
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - The callback, which receives the register and value of the new write.
+    pub fn set_overflow_callback(&mut self, callback: Option<Box<OplOverflowCallback>>) {
+        self.overflow_callback = callback;
+    }
+
+    /// Return the state of the envelope generator of an operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - The operator index used by Nuked-OPL3, from 0 to 35.
+    ///
+    /// # Returns
+    ///
+    /// The envelope state, or None if the operator index is out of range.
+    pub fn envelope(&self, slot: usize) -> Option<OplEnvelope> {
+        if slot >= 36 {
+            return None;
+        }
+        let slot = unsafe { &(*self.chip).slot[slot] };
+        let stage = match slot.eg_gen {
+            0 => OplEnvelopeStage::Attack,
+            1 => OplEnvelopeStage::Decay,
+            2 => OplEnvelopeStage::Sustain,
+            _ => OplEnvelopeStage::Release,
+        };
+        Some(OplEnvelope {
+            stage,
+            attenuation: slot.eg_out.min(OplEnvelope::SILENT),
+        })
     }
 
     /// Generates a stream of resampled audio samples.
@@ -769,4 +1253,111 @@ impl Opl3Chip {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[cfg(not(feature = "rust-core"))]
+    #[test]
+    fn bindings_layout() {
+        use alloc::vec;
+        use bindings::{Opl3Channel, Opl3Chip as Chip, Opl3Slot, Opl3Writebuf};
+        use std::mem::{align_of, offset_of, size_of};
+
+        extern "C" {
+            fn opl3_rs_layout(index: usize) -> usize;
+        }
+
+        // In the order of src/layout.c.
+        let mut layout = vec![
+            ("sizeof(opl3_slot)", size_of::<Opl3Slot>()),
+            ("alignof(opl3_slot)", align_of::<Opl3Slot>()),
+            ("eg_out", offset_of!(Opl3Slot, eg_out)),
+            ("eg_gen", offset_of!(Opl3Slot, eg_gen)),
+            ("reg_mult", offset_of!(Opl3Slot, reg_mult)),
+            ("slot_num", offset_of!(Opl3Slot, slot_num)),
+            ("sizeof(opl3_channel)", size_of::<Opl3Channel>()),
+            ("alignof(opl3_channel)", align_of::<Opl3Channel>()),
+            ("chtype", offset_of!(Opl3Channel, chtype)),
+            ("ch_num", offset_of!(Opl3Channel, ch_num)),
+            ("sizeof(opl3_writebuf)", size_of::<Opl3Writebuf>()),
+            ("alignof(opl3_writebuf)", align_of::<Opl3Writebuf>()),
+            ("reg", offset_of!(Opl3Writebuf, reg)),
+            ("data", offset_of!(Opl3Writebuf, data)),
+            ("sizeof(opl3_chip)", size_of::<Chip>()),
+            ("alignof(opl3_chip)", align_of::<Chip>()),
+            ("slot", offset_of!(Chip, slot)),
+            ("timer", offset_of!(Chip, timer)),
+            ("eg_timer", offset_of!(Chip, eg_timer)),
+            ("noise", offset_of!(Chip, noise)),
+            ("zeromod", offset_of!(Chip, zeromod)),
+            ("mixbuff", offset_of!(Chip, mixbuff)),
+            ("rm_tc_bit5", offset_of!(Chip, rm_tc_bit5)),
+        ];
+        #[cfg(feature = "stereoext")]
+        layout.push(("stereoext", offset_of!(Chip, stereoext)));
+        layout.extend([
+            ("rateratio", offset_of!(Chip, rateratio)),
+            ("samples", offset_of!(Chip, samples)),
+            ("writebuf_samplecnt", offset_of!(Chip, writebuf_samplecnt)),
+            ("writebuf_cur", offset_of!(Chip, writebuf_cur)),
+            ("writebuf_last", offset_of!(Chip, writebuf_last)),
+            ("writebuf_lasttime", offset_of!(Chip, writebuf_lasttime)),
+            ("writebuf", offset_of!(Chip, writebuf)),
+        ]);
+
+        for (index, (name, value)) in layout.iter().enumerate() {
+            assert_eq!(*value, unsafe { opl3_rs_layout(index) }, "{}", name);
+        }
+        assert_eq!(unsafe { opl3_rs_layout(layout.len()) }, 0);
+    }
+
+    #[test]
+    fn write_buffer_flush() {
+        let mut device = Opl3Device::new(44100);
+        device.set_write_delay(10);
+        for value in 0..5 {
+            device.write_register(0x20, value, OplRegisterFile::Primary, true);
+        }
+        assert_eq!(device.pending_writes(), 5);
+
+        // At 44100 Hz, a few samples cover the first write but not the rest.
+        let mut buffer = [0i16; 2 * 12];
+        device.generate_samples(&mut buffer).unwrap();
+        assert!((1..5).contains(&device.pending_writes()));
+
+        device.flush_writes();
+        assert_eq!(device.pending_writes(), 0);
+        let chip = device.backend::<Opl3Chip>().unwrap();
+        let slot = unsafe { &(*chip.chip).slot[0] };
+        assert_eq!(slot.reg_mult, 4);
+    }
+
+    #[test]
+    fn write_buffer_overflow() {
+        let mut device = Opl3Device::new(44100);
+        let overflows = Arc::new(AtomicUsize::new(0));
+        let counter = overflows.clone();
+        device.set_overflow_callback(Some(Box::new(move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        })));
+
+        for _ in 0..config::OPL_WRITEBUF_SIZE {
+            device
+                .try_write_register(0x20, 0x01, OplRegisterFile::Primary)
+                .unwrap();
+        }
+        assert_eq!(device.pending_writes(), config::OPL_WRITEBUF_SIZE);
+        assert!(matches!(
+            device.try_write_register(0x20, 0x02, OplRegisterFile::Primary),
+            Err(OplError::WriteBufferFull)
+        ));
+        assert_eq!(device.read_register(0x20, OplRegisterFile::Primary), 0x01);
+        assert_eq!(overflows.load(Ordering::Relaxed), 0);
+
+        device.write_register(0x20, 0x02, OplRegisterFile::Primary, true);
+        assert_eq!(overflows.load(Ordering::Relaxed), 1);
+        assert_eq!(device.pending_writes(), config::OPL_WRITEBUF_SIZE);
+    }
+}
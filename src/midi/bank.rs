@@ -0,0 +1,261 @@
+//! Instrument banks for the General MIDI synthesizer.
+//!
+//! A `MidiBank` holds up to 128 melodic instruments, selected by program change, and up to 128
+//! percussion instruments, selected by note number on the percussion channel. Banks can be built
+//! in code, or loaded from the OP2 format used by the DMX sound library (the `GENMIDI` lump of
+//! Doom and other games), which provides 2-op and double-voice instruments.
+
+use crate::formats::ByteReader;
+use crate::OplError;
+use alloc::{vec, vec::Vec};
+
+/// The signature at the start of an OP2 bank.
+pub const OP2_SIGNATURE: &[u8; 8] = b"#OPL_II#";
+/// The number of instruments in an OP2 bank: 128 melodic instruments followed by 47 percussion
+/// instruments for notes 35 to 81.
+pub const OP2_INSTRUMENTS: usize = 175;
+const OP2_FIRST_PERCUSSION_NOTE: usize = 35;
+const OP2_FIXED_PITCH: u16 = 0x0001;
+const OP2_DOUBLE_VOICE: u16 = 0x0004;
+
+/// The register values of a single operator.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MidiOperator {
+    /// Tremolo, vibrato, sustain, key scale rate and frequency multiplier (register 0x20).
+    pub characteristic: u8,
+    /// Key scale level and total level (register 0x40).
+    pub level: u8,
+    /// Attack and decay rates (register 0x60).
+    pub attack_decay: u8,
+    /// Sustain level and release rate (register 0x80).
+    pub sustain_release: u8,
+    /// Waveform select (register 0xE0).
+    pub waveform: u8,
+}
+
+/// A pair of operators played on one OPL channel.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MidiVoiceData {
+    /// The first operator, which modulates the carrier unless the connection bit is set.
+    pub modulator: MidiOperator,
+    /// The second operator.
+    pub carrier: MidiOperator,
+    /// Feedback and connection (register 0xC0). The output select bits are ignored.
+    pub feedback: u8,
+    /// An offset in semitones applied to every note played with the voice.
+    pub note_offset: i16,
+}
+
+/// How the voices of an instrument are played.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MidiInstrumentMode {
+    /// The first voice is played on a single 2-op channel.
+    #[default]
+    TwoOp,
+    /// Both voices are played together on two 2-op channels, the second one detuned.
+    DoubleVoice,
+    /// Both voices are played as the four operators of an OPL3 4-op channel pair.
+    FourOp,
+}
+
+/// A General MIDI instrument.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MidiInstrument {
+    /// How the voices are played.
+    pub mode: MidiInstrumentMode,
+    /// The voices of the instrument. The second voice is only used by double-voice and 4-op
+    /// instruments.
+    pub voices: [MidiVoiceData; 2],
+    /// The note to play regardless of the key pressed, as used by most percussion instruments.
+    pub fixed_note: Option<u8>,
+    /// The detune of the second voice of a double-voice instrument, in semitones.
+    pub detune: f64,
+}
+
+impl Default for MidiInstrument {
+    /// A plain 2-op tone, used for programs missing from the bank.
+    fn default() -> Self {
+        let op = |level| MidiOperator {
+            characteristic: 0x01,
+            level,
+            attack_decay: 0xF2,
+            sustain_release: 0x54,
+            waveform: 0x00,
+        };
+        MidiInstrument {
+            mode: MidiInstrumentMode::TwoOp,
+            voices: [
+                MidiVoiceData {
+                    modulator: op(0x1C),
+                    carrier: op(0x00),
+                    feedback: 0x06,
+                    note_offset: 0,
+                },
+                MidiVoiceData::default(),
+            ],
+            fixed_note: None,
+            detune: 0.0,
+        }
+    }
+}
+
+/// A bank of melodic and percussion instruments.
+#[derive(Clone, Debug)]
+pub struct MidiBank {
+    melodic: Vec<Option<MidiInstrument>>,
+    percussion: Vec<Option<MidiInstrument>>,
+}
+
+impl Default for MidiBank {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiBank {
+    /// Create an empty bank.
+    pub fn new() -> Self {
+        MidiBank {
+            melodic: vec![None; 128],
+            percussion: vec![None; 128],
+        }
+    }
+
+    /// Return the melodic instrument for a program number, if present.
+    pub fn melodic(&self, program: u8) -> Option<&MidiInstrument> {
+        self.melodic.get(program as usize)?.as_ref()
+    }
+
+    /// Return the percussion instrument for a note number, if present.
+    pub fn percussion(&self, note: u8) -> Option<&MidiInstrument> {
+        self.percussion.get(note as usize)?.as_ref()
+    }
+
+    /// Set the melodic instrument for a program number (0-127).
+    pub fn set_melodic(&mut self, program: u8, instrument: MidiInstrument) {
+        if let Some(slot) = self.melodic.get_mut(program as usize) {
+            *slot = Some(instrument);
+        }
+    }
+
+    /// Set the percussion instrument for a note number (0-127).
+    pub fn set_percussion(&mut self, note: u8, instrument: MidiInstrument) {
+        if let Some(slot) = self.percussion.get_mut(note as usize) {
+            *slot = Some(instrument);
+        }
+    }
+
+    /// Parse a bank in the DMX OP2 format.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the OP2 file.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the parsed `MidiBank` or an `OplError` on failure.
+    pub fn parse_op2(data: &[u8]) -> Result<MidiBank, OplError> {
+        let mut reader = ByteReader::new(data);
+        if reader.bytes(OP2_SIGNATURE.len())? != OP2_SIGNATURE {
+            return Err(OplError::BadSignature);
+        }
+
+        let mut bank = MidiBank::new();
+        for i in 0..OP2_INSTRUMENTS {
+            let flags = reader.u16_le()?;
+            let fine_tune = reader.u8()?;
+            let fixed_note = reader.u8()?;
+            let voices = [Self::op2_voice(&mut reader)?, Self::op2_voice(&mut reader)?];
+            let instrument = MidiInstrument {
+                mode: if flags & OP2_DOUBLE_VOICE != 0 {
+                    MidiInstrumentMode::DoubleVoice
+                } else {
+                    MidiInstrumentMode::TwoOp
+                },
+                voices,
+                fixed_note: (flags & OP2_FIXED_PITCH != 0).then_some(fixed_note),
+                // The fine tune is in 64ths of a semitone, centered on 128.
+                detune: (fine_tune as f64 - 128.0) / 64.0,
+            };
+            match i {
+                0..=127 => bank.set_melodic(i as u8, instrument),
+                _ => bank.set_percussion((i - 128 + OP2_FIRST_PERCUSSION_NOTE) as u8, instrument),
+            }
+        }
+        Ok(bank)
+    }
+
+    fn op2_voice(reader: &mut ByteReader) -> Result<MidiVoiceData, OplError> {
+        let modulator = Self::op2_operator(reader)?;
+        let feedback = reader.u8()?;
+        let carrier = Self::op2_operator(reader)?;
+        reader.u8()?;
+        let note_offset = reader.u16_le()? as i16;
+        Ok(MidiVoiceData {
+            modulator,
+            carrier,
+            feedback,
+            note_offset,
+        })
+    }
+
+    fn op2_operator(reader: &mut ByteReader) -> Result<MidiOperator, OplError> {
+        let characteristic = reader.u8()?;
+        let attack_decay = reader.u8()?;
+        let sustain_release = reader.u8()?;
+        let waveform = reader.u8()?;
+        let scale = reader.u8()?;
+        let level = reader.u8()?;
+        Ok(MidiOperator {
+            characteristic,
+            level: (scale & 0xC0) | (level & 0x3F),
+            attack_decay,
+            sustain_release,
+            waveform,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_op2() {
+        let mut data = OP2_SIGNATURE.to_vec();
+        for i in 0..OP2_INSTRUMENTS {
+            let (flags, note) = match i {
+                0 => (OP2_DOUBLE_VOICE, 0),
+                128 => (OP2_FIXED_PITCH, 60),
+                _ => (0, 0),
+            };
+            data.extend_from_slice(&flags.to_le_bytes());
+            data.extend_from_slice(&[160, note]);
+            for _ in 0..2 {
+                // Modulator, feedback, carrier, padding and note offset.
+                data.extend_from_slice(&[0x21, 0xF0, 0x44, 1, 0x40, 0x12, 0x0E]);
+                data.extend_from_slice(&[0x01, 0xF1, 0x55, 0, 0x80, 0x04, 0]);
+                data.extend_from_slice(&(-12i16).to_le_bytes());
+            }
+        }
+        data.extend_from_slice(&vec![0; OP2_INSTRUMENTS * 32]);
+
+        let bank = MidiBank::parse_op2(&data).unwrap();
+        let piano = bank.melodic(0).unwrap();
+        assert_eq!(piano.mode, MidiInstrumentMode::DoubleVoice);
+        assert_eq!(piano.detune, 0.5);
+        assert_eq!(piano.voices[1].modulator.level, 0x52);
+        assert_eq!(piano.voices[0].carrier.sustain_release, 0x55);
+        assert_eq!(piano.voices[0].note_offset, -12);
+        assert_eq!(bank.melodic(1).unwrap().mode, MidiInstrumentMode::TwoOp);
+
+        let drum = bank.percussion(35).unwrap();
+        assert_eq!(drum.fixed_note, Some(60));
+        assert!(bank.percussion(82).is_none());
+
+        assert!(matches!(
+            MidiBank::parse_op2(&data[1..]),
+            Err(OplError::BadSignature)
+        ));
+    }
+}
@@ -0,0 +1,783 @@
+//! A General MIDI synthesizer built on `Opl3Device`.
+//!
+//! `MidiSynth` accepts MIDI channel voice messages, such as those passed through an emulated
+//! MPU-401 or read from a MIDI file, and plays them on the 18 channels of an OPL3 using the
+//! instruments of a `MidiBank`. Channels are assigned to notes by a `VoiceAllocator`, so 4-op
+//! instruments are played on the six OPL3 channel pairs that support 4-op mode, which is enabled
+//! and disabled for each pair as voices are allocated, and the voice stolen when all channels are
+//! busy is chosen by a `StealPolicy`.
+//!
+//! The synthesizer handles note on and off, program change, pitch bend with the bend range set
+//! through RPN 0, and the modulation wheel, volume, pan, expression and sustain controllers, as
+//! well as the channel mode messages for all sound off, reset all controllers and all notes off.
+//! Channel 10 plays the percussion instruments of the bank. The modulation wheel enables the
+//! chip's vibrato on the voices of a channel, and pan selects the left, right or both outputs.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use opl3_rs::Opl3Device;
+//! use opl3_rs::midi::{MidiBank, MidiMessage, MidiSynth};
+//!
+//! let bank = MidiBank::parse_op2(&std::fs::read("GENMIDI.OP2").unwrap()).unwrap();
+//! let mut device = Opl3Device::new(44100);
+//! let mut synth = MidiSynth::new(bank);
+//! synth.reset(&mut device);
+//! synth.send(&mut device, MidiMessage::NoteOn { channel: 0, note: 60, velocity: 100 });
+//! ```
+
+use alloc::{vec, vec::Vec};
+mod bank;
+
+pub use bank::*;
+
+use crate::formats::{note_fnum, write_reg};
+use crate::voice::{StealPolicy, VoiceAllocator, VoiceKey, VoiceKind, VoiceState};
+use crate::{Opl3Device, OplError};
+
+/// The number of MIDI channels.
+pub const MIDI_CHANNELS: usize = 16;
+/// The MIDI channel that plays percussion instruments (channel 10, counting from 1).
+pub const MIDI_PERCUSSION_CHANNEL: u8 = 9;
+
+const OPL_CHANNELS: usize = 18;
+const OP_OFFSETS: [u16; 9] = [0x00, 0x01, 0x02, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x12];
+const RPN_NULL: u16 = 0x3FFF;
+const RPN_BEND_RANGE: u16 = 0;
+
+const CC_MODULATION: u8 = 1;
+const CC_DATA_ENTRY: u8 = 6;
+const CC_VOLUME: u8 = 7;
+const CC_PAN: u8 = 10;
+const CC_EXPRESSION: u8 = 11;
+const CC_DATA_ENTRY_LSB: u8 = 38;
+const CC_SUSTAIN: u8 = 64;
+const CC_RPN_LSB: u8 = 100;
+const CC_RPN_MSB: u8 = 101;
+const CC_ALL_SOUND_OFF: u8 = 120;
+const CC_RESET_CONTROLLERS: u8 = 121;
+const CC_ALL_NOTES_OFF: u8 = 123;
+
+/// A MIDI channel voice message.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MidiMessage {
+    /// Release a note.
+    NoteOff {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The note number.
+        note: u8,
+        /// The release velocity, which is ignored.
+        velocity: u8,
+    },
+    /// Start a note. A velocity of 0 releases the note instead.
+    NoteOn {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The note number.
+        note: u8,
+        /// The velocity.
+        velocity: u8,
+    },
+    /// Polyphonic key pressure, which is ignored.
+    PolyPressure {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The note number.
+        note: u8,
+        /// The pressure.
+        pressure: u8,
+    },
+    /// Set a controller.
+    ControlChange {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The controller number.
+        controller: u8,
+        /// The controller value.
+        value: u8,
+    },
+    /// Select the instrument of a channel.
+    ProgramChange {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The program number.
+        program: u8,
+    },
+    /// Channel pressure, which is ignored.
+    ChannelPressure {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The pressure.
+        pressure: u8,
+    },
+    /// Bend the pitch of a channel.
+    PitchBend {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The 14-bit bend value, where 8192 is the center.
+        value: u16,
+    },
+}
+
+impl MidiMessage {
+    /// Decode a channel voice message from its status byte and data bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The status byte followed by one or two data bytes.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the decoded `MidiMessage` or an `OplError` if the bytes are not
+    /// a complete channel voice message.
+    pub fn parse(bytes: &[u8]) -> Result<MidiMessage, OplError> {
+        let (&status, data) = bytes
+            .split_first()
+            .ok_or(OplError::InvalidFile("Empty MIDI message"))?;
+        if !(0x80..0xF0).contains(&status) {
+            return Err(OplError::InvalidFile("Not a MIDI channel voice message"));
+        }
+        let channel = status & 0x0F;
+        let len = match status & 0xF0 {
+            0xC0 | 0xD0 => 1,
+            _ => 2,
+        };
+        if data.len() < len || data[..len].iter().any(|&b| b & 0x80 != 0) {
+            return Err(OplError::InvalidFile("Incomplete MIDI message"));
+        }
+        let (d1, d2) = (data[0], data.get(1).copied().unwrap_or(0));
+        Ok(match status & 0xF0 {
+            0x80 => MidiMessage::NoteOff {
+                channel,
+                note: d1,
+                velocity: d2,
+            },
+            0x90 => MidiMessage::NoteOn {
+                channel,
+                note: d1,
+                velocity: d2,
+            },
+            0xA0 => MidiMessage::PolyPressure {
+                channel,
+                note: d1,
+                pressure: d2,
+            },
+            0xB0 => MidiMessage::ControlChange {
+                channel,
+                controller: d1,
+                value: d2,
+            },
+            0xC0 => MidiMessage::ProgramChange {
+                channel,
+                program: d1,
+            },
+            0xD0 => MidiMessage::ChannelPressure {
+                channel,
+                pressure: d1,
+            },
+            _ => MidiMessage::PitchBend {
+                channel,
+                value: d1 as u16 | ((d2 as u16) << 7),
+            },
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+struct ChannelState {
+    program: u8,
+    volume: u8,
+    expression: u8,
+    pan: u8,
+    modulation: u8,
+    sustain: bool,
+    bend: i16,
+    bend_range: f64,
+    rpn: u16,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        ChannelState {
+            program: 0,
+            volume: 100,
+            expression: 127,
+            pan: 64,
+            modulation: 0,
+            sustain: false,
+            bend: 0,
+            bend_range: 2.0,
+            rpn: RPN_NULL,
+        }
+    }
+}
+
+impl ChannelState {
+    fn reset_controllers(&mut self) {
+        *self = ChannelState {
+            program: self.program,
+            volume: self.volume,
+            pan: self.pan,
+            bend_range: self.bend_range,
+            ..Default::default()
+        };
+    }
+}
+
+/// The state of one OPL channel. The second channel of a pair in 4-op mode is owned by the voice
+/// on the first channel.
+#[derive(Copy, Clone, Default)]
+struct Voice {
+    instrument: MidiInstrument,
+    /// Which of the instrument's voices is played, for double-voice instruments.
+    part: usize,
+    channel: u8,
+    note: u8,
+    velocity: u8,
+    sustained: bool,
+    b0: u8,
+}
+
+/// A General MIDI synthesizer that plays on an `Opl3Device`.
+pub struct MidiSynth {
+    bank: MidiBank,
+    channels: [ChannelState; MIDI_CHANNELS],
+    voices: [Voice; OPL_CHANNELS],
+    allocator: VoiceAllocator,
+}
+
+impl MidiSynth {
+    /// Create a new synthesizer that plays the instruments of the given bank. Programs missing
+    /// from the bank are played with a plain 2-op tone, and missing percussion is not played.
+    pub fn new(bank: MidiBank) -> Self {
+        MidiSynth {
+            bank,
+            channels: [ChannelState::default(); MIDI_CHANNELS],
+            voices: [Voice::default(); OPL_CHANNELS],
+            allocator: VoiceAllocator::new(StealPolicy::Oldest),
+        }
+    }
+
+    /// Return the instrument bank.
+    pub fn bank(&self) -> &MidiBank {
+        &self.bank
+    }
+
+    /// Replace the instrument bank. Notes already playing keep their instruments.
+    pub fn set_bank(&mut self, bank: MidiBank) {
+        self.bank = bank;
+    }
+
+    /// Return the number of OPL channels currently keyed on, counting a 4-op pair once.
+    pub fn active_voices(&self) -> usize {
+        self.allocator.playing()
+    }
+
+    /// Return the policy for choosing a voice to steal when every channel is busy.
+    pub fn steal_policy(&self) -> StealPolicy {
+        self.allocator.policy()
+    }
+
+    /// Set the policy for choosing a voice to steal when every channel is busy. The default is
+    /// `StealPolicy::Oldest`.
+    pub fn set_steal_policy(&mut self, policy: StealPolicy) {
+        self.allocator.set_policy(policy);
+    }
+
+    /// Reset the synthesizer and put the device into OPL3 mode with all channels silenced. This
+    /// must be called before the first message is sent, and after the device has been reset.
+    pub fn reset(&mut self, device: &mut Opl3Device) {
+        self.channels = [ChannelState::default(); MIDI_CHANNELS];
+        self.voices = [Voice::default(); OPL_CHANNELS];
+
+        write_reg(device, 0x105, 0x01);
+        self.allocator.reset(device);
+        write_reg(device, 0x01, 0x20);
+        write_reg(device, 0x08, 0x00);
+        write_reg(device, 0xBD, 0xC0);
+        for ch in 0..OPL_CHANNELS {
+            let base = Self::channel_base(ch);
+            write_reg(device, 0xB0 + base, 0x00);
+            for op in [0, 3] {
+                let reg = Self::op_base(ch) + op;
+                write_reg(device, 0x40 + reg, 0x3F);
+                write_reg(device, 0x80 + reg, 0xFF);
+            }
+        }
+    }
+
+    /// Decode and play a channel voice message.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The `Opl3Device` to play on.
+    /// * `bytes`  - The status byte followed by one or two data bytes.
+    pub fn send_bytes(&mut self, device: &mut Opl3Device, bytes: &[u8]) -> Result<(), OplError> {
+        self.send(device, MidiMessage::parse(bytes)?);
+        Ok(())
+    }
+
+    /// Play a channel voice message.
+    pub fn send(&mut self, device: &mut Opl3Device, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } if velocity > 0 => self.note_on(device, channel & 0x0F, note, velocity),
+            MidiMessage::NoteOn { channel, note, .. }
+            | MidiMessage::NoteOff { channel, note, .. } => {
+                self.note_off(device, channel & 0x0F, note)
+            }
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => self.control_change(device, channel & 0x0F, controller, value),
+            MidiMessage::ProgramChange { channel, program } => {
+                self.channels[(channel & 0x0F) as usize].program = program & 0x7F
+            }
+            MidiMessage::PitchBend { channel, value } => {
+                let channel = channel & 0x0F;
+                self.channels[channel as usize].bend = (value & 0x3FFF) as i16 - 8192;
+                self.update_pitch(device, channel);
+            }
+            MidiMessage::PolyPressure { .. } | MidiMessage::ChannelPressure { .. } => {}
+        }
+    }
+
+    fn note_on(&mut self, device: &mut Opl3Device, channel: u8, note: u8, velocity: u8) {
+        let instrument = if channel == MIDI_PERCUSSION_CHANNEL {
+            match self.bank.percussion(note) {
+                Some(instrument) => *instrument,
+                None => return,
+            }
+        } else {
+            let program = self.channels[channel as usize].program;
+            self.bank.melodic(program).copied().unwrap_or_default()
+        };
+
+        // A repeated note replaces the one already playing.
+        self.release_note(device, channel, note, true);
+
+        let parts = match instrument.mode {
+            MidiInstrumentMode::DoubleVoice => 2,
+            _ => 1,
+        };
+        let kind = match instrument.mode {
+            MidiInstrumentMode::FourOp => VoiceKind::FourOp,
+            _ => VoiceKind::TwoOp,
+        };
+        let key = VoiceKey { channel, note };
+        let mut allocated = Vec::new();
+        for part in 0..parts {
+            // The parts share a key, so the second must not take the channel of the first.
+            let ch = self
+                .allocator
+                .allocate_except(device, kind, key, velocity, &allocated)
+                .channel;
+            allocated.push(ch);
+            self.voices[ch] = Voice {
+                instrument,
+                part,
+                channel,
+                note,
+                velocity,
+                sustained: false,
+                b0: 0,
+            };
+            self.load_instrument(device, ch);
+            self.write_pitch(device, ch);
+        }
+    }
+
+    fn note_off(&mut self, device: &mut Opl3Device, channel: u8, note: u8) {
+        let sustain = self.channels[channel as usize].sustain;
+        self.release_note(device, channel, note, !sustain);
+        if sustain {
+            for ch in self.allocator.find(VoiceKey { channel, note }) {
+                self.voices[ch].sustained = true;
+            }
+        }
+    }
+
+    /// Key off the voices playing a note, if `release` is set.
+    fn release_note(&mut self, device: &mut Opl3Device, channel: u8, note: u8, release: bool) {
+        if !release {
+            return;
+        }
+        let playing: Vec<usize> = self.allocator.find(VoiceKey { channel, note }).collect();
+        for ch in playing {
+            self.key_off(device, ch);
+        }
+    }
+
+    fn control_change(&mut self, device: &mut Opl3Device, channel: u8, controller: u8, value: u8) {
+        let state = &mut self.channels[channel as usize];
+        match controller {
+            CC_MODULATION => {
+                state.modulation = value;
+                self.update_voices(device, channel, Self::load_instrument);
+            }
+            CC_VOLUME | CC_EXPRESSION => {
+                if controller == CC_VOLUME {
+                    state.volume = value;
+                } else {
+                    state.expression = value;
+                }
+                self.update_voices(device, channel, Self::write_levels);
+            }
+            CC_PAN => {
+                state.pan = value;
+                self.update_voices(device, channel, Self::write_connection);
+            }
+            CC_SUSTAIN => {
+                state.sustain = value >= 64;
+                if !state.sustain {
+                    self.release_sustained(device, channel);
+                }
+            }
+            CC_RPN_MSB => state.rpn = (state.rpn & 0x7F) | ((value as u16) << 7),
+            CC_RPN_LSB => state.rpn = (state.rpn & 0x3F80) | value as u16,
+            CC_DATA_ENTRY if state.rpn == RPN_BEND_RANGE => {
+                state.bend_range =
+                    value as f64 + (state.bend_range - libm::trunc(state.bend_range));
+                self.update_pitch(device, channel);
+            }
+            CC_DATA_ENTRY_LSB if state.rpn == RPN_BEND_RANGE => {
+                state.bend_range = libm::trunc(state.bend_range) + value.min(99) as f64 / 100.0;
+                self.update_pitch(device, channel);
+            }
+            CC_ALL_SOUND_OFF => {
+                for ch in 0..OPL_CHANNELS {
+                    if self.sounding(ch, channel) {
+                        self.key_off(device, ch);
+                        // Cut the release short.
+                        for op in self.operator_regs(ch) {
+                            write_reg(device, 0x80 + op, 0xFF);
+                        }
+                        self.allocator.free(ch);
+                    }
+                }
+            }
+            CC_RESET_CONTROLLERS => {
+                state.reset_controllers();
+                self.release_sustained(device, channel);
+                self.update_voices(device, channel, Self::load_instrument);
+                self.update_pitch(device, channel);
+            }
+            CC_ALL_NOTES_OFF => {
+                for ch in 0..OPL_CHANNELS {
+                    if self.playing(ch, channel) {
+                        self.key_off(device, ch);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn release_sustained(&mut self, device: &mut Opl3Device, channel: u8) {
+        for ch in 0..OPL_CHANNELS {
+            if self.playing(ch, channel) && self.voices[ch].sustained {
+                self.key_off(device, ch);
+            }
+        }
+    }
+
+    /// Apply an update to each voice of a MIDI channel that is still sounding.
+    fn update_voices(
+        &mut self,
+        device: &mut Opl3Device,
+        channel: u8,
+        update: fn(&mut Self, &mut Opl3Device, usize),
+    ) {
+        for ch in 0..OPL_CHANNELS {
+            if self.sounding(ch, channel) {
+                update(self, device, ch);
+            }
+        }
+    }
+
+    fn update_pitch(&mut self, device: &mut Opl3Device, channel: u8) {
+        for ch in 0..OPL_CHANNELS {
+            if self.playing(ch, channel) {
+                self.write_pitch(device, ch);
+            }
+        }
+    }
+
+    /// Returns true if a channel is playing a held note of a MIDI channel.
+    fn playing(&self, ch: usize, channel: u8) -> bool {
+        self.allocator.state(ch) == VoiceState::Playing && self.voices[ch].channel == channel
+    }
+
+    /// Returns true if a channel is playing or releasing a note of a MIDI channel.
+    fn sounding(&self, ch: usize, channel: u8) -> bool {
+        self.allocator.state(ch) != VoiceState::Free && self.voices[ch].channel == channel
+    }
+
+    fn is_four_op(&self, ch: usize) -> bool {
+        self.allocator.is_four_op(ch)
+    }
+
+    fn channel_base(ch: usize) -> u16 {
+        (ch / 9 * 0x100 + ch % 9) as u16
+    }
+
+    fn op_base(ch: usize) -> u16 {
+        (ch / 9 * 0x100) as u16 + OP_OFFSETS[ch % 9]
+    }
+
+    /// Return the operator register offsets of a voice, in the order modulator then carrier of
+    /// each of its channels.
+    fn operator_regs(&self, ch: usize) -> Vec<u16> {
+        let mut regs = vec![Self::op_base(ch), Self::op_base(ch) + 3];
+        if self.is_four_op(ch) {
+            regs.extend([Self::op_base(ch + 3), Self::op_base(ch + 3) + 3]);
+        }
+        regs
+    }
+
+    /// Return the operator data of a voice, in the same order as `operator_regs`.
+    fn operators(&self, ch: usize) -> Vec<MidiOperator> {
+        let voice = &self.voices[ch];
+        let data = &voice.instrument.voices;
+        if self.is_four_op(ch) {
+            vec![
+                data[0].modulator,
+                data[0].carrier,
+                data[1].modulator,
+                data[1].carrier,
+            ]
+        } else {
+            vec![data[voice.part].modulator, data[voice.part].carrier]
+        }
+    }
+
+    /// Return which of a voice's operators are carriers, and so have their level scaled by the
+    /// velocity and volume.
+    fn carriers(&self, ch: usize) -> Vec<bool> {
+        let voice = &self.voices[ch];
+        let data = &voice.instrument.voices;
+        if self.is_four_op(ch) {
+            match (data[0].feedback & 1, data[1].feedback & 1) {
+                (0, 0) => vec![false, false, false, true],
+                (1, 0) => vec![true, false, false, true],
+                (0, _) => vec![false, true, false, true],
+                _ => vec![true, false, true, true],
+            }
+        } else {
+            vec![data[voice.part].feedback & 1 != 0, true]
+        }
+    }
+
+    fn load_instrument(&mut self, device: &mut Opl3Device, ch: usize) {
+        let vibrato = self.channels[self.voices[ch].channel as usize].modulation > 0;
+        for (reg, op) in self.operator_regs(ch).into_iter().zip(self.operators(ch)) {
+            let characteristic = op.characteristic | if vibrato { 0x40 } else { 0 };
+            write_reg(device, 0x20 + reg, characteristic);
+            write_reg(device, 0x60 + reg, op.attack_decay);
+            write_reg(device, 0x80 + reg, op.sustain_release);
+            write_reg(device, 0xE0 + reg, op.waveform);
+        }
+        self.write_levels(device, ch);
+        self.write_connection(device, ch);
+    }
+
+    fn write_levels(&mut self, device: &mut Opl3Device, ch: usize) {
+        let voice = &self.voices[ch];
+        let state = &self.channels[voice.channel as usize];
+        let gain = (voice.velocity as f64 / 127.0)
+            * (state.volume as f64 / 127.0)
+            * (state.expression as f64 / 127.0);
+        // MIDI volume follows a 40 log10 curve, and each step of the total level is 0.75 dB.
+        let attenuation = if gain > 0.0 {
+            libm::round(-40.0 * libm::log10(gain) / 0.75).min(63.0) as u8
+        } else {
+            63
+        };
+
+        let regs = self.operator_regs(ch);
+        let operators = self.operators(ch);
+        for ((reg, op), carrier) in regs.into_iter().zip(operators).zip(self.carriers(ch)) {
+            let level = if carrier {
+                (op.level & 0xC0) | ((op.level & 0x3F) + attenuation).min(0x3F)
+            } else {
+                op.level
+            };
+            write_reg(device, 0x40 + reg, level);
+        }
+    }
+
+    fn write_connection(&mut self, device: &mut Opl3Device, ch: usize) {
+        let voice = &self.voices[ch];
+        let output = match self.channels[voice.channel as usize].pan {
+            0..=42 => 0x10,
+            86.. => 0x20,
+            _ => 0x30,
+        };
+        let data = &voice.instrument.voices;
+        if self.is_four_op(ch) {
+            write_reg(
+                device,
+                0xC0 + Self::channel_base(ch),
+                (data[0].feedback & 0x0F) | output,
+            );
+            write_reg(
+                device,
+                0xC0 + Self::channel_base(ch + 3),
+                (data[1].feedback & 0x0F) | output,
+            );
+        } else {
+            let feedback = data[voice.part].feedback & 0x0F;
+            write_reg(device, 0xC0 + Self::channel_base(ch), feedback | output);
+        }
+    }
+
+    /// Write the frequency of a voice from its note, the instrument's note offset and detune, and
+    /// the channel's pitch bend, keying it on if it is held.
+    fn write_pitch(&mut self, device: &mut Opl3Device, ch: usize) {
+        let voice = &self.voices[ch];
+        let state = &self.channels[voice.channel as usize];
+        let instrument = &voice.instrument;
+        let mut note = instrument.fixed_note.unwrap_or(voice.note) as f64
+            + instrument.voices[voice.part].note_offset as f64
+            + state.bend as f64 / 8192.0 * state.bend_range;
+        if voice.part == 1 {
+            note += instrument.detune;
+        }
+
+        let (fnum, block) = note_fnum(note.clamp(0.0, 127.0));
+        let key = if self.allocator.state(ch) == VoiceState::Playing {
+            0x20
+        } else {
+            0
+        };
+        let b0 = key | (block << 2) | (fnum >> 8) as u8;
+        self.voices[ch].b0 = b0;
+        let base = Self::channel_base(ch);
+        write_reg(device, 0xA0 + base, fnum as u8);
+        write_reg(device, 0xB0 + base, b0);
+    }
+
+    fn key_off(&mut self, device: &mut Opl3Device, ch: usize) {
+        self.allocator.release(ch);
+        let voice = &mut self.voices[ch];
+        voice.sustained = false;
+        voice.b0 &= !0x20;
+        write_reg(device, 0xB0 + Self::channel_base(ch), voice.b0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::read_reg;
+
+    fn synth(bank: MidiBank) -> (MidiSynth, Opl3Device) {
+        let mut device = Opl3Device::new(44100);
+        let mut synth = MidiSynth::new(bank);
+        synth.reset(&mut device);
+        (synth, device)
+    }
+
+    fn send(synth: &mut MidiSynth, device: &mut Opl3Device, bytes: &[u8]) {
+        synth.send_bytes(device, bytes).unwrap();
+    }
+
+    #[test]
+    fn note_on_off_and_sustain() {
+        let (mut synth, mut device) = synth(MidiBank::new());
+        send(&mut synth, &mut device, &[0x90, 69, 127]);
+        let (fnum, block) = note_fnum(69.0);
+        assert_eq!(read_reg(&device, 0xA0), fnum as u8);
+        assert_eq!(
+            read_reg(&device, 0xB0),
+            0x20 | (block << 2) | (fnum >> 8) as u8
+        );
+        assert_eq!(read_reg(&device, 0xC0) & 0x30, 0x30);
+        assert_eq!(synth.active_voices(), 1);
+
+        // Held by the sustain pedal until it is released.
+        send(&mut synth, &mut device, &[0xB0, CC_SUSTAIN, 127]);
+        send(&mut synth, &mut device, &[0x80, 69, 0]);
+        assert_eq!(read_reg(&device, 0xB0) & 0x20, 0x20);
+        send(&mut synth, &mut device, &[0xB0, CC_SUSTAIN, 0]);
+        assert_eq!(read_reg(&device, 0xB0) & 0x20, 0);
+        assert_eq!(synth.active_voices(), 0);
+
+        // Velocity scales the carrier level.
+        send(&mut synth, &mut device, &[0x90, 60, 32]);
+        assert!(read_reg(&device, 0x43) & 0x3F > 0);
+        assert!(MidiMessage::parse(&[0x90, 60]).is_err());
+    }
+
+    #[test]
+    fn bend_range_rpn() {
+        let (mut synth, mut device) = synth(MidiBank::new());
+        for (controller, value) in [(CC_RPN_MSB, 0), (CC_RPN_LSB, 0), (CC_DATA_ENTRY, 12)] {
+            send(&mut synth, &mut device, &[0xB3, controller, value]);
+        }
+        send(&mut synth, &mut device, &[0x93, 48, 100]);
+        // Bend fully up, which is one step short of the full range.
+        send(&mut synth, &mut device, &[0xE3, 0x7F, 0x7F]);
+        let (fnum, block) = note_fnum(48.0 + 12.0 * 8191.0 / 8192.0);
+        assert_eq!(read_reg(&device, 0xA0), fnum as u8);
+        assert_eq!(
+            read_reg(&device, 0xB0) & 0x1F,
+            (block << 2) | (fnum >> 8) as u8
+        );
+    }
+
+    #[test]
+    fn four_op_pairs() {
+        let mut bank = MidiBank::new();
+        let mut instrument = MidiInstrument {
+            mode: MidiInstrumentMode::FourOp,
+            ..Default::default()
+        };
+        instrument.voices[1] = instrument.voices[0];
+        bank.set_melodic(5, instrument);
+        let (mut synth, mut device) = synth(bank);
+
+        send(&mut synth, &mut device, &[0xC0, 5]);
+        for note in 60..67 {
+            send(&mut synth, &mut device, &[0x90, note, 100]);
+        }
+        // Six pairs, so the seventh note steals the first pair.
+        assert_eq!(read_reg(&device, 0x104), 0x3F);
+        assert_eq!(synth.active_voices(), 6);
+        assert_eq!(read_reg(&device, 0xB3), 0);
+
+        // 2-op notes take the channels of released pairs, which leave 4-op mode.
+        send(&mut synth, &mut device, &[0xB0, CC_ALL_NOTES_OFF, 0]);
+        send(&mut synth, &mut device, &[0xC0, 0]);
+        for note in 60..78 {
+            send(&mut synth, &mut device, &[0x90, note, 100]);
+        }
+        assert_eq!(read_reg(&device, 0x104), 0);
+        assert_eq!(synth.active_voices(), 18);
+    }
+
+    #[test]
+    fn double_voice_same_note() {
+        let mut bank = MidiBank::new();
+        let instrument = MidiInstrument {
+            mode: MidiInstrumentMode::DoubleVoice,
+            ..Default::default()
+        };
+        bank.set_melodic(5, instrument);
+        let (mut synth, mut device) = synth(bank);
+        synth.set_steal_policy(StealPolicy::SameNote);
+
+        send(&mut synth, &mut device, &[0xC0, 5]);
+        send(&mut synth, &mut device, &[0x90, 60, 100]);
+        assert_eq!(synth.active_voices(), 2);
+
+        // A repeated note replaces both parts of the note already playing.
+        send(&mut synth, &mut device, &[0x90, 60, 100]);
+        assert_eq!(synth.active_voices(), 2);
+    }
+}
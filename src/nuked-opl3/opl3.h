@@ -43,8 +43,12 @@ extern "C" {
 #define OPL_ENABLE_STEREOEXT 0
 #endif
 
+#ifndef OPL_WRITEBUF_SIZE
 #define OPL_WRITEBUF_SIZE   1024
+#endif
+#ifndef OPL_WRITEBUF_DELAY
 #define OPL_WRITEBUF_DELAY  2
+#endif
 
 typedef struct _opl3_slot opl3_slot;
 typedef struct _opl3_channel opl3_channel;
@@ -0,0 +1,249 @@
+//! Generic playback of register-stream and tracker music formats through an `Opl3Device`.
+//!
+//! Each supported file format provides an implementation of `OplSequencer`, which knows how to
+//! perform the register writes for the current position in a song and how long to wait until the
+//! next event. `OplPlayer` owns an `Opl3Device` and interleaves sequencer events with sample
+//! generation, so a song can be rendered either from an audio callback or offline into a buffer.
+
+use crate::{Opl3Device, OplError};
+use alloc::vec::Vec;
+
+/// The `OplSequencer` trait is implemented by the sequencers for each supported music format.
+pub trait OplSequencer {
+    /// Perform all register writes due at the current song position and advance to the next event.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The `Opl3Device` to write registers to.
+    ///
+    /// # Returns
+    ///
+    /// The number of microseconds until the next event, or None if the end of the song has been
+    /// reached.
+    fn step(&mut self, device: &mut Opl3Device) -> Option<f64>;
+
+    /// Return the sequencer to the start of the song. The device has already been reset when this
+    /// is called, so any initial register setup required by the format should be written here.
+    fn rewind(&mut self, device: &mut Opl3Device);
+
+    /// Generate samples from the device. Sequencers that drive chips of their own, in addition to
+    /// the player's device, can override this to mix them into the output.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The `Opl3Device` driven by the player.
+    /// * `buffer` - A mutable reference to a buffer slice that will be filled with stereo,
+    ///   interleaved audio samples.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or an `OplError` on failure.
+    fn generate_samples(
+        &mut self,
+        device: &mut Opl3Device,
+        buffer: &mut [i16],
+    ) -> Result<(), OplError> {
+        device.generate_samples(buffer)
+    }
+}
+
+/// The `OplPlayer` struct drives an `Opl3Device` from an `OplSequencer`.
+pub struct OplPlayer<S: OplSequencer> {
+    device: Opl3Device,
+    sequencer: S,
+    pending_samples: usize,
+    finished: bool,
+    started: bool,
+}
+
+impl<S: OplSequencer> OplPlayer<S> {
+    /// Create a new player for the given sequencer.
+    ///
+    /// # Arguments
+    ///
+    /// * `sequencer`   - The sequencer that provides the song's register writes.
+    /// * `sample_rate` - The sample rate to initialize the internal `Opl3Device` with.
+    pub fn new(sequencer: S, sample_rate: u32) -> Self {
+        OplPlayer {
+            device: Opl3Device::new(sample_rate),
+            sequencer,
+            pending_samples: 0,
+            finished: false,
+            started: false,
+        }
+    }
+
+    /// Return a reference to the sequencer, for querying song position and metadata.
+    pub fn sequencer(&self) -> &S {
+        &self.sequencer
+    }
+
+    /// Return a mutable reference to the sequencer.
+    pub fn sequencer_mut(&mut self) -> &mut S {
+        &mut self.sequencer
+    }
+
+    /// Return a reference to the `Opl3Device` driven by this player.
+    pub fn device(&self) -> &Opl3Device {
+        &self.device
+    }
+
+    /// Return a mutable reference to the `Opl3Device` driven by this player.
+    pub fn device_mut(&mut self) -> &mut Opl3Device {
+        &mut self.device
+    }
+
+    /// Returns true if the end of the song has been reached.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Reset the device and return to the start of the song.
+    pub fn rewind(&mut self) {
+        _ = self.device.reset(None);
+        self.sequencer.rewind(&mut self.device);
+        self.pending_samples = 0;
+        self.finished = false;
+        self.started = true;
+    }
+
+    /// Generate a stream of 2 channel, interleaved audio samples in i16 format, executing song
+    /// events at the correct sample positions.
+    ///
+    /// The entire buffer is always filled. Once the end of the song is reached, the remainder of
+    /// the buffer is generated from the chip without any further register writes so that any
+    /// sounding notes can decay naturally.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - A mutable reference to a buffer slice that will be filled with stereo,
+    ///   interleaved audio samples.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the number of sample frames (stereo pairs) that were generated
+    /// before the end of the song, or an `OplError` on failure.
+    pub fn generate_samples(&mut self, buffer: &mut [i16]) -> Result<usize, OplError> {
+        if buffer.len() < 2 {
+            return Err(OplError::BufferUndersized);
+        }
+        if !self.started {
+            self.rewind();
+        }
+
+        let total_frames = buffer.len() / 2;
+        let mut frame = 0;
+        while frame < total_frames {
+            while self.pending_samples == 0 && !self.finished {
+                match self.sequencer.step(&mut self.device) {
+                    Some(usec) => self.pending_samples = self.device.run(usec),
+                    None => self.finished = true,
+                }
+            }
+
+            let frames = if self.finished {
+                total_frames - frame
+            } else {
+                self.pending_samples.min(total_frames - frame)
+            };
+            self.generate_frames(&mut buffer[frame * 2..(frame + frames) * 2])?;
+            if !self.finished {
+                self.pending_samples -= frames;
+                frame += frames;
+            } else {
+                return Ok(frame);
+            }
+        }
+        Ok(total_frames)
+    }
+
+    /// Render the entire song to a vector of interleaved stereo samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_frames` - An upper bound on the number of sample frames to render, to protect against
+    ///   songs that loop forever.
+    pub fn render_to_vec(&mut self, max_frames: usize) -> Result<Vec<i16>, OplError> {
+        const CHUNK_FRAMES: usize = 1024;
+        let mut output = Vec::new();
+        let mut chunk = [0i16; CHUNK_FRAMES * 2];
+        while !self.finished && output.len() / 2 < max_frames {
+            let frames = self.generate_samples(&mut chunk)?;
+            let frames = frames.min(max_frames - output.len() / 2);
+            output.extend_from_slice(&chunk[..frames * 2]);
+        }
+        Ok(output)
+    }
+
+    fn generate_frames(&mut self, buffer: &mut [i16]) -> Result<(), OplError> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.sequencer.generate_samples(&mut self.device, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sequencer with a fixed interval between events, and no register writes.
+    struct TickSequencer {
+        usec: f64,
+        ticks: Option<usize>,
+        tick: usize,
+    }
+
+    impl OplSequencer for TickSequencer {
+        fn step(&mut self, _device: &mut Opl3Device) -> Option<f64> {
+            if self.ticks.is_some_and(|ticks| self.tick >= ticks) {
+                return None;
+            }
+            self.tick += 1;
+            Some(self.usec)
+        }
+
+        fn rewind(&mut self, _device: &mut Opl3Device) {
+            self.tick = 0;
+        }
+    }
+
+    fn player(usec: f64, ticks: Option<usize>) -> OplPlayer<TickSequencer> {
+        let sequencer = TickSequencer {
+            usec,
+            ticks,
+            tick: 0,
+        };
+        OplPlayer::new(sequencer, 44100)
+    }
+
+    #[test]
+    fn render_stops_at_max_frames() {
+        let mut player = player(10_000.0, None);
+        assert_eq!(player.render_to_vec(5000).unwrap().len(), 2 * 5000);
+        assert!(!player.is_finished());
+    }
+
+    #[test]
+    fn render_stops_at_song_end() {
+        let mut player = player(10_000.0, Some(10));
+        assert_eq!(player.render_to_vec(100_000).unwrap().len(), 2 * 4410);
+        assert!(player.is_finished());
+
+        // Once finished, the buffer is still filled so notes can decay.
+        let mut buffer = [1i16; 2 * 100];
+        assert_eq!(player.generate_samples(&mut buffer).unwrap(), 0);
+
+        player.rewind();
+        assert!(!player.is_finished());
+        assert_eq!(player.render_to_vec(100_000).unwrap().len(), 2 * 4410);
+    }
+
+    #[test]
+    fn fractional_ticks_accumulate() {
+        // 44.1 samples per tick, so the fractions must be carried from tick to tick.
+        let mut player = player(1000.0, Some(10_000));
+        let frames = player.render_to_vec(1_000_000).unwrap().len() / 2;
+        assert!(frames.abs_diff(441_000) <= 1, "{frames} frames");
+    }
+}
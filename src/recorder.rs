@@ -0,0 +1,103 @@
+//! Register write recording for `Opl3Device`.
+//!
+//! A recorder is attached to an `Opl3Device` with `Opl3Device::start_recording`. While attached,
+//! it receives every register write made through `Opl3Device::write_register` (and therefore
+//! `write_data`), along with the passage of time reported to `Opl3Device::run`. Recording is
+//! stopped by detaching the recorder again with `Opl3Device::stop_recording`, which hands the
+//! concrete recorder back to the caller so that the captured song can be saved.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::any::Any;
+
+use crate::OplRegisterFile;
+
+/// Helper trait that allows a boxed `OplRecorder` or `OplBackend` to be converted back to its
+/// concrete type. This is implemented automatically for every type.
+pub trait AsAny: Any {
+    /// Return a reference to self as `dyn Any`.
+    fn as_any(&self) -> &dyn Any;
+    /// Return a mutable reference to self as `dyn Any`.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// Convert a boxed self into `Box<dyn Any>`.
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// The `OplRecorder` trait is implemented by types that capture the register traffic of an
+/// `Opl3Device`.
+pub trait OplRecorder: AsAny + Send {
+    /// Called when the recorder is attached to a device.
+    ///
+    /// # Arguments
+    ///
+    /// * `registers` - The current contents of the device's primary and secondary register files,
+    ///   so that the recorder can capture the chip state at the start of recording.
+    fn start(&mut self, registers: &[[u8; 256]; 2]);
+
+    /// Called for every register write made to the device.
+    ///
+    /// # Arguments
+    ///
+    /// * `reg`   - The register index written.
+    /// * `value` - The value written.
+    /// * `file`  - The register file that was written.
+    fn write(&mut self, reg: u8, value: u8, file: OplRegisterFile);
+
+    /// Called when time advances on the device via `run`.
+    ///
+    /// # Arguments
+    ///
+    /// * `usec` - The number of microseconds that elapsed.
+    fn advance(&mut self, usec: f64);
+
+    /// Called when the recorder is detached from the device.
+    fn stop(&mut self) {}
+}
+
+/// Return the register writes required to restore the given register state on a freshly reset
+/// chip, in the order they should be written. Registers that are zero are omitted, as are the
+/// timer registers, which do not affect the sound produced.
+///
+/// # Arguments
+///
+/// * `registers` - The contents of the primary and secondary register files, as passed to
+///   `OplRecorder::start`.
+pub fn initial_state(registers: &[[u8; 256]; 2]) -> Vec<(OplRegisterFile, u8, u8)> {
+    let mut writes = Vec::new();
+    let mut push = |file: OplRegisterFile, reg: usize, value: u8| {
+        if value != 0 {
+            writes.push((file, reg as u8, value));
+        }
+    };
+
+    // Restore the OPL3 mode and 4-op connection registers first, as they change how the rest of
+    // the register writes are interpreted.
+    for reg in [0x05, 0x04] {
+        push(OplRegisterFile::Secondary, reg, registers[1][reg]);
+    }
+    for reg in [0x01, 0x08] {
+        push(OplRegisterFile::Primary, reg, registers[0][reg]);
+    }
+    for (file, regs) in [
+        (OplRegisterFile::Primary, &registers[0]),
+        (OplRegisterFile::Secondary, &registers[1]),
+    ] {
+        for (reg, &value) in regs.iter().enumerate().skip(0x20) {
+            push(file, reg, value);
+        }
+    }
+    writes
+}
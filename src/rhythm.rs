@@ -0,0 +1,259 @@
+//! Rhythm (percussion) mode.
+//!
+//! Setting bit 5 of register 0xBD turns channels 6 to 8 into five percussion instruments: the
+//! bass drum, which uses both operators of channel 6, and the snare drum, tom-tom, cymbal and
+//! hi-hat, which each use a single operator of channels 7 and 8. The drums are keyed on and off
+//! by the low five bits of 0xBD instead of by the key on bits of their channels. The snare drum
+//! and hi-hat share the frequency of channel 7, and the tom-tom and cymbal share the frequency of
+//! channel 8.
+//!
+//! This module adds methods to `Opl3Device` for enabling rhythm mode, loading drum patches into
+//! the right operators, setting the shared frequencies and triggering each drum. The 0xBD bits
+//! are read from and written through the device's tracked register file, so the tremolo and
+//! vibrato depth bits set elsewhere are preserved.
+//!
+//! # Example
+//!
+//! ```
+//! use opl3_rs::Opl3Device;
+//! use opl3_rs::rhythm::{OplDrum, OplDrumPatch};
+//! use opl3_rs::OplOperatorPatch;
+//!
+//! let mut device = Opl3Device::new(44100);
+//! device.set_rhythm_mode(true);
+//!
+//! let operator = OplOperatorPatch {
+//!     characteristic: 0x01,
+//!     level: 0x00,
+//!     attack_decay: 0xF8,
+//!     sustain_release: 0x46,
+//!     waveform: 0x00,
+//! };
+//! let patch = OplDrumPatch { operator, carrier: operator, feedback: 0x00 };
+//! device.set_drum_patch(OplDrum::BassDrum, &patch);
+//! device.set_drum_frequency(OplDrum::BassDrum, 0x158, 1);
+//! device.trigger_drum(OplDrum::BassDrum);
+//! assert!(device.is_drum_on(OplDrum::BassDrum));
+//! ```
+
+use crate::{Opl3Device, OplOperatorPatch, OplRegisterFile};
+
+const RHYTHM_REGISTER: u8 = 0xBD;
+const RHYTHM_ENABLE: u8 = 0x20;
+const RHYTHM_DRUM_MASK: u8 = 0x1F;
+
+/// One of the five percussion instruments of rhythm mode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum OplDrum {
+    /// The bass drum, played by both operators of channel 6.
+    BassDrum,
+    /// The snare drum, played by the carrier of channel 7.
+    SnareDrum,
+    /// The tom-tom, played by the modulator of channel 8.
+    TomTom,
+    /// The top cymbal, played by the carrier of channel 8.
+    Cymbal,
+    /// The hi-hat, played by the modulator of channel 7.
+    HiHat,
+}
+
+impl OplDrum {
+    /// All of the drums, in the order of their bits in register 0xBD from highest to lowest.
+    pub const ALL: [OplDrum; 5] = [
+        OplDrum::BassDrum,
+        OplDrum::SnareDrum,
+        OplDrum::TomTom,
+        OplDrum::Cymbal,
+        OplDrum::HiHat,
+    ];
+
+    /// Return the bit of register 0xBD that keys the drum on.
+    pub fn bit(self) -> u8 {
+        match self {
+            OplDrum::BassDrum => 0x10,
+            OplDrum::SnareDrum => 0x08,
+            OplDrum::TomTom => 0x04,
+            OplDrum::Cymbal => 0x02,
+            OplDrum::HiHat => 0x01,
+        }
+    }
+
+    /// Return the channel whose frequency sets the pitch of the drum.
+    pub fn channel(self) -> usize {
+        match self {
+            OplDrum::BassDrum => 6,
+            OplDrum::SnareDrum | OplDrum::HiHat => 7,
+            OplDrum::TomTom | OplDrum::Cymbal => 8,
+        }
+    }
+
+    /// Return the offset of the drum's operator registers. For the bass drum this is the
+    /// modulator, and the carrier is three higher.
+    pub fn operator_offset(self) -> u8 {
+        match self {
+            OplDrum::BassDrum => 0x10,
+            OplDrum::SnareDrum => 0x14,
+            OplDrum::TomTom => 0x12,
+            OplDrum::Cymbal => 0x15,
+            OplDrum::HiHat => 0x11,
+        }
+    }
+}
+
+/// The settings of a percussion instrument.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OplDrumPatch {
+    /// The operator of the drum. For the bass drum, this is the modulator.
+    pub operator: OplOperatorPatch,
+    /// The carrier of the bass drum. Ignored for the other drums.
+    pub carrier: OplOperatorPatch,
+    /// Feedback and connection of the bass drum (register 0xC6). The output select bits are
+    /// written as given, and should usually be set to 0x30 in OPL3 mode. Ignored for the other
+    /// drums.
+    pub feedback: u8,
+}
+
+impl Opl3Device {
+    /// Enable or disable rhythm mode. Disabling rhythm mode also keys off all of the drums, so
+    /// that channels 6 to 8 can be used as melodic channels again.
+    pub fn set_rhythm_mode(&mut self, enable: bool) {
+        let bd = self.read_register(RHYTHM_REGISTER, OplRegisterFile::Primary);
+        let bd = if enable {
+            bd | RHYTHM_ENABLE
+        } else {
+            bd & !(RHYTHM_ENABLE | RHYTHM_DRUM_MASK)
+        };
+        self.write_rhythm(bd);
+    }
+
+    /// Returns true if rhythm mode is enabled.
+    pub fn is_rhythm_mode(&self) -> bool {
+        self.read_register(RHYTHM_REGISTER, OplRegisterFile::Primary) & RHYTHM_ENABLE != 0
+    }
+
+    /// Load a patch into the operators of a drum.
+    ///
+    /// # Arguments
+    ///
+    /// * `drum`  - The drum to load.
+    /// * `patch` - The patch. Only the bass drum uses the carrier and feedback of the patch.
+    pub fn set_drum_patch(&mut self, drum: OplDrum, patch: &OplDrumPatch) {
+        let offset = drum.operator_offset();
+        self.write_operator(offset as u16, &patch.operator);
+        if drum == OplDrum::BassDrum {
+            self.write_operator(offset as u16 + 3, &patch.carrier);
+            self.write_register(0xC6, patch.feedback, OplRegisterFile::Primary, true);
+        }
+    }
+
+    /// Set the frequency of the channel that plays a drum. The snare drum and hi-hat share channel
+    /// 7, and the tom-tom and cymbal share channel 8, so setting the frequency of one of them also
+    /// sets it for the other. The key on bit of the channel is cleared, as the drums are keyed on
+    /// through register 0xBD.
+    ///
+    /// # Arguments
+    ///
+    /// * `drum`  - The drum whose channel is set.
+    /// * `fnum`  - The 10-bit F-number.
+    /// * `block` - The 3-bit block (octave).
+    pub fn set_drum_frequency(&mut self, drum: OplDrum, fnum: u16, block: u8) {
+        let channel = drum.channel() as u8;
+        let b0 = ((block & 0x07) << 2) | ((fnum >> 8) & 0x03) as u8;
+        self.write_register(0xA0 + channel, fnum as u8, OplRegisterFile::Primary, true);
+        self.write_register(0xB0 + channel, b0, OplRegisterFile::Primary, true);
+    }
+
+    /// Key on a drum. If the drum is already on, it is keyed off first so that its envelope
+    /// restarts. Rhythm mode must be enabled for the drum to sound.
+    pub fn trigger_drum(&mut self, drum: OplDrum) {
+        let bd = self.read_register(RHYTHM_REGISTER, OplRegisterFile::Primary);
+        if bd & drum.bit() != 0 {
+            self.write_rhythm(bd & !drum.bit());
+        }
+        self.write_rhythm(bd | drum.bit());
+    }
+
+    /// Key off a drum, letting it decay at its release rate.
+    pub fn release_drum(&mut self, drum: OplDrum) {
+        let bd = self.read_register(RHYTHM_REGISTER, OplRegisterFile::Primary);
+        self.write_rhythm(bd & !drum.bit());
+    }
+
+    /// Returns true if a drum is keyed on.
+    pub fn is_drum_on(&self, drum: OplDrum) -> bool {
+        self.read_register(RHYTHM_REGISTER, OplRegisterFile::Primary) & drum.bit() != 0
+    }
+
+    fn write_rhythm(&mut self, value: u8) {
+        self.write_register(RHYTHM_REGISTER, value, OplRegisterFile::Primary, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OplEnvelopeStage;
+
+    fn reg(device: &Opl3Device, reg: u8) -> u8 {
+        device.read_register(reg, OplRegisterFile::Primary)
+    }
+
+    #[test]
+    fn rhythm_bits() {
+        let mut device = Opl3Device::new(44100);
+        device.write_register(0xBD, 0xC0, OplRegisterFile::Primary, false);
+        device.set_rhythm_mode(true);
+        assert!(device.is_rhythm_mode());
+        assert_eq!(reg(&device, 0xBD), 0xE0);
+
+        device.trigger_drum(OplDrum::SnareDrum);
+        device.trigger_drum(OplDrum::HiHat);
+        device.trigger_drum(OplDrum::HiHat);
+        assert_eq!(reg(&device, 0xBD), 0xE9);
+        device.release_drum(OplDrum::SnareDrum);
+        assert!(!device.is_drum_on(OplDrum::SnareDrum));
+        assert!(device.is_drum_on(OplDrum::HiHat));
+
+        device.set_rhythm_mode(false);
+        assert_eq!(reg(&device, 0xBD), 0xC0);
+    }
+
+    #[test]
+    fn drum_patches() {
+        let mut device = Opl3Device::new(44100);
+        device.set_rhythm_mode(true);
+        let patch = OplDrumPatch {
+            operator: OplOperatorPatch {
+                characteristic: 0x01,
+                level: 0x0A,
+                attack_decay: 0xF8,
+                sustain_release: 0x46,
+                waveform: 0x01,
+            },
+            carrier: OplOperatorPatch {
+                level: 0x00,
+                attack_decay: 0xF6,
+                ..Default::default()
+            },
+            feedback: 0x3E,
+        };
+        device.set_drum_patch(OplDrum::BassDrum, &patch);
+        device.set_drum_patch(OplDrum::Cymbal, &patch);
+        assert_eq!(reg(&device, 0x50), 0x0A);
+        assert_eq!(reg(&device, 0x73), 0xF6);
+        assert_eq!(reg(&device, 0xC6), 0x3E);
+        assert_eq!(reg(&device, 0xF5), 0x01);
+        assert_eq!(reg(&device, 0xC8), 0x00);
+
+        device.set_drum_frequency(OplDrum::TomTom, 0x2AE, 3);
+        assert_eq!(reg(&device, 0xA8), 0xAE);
+        assert_eq!(reg(&device, 0xB8), 0x0E);
+
+        device.set_drum_frequency(OplDrum::BassDrum, 0x158, 1);
+        device.trigger_drum(OplDrum::BassDrum);
+        let mut buffer = [0i16; 2 * 441];
+        device.generate_samples(&mut buffer).unwrap();
+        let envelope = device.operator_envelope(6, 1).unwrap();
+        assert_ne!(envelope.stage, OplEnvelopeStage::Release);
+    }
+}
@@ -0,0 +1,238 @@
+//! A bounded, wait-free single-producer/single-consumer ring buffer, used to pass commands and
+//! samples between threads without locking.
+//!
+//! `channel` returns the two ends of a ring. Neither end can be cloned, so there is only ever one
+//! producer and one consumer, and each end only stores to its own position.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // Positions run from 0 to twice the capacity, so that a full ring can be told apart from an
+    // empty one without giving up a slot.
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+// Each slot is only accessed by one end at a time, as ordered by the read and write positions.
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn len(&self) -> usize {
+        let read = self.read.load(Ordering::Acquire);
+        let write = self.write.load(Ordering::Acquire);
+        self.distance(read, write)
+    }
+
+    fn distance(&self, read: usize, write: usize) -> usize {
+        if write >= read {
+            write - read
+        } else {
+            write + 2 * self.capacity() - read
+        }
+    }
+
+    fn advance(&self, position: usize, count: usize) -> usize {
+        (position + count) % (2 * self.capacity())
+    }
+
+    fn slot(&self, position: usize) -> *mut MaybeUninit<T> {
+        self.slots[position % self.capacity()].get()
+    }
+}
+
+/// Create a ring buffer that holds up to `capacity` values, and return its producer and consumer.
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+pub(crate) fn channel<T: Copy>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "ring buffer capacity must not be 0");
+    let slots: Vec<UnsafeCell<MaybeUninit<T>>> = (0..capacity)
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect();
+    let ring = Arc::new(Ring {
+        slots: slots.into_boxed_slice(),
+        read: AtomicUsize::new(0),
+        write: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            ring: Arc::clone(&ring),
+        },
+        Consumer { ring },
+    )
+}
+
+/// The writing end of a ring buffer.
+pub(crate) struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T: Copy> Producer<T> {
+    /// Add a value to the ring, or return it if the ring is full.
+    pub(crate) fn push(&mut self, value: T) -> Result<(), T> {
+        let write = self.ring.write.load(Ordering::Relaxed);
+        let read = self.ring.read.load(Ordering::Acquire);
+        if self.ring.distance(read, write) == self.ring.capacity() {
+            return Err(value);
+        }
+        unsafe { (*self.ring.slot(write)).write(value) };
+        self.ring
+            .write
+            .store(self.ring.advance(write, 1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Add as many values from the slice to the ring as fit.
+    ///
+    /// # Returns
+    ///
+    /// The number of values added.
+    pub(crate) fn push_slice(&mut self, values: &[T]) -> usize {
+        let write = self.ring.write.load(Ordering::Relaxed);
+        let read = self.ring.read.load(Ordering::Acquire);
+        let count = values
+            .len()
+            .min(self.ring.capacity() - self.ring.distance(read, write));
+        for (i, value) in values[..count].iter().enumerate() {
+            unsafe { (*self.ring.slot(write + i)).write(*value) };
+        }
+        self.ring
+            .write
+            .store(self.ring.advance(write, count), Ordering::Release);
+        count
+    }
+
+    /// Return the number of values in the ring.
+    pub(crate) fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Return the number of values the ring can hold.
+    pub(crate) fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+}
+
+/// The reading end of a ring buffer.
+pub(crate) struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Remove the oldest value from the ring, or return None if the ring is empty.
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        let read = self.ring.read.load(Ordering::Relaxed);
+        let write = self.ring.write.load(Ordering::Acquire);
+        if read == write {
+            return None;
+        }
+        // The slot was written by the producer before it published the write position.
+        let value = unsafe { (*self.ring.slot(read)).assume_init() };
+        self.ring
+            .read
+            .store(self.ring.advance(read, 1), Ordering::Release);
+        Some(value)
+    }
+
+    /// Remove the oldest values from the ring into the slice, until the slice is full or the ring
+    /// is empty.
+    ///
+    /// # Returns
+    ///
+    /// The number of values removed.
+    pub(crate) fn pop_slice(&mut self, values: &mut [T]) -> usize {
+        let read = self.ring.read.load(Ordering::Relaxed);
+        let write = self.ring.write.load(Ordering::Acquire);
+        let count = values.len().min(self.ring.distance(read, write));
+        for (i, value) in values[..count].iter_mut().enumerate() {
+            *value = unsafe { (*self.ring.slot(read + i)).assume_init() };
+        }
+        self.ring
+            .read
+            .store(self.ring.advance(read, count), Ordering::Release);
+        count
+    }
+
+    /// Return the number of values in the ring.
+    pub(crate) fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Return the number of values the ring can hold.
+    pub(crate) fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_pop_wraps() {
+        let (mut producer, mut consumer) = channel::<u32>(3);
+        assert_eq!(consumer.pop(), None);
+        for round in 0..10 {
+            for i in 0..3 {
+                assert_eq!(producer.push(round * 3 + i), Ok(()));
+            }
+            assert_eq!(producer.push(99), Err(99));
+            assert_eq!(producer.len(), 3);
+            for i in 0..3 {
+                assert_eq!(consumer.pop(), Some(round * 3 + i));
+            }
+            assert_eq!(producer.len(), 0);
+        }
+        assert_eq!(producer.capacity(), 3);
+    }
+
+    #[test]
+    fn slices_wrap() {
+        let (mut producer, mut consumer) = channel::<u32>(5);
+        let mut out = [0; 4];
+        assert_eq!(producer.push_slice(&[1, 2, 3]), 3);
+        assert_eq!(consumer.pop_slice(&mut out[..2]), 2);
+        assert_eq!(out[..2], [1, 2]);
+        assert_eq!(producer.push_slice(&[4, 5, 6, 7, 8]), 4);
+        assert_eq!(consumer.len(), 5);
+        assert_eq!(consumer.pop_slice(&mut out), 4);
+        assert_eq!(out, [3, 4, 5, 6]);
+        assert_eq!(consumer.pop_slice(&mut out), 1);
+        assert_eq!(out[0], 7);
+        assert_eq!(consumer.pop_slice(&mut out), 0);
+    }
+
+    #[test]
+    fn threaded_transfer() {
+        let (mut producer, mut consumer) = channel::<u32>(16);
+        let thread = std::thread::spawn(move || {
+            for i in 0..100_000 {
+                while producer.push(i).is_err() {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < 100_000 {
+            match consumer.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,1561 @@
+//! A port of Nuked-OPL3 1.8 to Rust.
+//!
+//! With the `rust-core` feature, this module replaces the C library and its bindings, so that the
+//! crate can be built without a C compiler or libclang, for example for `wasm32`. It follows the
+//! structure of `opl3.c` function by function, and is tested against the C library sample for
+//! sample by the differential tests at the end of this file.
+//!
+//! The C chip struct is full of pointers into itself: each slot points to its channel, its
+//! modulation input and its tremolo source, and each channel points to its slots, its 4-op pair and
+//! the slot outputs it mixes. Here those pointers are replaced by indices into the chip's slot and
+//! channel arrays, so the struct can be moved freely. The remaining fields keep the names and types
+//! they have in `opl3.h`, and the entry points keep the names and signatures of the bindings, so
+//! that `Opl3Chip` can use either core unchanged.
+
+#![allow(non_snake_case)]
+
+use crate::config::{OPL_QUIRK_CHANNELSAMPLEDELAY, OPL_WRITEBUF_SIZE};
+
+const RSM_FRAC: u32 = 10;
+
+// Channel types
+const CH_2OP: u8 = 0;
+const CH_4OP: u8 = 1;
+const CH_4OP2: u8 = 2;
+const CH_DRUM: u8 = 3;
+
+// Envelope key types
+const EGK_NORM: u8 = 0x01;
+const EGK_DRUM: u8 = 0x02;
+
+// Envelope generator states
+const EG_ATTACK: u8 = 0;
+const EG_DECAY: u8 = 1;
+const EG_SUSTAIN: u8 = 2;
+const EG_RELEASE: u8 = 3;
+
+// logsin table
+const LOGSINROM: [u16; 256] = [
+    0x859, 0x6c3, 0x607, 0x58b, 0x52e, 0x4e4, 0x4a6, 0x471, 0x443, 0x41a, 0x3f5, 0x3d3, 0x3b5,
+    0x398, 0x37e, 0x365, 0x34e, 0x339, 0x324, 0x311, 0x2ff, 0x2ed, 0x2dc, 0x2cd, 0x2bd, 0x2af,
+    0x2a0, 0x293, 0x286, 0x279, 0x26d, 0x261, 0x256, 0x24b, 0x240, 0x236, 0x22c, 0x222, 0x218,
+    0x20f, 0x206, 0x1fd, 0x1f5, 0x1ec, 0x1e4, 0x1dc, 0x1d4, 0x1cd, 0x1c5, 0x1be, 0x1b7, 0x1b0,
+    0x1a9, 0x1a2, 0x19b, 0x195, 0x18f, 0x188, 0x182, 0x17c, 0x177, 0x171, 0x16b, 0x166, 0x160,
+    0x15b, 0x155, 0x150, 0x14b, 0x146, 0x141, 0x13c, 0x137, 0x133, 0x12e, 0x129, 0x125, 0x121,
+    0x11c, 0x118, 0x114, 0x10f, 0x10b, 0x107, 0x103, 0x0ff, 0x0fb, 0x0f8, 0x0f4, 0x0f0, 0x0ec,
+    0x0e9, 0x0e5, 0x0e2, 0x0de, 0x0db, 0x0d7, 0x0d4, 0x0d1, 0x0cd, 0x0ca, 0x0c7, 0x0c4, 0x0c1,
+    0x0be, 0x0bb, 0x0b8, 0x0b5, 0x0b2, 0x0af, 0x0ac, 0x0a9, 0x0a7, 0x0a4, 0x0a1, 0x09f, 0x09c,
+    0x099, 0x097, 0x094, 0x092, 0x08f, 0x08d, 0x08a, 0x088, 0x086, 0x083, 0x081, 0x07f, 0x07d,
+    0x07a, 0x078, 0x076, 0x074, 0x072, 0x070, 0x06e, 0x06c, 0x06a, 0x068, 0x066, 0x064, 0x062,
+    0x060, 0x05e, 0x05c, 0x05b, 0x059, 0x057, 0x055, 0x053, 0x052, 0x050, 0x04e, 0x04d, 0x04b,
+    0x04a, 0x048, 0x046, 0x045, 0x043, 0x042, 0x040, 0x03f, 0x03e, 0x03c, 0x03b, 0x039, 0x038,
+    0x037, 0x035, 0x034, 0x033, 0x031, 0x030, 0x02f, 0x02e, 0x02d, 0x02b, 0x02a, 0x029, 0x028,
+    0x027, 0x026, 0x025, 0x024, 0x023, 0x022, 0x021, 0x020, 0x01f, 0x01e, 0x01d, 0x01c, 0x01b,
+    0x01a, 0x019, 0x018, 0x017, 0x017, 0x016, 0x015, 0x014, 0x014, 0x013, 0x012, 0x011, 0x011,
+    0x010, 0x00f, 0x00f, 0x00e, 0x00d, 0x00d, 0x00c, 0x00c, 0x00b, 0x00a, 0x00a, 0x009, 0x009,
+    0x008, 0x008, 0x007, 0x007, 0x007, 0x006, 0x006, 0x005, 0x005, 0x005, 0x004, 0x004, 0x004,
+    0x003, 0x003, 0x003, 0x002, 0x002, 0x002, 0x002, 0x001, 0x001, 0x001, 0x001, 0x001, 0x001,
+    0x001, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000,
+];
+
+// exp table
+const EXPROM: [u16; 256] = [
+    0x7fa, 0x7f5, 0x7ef, 0x7ea, 0x7e4, 0x7df, 0x7da, 0x7d4, 0x7cf, 0x7c9, 0x7c4, 0x7bf, 0x7b9,
+    0x7b4, 0x7ae, 0x7a9, 0x7a4, 0x79f, 0x799, 0x794, 0x78f, 0x78a, 0x784, 0x77f, 0x77a, 0x775,
+    0x770, 0x76a, 0x765, 0x760, 0x75b, 0x756, 0x751, 0x74c, 0x747, 0x742, 0x73d, 0x738, 0x733,
+    0x72e, 0x729, 0x724, 0x71f, 0x71a, 0x715, 0x710, 0x70b, 0x706, 0x702, 0x6fd, 0x6f8, 0x6f3,
+    0x6ee, 0x6e9, 0x6e5, 0x6e0, 0x6db, 0x6d6, 0x6d2, 0x6cd, 0x6c8, 0x6c4, 0x6bf, 0x6ba, 0x6b5,
+    0x6b1, 0x6ac, 0x6a8, 0x6a3, 0x69e, 0x69a, 0x695, 0x691, 0x68c, 0x688, 0x683, 0x67f, 0x67a,
+    0x676, 0x671, 0x66d, 0x668, 0x664, 0x65f, 0x65b, 0x657, 0x652, 0x64e, 0x649, 0x645, 0x641,
+    0x63c, 0x638, 0x634, 0x630, 0x62b, 0x627, 0x623, 0x61e, 0x61a, 0x616, 0x612, 0x60e, 0x609,
+    0x605, 0x601, 0x5fd, 0x5f9, 0x5f5, 0x5f0, 0x5ec, 0x5e8, 0x5e4, 0x5e0, 0x5dc, 0x5d8, 0x5d4,
+    0x5d0, 0x5cc, 0x5c8, 0x5c4, 0x5c0, 0x5bc, 0x5b8, 0x5b4, 0x5b0, 0x5ac, 0x5a8, 0x5a4, 0x5a0,
+    0x59c, 0x599, 0x595, 0x591, 0x58d, 0x589, 0x585, 0x581, 0x57e, 0x57a, 0x576, 0x572, 0x56f,
+    0x56b, 0x567, 0x563, 0x560, 0x55c, 0x558, 0x554, 0x551, 0x54d, 0x549, 0x546, 0x542, 0x53e,
+    0x53b, 0x537, 0x534, 0x530, 0x52c, 0x529, 0x525, 0x522, 0x51e, 0x51b, 0x517, 0x514, 0x510,
+    0x50c, 0x509, 0x506, 0x502, 0x4ff, 0x4fb, 0x4f8, 0x4f4, 0x4f1, 0x4ed, 0x4ea, 0x4e7, 0x4e3,
+    0x4e0, 0x4dc, 0x4d9, 0x4d6, 0x4d2, 0x4cf, 0x4cc, 0x4c8, 0x4c5, 0x4c2, 0x4be, 0x4bb, 0x4b8,
+    0x4b5, 0x4b1, 0x4ae, 0x4ab, 0x4a8, 0x4a4, 0x4a1, 0x49e, 0x49b, 0x498, 0x494, 0x491, 0x48e,
+    0x48b, 0x488, 0x485, 0x482, 0x47e, 0x47b, 0x478, 0x475, 0x472, 0x46f, 0x46c, 0x469, 0x466,
+    0x463, 0x460, 0x45d, 0x45a, 0x457, 0x454, 0x451, 0x44e, 0x44b, 0x448, 0x445, 0x442, 0x43f,
+    0x43c, 0x439, 0x436, 0x433, 0x430, 0x42d, 0x42a, 0x428, 0x425, 0x422, 0x41f, 0x41c, 0x419,
+    0x416, 0x414, 0x411, 0x40e, 0x40b, 0x408, 0x406, 0x403, 0x400,
+];
+
+// Stereo extension panning table, the default `OPL_SIN` of `opl3.c` evaluated for 0 to 255.
+#[cfg(feature = "stereoext")]
+const PANPOT_LUT: [i32; 256] = [
+    0, 402, 804, 1206, 1608, 2010, 2412, 2814, 3215, 3617, 4018, 4420, 4821, 5222, 5622, 6023,
+    6423, 6823, 7223, 7623, 8022, 8421, 8819, 9218, 9616, 10013, 10410, 10807, 11204, 11600, 11995,
+    12390, 12785, 13179, 13573, 13966, 14359, 14751, 15142, 15533, 15923, 16313, 16702, 17091,
+    17479, 17866, 18253, 18638, 19024, 19408, 19792, 20175, 20557, 20938, 21319, 21699, 22078,
+    22456, 22833, 23210, 23586, 23960, 24334, 24707, 25079, 25450, 25820, 26189, 26557, 26925,
+    27291, 27656, 28020, 28383, 28745, 29105, 29465, 29824, 30181, 30538, 30893, 31247, 31600,
+    31952, 32302, 32651, 32999, 33346, 33692, 34036, 34379, 34721, 35061, 35400, 35738, 36074,
+    36409, 36743, 37075, 37406, 37736, 38064, 38390, 38716, 39039, 39362, 39682, 40002, 40319,
+    40636, 40950, 41263, 41575, 41885, 42194, 42501, 42806, 43110, 43412, 43712, 44011, 44308,
+    44603, 44897, 45189, 45480, 45768, 46055, 46340, 46624, 46906, 47186, 47464, 47740, 48015,
+    48288, 48558, 48828, 49095, 49360, 49624, 49886, 50146, 50403, 50660, 50914, 51166, 51416,
+    51665, 51911, 52155, 52398, 52639, 52877, 53114, 53348, 53581, 53811, 54040, 54266, 54491,
+    54713, 54933, 55152, 55368, 55582, 55794, 56004, 56212, 56417, 56621, 56822, 57022, 57219,
+    57414, 57606, 57797, 57986, 58172, 58356, 58538, 58718, 58895, 59070, 59243, 59414, 59583,
+    59749, 59913, 60075, 60235, 60392, 60547, 60700, 60850, 60998, 61144, 61288, 61429, 61568,
+    61705, 61839, 61971, 62100, 62228, 62353, 62475, 62596, 62714, 62829, 62942, 63053, 63162,
+    63268, 63371, 63473, 63571, 63668, 63762, 63854, 63943, 64030, 64115, 64197, 64276, 64353,
+    64428, 64501, 64571, 64638, 64703, 64766, 64826, 64884, 64939, 64992, 65043, 65091, 65136,
+    65179, 65220, 65258, 65294, 65327, 65358, 65386, 65412, 65436, 65457, 65475, 65491, 65505,
+    65516, 65524, 65531, 65534,
+];
+
+// Frequency multiplier table multiplied by 2:
+// 1/2, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 10, 12, 12, 15, 15
+const MT: [u8; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+const KSLROM: [u8; 16] = [
+    0, 32, 40, 45, 48, 51, 53, 55, 56, 58, 59, 60, 61, 62, 63, 64,
+];
+
+const KSLSHIFT: [u8; 4] = [8, 1, 2, 0];
+
+const EG_INCSTEP: [[u8; 4]; 4] = [[0, 0, 0, 0], [1, 0, 0, 0], [1, 0, 1, 0], [1, 1, 1, 0]];
+
+// Address decoding
+const AD_SLOT: [i8; 0x20] = [
+    0, 1, 2, 3, 4, 5, -1, -1, 6, 7, 8, 9, 10, 11, -1, -1, 12, 13, 14, 15, 16, 17, -1, -1, -1, -1,
+    -1, -1, -1, -1, -1, -1,
+];
+
+const CH_SLOT: [u8; 18] = [
+    0, 1, 2, 6, 7, 8, 12, 13, 14, 18, 19, 20, 24, 25, 26, 30, 31, 32,
+];
+
+/// The modulation input of a slot, which is a pointer to an `int16_t` in `opl3.c`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Opl3Mod {
+    /// No modulation (`chip->zeromod`).
+    Zero,
+    /// The feedback of the given slot (`slot->fbmod`).
+    Feedback(u8),
+    /// The output of the given slot (`slot->out`).
+    Output(u8),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Opl3Slot {
+    pub channel: u8,
+    pub out: i16,
+    pub fbmod: i16,
+    pub mod_: Opl3Mod,
+    pub prout: i16,
+    pub eg_rout: u16,
+    pub eg_out: u16,
+    pub eg_gen: u8,
+    pub eg_ksl: u8,
+    /// Whether the slot follows the chip's tremolo, which is a pointer to either
+    /// `chip->tremolo` or `chip->zeromod` in `opl3.c`.
+    pub trem: bool,
+    pub reg_vib: u8,
+    pub reg_type: u8,
+    pub reg_ksr: u8,
+    pub reg_mult: u8,
+    pub reg_ksl: u8,
+    pub reg_tl: u8,
+    pub reg_ar: u8,
+    pub reg_dr: u8,
+    pub reg_sl: u8,
+    pub reg_rr: u8,
+    pub reg_wf: u8,
+    pub key: u8,
+    pub pg_reset: u32,
+    pub pg_phase: u32,
+    pub pg_phase_out: u16,
+    pub slot_num: u8,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Opl3Channel {
+    pub slotz: [u8; 2],
+    pub pair: u8,
+    /// The slots whose outputs are mixed into the channel. `None` is `chip->zeromod`.
+    pub out: [Option<u8>; 4],
+    #[cfg(feature = "stereoext")]
+    pub leftpan: i32,
+    #[cfg(feature = "stereoext")]
+    pub rightpan: i32,
+    pub chtype: u8,
+    pub f_num: u16,
+    pub block: u8,
+    pub fb: u8,
+    pub con: u8,
+    pub alg: u8,
+    pub ksv: u8,
+    pub cha: u16,
+    pub chb: u16,
+    pub chc: u16,
+    pub chd: u16,
+    pub ch_num: u8,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Opl3Writebuf {
+    pub time: u64,
+    pub reg: u16,
+    pub data: u8,
+}
+
+#[derive(Clone, Debug)]
+pub struct Opl3Chip {
+    pub channel: [Opl3Channel; 18],
+    pub slot: [Opl3Slot; 36],
+    pub timer: u16,
+    pub eg_timer: u64,
+    pub eg_timerrem: u8,
+    pub eg_state: u8,
+    pub eg_add: u8,
+    pub eg_timer_lo: u8,
+    pub newm: u8,
+    pub nts: u8,
+    pub rhy: u8,
+    pub vibpos: u8,
+    pub vibshift: u8,
+    pub tremolo: u8,
+    pub tremolopos: u8,
+    pub tremoloshift: u8,
+    pub noise: u32,
+    pub mixbuff: [i32; 4],
+    pub rm_hh_bit2: u8,
+    pub rm_hh_bit3: u8,
+    pub rm_hh_bit7: u8,
+    pub rm_hh_bit8: u8,
+    pub rm_tc_bit3: u8,
+    pub rm_tc_bit5: u8,
+    #[cfg(feature = "stereoext")]
+    pub stereoext: u8,
+    pub rateratio: i32,
+    pub samplecnt: i32,
+    pub oldsamples: [i16; 4],
+    pub samples: [i16; 4],
+    pub writebuf_samplecnt: u64,
+    pub writebuf_cur: u32,
+    pub writebuf_last: u32,
+    pub writebuf_lasttime: u64,
+    pub writebuf: [Opl3Writebuf; OPL_WRITEBUF_SIZE],
+}
+
+fn envelope_calc_exp(level: u32) -> i16 {
+    let level = level.min(0x1fff);
+    (((EXPROM[(level & 0xff) as usize] as i32) << 1) >> (level >> 8)) as i16
+}
+
+fn envelope_calc_exp_neg(level: u32, neg: u16) -> i16 {
+    (envelope_calc_exp(level) as u16 ^ neg) as i16
+}
+
+fn logsin(phase: u16) -> u16 {
+    if phase & 0x100 != 0 {
+        LOGSINROM[((phase & 0xff) ^ 0xff) as usize]
+    } else {
+        LOGSINROM[(phase & 0xff) as usize]
+    }
+}
+
+fn logsin_half(phase: u16) -> u16 {
+    if phase & 0x80 != 0 {
+        LOGSINROM[(((phase ^ 0xff) << 1) & 0xff) as usize]
+    } else {
+        LOGSINROM[((phase << 1) & 0xff) as usize]
+    }
+}
+
+/// Compute the output of waveform `wf`, as done by `OPL3_EnvelopeCalcSin0` to
+/// `OPL3_EnvelopeCalcSin7`.
+fn envelope_sin(wf: u8, phase: u16, envelope: u16) -> i16 {
+    let phase = phase & 0x3ff;
+    let env = (envelope as u32) << 3;
+    match wf {
+        0 => {
+            let neg = if phase & 0x200 != 0 { 0xffff } else { 0 };
+            envelope_calc_exp_neg(logsin(phase) as u32 + env, neg)
+        }
+        1 => {
+            let out = if phase & 0x200 != 0 {
+                0x1000
+            } else {
+                logsin(phase)
+            };
+            envelope_calc_exp(out as u32 + env)
+        }
+        2 => envelope_calc_exp(logsin(phase) as u32 + env),
+        3 => {
+            let out = if phase & 0x100 != 0 {
+                0x1000
+            } else {
+                LOGSINROM[(phase & 0xff) as usize]
+            };
+            envelope_calc_exp(out as u32 + env)
+        }
+        4 => {
+            let neg = if phase & 0x300 == 0x100 { 0xffff } else { 0 };
+            let out = if phase & 0x200 != 0 {
+                0x1000
+            } else {
+                logsin_half(phase)
+            };
+            envelope_calc_exp_neg(out as u32 + env, neg)
+        }
+        5 => {
+            let out = if phase & 0x200 != 0 {
+                0x1000
+            } else {
+                logsin_half(phase)
+            };
+            envelope_calc_exp(out as u32 + env)
+        }
+        6 => {
+            let neg = if phase & 0x200 != 0 { 0xffff } else { 0 };
+            envelope_calc_exp_neg(env, neg)
+        }
+        _ => {
+            let (phase, neg) = if phase & 0x200 != 0 {
+                ((phase & 0x1ff) ^ 0x1ff, 0xffff)
+            } else {
+                (phase, 0)
+            };
+            envelope_calc_exp_neg(((phase << 3) as u32) + env, neg)
+        }
+    }
+}
+
+fn clip_sample(sample: i32) -> i16 {
+    sample.clamp(-32768, 32767) as i16
+}
+
+impl Opl3Chip {
+    /// Create a chip in its reset state, as done by `OPL3_Reset`.
+    pub fn new(samplerate: u32) -> Self {
+        let mut chip = Opl3Chip {
+            channel: [Opl3Channel {
+                slotz: [0; 2],
+                pair: 0,
+                out: [None; 4],
+                #[cfg(feature = "stereoext")]
+                leftpan: 0x10000,
+                #[cfg(feature = "stereoext")]
+                rightpan: 0x10000,
+                chtype: CH_2OP,
+                f_num: 0,
+                block: 0,
+                fb: 0,
+                con: 0,
+                alg: 0,
+                ksv: 0,
+                cha: 0xffff,
+                chb: 0xffff,
+                chc: 0,
+                chd: 0,
+                ch_num: 0,
+            }; 18],
+            slot: [Opl3Slot {
+                channel: 0,
+                out: 0,
+                fbmod: 0,
+                mod_: Opl3Mod::Zero,
+                prout: 0,
+                eg_rout: 0x1ff,
+                eg_out: 0x1ff,
+                eg_gen: EG_RELEASE,
+                eg_ksl: 0,
+                trem: false,
+                reg_vib: 0,
+                reg_type: 0,
+                reg_ksr: 0,
+                reg_mult: 0,
+                reg_ksl: 0,
+                reg_tl: 0,
+                reg_ar: 0,
+                reg_dr: 0,
+                reg_sl: 0,
+                reg_rr: 0,
+                reg_wf: 0,
+                key: 0,
+                pg_reset: 0,
+                pg_phase: 0,
+                pg_phase_out: 0,
+                slot_num: 0,
+            }; 36],
+            timer: 0,
+            eg_timer: 0,
+            eg_timerrem: 0,
+            eg_state: 0,
+            eg_add: 0,
+            eg_timer_lo: 0,
+            newm: 0,
+            nts: 0,
+            rhy: 0,
+            vibpos: 0,
+            vibshift: 1,
+            tremolo: 0,
+            tremolopos: 0,
+            tremoloshift: 4,
+            noise: 1,
+            mixbuff: [0; 4],
+            rm_hh_bit2: 0,
+            rm_hh_bit3: 0,
+            rm_hh_bit7: 0,
+            rm_hh_bit8: 0,
+            rm_tc_bit3: 0,
+            rm_tc_bit5: 0,
+            #[cfg(feature = "stereoext")]
+            stereoext: 0,
+            rateratio: ((samplerate << RSM_FRAC) / 49716) as i32,
+            samplecnt: 0,
+            oldsamples: [0; 4],
+            samples: [0; 4],
+            writebuf_samplecnt: 0,
+            writebuf_cur: 0,
+            writebuf_last: 0,
+            writebuf_lasttime: 0,
+            writebuf: [Opl3Writebuf::default(); OPL_WRITEBUF_SIZE],
+        };
+        for (slotnum, slot) in chip.slot.iter_mut().enumerate() {
+            slot.slot_num = slotnum as u8;
+        }
+        for (channum, &local_ch_slot) in CH_SLOT.iter().enumerate() {
+            let channel = &mut chip.channel[channum];
+            channel.slotz = [local_ch_slot, local_ch_slot + 3];
+            // Channels without a 4-op pair never use it.
+            channel.pair = match channum % 9 {
+                0..=2 => channum as u8 + 3,
+                3..=5 => channum as u8 - 3,
+                _ => channum as u8,
+            };
+            channel.ch_num = channum as u8;
+            chip.slot[local_ch_slot as usize].channel = channum as u8;
+            chip.slot[local_ch_slot as usize + 3].channel = channum as u8;
+            chip.channel_setup_alg(channum);
+        }
+        chip
+    }
+
+    fn envelope_update_ksl(&mut self, slot: u8) {
+        let slot = &mut self.slot[slot as usize];
+        let channel = &self.channel[slot.channel as usize];
+        let ksl = ((KSLROM[(channel.f_num >> 6) as usize] as i32) << 2)
+            - ((0x08 - channel.block as i32) << 5);
+        slot.eg_ksl = ksl.max(0) as u8;
+    }
+
+    fn envelope_calc(&mut self, slot: usize) {
+        let tremolo = self.tremolo;
+        let (eg_add, eg_state, eg_timer_lo) = (self.eg_add, self.eg_state, self.eg_timer_lo);
+        let slot = &mut self.slot[slot];
+        let ksv = self.channel[slot.channel as usize].ksv;
+
+        let mut reg_rate = 0;
+        let mut reset = 0;
+        slot.eg_out = slot.eg_rout
+            + ((slot.reg_tl as u16) << 2)
+            + ((slot.eg_ksl as u16) >> KSLSHIFT[slot.reg_ksl as usize])
+            + if slot.trem { tremolo as u16 } else { 0 };
+        if slot.key != 0 && slot.eg_gen == EG_RELEASE {
+            reset = 1;
+            reg_rate = slot.reg_ar;
+        } else {
+            match slot.eg_gen {
+                EG_ATTACK => reg_rate = slot.reg_ar,
+                EG_DECAY => reg_rate = slot.reg_dr,
+                EG_SUSTAIN => {
+                    if slot.reg_type == 0 {
+                        reg_rate = slot.reg_rr;
+                    }
+                }
+                _ => reg_rate = slot.reg_rr,
+            }
+        }
+        slot.pg_reset = reset;
+        let ks = ksv >> ((slot.reg_ksr ^ 1) << 1);
+        let nonzero = reg_rate != 0;
+        let rate = ks + (reg_rate << 2);
+        let mut rate_hi = rate >> 2;
+        let rate_lo = rate & 0x03;
+        if rate_hi & 0x10 != 0 {
+            rate_hi = 0x0f;
+        }
+        let eg_shift = rate_hi + eg_add;
+        let mut shift = 0;
+        if nonzero {
+            if rate_hi < 12 {
+                if eg_state != 0 {
+                    shift = match eg_shift {
+                        12 => 1,
+                        13 => (rate_lo >> 1) & 0x01,
+                        14 => rate_lo & 0x01,
+                        _ => 0,
+                    };
+                }
+            } else {
+                shift = (rate_hi & 0x03) + EG_INCSTEP[rate_lo as usize][eg_timer_lo as usize];
+                if shift & 0x04 != 0 {
+                    shift = 0x03;
+                }
+                if shift == 0 {
+                    shift = eg_state;
+                }
+            }
+        }
+        let mut eg_rout = slot.eg_rout as i32;
+        let mut eg_inc = 0i32;
+        // Instant attack
+        if reset != 0 && rate_hi == 0x0f {
+            eg_rout = 0x00;
+        }
+        // Envelope off
+        let eg_off = slot.eg_rout & 0x1f8 == 0x1f8;
+        if slot.eg_gen != EG_ATTACK && reset == 0 && eg_off {
+            eg_rout = 0x1ff;
+        }
+        match slot.eg_gen {
+            EG_ATTACK => {
+                if slot.eg_rout == 0 {
+                    slot.eg_gen = EG_DECAY;
+                } else if slot.key != 0 && shift > 0 && rate_hi != 0x0f {
+                    eg_inc = !(slot.eg_rout as i32) >> (4 - shift);
+                }
+            }
+            EG_DECAY => {
+                if (slot.eg_rout >> 4) == slot.reg_sl as u16 {
+                    slot.eg_gen = EG_SUSTAIN;
+                } else if !eg_off && reset == 0 && shift > 0 {
+                    eg_inc = 1 << (shift - 1);
+                }
+            }
+            _ => {
+                if !eg_off && reset == 0 && shift > 0 {
+                    eg_inc = 1 << (shift - 1);
+                }
+            }
+        }
+        slot.eg_rout = ((eg_rout + eg_inc) & 0x1ff) as u16;
+        // Key off
+        if reset != 0 {
+            slot.eg_gen = EG_ATTACK;
+        }
+        if slot.key == 0 {
+            slot.eg_gen = EG_RELEASE;
+        }
+    }
+
+    fn envelope_key_on(&mut self, slot: u8, kind: u8) {
+        self.slot[slot as usize].key |= kind;
+    }
+
+    fn envelope_key_off(&mut self, slot: u8, kind: u8) {
+        self.slot[slot as usize].key &= !kind;
+    }
+
+    fn phase_generate(&mut self, slot: usize) {
+        let channel = &self.channel[self.slot[slot].channel as usize];
+        let (mut f_num, block) = (channel.f_num, channel.block);
+        let slot = &mut self.slot[slot];
+        if slot.reg_vib != 0 {
+            let mut range = ((f_num >> 7) & 7) as i8;
+            let vibpos = self.vibpos;
+
+            if vibpos & 3 == 0 {
+                range = 0;
+            } else if vibpos & 1 != 0 {
+                range >>= 1;
+            }
+            range >>= self.vibshift;
+
+            if vibpos & 4 != 0 {
+                range = -range;
+            }
+            f_num = f_num.wrapping_add(range as u16);
+        }
+        let basefreq = ((f_num as u32) << block) >> 1;
+        let phase = (slot.pg_phase >> 9) as u16;
+        if slot.pg_reset != 0 {
+            slot.pg_phase = 0;
+        }
+        slot.pg_phase = slot
+            .pg_phase
+            .wrapping_add((basefreq * MT[slot.reg_mult as usize] as u32) >> 1);
+        // Rhythm mode
+        let noise = self.noise;
+        slot.pg_phase_out = phase;
+        if slot.slot_num == 13 {
+            // hh
+            self.rm_hh_bit2 = ((phase >> 2) & 1) as u8;
+            self.rm_hh_bit3 = ((phase >> 3) & 1) as u8;
+            self.rm_hh_bit7 = ((phase >> 7) & 1) as u8;
+            self.rm_hh_bit8 = ((phase >> 8) & 1) as u8;
+        }
+        if slot.slot_num == 17 && (self.rhy & 0x20) != 0 {
+            // tc
+            self.rm_tc_bit3 = ((phase >> 3) & 1) as u8;
+            self.rm_tc_bit5 = ((phase >> 5) & 1) as u8;
+        }
+        if self.rhy & 0x20 != 0 {
+            let rm_xor = (self.rm_hh_bit2 ^ self.rm_hh_bit7)
+                | (self.rm_hh_bit3 ^ self.rm_tc_bit5)
+                | (self.rm_tc_bit3 ^ self.rm_tc_bit5);
+            match slot.slot_num {
+                13 => {
+                    // hh
+                    slot.pg_phase_out = (rm_xor as u16) << 9;
+                    if (rm_xor as u32 ^ (noise & 1)) != 0 {
+                        slot.pg_phase_out |= 0xd0;
+                    } else {
+                        slot.pg_phase_out |= 0x34;
+                    }
+                }
+                16 => {
+                    // sd
+                    slot.pg_phase_out = ((self.rm_hh_bit8 as u16) << 9)
+                        | (((self.rm_hh_bit8 as u32 ^ (noise & 1)) as u16) << 8);
+                }
+                17 => {
+                    // tc
+                    slot.pg_phase_out = ((rm_xor as u16) << 9) | 0x80;
+                }
+                _ => {}
+            }
+        }
+        let n_bit = ((noise >> 14) ^ noise) & 0x01;
+        self.noise = (noise >> 1) | (n_bit << 22);
+    }
+
+    fn slot_write_20(&mut self, slot: usize, data: u8) {
+        let slot = &mut self.slot[slot];
+        slot.trem = (data >> 7) & 0x01 != 0;
+        slot.reg_vib = (data >> 6) & 0x01;
+        slot.reg_type = (data >> 5) & 0x01;
+        slot.reg_ksr = (data >> 4) & 0x01;
+        slot.reg_mult = data & 0x0f;
+    }
+
+    fn slot_write_40(&mut self, slot: usize, data: u8) {
+        self.slot[slot].reg_ksl = (data >> 6) & 0x03;
+        self.slot[slot].reg_tl = data & 0x3f;
+        self.envelope_update_ksl(slot as u8);
+    }
+
+    fn slot_write_60(&mut self, slot: usize, data: u8) {
+        self.slot[slot].reg_ar = (data >> 4) & 0x0f;
+        self.slot[slot].reg_dr = data & 0x0f;
+    }
+
+    fn slot_write_80(&mut self, slot: usize, data: u8) {
+        let slot = &mut self.slot[slot];
+        slot.reg_sl = (data >> 4) & 0x0f;
+        if slot.reg_sl == 0x0f {
+            slot.reg_sl = 0x1f;
+        }
+        slot.reg_rr = data & 0x0f;
+    }
+
+    fn slot_write_e0(&mut self, slot: usize, data: u8) {
+        let slot = &mut self.slot[slot];
+        slot.reg_wf = data & 0x07;
+        if self.newm == 0x00 {
+            slot.reg_wf &= 0x03;
+        }
+    }
+
+    fn slot_generate(&mut self, slot: usize) {
+        let modulation = match self.slot[slot].mod_ {
+            Opl3Mod::Zero => 0,
+            Opl3Mod::Feedback(from) => self.slot[from as usize].fbmod,
+            Opl3Mod::Output(from) => self.slot[from as usize].out,
+        };
+        let slot = &mut self.slot[slot];
+        slot.out = envelope_sin(
+            slot.reg_wf,
+            (slot.pg_phase_out as i32 + modulation as i32) as u16,
+            slot.eg_out,
+        );
+    }
+
+    fn slot_calc_fb(&mut self, slot: usize) {
+        let fb = self.channel[self.slot[slot].channel as usize].fb;
+        let slot = &mut self.slot[slot];
+        if fb != 0x00 {
+            slot.fbmod = ((slot.prout as i32 + slot.out as i32) >> (0x09 - fb)) as i16;
+        } else {
+            slot.fbmod = 0;
+        }
+        slot.prout = slot.out;
+    }
+
+    fn channel_update_rhythm(&mut self, data: u8) {
+        self.rhy = data & 0x3f;
+        if self.rhy & 0x20 != 0 {
+            let [c6s0, c6s1] = self.channel[6].slotz;
+            let [c7s0, c7s1] = self.channel[7].slotz;
+            let [c8s0, c8s1] = self.channel[8].slotz;
+            self.channel[6].out = [Some(c6s1), Some(c6s1), None, None];
+            self.channel[7].out = [Some(c7s0), Some(c7s0), Some(c7s1), Some(c7s1)];
+            self.channel[8].out = [Some(c8s0), Some(c8s0), Some(c8s1), Some(c8s1)];
+            for chnum in 6..9 {
+                self.channel[chnum].chtype = CH_DRUM;
+            }
+            self.channel_setup_alg(6);
+            self.channel_setup_alg(7);
+            self.channel_setup_alg(8);
+            let drums = [
+                (0x01, c7s0), // hh
+                (0x02, c8s1), // tc
+                (0x04, c8s0), // tom
+                (0x08, c7s1), // sd
+                (0x10, c6s0), // bd
+                (0x10, c6s1),
+            ];
+            for (bit, slot) in drums {
+                if self.rhy & bit != 0 {
+                    self.envelope_key_on(slot, EGK_DRUM);
+                } else {
+                    self.envelope_key_off(slot, EGK_DRUM);
+                }
+            }
+        } else {
+            for chnum in 6..9 {
+                self.channel[chnum].chtype = CH_2OP;
+                self.channel_setup_alg(chnum);
+                let [slot0, slot1] = self.channel[chnum].slotz;
+                self.envelope_key_off(slot0, EGK_DRUM);
+                self.envelope_key_off(slot1, EGK_DRUM);
+            }
+        }
+    }
+
+    /// Update the key scale values of a channel and the levels of its slots after its frequency
+    /// changed, and copy the frequency to the second half of a 4-op channel. As in `opl3.c`, the
+    /// block is only copied by writes to register 0xB0.
+    fn channel_update_frequency(&mut self, channel: usize, copy_block: bool) {
+        let ch = &mut self.channel[channel];
+        ch.ksv = (ch.block << 1) | ((ch.f_num >> (0x09 - self.nts)) & 0x01) as u8;
+        let [slot0, slot1] = ch.slotz;
+        self.envelope_update_ksl(slot0);
+        self.envelope_update_ksl(slot1);
+        let ch = self.channel[channel];
+        if self.newm != 0 && ch.chtype == CH_4OP {
+            let pair = &mut self.channel[ch.pair as usize];
+            pair.f_num = ch.f_num;
+            if copy_block {
+                pair.block = ch.block;
+            }
+            pair.ksv = ch.ksv;
+            let [slot0, slot1] = pair.slotz;
+            self.envelope_update_ksl(slot0);
+            self.envelope_update_ksl(slot1);
+        }
+    }
+
+    fn channel_write_a0(&mut self, channel: usize, data: u8) {
+        if self.newm != 0 && self.channel[channel].chtype == CH_4OP2 {
+            return;
+        }
+        let ch = &mut self.channel[channel];
+        ch.f_num = (ch.f_num & 0x300) | data as u16;
+        self.channel_update_frequency(channel, false);
+    }
+
+    fn channel_write_b0(&mut self, channel: usize, data: u8) {
+        if self.newm != 0 && self.channel[channel].chtype == CH_4OP2 {
+            return;
+        }
+        let ch = &mut self.channel[channel];
+        ch.f_num = (ch.f_num & 0xff) | (((data & 0x03) as u16) << 8);
+        ch.block = (data >> 2) & 0x07;
+        self.channel_update_frequency(channel, true);
+    }
+
+    fn channel_setup_alg(&mut self, channel: usize) {
+        let ch = self.channel[channel];
+        let [s0, s1] = ch.slotz;
+        if ch.chtype == CH_DRUM {
+            if ch.ch_num == 7 || ch.ch_num == 8 {
+                self.slot[s0 as usize].mod_ = Opl3Mod::Zero;
+                self.slot[s1 as usize].mod_ = Opl3Mod::Zero;
+                return;
+            }
+            self.slot[s0 as usize].mod_ = Opl3Mod::Feedback(s0);
+            self.slot[s1 as usize].mod_ = match ch.alg & 0x01 {
+                0x00 => Opl3Mod::Output(s0),
+                _ => Opl3Mod::Zero,
+            };
+            return;
+        }
+        if ch.alg & 0x08 != 0 {
+            return;
+        }
+        if ch.alg & 0x04 != 0 {
+            let pair = ch.pair as usize;
+            let [p0, p1] = self.channel[pair].slotz;
+            self.channel[pair].out = [None; 4];
+            let (mods, out) = match ch.alg & 0x03 {
+                0x00 => (
+                    [
+                        Opl3Mod::Feedback(p0),
+                        Opl3Mod::Output(p0),
+                        Opl3Mod::Output(p1),
+                        Opl3Mod::Output(s0),
+                    ],
+                    [Some(s1), None, None, None],
+                ),
+                0x01 => (
+                    [
+                        Opl3Mod::Feedback(p0),
+                        Opl3Mod::Output(p0),
+                        Opl3Mod::Zero,
+                        Opl3Mod::Output(s0),
+                    ],
+                    [Some(p1), Some(s1), None, None],
+                ),
+                0x02 => (
+                    [
+                        Opl3Mod::Feedback(p0),
+                        Opl3Mod::Zero,
+                        Opl3Mod::Output(p1),
+                        Opl3Mod::Output(s0),
+                    ],
+                    [Some(p0), Some(s1), None, None],
+                ),
+                _ => (
+                    [
+                        Opl3Mod::Feedback(p0),
+                        Opl3Mod::Zero,
+                        Opl3Mod::Output(p1),
+                        Opl3Mod::Zero,
+                    ],
+                    [Some(p0), Some(s0), Some(s1), None],
+                ),
+            };
+            for (slot, mod_) in [p0, p1, s0, s1].into_iter().zip(mods) {
+                self.slot[slot as usize].mod_ = mod_;
+            }
+            self.channel[channel].out = out;
+        } else {
+            self.slot[s0 as usize].mod_ = Opl3Mod::Feedback(s0);
+            match ch.alg & 0x01 {
+                0x00 => {
+                    self.slot[s1 as usize].mod_ = Opl3Mod::Output(s0);
+                    self.channel[channel].out = [Some(s1), None, None, None];
+                }
+                _ => {
+                    self.slot[s1 as usize].mod_ = Opl3Mod::Zero;
+                    self.channel[channel].out = [Some(s0), Some(s1), None, None];
+                }
+            }
+        }
+    }
+
+    fn channel_update_alg(&mut self, channel: usize) {
+        let ch = &mut self.channel[channel];
+        ch.alg = ch.con;
+        let (chtype, con, pair) = (ch.chtype, ch.con, ch.pair as usize);
+        if self.newm != 0 {
+            if chtype == CH_4OP {
+                self.channel[pair].alg = 0x04 | (con << 1) | self.channel[pair].con;
+                self.channel[channel].alg = 0x08;
+                self.channel_setup_alg(pair);
+            } else if chtype == CH_4OP2 {
+                self.channel[channel].alg = 0x04 | (self.channel[pair].con << 1) | con;
+                self.channel[pair].alg = 0x08;
+                self.channel_setup_alg(channel);
+            } else {
+                self.channel_setup_alg(channel);
+            }
+        } else {
+            self.channel_setup_alg(channel);
+        }
+    }
+
+    fn channel_write_c0(&mut self, channel: usize, data: u8) {
+        self.channel[channel].fb = (data & 0x0e) >> 1;
+        self.channel[channel].con = data & 0x01;
+        self.channel_update_alg(channel);
+        let newm = self.newm;
+        let ch = &mut self.channel[channel];
+        if newm != 0 {
+            let bit = |n: u8| if (data >> n) & 0x01 != 0 { 0xffff } else { 0 };
+            ch.cha = bit(4);
+            ch.chb = bit(5);
+            ch.chc = bit(6);
+            ch.chd = bit(7);
+        } else {
+            ch.cha = 0xffff;
+            ch.chb = 0xffff;
+            ch.chc = 0;
+            ch.chd = 0;
+        }
+        #[cfg(feature = "stereoext")]
+        if self.stereoext == 0 {
+            ch.leftpan = (ch.cha as i32) << 16;
+            ch.rightpan = (ch.chb as i32) << 16;
+        }
+    }
+
+    #[cfg(feature = "stereoext")]
+    fn channel_write_d0(&mut self, channel: usize, data: u8) {
+        if self.stereoext != 0 {
+            let ch = &mut self.channel[channel];
+            ch.leftpan = PANPOT_LUT[(data ^ 0xff) as usize];
+            ch.rightpan = PANPOT_LUT[data as usize];
+        }
+    }
+
+    /// Return the slots keyed on and off by the key on bit of a channel.
+    fn channel_key_slots(&self, channel: usize) -> ([u8; 4], usize) {
+        let ch = &self.channel[channel];
+        let [s0, s1] = ch.slotz;
+        if self.newm != 0 {
+            match ch.chtype {
+                CH_4OP => {
+                    let [p0, p1] = self.channel[ch.pair as usize].slotz;
+                    ([s0, s1, p0, p1], 4)
+                }
+                CH_2OP | CH_DRUM => ([s0, s1, 0, 0], 2),
+                _ => ([0; 4], 0),
+            }
+        } else {
+            ([s0, s1, 0, 0], 2)
+        }
+    }
+
+    fn channel_key_on(&mut self, channel: usize) {
+        let (slots, count) = self.channel_key_slots(channel);
+        for slot in &slots[..count] {
+            self.envelope_key_on(*slot, EGK_NORM);
+        }
+    }
+
+    fn channel_key_off(&mut self, channel: usize) {
+        let (slots, count) = self.channel_key_slots(channel);
+        for slot in &slots[..count] {
+            self.envelope_key_off(*slot, EGK_NORM);
+        }
+    }
+
+    fn channel_set_4op(&mut self, data: u8) {
+        for bit in 0..6 {
+            let chnum = if bit >= 3 { bit + 9 - 3 } else { bit };
+            if (data >> bit) & 0x01 != 0 {
+                self.channel[chnum].chtype = CH_4OP;
+                self.channel[chnum + 3].chtype = CH_4OP2;
+                self.channel_update_alg(chnum);
+            } else {
+                self.channel[chnum].chtype = CH_2OP;
+                self.channel[chnum + 3].chtype = CH_2OP;
+                self.channel_update_alg(chnum);
+                self.channel_update_alg(chnum + 3);
+            }
+        }
+    }
+
+    fn process_slot(&mut self, slot: usize) {
+        self.slot_calc_fb(slot);
+        self.envelope_calc(slot);
+        self.phase_generate(slot);
+        self.slot_generate(slot);
+    }
+
+    fn channel_accm(&self, channel: &Opl3Channel) -> i16 {
+        channel
+            .out
+            .iter()
+            .map(|out| out.map_or(0, |slot| self.slot[slot as usize].out as i32))
+            .sum::<i32>() as i16
+    }
+
+    /// Mix the channels into the first (`right == false`) or second output of both pairs.
+    fn mix(&self, right: bool) -> [i32; 2] {
+        let mut mix = [0i32; 2];
+        for channel in &self.channel {
+            let accm = self.channel_accm(channel) as i32;
+            #[cfg(feature = "stereoext")]
+            {
+                let pan = if right {
+                    channel.rightpan
+                } else {
+                    channel.leftpan
+                };
+                mix[0] += (accm.wrapping_mul(pan) >> 16) as i16 as i32;
+            }
+            #[cfg(not(feature = "stereoext"))]
+            {
+                let ch = if right { channel.chb } else { channel.cha };
+                mix[0] += (accm & ch as i32) as i16 as i32;
+            }
+            let ch = if right { channel.chd } else { channel.chc };
+            mix[1] += (accm & ch as i32) as i16 as i32;
+        }
+        mix
+    }
+
+    /// Generate one sample at the native rate for both pairs of outputs, as done by
+    /// `OPL3_Generate4Ch`.
+    pub fn generate_4ch(&mut self, buf4: &mut [i16; 4]) {
+        buf4[1] = clip_sample(self.mixbuff[1]);
+        buf4[3] = clip_sample(self.mixbuff[3]);
+
+        let first = if OPL_QUIRK_CHANNELSAMPLEDELAY { 15 } else { 36 };
+        for slot in 0..first {
+            self.process_slot(slot);
+        }
+
+        let mix = self.mix(false);
+        self.mixbuff[0] = mix[0];
+        self.mixbuff[2] = mix[1];
+
+        if OPL_QUIRK_CHANNELSAMPLEDELAY {
+            for slot in 15..18 {
+                self.process_slot(slot);
+            }
+        }
+
+        buf4[0] = clip_sample(self.mixbuff[0]);
+        buf4[2] = clip_sample(self.mixbuff[2]);
+
+        if OPL_QUIRK_CHANNELSAMPLEDELAY {
+            for slot in 18..33 {
+                self.process_slot(slot);
+            }
+        }
+
+        let mix = self.mix(true);
+        self.mixbuff[1] = mix[0];
+        self.mixbuff[3] = mix[1];
+
+        if OPL_QUIRK_CHANNELSAMPLEDELAY {
+            for slot in 33..36 {
+                self.process_slot(slot);
+            }
+        }
+
+        if (self.timer & 0x3f) == 0x3f {
+            self.tremolopos = (self.tremolopos + 1) % 210;
+        }
+        if self.tremolopos < 105 {
+            self.tremolo = self.tremolopos >> self.tremoloshift;
+        } else {
+            self.tremolo = (210 - self.tremolopos) >> self.tremoloshift;
+        }
+
+        if (self.timer & 0x3ff) == 0x3ff {
+            self.vibpos = (self.vibpos + 1) & 7;
+        }
+
+        self.timer = self.timer.wrapping_add(1);
+
+        if self.eg_state != 0 {
+            let mut shift = 0;
+            while shift < 13 && ((self.eg_timer >> shift) & 1) == 0 {
+                shift += 1;
+            }
+            self.eg_add = if shift > 12 { 0 } else { shift + 1 };
+            self.eg_timer_lo = (self.eg_timer & 0x3) as u8;
+        }
+
+        if self.eg_timerrem != 0 || self.eg_state != 0 {
+            if self.eg_timer == 0xfffffffff {
+                self.eg_timer = 0;
+                self.eg_timerrem = 1;
+            } else {
+                self.eg_timer += 1;
+                self.eg_timerrem = 0;
+            }
+        }
+
+        self.eg_state ^= 1;
+
+        loop {
+            let writebuf = self.writebuf[self.writebuf_cur as usize];
+            if writebuf.time > self.writebuf_samplecnt || writebuf.reg & 0x200 == 0 {
+                break;
+            }
+            self.writebuf[self.writebuf_cur as usize].reg &= 0x1ff;
+            self.write_reg(writebuf.reg & 0x1ff, writebuf.data);
+            self.writebuf_cur = (self.writebuf_cur + 1) % OPL_WRITEBUF_SIZE as u32;
+        }
+        self.writebuf_samplecnt += 1;
+    }
+
+    /// Generate one sample at the output sample rate for both pairs of outputs, as done by
+    /// `OPL3_Generate4ChResampled`.
+    pub fn generate_4ch_resampled(&mut self, buf4: &mut [i16; 4]) {
+        while self.samplecnt >= self.rateratio {
+            self.oldsamples = self.samples;
+            let mut samples = [0; 4];
+            self.generate_4ch(&mut samples);
+            self.samples = samples;
+            self.samplecnt -= self.rateratio;
+        }
+        for (i, sample) in buf4.iter_mut().enumerate() {
+            *sample = ((self.oldsamples[i] as i32 * (self.rateratio - self.samplecnt)
+                + self.samples[i] as i32 * self.samplecnt)
+                / self.rateratio) as i16;
+        }
+        self.samplecnt += 1 << RSM_FRAC;
+    }
+
+    /// Write a register immediately, as done by `OPL3_WriteReg`. Bit 8 of `reg` selects the
+    /// secondary register file.
+    pub fn write_reg(&mut self, reg: u16, v: u8) {
+        let high = ((reg >> 8) & 0x01) as usize;
+        let regm = (reg & 0xff) as u8;
+        let slot = AD_SLOT[(regm & 0x1f) as usize];
+        let slot = (slot >= 0).then(|| 18 * high + slot as usize);
+        let channel = ((regm & 0x0f) < 9).then(|| 9 * high + (regm & 0x0f) as usize);
+        match regm & 0xf0 {
+            0x00 => {
+                if high != 0 {
+                    match regm & 0x0f {
+                        0x04 => self.channel_set_4op(v),
+                        0x05 => {
+                            self.newm = v & 0x01;
+                            #[cfg(feature = "stereoext")]
+                            {
+                                self.stereoext = (v >> 1) & 0x01;
+                            }
+                        }
+                        _ => {}
+                    }
+                } else if regm & 0x0f == 0x08 {
+                    self.nts = (v >> 6) & 0x01;
+                }
+            }
+            0x20 | 0x30 => {
+                if let Some(slot) = slot {
+                    self.slot_write_20(slot, v);
+                }
+            }
+            0x40 | 0x50 => {
+                if let Some(slot) = slot {
+                    self.slot_write_40(slot, v);
+                }
+            }
+            0x60 | 0x70 => {
+                if let Some(slot) = slot {
+                    self.slot_write_60(slot, v);
+                }
+            }
+            0x80 | 0x90 => {
+                if let Some(slot) = slot {
+                    self.slot_write_80(slot, v);
+                }
+            }
+            0xe0 | 0xf0 => {
+                if let Some(slot) = slot {
+                    self.slot_write_e0(slot, v);
+                }
+            }
+            0xa0 => {
+                if let Some(channel) = channel {
+                    self.channel_write_a0(channel, v);
+                }
+            }
+            0xb0 => {
+                if regm == 0xbd && high == 0 {
+                    self.tremoloshift = (((v >> 7) ^ 1) << 1) + 2;
+                    self.vibshift = ((v >> 6) & 0x01) ^ 1;
+                    self.channel_update_rhythm(v);
+                } else if let Some(channel) = channel {
+                    self.channel_write_b0(channel, v);
+                    if v & 0x20 != 0 {
+                        self.channel_key_on(channel);
+                    } else {
+                        self.channel_key_off(channel);
+                    }
+                }
+            }
+            0xc0 => {
+                if let Some(channel) = channel {
+                    self.channel_write_c0(channel, v);
+                }
+            }
+            #[cfg(feature = "stereoext")]
+            0xd0 => {
+                if let Some(channel) = channel {
+                    self.channel_write_d0(channel, v);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// The entry points of the C library, with the signatures of the bindings.
+
+/// # Safety
+///
+/// `chip` must point to memory suitable for an `Opl3Chip`, which need not be initialized.
+pub unsafe fn Opl3Reset(chip: *mut Opl3Chip, samplerate: u32) {
+    chip.write(Opl3Chip::new(samplerate));
+}
+
+/// # Safety
+///
+/// `chip` must point to an initialized `Opl3Chip`.
+pub unsafe fn Opl3WriteReg(chip: *mut Opl3Chip, reg: u16, v: u8) {
+    (*chip).write_reg(reg, v);
+}
+
+/// # Safety
+///
+/// `chip` must point to an initialized `Opl3Chip`, and `buf4` to space for four samples.
+pub unsafe fn Opl3Generate4Ch(chip: *mut Opl3Chip, buf4: *mut i16) {
+    (*chip).generate_4ch(&mut *(buf4 as *mut [i16; 4]));
+}
+
+/// # Safety
+///
+/// `chip` must point to an initialized `Opl3Chip`, and `buf` to space for two samples.
+pub unsafe fn Opl3Generate(chip: *mut Opl3Chip, buf: *mut i16) {
+    let mut samples = [0; 4];
+    (*chip).generate_4ch(&mut samples);
+    *buf = samples[0];
+    *buf.add(1) = samples[1];
+}
+
+/// # Safety
+///
+/// `chip` must point to an initialized `Opl3Chip`, and `buf4` to space for four samples.
+pub unsafe fn Opl3Generate4ChResampled(chip: *mut Opl3Chip, buf4: *mut i16) {
+    (*chip).generate_4ch_resampled(&mut *(buf4 as *mut [i16; 4]));
+}
+
+/// # Safety
+///
+/// `chip` must point to an initialized `Opl3Chip`, and `buf` to space for two samples.
+pub unsafe fn Opl3GenerateResampled(chip: *mut Opl3Chip, buf: *mut i16) {
+    let mut samples = [0; 4];
+    (*chip).generate_4ch_resampled(&mut samples);
+    *buf = samples[0];
+    *buf.add(1) = samples[1];
+}
+
+/// # Safety
+///
+/// `chip` must point to an initialized `Opl3Chip`, and `sndptr1` and `sndptr2` to space for
+/// `numsamples` stereo samples each.
+pub unsafe fn Opl3Generate4ChStream(
+    chip: *mut Opl3Chip,
+    sndptr1: *mut i16,
+    sndptr2: *mut i16,
+    numsamples: u32,
+) {
+    let mut samples = [0; 4];
+    for i in 0..numsamples as usize {
+        (*chip).generate_4ch_resampled(&mut samples);
+        *sndptr1.add(2 * i) = samples[0];
+        *sndptr1.add(2 * i + 1) = samples[1];
+        *sndptr2.add(2 * i) = samples[2];
+        *sndptr2.add(2 * i + 1) = samples[3];
+    }
+}
+
+/// # Safety
+///
+/// `chip` must point to an initialized `Opl3Chip`, and `sndptr` to space for `numsamples` stereo
+/// samples.
+pub unsafe fn Opl3GenerateStream(chip: *mut Opl3Chip, sndptr: *mut i16, numsamples: u32) {
+    for i in 0..numsamples as usize {
+        Opl3GenerateResampled(chip, sndptr.add(2 * i));
+    }
+}
+
+// Differential tests, comparing the Rust core with the C library sample for sample.
+#[cfg(all(test, not(feature = "rust-core")))]
+mod tests {
+    use super::*;
+    use crate::bindings;
+    use crate::config::OPL_WRITEBUF_DELAY;
+    use alloc::{boxed::Box, format, vec, vec::Vec};
+
+    /// A step of a register-write corpus.
+    #[derive(Copy, Clone, Debug)]
+    enum Step {
+        /// Write a register immediately. Bit 8 selects the secondary register file.
+        Write(u16, u8),
+        /// Queue a register write in the write buffer.
+        Buffered(u16, u8),
+        /// Generate the given number of samples, comparing the output of both cores.
+        Run(usize),
+    }
+
+    /// A C chip and a Rust chip, run in lockstep.
+    struct Cores {
+        c: *mut bindings::Opl3Chip,
+        rust: Box<Opl3Chip>,
+        resampled: bool,
+    }
+
+    impl Cores {
+        fn new(sample_rate: u32, resampled: bool) -> Self {
+            unsafe {
+                let layout = alloc::alloc::Layout::new::<bindings::Opl3Chip>();
+                let c = alloc::alloc::alloc(layout) as *mut bindings::Opl3Chip;
+                bindings::Opl3Reset(c, sample_rate);
+                Cores {
+                    c,
+                    rust: Box::new(Opl3Chip::new(sample_rate)),
+                    resampled,
+                }
+            }
+        }
+
+        /// The buffered write of `opl3.c`, which `Opl3Chip` implements outside of the core.
+        fn write_buffered(&mut self, reg: u16, v: u8) {
+            unsafe { bindings::Opl3WriteRegBuffered(self.c, reg, v) };
+            let chip = &mut self.rust;
+            let last = chip.writebuf_last as usize;
+            let writebuf = chip.writebuf[last];
+            if writebuf.reg & 0x200 != 0 {
+                chip.write_reg(writebuf.reg & 0x1ff, writebuf.data);
+                chip.writebuf_cur = (last as u32 + 1) % OPL_WRITEBUF_SIZE as u32;
+                chip.writebuf_samplecnt = writebuf.time;
+            }
+            let time = (chip.writebuf_lasttime + OPL_WRITEBUF_DELAY).max(chip.writebuf_samplecnt);
+            chip.writebuf[last] = Opl3Writebuf {
+                time,
+                reg: reg | 0x200,
+                data: v,
+            };
+            chip.writebuf_lasttime = time;
+            chip.writebuf_last = (last as u32 + 1) % OPL_WRITEBUF_SIZE as u32;
+        }
+
+        fn run(&mut self, name: &str, steps: &[Step]) {
+            let mut sample = 0;
+            let mut audible = false;
+            for step in steps {
+                match *step {
+                    Step::Write(reg, v) => {
+                        unsafe { bindings::Opl3WriteReg(self.c, reg, v) };
+                        self.rust.write_reg(reg, v);
+                    }
+                    Step::Buffered(reg, v) => self.write_buffered(reg, v),
+                    Step::Run(count) => {
+                        for _ in 0..count {
+                            let mut c = [0i16; 4];
+                            let mut rust = [0i16; 4];
+                            unsafe {
+                                if self.resampled {
+                                    bindings::Opl3Generate4ChResampled(self.c, c.as_mut_ptr());
+                                } else {
+                                    bindings::Opl3Generate4Ch(self.c, c.as_mut_ptr());
+                                }
+                            }
+                            if self.resampled {
+                                self.rust.generate_4ch_resampled(&mut rust);
+                            } else {
+                                self.rust.generate_4ch(&mut rust);
+                            }
+                            assert_eq!(rust, c, "{}: sample {}", name, sample);
+                            audible |= rust != [0; 4];
+                            sample += 1;
+                        }
+                    }
+                }
+            }
+            assert!(audible, "{}: no output", name);
+        }
+    }
+
+    impl Drop for Cores {
+        fn drop(&mut self) {
+            unsafe {
+                let layout = alloc::alloc::Layout::new::<bindings::Opl3Chip>();
+                alloc::alloc::dealloc(self.c as *mut u8, layout);
+            }
+        }
+    }
+
+    /// Return the register of an operator of a 2-op channel from 0 to 17, with bit 8 set for the
+    /// secondary register file.
+    fn operator(base: u16, channel: usize, carrier: bool) -> u16 {
+        const OFFSETS: [u16; 9] = [0, 1, 2, 8, 9, 10, 16, 17, 18];
+        let file = if channel >= 9 { 0x100 } else { 0 };
+        file | (base + OFFSETS[channel % 9] + if carrier { 3 } else { 0 })
+    }
+
+    fn channel_reg(base: u16, channel: usize) -> u16 {
+        let file = if channel >= 9 { 0x100 } else { 0 };
+        file | (base + (channel % 9) as u16)
+    }
+
+    /// Set up both operators of a channel with settings varied by `n`.
+    fn patch(steps: &mut Vec<Step>, channel: usize, n: usize, c0: u8) {
+        for carrier in [false, true] {
+            let n = n + carrier as usize;
+            let values = [
+                (0x20, ((n * 0x35) & 0xF0) as u8 | (n % 16) as u8),
+                (0x40, ((n * 0x47) & 0xC0) as u8 | (n * 7 % 0x30) as u8),
+                (0x60, (0xF0 - (n % 8) as u8 * 0x10) | (n % 16) as u8),
+                (0x80, ((n * 3 % 16) << 4) as u8 | (n * 5 % 16) as u8),
+                (0xE0, (n % 8) as u8),
+            ];
+            for (base, value) in values {
+                steps.push(Step::Write(operator(base, channel, carrier), value));
+            }
+        }
+        steps.push(Step::Write(channel_reg(0xC0, channel), c0));
+    }
+
+    fn key_on(steps: &mut Vec<Step>, channel: usize, fnum: u16, block: u8) {
+        steps.push(Step::Write(channel_reg(0xA0, channel), fnum as u8));
+        steps.push(Step::Write(
+            channel_reg(0xB0, channel),
+            0x20 | (block << 2) | (fnum >> 8) as u8,
+        ));
+    }
+
+    fn key_off(steps: &mut Vec<Step>, channel: usize) {
+        steps.push(Step::Write(channel_reg(0xB0, channel), 0x00));
+    }
+
+    fn melodic(opl3: bool) -> Vec<Step> {
+        let mut steps = vec![];
+        if opl3 {
+            steps.push(Step::Write(0x105, 0x01));
+        }
+        steps.push(Step::Write(0xBD, 0xC0));
+        for channel in 0..18 {
+            patch(
+                &mut steps,
+                channel,
+                channel * 3,
+                (channel as u8).wrapping_mul(0x12) | 0x30,
+            );
+            key_on(
+                &mut steps,
+                channel,
+                0x100 + channel as u16 * 37,
+                (channel % 8) as u8,
+            );
+            steps.push(Step::Run(50));
+        }
+        steps.push(Step::Run(4000));
+        steps.push(Step::Write(0x08, 0x40));
+        for channel in (0..18).step_by(2) {
+            key_off(&mut steps, channel);
+            steps.push(Step::Run(100));
+        }
+        steps.push(Step::Run(8000));
+        steps
+    }
+
+    fn four_op() -> Vec<Step> {
+        let mut steps = vec![Step::Write(0x105, 0x01), Step::Write(0x104, 0x3F)];
+        for (i, channel) in [0, 1, 2, 9, 10, 11].into_iter().enumerate() {
+            let algorithm = i % 4;
+            patch(
+                &mut steps,
+                channel,
+                i * 5,
+                0xF0 | (i as u8 * 2) | (algorithm & 1) as u8,
+            );
+            patch(
+                &mut steps,
+                channel + 3,
+                i * 5 + 2,
+                0x30 | (algorithm >> 1) as u8,
+            );
+            key_on(&mut steps, channel, 0x2AE - i as u16 * 40, 4);
+            // Writes to the second half of a 4-op channel are ignored.
+            key_on(&mut steps, channel + 3, 0x111, 1);
+        }
+        steps.push(Step::Run(6000));
+        steps.push(Step::Write(0x104, 0x05));
+        steps.push(Step::Run(2000));
+        key_off(&mut steps, 0);
+        key_off(&mut steps, 11);
+        steps.push(Step::Run(2000));
+        steps.push(Step::Write(0x105, 0x00));
+        steps.push(Step::Run(2000));
+        steps
+    }
+
+    fn rhythm() -> Vec<Step> {
+        let mut steps = vec![Step::Write(0x105, 0x01)];
+        for channel in 6..9 {
+            patch(&mut steps, channel, channel * 2, 0x30 | channel as u8);
+        }
+        key_on(&mut steps, 6, 0x158, 1);
+        key_on(&mut steps, 7, 0x1C5, 3);
+        key_on(&mut steps, 8, 0x2AE, 2);
+        for bd in [0x3F, 0x20, 0x31, 0x2A, 0x24, 0x3F, 0xE6, 0x00, 0x3B] {
+            steps.push(Step::Write(0xBD, bd));
+            steps.push(Step::Run(1500));
+        }
+        steps
+    }
+
+    /// A register-write corpus of random writes, using a linear congruential generator so that
+    /// it is the same on every run.
+    fn random(seed: u32, length: usize) -> Vec<Step> {
+        const REGISTERS: [(u16, u16); 9] = [
+            (0x20, 0x36),
+            (0x40, 0x56),
+            (0x60, 0x76),
+            (0x80, 0x96),
+            (0xA0, 0xA9),
+            (0xB0, 0xBD),
+            (0xC0, 0xC9),
+            (0xE0, 0xF6),
+            (0x01, 0x08),
+        ];
+        let mut state = seed;
+        let mut next = move |n: u32| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 8) % n
+        };
+        let mut steps = vec![];
+        for _ in 0..length {
+            let step = match next(20) {
+                0 => Step::Write(0x104, next(64) as u8),
+                1 => Step::Write(0x105, next(2) as u8),
+                2..=4 => Step::Run(next(400) as usize),
+                choice => {
+                    let (start, end) = REGISTERS[next(REGISTERS.len() as u32) as usize];
+                    let reg = start + next((end - start + 1) as u32) as u16;
+                    let reg = reg | if next(2) == 1 { 0x100 } else { 0 };
+                    let value = next(256) as u8;
+                    if choice == 5 {
+                        Step::Buffered(reg, value)
+                    } else {
+                        Step::Write(reg, value)
+                    }
+                }
+            };
+            steps.push(step);
+        }
+        steps.push(Step::Run(2000));
+        steps
+    }
+
+    #[test]
+    fn corpus_matches_c_core() {
+        for (name, steps) in [
+            ("opl2", melodic(false)),
+            ("opl3", melodic(true)),
+            ("4-op", four_op()),
+            ("rhythm", rhythm()),
+        ] {
+            Cores::new(49716, false).run(name, &steps);
+            Cores::new(44100, true).run(name, &steps);
+        }
+    }
+
+    #[test]
+    fn random_writes_match_c_core() {
+        for seed in 1..=6 {
+            let steps = random(seed, 1500);
+            let name = format!("seed {}", seed);
+            Cores::new(49716, false).run(&name, &steps);
+            Cores::new(22050 + seed * 4000, true).run(&name, &steps);
+        }
+    }
+}
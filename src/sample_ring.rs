@@ -0,0 +1,302 @@
+//! A lock-free ring of stereo samples between the thread that generates audio and the audio
+//! callback that plays it.
+//!
+//! `sample_ring` creates a bounded ring of stereo frames, and returns an `OplSampleProducer` and an
+//! `OplSampleConsumer` that can be moved to different threads. Neither end ever waits for the
+//! other: the consumer pads a short read with silence and counts the missing frames as underruns,
+//! and the producer drops the frames that don't fit and counts them as overruns. Both ends can
+//! query the fill level and the counters.
+//!
+//! `OplSampleProducer::fill_from_device` renders just enough samples from an `Opl3Device` to bring
+//! the ring up to a target fill level, which keeps the latency bounded. `OplSampleProducer::fill`
+//! does the same with any generator, such as `shared::OplAudioHandle::generate_samples`.
+//!
+//! # Example
+//!
+//! ```
+//! use opl3_rs::sample_ring::sample_ring;
+//! use opl3_rs::Opl3Device;
+//!
+//! let mut device = Opl3Device::new(44100);
+//! let (mut producer, mut consumer) = sample_ring(4096);
+//!
+//! // Keep about 20ms of audio queued.
+//! producer.fill_from_device(&mut device, 882).unwrap();
+//! assert_eq!(producer.len(), 882);
+//!
+//! // In the audio callback:
+//! let mut buffer = [0i16; 1024];
+//! assert_eq!(consumer.read(&mut buffer), 512);
+//! assert_eq!(consumer.len(), 370);
+//! assert_eq!(consumer.underruns(), 0);
+//! ```
+
+use crate::ring::{self, Consumer, Producer};
+use crate::{Opl3Device, OplError};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of frames copied or generated at a time, through a buffer on the stack.
+const CHUNK_FRAMES: usize = 256;
+
+/// The underrun and overrun counters, shared by both ends of the ring.
+#[derive(Default)]
+struct OplSampleRingCounters {
+    underruns: AtomicUsize,
+    overruns: AtomicUsize,
+}
+
+/// Create a ring of stereo samples, and return its producer and consumer.
+///
+/// # Arguments
+///
+/// * `capacity` - The number of stereo frames the ring can hold.
+///
+/// # Returns
+///
+/// A tuple of the producer, for the thread that generates samples, and the consumer, for the audio
+/// callback.
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+pub fn sample_ring(capacity: usize) -> (OplSampleProducer, OplSampleConsumer) {
+    let (frames, consumer_frames) = ring::channel(capacity);
+    let counters = Arc::new(OplSampleRingCounters::default());
+    (
+        OplSampleProducer {
+            frames,
+            counters: Arc::clone(&counters),
+        },
+        OplSampleConsumer {
+            frames: consumer_frames,
+            counters,
+        },
+    )
+}
+
+/// The end of a sample ring that samples are written to. See the `sample_ring` module.
+pub struct OplSampleProducer {
+    frames: Producer<[i16; 2]>,
+    counters: Arc<OplSampleRingCounters>,
+}
+
+impl OplSampleProducer {
+    /// Write interleaved stereo samples to the ring. Frames that don't fit are dropped and counted
+    /// as overruns. A trailing odd sample is ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - The interleaved stereo samples, as produced by `Opl3Device::generate_samples`.
+    ///
+    /// # Returns
+    ///
+    /// The number of frames written.
+    pub fn write(&mut self, samples: &[i16]) -> usize {
+        let mut written = 0;
+        for chunk in samples.chunks(CHUNK_FRAMES * 2) {
+            let mut frames = [[0i16; 2]; CHUNK_FRAMES];
+            let count = chunk.len() / 2;
+            for (frame, sample) in frames.iter_mut().zip(chunk.chunks_exact(2)) {
+                *frame = [sample[0], sample[1]];
+            }
+            let pushed = self.frames.push_slice(&frames[..count]);
+            written += pushed;
+            if pushed < count {
+                self.counters
+                    .overruns
+                    .fetch_add(samples.len() / 2 - written, Ordering::Relaxed);
+                break;
+            }
+        }
+        written
+    }
+
+    /// Generate samples until the ring holds at least `target` frames. Samples are generated in
+    /// blocks of up to 256 frames, without allocating.
+    ///
+    /// # Arguments
+    ///
+    /// * `target`   - The fill level to reach, in frames. It is limited to the capacity.
+    /// * `generate` - A function that fills a buffer with interleaved stereo samples.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the number of frames generated, or the error returned by
+    /// `generate`.
+    pub fn fill<F>(&mut self, target: usize, mut generate: F) -> Result<usize, OplError>
+    where
+        F: FnMut(&mut [i16]) -> Result<(), OplError>,
+    {
+        // The consumer can only make room while we render, so the ring can't overrun.
+        let needed = target.min(self.capacity()).saturating_sub(self.len());
+        let mut buffer = [0i16; CHUNK_FRAMES * 2];
+        let mut generated = 0;
+        while generated < needed {
+            let frames = (needed - generated).min(CHUNK_FRAMES);
+            generate(&mut buffer[..frames * 2])?;
+            self.write(&buffer[..frames * 2]);
+            generated += frames;
+        }
+        Ok(generated)
+    }
+
+    /// Generate samples from the device until the ring holds at least `target` frames. See
+    /// `fill`.
+    pub fn fill_from_device(
+        &mut self,
+        device: &mut Opl3Device,
+        target: usize,
+    ) -> Result<usize, OplError> {
+        self.fill(target, |buffer| device.generate_samples(buffer))
+    }
+
+    /// Return the number of frames in the ring.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns true if the ring holds no frames.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the number of frames the ring can hold.
+    pub fn capacity(&self) -> usize {
+        self.frames.capacity()
+    }
+
+    /// Return the number of frames the consumer has been short of since the ring was created.
+    pub fn underruns(&self) -> usize {
+        self.counters.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of frames that have been dropped because the ring was full.
+    pub fn overruns(&self) -> usize {
+        self.counters.overruns.load(Ordering::Relaxed)
+    }
+}
+
+/// The end of a sample ring that samples are read from. See the `sample_ring` module.
+pub struct OplSampleConsumer {
+    frames: Consumer<[i16; 2]>,
+    counters: Arc<OplSampleRingCounters>,
+}
+
+impl OplSampleConsumer {
+    /// Read interleaved stereo samples from the ring. If the ring runs out, the rest of the buffer
+    /// is filled with silence and the missing frames are counted as underruns. A trailing odd
+    /// sample is set to 0.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - The buffer to fill with interleaved stereo samples.
+    ///
+    /// # Returns
+    ///
+    /// The number of frames read from the ring.
+    pub fn read(&mut self, buffer: &mut [i16]) -> usize {
+        let mut read = 0;
+        for chunk in buffer.chunks_mut(CHUNK_FRAMES * 2) {
+            let mut frames = [[0i16; 2]; CHUNK_FRAMES];
+            let count = chunk.len() / 2;
+            let popped = self.frames.pop_slice(&mut frames[..count]);
+            for (sample, frame) in chunk.chunks_exact_mut(2).zip(frames.iter()) {
+                sample.copy_from_slice(frame);
+            }
+            read += popped;
+            if popped < count {
+                break;
+            }
+        }
+        buffer[read * 2..].fill(0);
+        let missing = buffer.len() / 2 - read;
+        if missing > 0 {
+            self.counters
+                .underruns
+                .fetch_add(missing, Ordering::Relaxed);
+        }
+        read
+    }
+
+    /// Return the number of frames in the ring.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns true if the ring holds no frames.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the number of frames the ring can hold.
+    pub fn capacity(&self) -> usize {
+        self.frames.capacity()
+    }
+
+    /// Return the number of frames this consumer has been short of since the ring was created.
+    pub fn underruns(&self) -> usize {
+        self.counters.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of frames that have been dropped because the ring was full.
+    pub fn overruns(&self) -> usize {
+        self.counters.overruns.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::OplNullBackend;
+    use alloc::boxed::Box;
+    use alloc::{vec, vec::Vec};
+
+    #[test]
+    fn underruns_are_silent() {
+        let (mut producer, mut consumer) = sample_ring(8);
+        assert_eq!(producer.write(&[1, -1, 2, -2]), 2);
+        let mut buffer = [7i16; 9];
+        assert_eq!(consumer.read(&mut buffer), 2);
+        assert_eq!(buffer, [1, -1, 2, -2, 0, 0, 0, 0, 0]);
+        assert_eq!(consumer.underruns(), 2);
+        assert_eq!(producer.underruns(), 2);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn overruns_are_dropped() {
+        let (mut producer, mut consumer) = sample_ring(300);
+        let samples: Vec<i16> = (0..1000).collect();
+        assert_eq!(producer.write(&samples), 300);
+        assert_eq!(producer.overruns(), 200);
+        assert_eq!(producer.write(&[1, 2]), 0);
+        assert_eq!(consumer.overruns(), 201);
+
+        let mut buffer = vec![0; 600];
+        assert_eq!(consumer.read(&mut buffer), 300);
+        assert_eq!(buffer[..], samples[..600]);
+        assert_eq!(consumer.underruns(), 0);
+    }
+
+    #[test]
+    fn fill_reaches_target() {
+        let mut device = Opl3Device::with_backend(Box::new(OplNullBackend::new()), 44100);
+        let (mut producer, mut consumer) = sample_ring(1024);
+        assert_eq!(producer.fill_from_device(&mut device, 600).unwrap(), 600);
+        assert_eq!(producer.fill_from_device(&mut device, 600).unwrap(), 0);
+
+        let mut buffer = [0; 200];
+        consumer.read(&mut buffer);
+        assert_eq!(producer.len(), 500);
+        assert_eq!(producer.fill_from_device(&mut device, 2000).unwrap(), 524);
+        assert_eq!(consumer.len(), 1024);
+        assert_eq!(producer.overruns(), 0);
+
+        // Errors from the generator are passed on, and nothing is written.
+        consumer.read(&mut buffer);
+        let result = producer.fill(2000, |_| Err(OplError::BufferUndersized));
+        assert!(matches!(result, Err(OplError::BufferUndersized)));
+        assert_eq!(producer.len(), 924);
+    }
+}
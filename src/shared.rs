@@ -0,0 +1,474 @@
+//! Sharing an `Opl3Device` between an emulator thread and an audio thread.
+//!
+//! An emulator drives an OPL3 from its CPU thread, while samples are usually generated on an audio
+//! callback that must never block. `Opl3Device::split` divides a device into two handles that can
+//! be moved to those threads:
+//!
+//! * `OplControlHandle` takes the place of the device on the emulator side. Register writes and
+//!   the time passed to `run` are sent to the audio side through a bounded, lock-free queue. The
+//!   handle keeps its own copy of the registers and timers, so `read_register` and `read_status`
+//!   are answered immediately, and stay consistent with the writes the emulator has made.
+//! * `OplAudioHandle` owns the device. Queued commands are applied as `generate_samples` fills
+//!   its buffer: the time sent by `run` is generated before the writes that follow it, so each
+//!   write takes effect at the point in the audio that the emulator made it.
+//!
+//! The audio thread generates samples whether or not the emulator has sent enough time to cover
+//! them. Time sent later first makes up for the samples generated ahead of it, which keeps the
+//! latency between the threads from growing. Each call applies at most as many commands as the
+//! queue can hold, so a busy emulator can't keep the audio thread from returning.
+//!
+//! If the audio thread falls behind and the queue fills up, writes are rejected with
+//! `OplError::CommandQueueFull` and nothing is changed, so the emulator can retry them later.
+//! Elapsed time is never lost: it is held back and sent with the next command that fits.
+//!
+//! # Example
+//!
+//! ```
+//! use opl3_rs::{Opl3Device, OplRegisterFile};
+//!
+//! let (mut control, mut audio) = Opl3Device::new(44100).split(256);
+//!
+//! let thread = std::thread::spawn(move || {
+//!     // Set timer 1 to overflow after one tick, and start it.
+//!     control.write_address(0x02, OplRegisterFile::Primary).unwrap();
+//!     control.write_data(0xFF, OplRegisterFile::Primary, false).unwrap();
+//!     control.write_address(0x04, OplRegisterFile::Primary).unwrap();
+//!     control.write_data(0x01, OplRegisterFile::Primary, false).unwrap();
+//!     control.run(100.0);
+//!     assert_eq!(control.read_status(), 0xC0);
+//! });
+//! thread.join().unwrap();
+//!
+//! let mut buffer = vec![0; 512];
+//! audio.generate_samples(&mut buffer).unwrap();
+//! assert_eq!(audio.device().read_register(0x04, OplRegisterFile::Primary), 0x01);
+//! ```
+
+use crate::ring::{self, Consumer, Producer};
+use crate::{Opl3Device, OplError, OplRegisterFile, OplTimers};
+
+/// A command sent from an `OplControlHandle` to its `OplAudioHandle`.
+#[derive(Copy, Clone, Debug)]
+enum OplCommand {
+    Write {
+        reg: u8,
+        value: u8,
+        file: OplRegisterFile,
+        buffered: bool,
+    },
+    Run(f64),
+}
+
+impl Opl3Device {
+    /// Split the device into a handle for the emulator thread and a handle for the audio thread.
+    /// See the `shared` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `queue_capacity` - The number of commands that can be queued for the audio thread.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of the control handle and the audio handle, which owns the device.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `queue_capacity` is 0.
+    pub fn split(self, queue_capacity: usize) -> (OplControlHandle, OplAudioHandle) {
+        let (queue, commands) = ring::channel(queue_capacity);
+        let control = OplControlHandle {
+            queue,
+            addr_reg: self.addr_reg,
+            registers: self.registers,
+            timers: self.timers.clone(),
+            pending_usec: 0.0,
+        };
+        let audio = OplAudioHandle {
+            commands,
+            device: self,
+            pending_frames: 0,
+            ahead_frames: 0,
+        };
+        (control, audio)
+    }
+}
+
+/// The emulator side of a shared `Opl3Device`. See the `shared` module.
+pub struct OplControlHandle {
+    queue: Producer<OplCommand>,
+    addr_reg: [u8; 2],
+    registers: [[u8; 256]; 2],
+    timers: OplTimers,
+    pending_usec: f64,
+}
+
+impl OplControlHandle {
+    /// Advance the timers by the given number of microseconds, and send the elapsed time to the
+    /// audio thread. See `Opl3Device::run`.
+    ///
+    /// # Arguments
+    ///
+    /// * `usec` - The number of microseconds that have passed since the last call to `run`.
+    pub fn run(&mut self, usec: f64) {
+        self.timers.run(usec);
+        self.pending_usec += usec;
+        // If the queue is full, the time is sent with the next command instead.
+        _ = self.send_time();
+    }
+
+    /// Read a byte from the OPL3 device's Status register. The status is computed from the
+    /// handle's own copy of the timers, without waiting for the audio thread.
+    pub fn read_status(&self) -> u8 {
+        self.timers.status()
+    }
+
+    /// Write a byte to the OPL3 device's Address register. See `Opl3Device::write_address`.
+    pub fn write_address(&mut self, addr: u8, file: OplRegisterFile) -> Result<(), OplError> {
+        match file {
+            OplRegisterFile::Primary => self.addr_reg[0] = addr,
+            OplRegisterFile::Secondary => self.addr_reg[1] = addr,
+        }
+        Ok(())
+    }
+
+    /// Write a byte to the OPL3 device's Data register. See `Opl3Device::write_data`.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or `OplError::CommandQueueFull` if the write
+    /// could not be queued.
+    pub fn write_data(
+        &mut self,
+        data: u8,
+        file: OplRegisterFile,
+        buffered: bool,
+    ) -> Result<(), OplError> {
+        let addr = match file {
+            OplRegisterFile::Primary => self.addr_reg[0],
+            OplRegisterFile::Secondary => self.addr_reg[1],
+        };
+        self.write_register(addr, data, file, buffered)
+    }
+
+    /// Queue a write to the specified register for the audio thread. The handle's copy of the
+    /// registers and timers is only updated if the write is queued. See
+    /// `Opl3Device::write_register`.
+    ///
+    /// # Arguments
+    ///
+    /// * `reg`      - The internal register index to write.
+    /// * `value`    - The value to write to the register.
+    /// * `file`     - The register file to write to.
+    /// * `buffered` - Whether the audio thread should write the register in buffered mode.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or `OplError::CommandQueueFull` if the write
+    /// could not be queued.
+    pub fn write_register(
+        &mut self,
+        reg: u8,
+        value: u8,
+        file: OplRegisterFile,
+        buffered: bool,
+    ) -> Result<(), OplError> {
+        // Elapsed time must reach the audio thread before any later writes.
+        self.send_time()?;
+        self.queue
+            .push(OplCommand::Write {
+                reg,
+                value,
+                file,
+                buffered,
+            })
+            .map_err(|_| OplError::CommandQueueFull)?;
+
+        match file {
+            OplRegisterFile::Primary => {
+                self.registers[0][reg as usize] = value;
+                self.timers.write_register(reg, value);
+            }
+            OplRegisterFile::Secondary => self.registers[1][reg as usize] = value,
+        }
+        Ok(())
+    }
+
+    /// Return the value of the given chip register, as last written through this handle. See
+    /// `Opl3Device::read_register`.
+    pub fn read_register(&self, reg: u8, file: OplRegisterFile) -> u8 {
+        match file {
+            OplRegisterFile::Primary => self.registers[0][reg as usize],
+            OplRegisterFile::Secondary => self.registers[1][reg as usize],
+        }
+    }
+
+    /// Return the number of commands that the audio thread has not yet applied.
+    pub fn pending_commands(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Return the number of commands that can be queued.
+    pub fn queue_capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+
+    fn send_time(&mut self) -> Result<(), OplError> {
+        if self.pending_usec > 0.0 {
+            self.queue
+                .push(OplCommand::Run(self.pending_usec))
+                .map_err(|_| OplError::CommandQueueFull)?;
+            self.pending_usec = 0.0;
+        }
+        Ok(())
+    }
+}
+
+/// The audio side of a shared `Opl3Device`, which owns the device. See the `shared` module.
+pub struct OplAudioHandle {
+    commands: Consumer<OplCommand>,
+    device: Opl3Device,
+    /// Frames of time sent by the control handle that have not been generated yet.
+    pending_frames: usize,
+    /// Frames generated before the control handle sent the time they cover.
+    ahead_frames: usize,
+}
+
+impl OplAudioHandle {
+    /// Apply queued commands to the device, up to the capacity of the queue. This is done by
+    /// `generate` and `generate_samples`, so it only needs to be called to bring the device up to
+    /// date without generating samples. The time sent with the commands is skipped.
+    ///
+    /// # Returns
+    ///
+    /// The number of commands applied.
+    pub fn apply_commands(&mut self) -> usize {
+        let mut applied = 0;
+        while applied < self.commands.capacity() {
+            let Some(command) = self.commands.pop() else {
+                break;
+            };
+            self.apply(command);
+            applied += 1;
+        }
+        self.pending_frames = 0;
+        applied
+    }
+
+    /// Generate a 2 channel audio sample in interleaved i16 format, applying the queued commands
+    /// that come before it. See `Opl3Device::generate`.
+    pub fn generate(&mut self, sample: &mut [i16]) -> Result<(), OplError> {
+        if sample.len() < 2 {
+            return Err(OplError::BufferUndersized);
+        }
+        self.generate_samples(&mut sample[..2])
+    }
+
+    /// Generate a stream of 2 channel, interleaved audio samples in i16 format, applying the
+    /// queued commands at the frames where they were made. See `Opl3Device::generate_samples`.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - A mutable reference to a buffer slice that will be filled with stereo,
+    ///   interleaved audio samples.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or an `OplError` on failure.
+    pub fn generate_samples(&mut self, buffer: &mut [i16]) -> Result<(), OplError> {
+        if buffer.len() < 2 {
+            return Err(OplError::BufferUndersized);
+        }
+        let frames = buffer.len() / 2;
+        let mut position = 0;
+        let mut applied = 0;
+        loop {
+            let count = self.pending_frames.min(frames - position);
+            if count > 0 {
+                self.device
+                    .generate_samples(&mut buffer[position * 2..(position + count) * 2])?;
+                position += count;
+                self.pending_frames -= count;
+            }
+            // Commands left over when the buffer is full belong to a later call.
+            if position == frames || applied == self.commands.capacity() {
+                break;
+            }
+            let Some(command) = self.commands.pop() else {
+                break;
+            };
+            self.apply(command);
+            applied += 1;
+        }
+
+        // The emulator hasn't sent the time for the rest of the buffer yet.
+        if position < frames {
+            self.ahead_frames += frames - position;
+            self.device.generate_samples(&mut buffer[position * 2..])?;
+        }
+        Ok(())
+    }
+
+    /// Return a reference to the device. Commands that have not been applied yet are not
+    /// reflected in its state.
+    pub fn device(&self) -> &Opl3Device {
+        &self.device
+    }
+
+    /// Return a mutable reference to the device, for example to attach a recorder. Registers
+    /// written directly to the device are not seen by the control handle.
+    pub fn device_mut(&mut self) -> &mut Opl3Device {
+        &mut self.device
+    }
+
+    /// Apply the queued commands and return the device. Only one pass is made over the queue, as
+    /// with `apply_commands`, so commands sent after the call, or while it runs, are dropped along
+    /// with the queue.
+    pub fn into_device(mut self) -> Opl3Device {
+        self.apply_commands();
+        self.device
+    }
+
+    fn apply(&mut self, command: OplCommand) {
+        match command {
+            OplCommand::Write {
+                reg,
+                value,
+                file,
+                buffered,
+            } => self.device.write_register(reg, value, file, buffered),
+            OplCommand::Run(usec) => {
+                let frames = self.device.run(usec);
+                let caught_up = frames.min(self.ahead_frames);
+                self.ahead_frames -= caught_up;
+                self.pending_frames += frames - caught_up;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::OplNullBackend;
+    use alloc::boxed::Box;
+
+    fn null_device() -> Opl3Device {
+        Opl3Device::with_backend(Box::new(OplNullBackend::new()), 44100)
+    }
+
+    #[test]
+    fn commands_reach_device() {
+        let (mut control, mut audio) = null_device().split(4);
+        control
+            .write_register(0x20, 0x01, OplRegisterFile::Primary, false)
+            .unwrap();
+        control
+            .write_address(0x05, OplRegisterFile::Secondary)
+            .unwrap();
+        control
+            .write_data(0x01, OplRegisterFile::Secondary, false)
+            .unwrap();
+        assert_eq!(
+            control.read_register(0x05, OplRegisterFile::Secondary),
+            0x01
+        );
+        assert_eq!(control.pending_commands(), 2);
+        assert_eq!(
+            audio.device().read_register(0x20, OplRegisterFile::Primary),
+            0
+        );
+
+        let mut buffer = [0; 8];
+        audio.generate_samples(&mut buffer).unwrap();
+        assert_eq!(control.pending_commands(), 0);
+        let device = audio.into_device();
+        assert_eq!(device.read_register(0x20, OplRegisterFile::Primary), 0x01);
+        assert_eq!(device.read_register(0x05, OplRegisterFile::Secondary), 0x01);
+        assert_eq!(device.backend::<OplNullBackend>().unwrap().writes(), 2);
+    }
+
+    #[test]
+    fn full_queue_rejects_writes() {
+        let (mut control, mut audio) = null_device().split(2);
+        control
+            .write_register(0x02, 0xFF, OplRegisterFile::Primary, false)
+            .unwrap();
+        control
+            .write_register(0x04, 0x01, OplRegisterFile::Primary, false)
+            .unwrap();
+
+        // The timers advance on the control side even though the time can't be queued.
+        control.run(100.0);
+        assert_eq!(control.read_status(), 0xC0);
+        assert!(matches!(
+            control.write_register(0x04, 0x80, OplRegisterFile::Primary, false),
+            Err(OplError::CommandQueueFull)
+        ));
+        assert_eq!(control.read_register(0x04, OplRegisterFile::Primary), 0x01);
+        assert_eq!(control.read_status(), 0xC0);
+
+        assert_eq!(audio.apply_commands(), 2);
+        control
+            .write_register(0x04, 0x80, OplRegisterFile::Primary, false)
+            .unwrap();
+        assert_eq!(control.read_status(), 0);
+        assert_eq!(control.pending_commands(), 2);
+
+        // The held back time is sent before the write that follows it.
+        assert_eq!(audio.apply_commands(), 2);
+        assert_eq!(audio.device().stats().data_writes, 3);
+        assert_eq!(audio.device_mut().read_status(), 0);
+    }
+
+    #[test]
+    fn writes_follow_elapsed_time() {
+        let (mut control, mut audio) = Opl3Device::new(50000).split(16);
+        for op in [0x00, 0x03] {
+            for (reg, value) in [(0x20, 0x01), (0x40, 0x00), (0x60, 0xF0), (0x80, 0x0F)] {
+                control
+                    .write_register(reg + op, value, OplRegisterFile::Primary, false)
+                    .unwrap();
+            }
+        }
+        control
+            .write_register(0xA0, 0x44, OplRegisterFile::Primary, false)
+            .unwrap();
+        // Key the note on after 100 frames.
+        control.run(2000.0);
+        control
+            .write_register(0xB0, 0x32, OplRegisterFile::Primary, false)
+            .unwrap();
+
+        // The write waits for a later call when the buffer ends before it.
+        let mut buffer = [0i16; 2 * 60];
+        audio.generate_samples(&mut buffer).unwrap();
+        assert!(buffer.iter().all(|&sample| sample == 0));
+        assert_eq!(control.pending_commands(), 1);
+
+        audio.generate_samples(&mut buffer).unwrap();
+        assert_eq!(control.pending_commands(), 0);
+        assert!(buffer[..2 * 40].iter().all(|&sample| sample == 0));
+        assert!(buffer[2 * 40..].iter().any(|&sample| sample != 0));
+    }
+
+    #[test]
+    fn generating_ahead_catches_up() {
+        let (mut control, mut audio) = null_device().split(4);
+        let mut buffer = [0i16; 2 * 100];
+        audio.generate_samples(&mut buffer).unwrap();
+
+        // The first 100 frames of time were already generated, so the write comes 10 frames in.
+        control.run(110.0 * 1_000_000.0 / 44100.0);
+        control
+            .write_register(0x20, 0x01, OplRegisterFile::Primary, false)
+            .unwrap();
+        audio.generate_samples(&mut buffer[..2 * 6]).unwrap();
+        assert_eq!(control.pending_commands(), 1);
+        audio.generate_samples(&mut buffer[..2 * 6]).unwrap();
+        assert_eq!(control.pending_commands(), 0);
+        assert_eq!(
+            audio.device().read_register(0x20, OplRegisterFile::Primary),
+            0x01
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Continuous stereo panning with the Nuked-OPL3 stereo extension.
+//!
+//! The OPL3 can only send each channel to the left output, the right output or both, using the
+//! output select bits of registers 0xC0 to 0xC8. Nuked-OPL3 has an optional extension that adds a
+//! pan position for each channel, set through registers 0xD0 to 0xD8 of both register files while
+//! bit 1 of register 0x105 is set. Positions follow a constant power law, so a centered channel is
+//! about 3 dB quieter on each side than a channel panned fully to one side.
+//!
+//! This module is only available with the `stereoext` feature, which builds Nuked-OPL3 with the
+//! extension. The extension does not exist on real hardware, so files recorded from a device
+//! using it will not play back the same way elsewhere.
+//!
+//! # Example
+//!
+//! ```
+//! use opl3_rs::Opl3Device;
+//!
+//! let mut device = Opl3Device::new(44100);
+//! device.set_stereo_extension(true);
+//! // Place channel 0 halfway to the left.
+//! device.set_channel_pan(0, -0.5).unwrap();
+//! ```
+
+use crate::{Opl3Device, OplError, OplRegisterFile};
+
+const STEREOEXT_ENABLE: u8 = 0x02;
+
+impl Opl3Device {
+    /// Enable or disable the stereo extension by setting bit 1 of register 0x105. The OPL3 mode
+    /// bit of the register is left unchanged. While the extension is disabled, channels are panned
+    /// by their output select bits as usual, and writes to the pan registers are ignored.
+    pub fn set_stereo_extension(&mut self, enable: bool) {
+        let value = self.read_register(0x05, OplRegisterFile::Secondary);
+        let value = if enable {
+            value | STEREOEXT_ENABLE
+        } else {
+            value & !STEREOEXT_ENABLE
+        };
+        self.write_register(0x05, value, OplRegisterFile::Secondary, true);
+    }
+
+    /// Returns true if the stereo extension is enabled.
+    pub fn is_stereo_extension(&self) -> bool {
+        self.read_register(0x05, OplRegisterFile::Secondary) & STEREOEXT_ENABLE != 0
+    }
+
+    /// Set the pan position of a channel. The stereo extension must be enabled first.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel, from 0 to 17. Channels 9 to 17 are in the secondary register
+    ///               file.
+    /// * `pan`     - The pan position, from -1.0 (fully left) through 0.0 (center) to 1.0 (fully
+    ///               right). Values outside this range are clamped. The position is stored with
+    ///               8 bits of precision.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either `()` on success or `OplError::ChannelOutOfRange` if the channel
+    /// number is out of range.
+    pub fn set_channel_pan(&mut self, channel: usize, pan: f64) -> Result<(), OplError> {
+        let (reg, file) = Self::pan_register(channel)?;
+        let value = libm::round((pan.clamp(-1.0, 1.0) + 1.0) / 2.0 * 255.0) as u8;
+        self.write_register(reg, value, file, true);
+        Ok(())
+    }
+
+    /// Return the pan position of a channel, as last set by `set_channel_pan` or a write to its
+    /// pan register.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel, from 0 to 17.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the pan position from -1.0 to 1.0, or
+    /// `OplError::ChannelOutOfRange` if the channel number is out of range.
+    pub fn channel_pan(&self, channel: usize) -> Result<f64, OplError> {
+        let (reg, file) = Self::pan_register(channel)?;
+        Ok(self.read_register(reg, file) as f64 / 255.0 * 2.0 - 1.0)
+    }
+
+    fn pan_register(channel: usize) -> Result<(u8, OplRegisterFile), OplError> {
+        match channel {
+            0..=8 => Ok((0xD0 + channel as u8, OplRegisterFile::Primary)),
+            9..=17 => Ok((0xD0 + (channel - 9) as u8, OplRegisterFile::Secondary)),
+            _ => Err(OplError::ChannelOutOfRange),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pan_registers() {
+        let mut device = Opl3Device::new(44100);
+        device.write_register(0x05, 0x01, OplRegisterFile::Secondary, false);
+        device.set_stereo_extension(true);
+        assert!(device.is_stereo_extension());
+        assert_eq!(device.read_register(0x05, OplRegisterFile::Secondary), 0x03);
+
+        device.set_channel_pan(0, -1.0).unwrap();
+        device.set_channel_pan(11, 2.0).unwrap();
+        device.set_channel_pan(4, 0.0).unwrap();
+        assert_eq!(device.read_register(0xD0, OplRegisterFile::Primary), 0x00);
+        assert_eq!(device.read_register(0xD2, OplRegisterFile::Secondary), 0xFF);
+        assert_eq!(device.read_register(0xD4, OplRegisterFile::Primary), 0x80);
+        assert_eq!(device.channel_pan(11).unwrap(), 1.0);
+        assert!(matches!(
+            device.set_channel_pan(18, 0.0),
+            Err(OplError::ChannelOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn hard_left() {
+        let mut device = Opl3Device::new(44100);
+        device.set_stereo_extension(true);
+        device.set_channel_pan(0, -1.0).unwrap();
+        for (reg, value) in [
+            (0x20, 0x01),
+            (0x23, 0x01),
+            (0x40, 0x3F),
+            (0x43, 0x00),
+            (0x60, 0xF0),
+            (0x63, 0xF0),
+            (0xC0, 0x30),
+            (0xA0, 0x44),
+            (0xB0, 0x32),
+        ] {
+            device.write_register(reg, value, OplRegisterFile::Primary, true);
+        }
+        let mut buffer = [0i16; 2 * 441];
+        device.generate_samples(&mut buffer).unwrap();
+        assert!(buffer.chunks(2).any(|frame| frame[0] != 0));
+        assert!(buffer.chunks(2).all(|frame| frame[1] == 0));
+    }
+}
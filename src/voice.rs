@@ -0,0 +1,477 @@
+//! Polyphonic allocation of OPL3 channels to notes.
+//!
+//! `VoiceAllocator` keeps track of which of the 18 OPL3 channels are playing, releasing or free,
+//! and chooses a channel for each new note according to the kind of patch it uses. 4-op patches are
+//! given one of the six channel pairs that support 4-op mode, and the allocator sets and clears the
+//! bits of register 0x104 as pairs change between 2-op and 4-op use. The second channel of a pair
+//! in 4-op mode is not offered to 2-op notes until the pair is taken back.
+//!
+//! A released voice is tracked as releasing until the envelopes of its carrier operators have
+//! decayed to silence, which is read from the emulated chip by `VoiceAllocator::update`. Free
+//! channels are preferred for new notes, then releasing ones. When every channel is playing, a
+//! voice is stolen according to the `StealPolicy`.
+//!
+//! The allocator only writes the 4-op enable register and keys off stolen voices. Loading patches,
+//! setting the frequency and keying notes on and off are left to the caller.
+//!
+//! # Example
+//!
+//! ```
+//! use opl3_rs::Opl3Device;
+//! use opl3_rs::voice::{StealPolicy, VoiceAllocator, VoiceKey, VoiceKind};
+//!
+//! let mut device = Opl3Device::new(44100);
+//! let mut voices = VoiceAllocator::new(StealPolicy::Oldest);
+//! voices.reset(&mut device);
+//!
+//! let key = VoiceKey { channel: 0, note: 60 };
+//! let allocation = voices.allocate(&mut device, VoiceKind::FourOp, key, 100);
+//! // ... load the patch and key on the note on allocation.channel ...
+//! assert_eq!(voices.four_op_mask(), 0x01);
+//! ```
+
+use crate::formats::{read_reg, write_reg};
+use crate::Opl3Device;
+use alloc::{vec, vec::Vec};
+
+/// The number of OPL3 channels managed by the allocator.
+pub const VOICE_CHANNELS: usize = 18;
+/// The first channel of each pair that can be put into 4-op mode, in the order of their bits in
+/// register 0x104. The second channel of each pair is three channels higher.
+pub const FOUR_OP_PAIRS: [usize; 6] = [0, 1, 2, 9, 10, 11];
+
+/// The kind of patch a note is played with.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VoiceKind {
+    /// A 2-op patch, which needs a single channel.
+    #[default]
+    TwoOp,
+    /// A 4-op patch, which needs a channel pair in 4-op mode.
+    FourOp,
+}
+
+/// How a voice is chosen to be stolen when every channel is playing.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum StealPolicy {
+    /// Steal the voice that started playing first.
+    #[default]
+    Oldest,
+    /// Steal the voice with the lowest level, then the oldest.
+    Quietest,
+    /// Reuse a voice already playing or releasing the same note on the same channel, even if
+    /// other channels are free. Otherwise steal the oldest voice.
+    SameNote,
+}
+
+/// The state of a channel.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VoiceState {
+    /// The channel is silent and available.
+    #[default]
+    Free,
+    /// The channel is playing a held note.
+    Playing,
+    /// The note has been released and its envelope is still decaying.
+    Releasing,
+}
+
+/// Identifies the note played by a voice, such as its MIDI channel and note number.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VoiceKey {
+    /// The channel of the note, as used by the caller.
+    pub channel: u8,
+    /// The note number.
+    pub note: u8,
+}
+
+/// The result of allocating a voice.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VoiceAllocation {
+    /// The channel to play the note on. For 4-op patches this is the first channel of the pair.
+    pub channel: usize,
+    /// The notes that were cut off to make room, if playing voices were stolen: the note on
+    /// `channel`, then the note on the second channel of the pair when a 4-op note takes it.
+    pub stolen: [Option<VoiceKey>; 2],
+}
+
+#[derive(Copy, Clone, Default)]
+struct Slot {
+    state: VoiceState,
+    key: VoiceKey,
+    level: u8,
+    /// When the voice was last started or released.
+    age: u64,
+}
+
+/// Assigns notes to OPL3 channels. See the module documentation.
+pub struct VoiceAllocator {
+    slots: [Slot; VOICE_CHANNELS],
+    four_op_mask: u8,
+    clock: u64,
+    policy: StealPolicy,
+}
+
+impl VoiceAllocator {
+    /// Create a new allocator with all channels free and in 2-op mode.
+    pub fn new(policy: StealPolicy) -> Self {
+        VoiceAllocator {
+            slots: [Slot::default(); VOICE_CHANNELS],
+            four_op_mask: 0,
+            clock: 0,
+            policy,
+        }
+    }
+
+    /// Return the stealing policy.
+    pub fn policy(&self) -> StealPolicy {
+        self.policy
+    }
+
+    /// Set the stealing policy.
+    pub fn set_policy(&mut self, policy: StealPolicy) {
+        self.policy = policy;
+    }
+
+    /// Free all channels and put every pair back into 2-op mode.
+    pub fn reset(&mut self, device: &mut Opl3Device) {
+        self.slots = [Slot::default(); VOICE_CHANNELS];
+        self.four_op_mask = 0;
+        self.clock = 0;
+        write_reg(device, 0x104, 0);
+    }
+
+    /// Return the value of register 0x104: one bit for each pair in `FOUR_OP_PAIRS` that is in
+    /// 4-op mode.
+    pub fn four_op_mask(&self) -> u8 {
+        self.four_op_mask
+    }
+
+    /// Returns true if the channel is the first channel of a pair in 4-op mode.
+    pub fn is_four_op(&self, channel: usize) -> bool {
+        Self::pair_bit(channel).is_some_and(|bit| self.four_op_mask & bit != 0)
+    }
+
+    /// Returns false for the second channel of a pair in 4-op mode, which is played through the
+    /// first channel of the pair.
+    pub fn owns(&self, channel: usize) -> bool {
+        channel < VOICE_CHANNELS && (channel < 3 || !self.is_four_op(channel - 3))
+    }
+
+    /// Return the state of a channel.
+    pub fn state(&self, channel: usize) -> VoiceState {
+        self.slots
+            .get(channel)
+            .map_or(VoiceState::Free, |slot| slot.state)
+    }
+
+    /// Return the note played by a channel, if it is playing or releasing.
+    pub fn key(&self, channel: usize) -> Option<VoiceKey> {
+        let slot = self.slots.get(channel)?;
+        (slot.state != VoiceState::Free).then_some(slot.key)
+    }
+
+    /// Return the channels holding a note.
+    pub fn find(&self, key: VoiceKey) -> impl Iterator<Item = usize> + '_ {
+        (0..VOICE_CHANNELS).filter(move |&ch| {
+            self.slots[ch].state == VoiceState::Playing && self.slots[ch].key == key
+        })
+    }
+
+    /// Return the number of voices playing a held note, counting a 4-op pair once.
+    pub fn playing(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| slot.state == VoiceState::Playing)
+            .count()
+    }
+
+    /// Choose a channel for a new note, stealing a voice if necessary.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The `Opl3Device` being played, whose envelopes are checked for releasing
+    ///   voices that have become silent.
+    /// * `kind`   - The kind of patch the note is played with.
+    /// * `key`    - The note, for finding its voice later.
+    /// * `level`  - The loudness of the note, such as its velocity, used by `StealPolicy::Quietest`.
+    ///
+    /// # Returns
+    ///
+    /// The channel assigned to the note and the notes that were stolen, if any.
+    pub fn allocate(
+        &mut self,
+        device: &mut Opl3Device,
+        kind: VoiceKind,
+        key: VoiceKey,
+        level: u8,
+    ) -> VoiceAllocation {
+        self.allocate_except(device, kind, key, level, &[])
+    }
+
+    /// Choose a channel for a new note without taking any of the given channels, such as those
+    /// already allocated to other parts of the same note. See `allocate`.
+    ///
+    /// # Arguments
+    ///
+    /// * `device`  - The `Opl3Device` being played.
+    /// * `kind`    - The kind of patch the note is played with.
+    /// * `key`     - The note, for finding its voice later.
+    /// * `level`   - The loudness of the note.
+    /// * `exclude` - The channels that must not be taken. They are only taken if every channel
+    ///   is excluded.
+    ///
+    /// # Returns
+    ///
+    /// The channel assigned to the note and the notes that were stolen, if any.
+    pub fn allocate_except(
+        &mut self,
+        device: &mut Opl3Device,
+        kind: VoiceKind,
+        key: VoiceKey,
+        level: u8,
+        exclude: &[usize],
+    ) -> VoiceAllocation {
+        self.update(device);
+        let four_op = kind == VoiceKind::FourOp;
+        let candidates: Vec<Slot> = (0..VOICE_CHANNELS)
+            .map(|ch| self.candidate(ch, four_op))
+            .collect();
+        let mut usable: Vec<usize> = if four_op {
+            FOUR_OP_PAIRS.to_vec()
+        } else {
+            (0..VOICE_CHANNELS).filter(|&ch| self.owns(ch)).collect()
+        };
+        let excluded = |ch: usize| {
+            exclude.contains(&ch)
+                || ((four_op || self.is_four_op(ch)) && exclude.contains(&(ch + 3)))
+        };
+        if usable.iter().any(|&ch| !excluded(ch)) {
+            usable.retain(|&ch| !excluded(ch));
+        }
+        let oldest = |state: VoiceState| {
+            usable
+                .iter()
+                .copied()
+                .filter(|&ch| candidates[ch].state == state)
+                .min_by_key(|&ch| candidates[ch].age)
+        };
+
+        let same_note = usable.iter().copied().find(|&ch| {
+            self.policy == StealPolicy::SameNote
+                && candidates[ch].state != VoiceState::Free
+                && candidates[ch].key == key
+        });
+        let ch = same_note
+            .or_else(|| oldest(VoiceState::Free))
+            .or_else(|| oldest(VoiceState::Releasing))
+            .or_else(|| match self.policy {
+                StealPolicy::Quietest => usable
+                    .iter()
+                    .copied()
+                    .min_by_key(|&ch| (candidates[ch].level, candidates[ch].age)),
+                _ => oldest(VoiceState::Playing),
+            })
+            .unwrap_or(usable[0]);
+
+        let pair = if four_op || self.is_four_op(ch) {
+            vec![ch, ch + 3]
+        } else {
+            vec![ch]
+        };
+        let mut stolen = [None; 2];
+        for (i, &c) in pair.iter().enumerate() {
+            if self.slots[c].state == VoiceState::Playing {
+                Self::key_off(device, c);
+                stolen[i] = Some(self.slots[c].key);
+            }
+        }
+
+        if four_op != self.is_four_op(ch) {
+            self.slots[ch + 3] = Slot::default();
+            if let Some(bit) = Self::pair_bit(ch) {
+                self.four_op_mask ^= bit;
+                write_reg(device, 0x104, self.four_op_mask);
+            }
+        }
+
+        self.clock += 1;
+        self.slots[ch] = Slot {
+            state: VoiceState::Playing,
+            key,
+            level,
+            age: self.clock,
+        };
+        VoiceAllocation {
+            channel: ch,
+            stolen,
+        }
+    }
+
+    /// Mark a voice as releasing, once the caller has keyed it off.
+    pub fn release(&mut self, channel: usize) {
+        if let Some(slot) = self
+            .slots
+            .get_mut(channel)
+            .filter(|slot| slot.state == VoiceState::Playing)
+        {
+            self.clock += 1;
+            slot.state = VoiceState::Releasing;
+            slot.age = self.clock;
+        }
+    }
+
+    /// Mark a voice as free immediately, such as after its sound has been cut off.
+    pub fn free(&mut self, channel: usize) {
+        if let Some(slot) = self.slots.get_mut(channel) {
+            slot.state = VoiceState::Free;
+        }
+    }
+
+    /// Free the releasing voices whose carrier envelopes have decayed to silence.
+    pub fn update(&mut self, device: &Opl3Device) {
+        for ch in 0..VOICE_CHANNELS {
+            if self.slots[ch].state == VoiceState::Releasing
+                && self.carriers(device, ch).into_iter().all(|(c, op)| {
+                    device
+                        .operator_envelope(c, op)
+                        .is_none_or(|envelope| envelope.is_silent())
+                })
+            {
+                self.slots[ch].state = VoiceState::Free;
+            }
+        }
+    }
+
+    /// Return the state of a channel as a candidate for a new voice. A pair that is not yet in
+    /// 4-op mode is as busy as the busier of its channels.
+    fn candidate(&self, ch: usize, four_op: bool) -> Slot {
+        let slot = self.slots[ch];
+        if !four_op || self.is_four_op(ch) || Self::pair_bit(ch).is_none() {
+            return slot;
+        }
+        let other = self.slots[ch + 3];
+        let busier = |state: VoiceState| slot.state == state || other.state == state;
+        Slot {
+            state: if busier(VoiceState::Playing) {
+                VoiceState::Playing
+            } else if busier(VoiceState::Releasing) {
+                VoiceState::Releasing
+            } else {
+                VoiceState::Free
+            },
+            key: slot.key,
+            level: slot.level.max(other.level),
+            age: slot.age.max(other.age),
+        }
+    }
+
+    /// Return the operators of a voice that produce output, as channel and operator numbers.
+    fn carriers(&self, device: &Opl3Device, ch: usize) -> Vec<(usize, usize)> {
+        let connection = |c: usize| read_reg(device, 0xC0 + Self::channel_base(c)) & 1;
+        if self.is_four_op(ch) {
+            match (connection(ch), connection(ch + 3)) {
+                (0, 0) => vec![(ch + 3, 1)],
+                (1, 0) => vec![(ch, 0), (ch + 3, 1)],
+                (0, _) => vec![(ch, 1), (ch + 3, 1)],
+                _ => vec![(ch, 0), (ch + 3, 0), (ch + 3, 1)],
+            }
+        } else if connection(ch) != 0 {
+            vec![(ch, 0), (ch, 1)]
+        } else {
+            vec![(ch, 1)]
+        }
+    }
+
+    fn key_off(device: &mut Opl3Device, ch: usize) {
+        let reg = 0xB0 + Self::channel_base(ch);
+        write_reg(device, reg, read_reg(device, reg) & !0x20);
+    }
+
+    fn channel_base(ch: usize) -> u16 {
+        (ch / 9 * 0x100 + ch % 9) as u16
+    }
+
+    fn pair_bit(ch: usize) -> Option<u8> {
+        FOUR_OP_PAIRS.iter().position(|&p| p == ch).map(|i| 1 << i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(note: u8) -> VoiceKey {
+        VoiceKey { channel: 0, note }
+    }
+
+    #[test]
+    fn stealing_policies() {
+        let mut device = Opl3Device::new(44100);
+        let mut voices = VoiceAllocator::new(StealPolicy::Oldest);
+        for note in 0..18 {
+            voices.allocate(&mut device, VoiceKind::TwoOp, key(note), 100 - note);
+        }
+        assert_eq!(voices.playing(), 18);
+        let stolen = voices.allocate(&mut device, VoiceKind::TwoOp, key(18), 100);
+        assert_eq!(stolen.stolen, [Some(key(0)), None]);
+
+        voices.set_policy(StealPolicy::Quietest);
+        let stolen = voices.allocate(&mut device, VoiceKind::TwoOp, key(19), 100);
+        assert_eq!(stolen.stolen, [Some(key(17)), None]);
+
+        voices.set_policy(StealPolicy::SameNote);
+        let ch = voices.find(key(5)).next().unwrap();
+        let same = voices.allocate(&mut device, VoiceKind::TwoOp, key(5), 100);
+        assert_eq!(same.channel, ch);
+
+        // A 4-op note takes a whole pair, stealing both of its voices.
+        voices.set_policy(StealPolicy::Oldest);
+        let pair = voices.allocate(&mut device, VoiceKind::FourOp, key(20), 100);
+        assert_eq!(pair.channel, 1);
+        assert_eq!(pair.stolen, [Some(key(1)), Some(key(4))]);
+        assert!(voices.is_four_op(pair.channel));
+        assert!(!voices.owns(pair.channel + 3));
+        assert_eq!(voices.playing(), 17);
+
+        // A freed channel doesn't report the note it played before.
+        voices.free(9);
+        let pair = voices.allocate(&mut device, VoiceKind::FourOp, key(21), 100);
+        assert_eq!(pair.channel, 9);
+        assert_eq!(pair.stolen, [None, Some(key(12))]);
+        assert_eq!(
+            device.read_register(0x04, crate::OplRegisterFile::Secondary),
+            voices.four_op_mask()
+        );
+    }
+
+    #[test]
+    fn release_tracking() {
+        let mut device = Opl3Device::new(44100);
+        let mut voices = VoiceAllocator::new(StealPolicy::Oldest);
+        voices.reset(&mut device);
+        let ch = voices
+            .allocate(&mut device, VoiceKind::TwoOp, key(60), 100)
+            .channel;
+        for op in [0x00, 0x03] {
+            write_reg(&mut device, 0x20 + op, 0x01);
+            write_reg(&mut device, 0x40 + op, 0x00);
+            write_reg(&mut device, 0x60 + op, 0xF0);
+            write_reg(&mut device, 0x80 + op, 0x0F);
+        }
+        write_reg(&mut device, 0xA0, 0x44);
+        write_reg(&mut device, 0xB0, 0x32);
+        let mut buffer = [0i16; 2 * 441];
+        device.generate_samples(&mut buffer).unwrap();
+
+        write_reg(&mut device, 0xB0, 0x12);
+        voices.release(ch);
+        voices.update(&device);
+        assert_eq!(voices.state(ch), VoiceState::Releasing);
+        assert_eq!(voices.key(ch), Some(key(60)));
+
+        device.generate_samples(&mut buffer).unwrap();
+        voices.update(&device);
+        assert_eq!(voices.state(ch), VoiceState::Free);
+        assert_eq!(voices.key(ch), None);
+    }
+}